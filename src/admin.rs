@@ -0,0 +1,159 @@
+use crate::arbitration::ARBITER;
+use crate::battery::Batteries;
+use crate::config::ConfigHandle;
+use crate::copilot::{AxisMerge, Pairing, COPILOT};
+use crate::logger::{log_detail, Verbosity};
+use crate::profiles::{ProfileManager, DEFAULT_PROFILE};
+use crate::quarantine::QUARANTINE;
+use crate::relay::RELAY;
+use crate::stats::STATS;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::Semaphore;
+
+// Clients served at once; further connections wait in the listen backlog
+const MAX_CLIENTS: usize = 8;
+
+/// Comandos que sólo consultan estado; los únicos que `[mqtt] commands`
+/// puede permitir.
+pub const READ_ONLY_COMMANDS: &[&str] =
+    &["ping", "stats", "battery", "bans", "profile", "relay", "copilot", "token", "takeover"];
+
+/// Socket de administración: protocolo de texto, un comando por línea.
+///
+/// Ejemplo: `echo reload | socat - UNIX-CONNECT:/run/retro-control-server.sock`
+pub fn bind_admin_socket(path: &Path) -> std::io::Result<UnixListener> {
+    // A stale socket from a previous run would make bind() fail
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    log_detail(Verbosity::Low, "Socket de administración", &path.display().to_string());
+    Ok(listener)
+}
+
+pub async fn run_admin_socket(
+    listener: UnixListener,
+    config: ConfigHandle,
+    profiles: ProfileManager,
+    batteries: Batteries,
+) -> std::io::Result<()> {
+    let in_flight = Arc::new(Semaphore::new(MAX_CLIENTS));
+    loop {
+        let permit = in_flight.clone().acquire_owned().await.map_err(std::io::Error::other)?;
+        let (stream, _) = listener.accept().await?;
+        let config_clone = config.clone();
+        let profiles_clone = profiles.clone();
+        let batteries_clone = batteries.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            if let Err(e) = handle_admin_client(stream, config_clone, profiles_clone, batteries_clone).await {
+                log_detail(Verbosity::Medium, "Error en cliente de administración", &e.to_string());
+            }
+        });
+    }
+}
+
+async fn handle_admin_client(
+    stream: UnixStream,
+    config: ConfigHandle,
+    profiles: ProfileManager,
+    batteries: Batteries,
+) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        let reply = handle_command(line.trim(), &config, &profiles, &batteries);
+        writer.write_all(reply.as_bytes()).await?;
+        writer.write_all(b"\n").await?;
+    }
+    Ok(())
+}
+
+pub fn handle_command(command: &str, config: &ConfigHandle, profiles: &ProfileManager, batteries: &Batteries) -> String {
+    // `profile <nombre>` lo usan los scripts de inicio de juego del frontend
+    if let Some(name) = command.strip_prefix("profile ") {
+        return match profiles.select(&config.get(), Some(name.trim()), "socket de administración") {
+            Ok(()) => "ok".to_string(),
+            Err(e) => format!("error: {}", e),
+        };
+    }
+    if let Some(name) = command.strip_prefix("relay ") {
+        let name = Some(name.trim()).filter(|name| *name != "off");
+        return match RELAY.select(&config.get(), name) {
+            Ok(()) => "ok".to_string(),
+            Err(e) => format!("error: {}", e),
+        };
+    }
+    if let Some(args) = command.strip_prefix("copilot ") {
+        return match parse_pairing(args, config.get().gamepad.slots) {
+            Ok(pairing) => {
+                COPILOT.set(pairing);
+                "ok".to_string()
+            }
+            Err(e) => format!("error: {}", e),
+        };
+    }
+    if let Some(target) = command.strip_prefix("unban ") {
+        let ip = match target.trim() {
+            "all" => None,
+            ip => match ip.parse() {
+                Ok(ip) => Some(ip),
+                Err(_) => return format!("error: IP inválida '{}'", ip),
+            },
+        };
+        return format!("ok {}", QUARANTINE.clear(ip));
+    }
+    match command {
+        "profile" => profiles.active().unwrap_or_else(|| DEFAULT_PROFILE.to_string()),
+        "reload" => match config.reload() {
+            Ok(()) => "ok".to_string(),
+            Err(e) => format!("error: {}", e),
+        },
+        "ping" => "pong".to_string(),
+        "stats" => STATS.render(),
+        "battery" => batteries.render(),
+        "bans" => QUARANTINE.render(),
+        "copilot" => COPILOT.render(),
+        "token" => ARBITER.render(),
+        "token release" => ARBITER.revoke().map_or_else(|| "ok free".to_string(), |client| format!("ok {}", client)),
+        "takeover" => ARBITER.render_takeover(),
+        "takeover approve" | "takeover deny" => {
+            if ARBITER.answer_admin(command == "takeover approve") {
+                "ok".to_string()
+            } else {
+                "error: no hay ninguna cesión pendiente".to_string()
+            }
+        }
+        "relay" => RELAY.active().map_or_else(|| "off".to_string(), |target| format!("{} {}", target.name, target.ip)),
+        "" => String::new(),
+        other => format!("error: comando desconocido '{}'", other),
+    }
+}
+
+/// `<piloto> <copiloto> [sum|last]` (jugadores desde 1) u `off`.
+fn parse_pairing(args: &str, slots: usize) -> Result<Option<Pairing>, String> {
+    let args: Vec<&str> = args.split_whitespace().collect();
+    if args == ["off"] {
+        return Ok(None);
+    }
+    let player = |arg: &str| match arg.parse::<usize>() {
+        Ok(n) if (1..=slots).contains(&n) => Ok(n - 1),
+        _ => Err(format!("jugador inválido '{}' (1-{})", arg, slots)),
+    };
+    let (pilot, copilot, axes) = match args[..] {
+        [pilot, copilot] => (player(pilot)?, player(copilot)?, AxisMerge::Sum),
+        [pilot, copilot, axes] => {
+            let axes = AxisMerge::parse(axes).ok_or_else(|| format!("modo de ejes desconocido '{}' (sum o last)", axes))?;
+            (player(pilot)?, player(copilot)?, axes)
+        }
+        _ => return Err("uso: copilot <piloto> <copiloto> [sum|last] | copilot off".to_string()),
+    };
+    if pilot == copilot {
+        return Err("el piloto y el copiloto deben ser jugadores distintos".to_string());
+    }
+    Ok(Some(Pairing { pilot, copilot, axes }))
+}
@@ -0,0 +1,208 @@
+use crate::bus::{InputAction, SharedBus, Target};
+use crate::config::{Config, ConfigHandle};
+use crate::http::{constant_time_eq, respond, Request};
+use crate::logger::{log_detail, Verbosity};
+use crate::servers::keyboard_server::type_text;
+use evdev::Key;
+use std::net::SocketAddr;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Semaphore};
+
+// Injected snapshots waiting for the gamepad server
+const INJECTION_QUEUE: usize = 64;
+// Requests handled at once; further connections wait in the listen backlog
+const MAX_REQUESTS: usize = 8;
+const DEFAULT_HOLD: Duration = Duration::from_millis(100);
+const MAX_HOLD: Duration = Duration::from_secs(10);
+
+/// Entradas que se pueden inyectar: `/input/<nombre>` en la API REST y
+/// `<topic>/input/<nombre>` en MQTT.
+pub const INPUTS: [&str; 3] = ["key", "gamepad", "text"];
+
+// Snapshot button names, in the order of Xbox360Layout::BUTTON_CODES
+const BUTTONS: [&str; 11] = ["a", "b", "x", "y", "lb", "rb", "back", "start", "guide", "l3", "r3"];
+
+/// Snapshot de mando que no llega por el protocolo propio (API REST, Remote
+/// RetroPad). El servidor de mando lo trata como uno recibido del jugador
+/// `slot`: pasa por co-piloto, filtros, chords, encoder y perfiles igual que
+/// el de un cliente.
+pub struct Injection {
+    pub slot: usize,
+    pub buttons: [u8; 12],
+    pub axes: [i16; 8],
+}
+
+/// Cola de snapshots inyectados hacia el servidor de mando.
+pub struct Injector {
+    sender: mpsc::Sender<Injection>,
+    receiver: Mutex<Option<mpsc::Receiver<Injection>>>,
+}
+
+pub static INJECTOR: LazyLock<Injector> = LazyLock::new(|| {
+    let (sender, receiver) = mpsc::channel(INJECTION_QUEUE);
+    Injector { sender, receiver: Mutex::new(Some(receiver)) }
+});
+
+impl Injector {
+    /// Extremo de lectura, para el servidor de mando. Sólo hay uno.
+    pub fn take_receiver(&self) -> mpsc::Receiver<Injection> {
+        self.receiver.lock().unwrap().take().expect("la cola de inyección ya tiene lector")
+    }
+
+    pub async fn send(&self, injection: Injection) -> Result<(), String> {
+        self.sender.send(injection).await.map_err(|_| "el servidor de mando no está activo".to_string())
+    }
+}
+
+/// API REST (`[api] listen`) para automatización y scripts de prueba:
+/// inyecta entrada sin implementar el protocolo binario. Cada petición debe
+/// llevar `Authorization: Bearer <[api] token>`.
+///
+/// ```text
+/// curl -X POST -H 'Authorization: Bearer secreto' 'http://127.0.0.1:5561/input/gamepad?player=1&buttons=start'
+/// ```
+pub async fn run_api(listener: TcpListener, bus: SharedBus, config: ConfigHandle) -> std::io::Result<()> {
+    let in_flight = Arc::new(Semaphore::new(MAX_REQUESTS));
+    loop {
+        let permit = in_flight.clone().acquire_owned().await.map_err(std::io::Error::other)?;
+        let (stream, addr) = listener.accept().await?;
+        if !config.get().is_allowed(addr.ip()) {
+            continue;
+        }
+        let bus = bus.clone();
+        let config = config.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            if let Err(e) = handle_request(stream, addr, bus, config).await {
+                log_detail(Verbosity::Medium, "Error en cliente de la API", &format!("{}: {}", addr, e));
+            }
+        });
+    }
+}
+
+async fn handle_request(mut stream: TcpStream, addr: SocketAddr, bus: SharedBus, config: ConfigHandle) -> std::io::Result<()> {
+    let request = Request::read(&mut stream).await?;
+    let config = config.get();
+    let expected = config.api.token.as_deref().map(|token| format!("Bearer {}", token));
+    let authorized = match (expected, request.header("authorization")) {
+        (Some(expected), Some(given)) => constant_time_eq(expected.as_bytes(), given.as_bytes()),
+        _ => false,
+    };
+    if !authorized {
+        log_detail(Verbosity::Medium, "API: petición sin autorización", &format!("{} {}", addr, request.path));
+        return respond(&mut stream, "401 Unauthorized", "text/plain", "error: token inválido\n").await;
+    }
+    if request.method != "POST" {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", "error: usa POST\n").await;
+    }
+    let Some(input) = request.path.strip_prefix("/input/").filter(|input| INPUTS.contains(input)) else {
+        return respond(&mut stream, "404 Not Found", "text/plain", "error: ruta desconocida\n").await;
+    };
+    match inject(input, &|name| request.param(name), &request.body, &bus, &config).await {
+        Ok(()) => {
+            log_detail(Verbosity::Medium, "Entrada por API", &format!("{} {}", addr, request.path));
+            respond(&mut stream, "200 OK", "text/plain", "ok\n").await
+        }
+        Err(e) => respond(&mut stream, "400 Bad Request", "text/plain", &format!("error: {}\n", e)).await,
+    }
+}
+
+/// Inyecta la entrada `input`, una de `INPUTS`. `param` da cada parámetro
+/// por nombre; `body` es el texto de `text` si no llega como parámetro.
+pub async fn inject(
+    input: &str,
+    param: &(dyn Fn(&str) -> Option<String> + Sync),
+    body: &[u8],
+    bus: &SharedBus,
+    config: &Config,
+) -> Result<(), String> {
+    let hold = match param("hold_ms") {
+        None => DEFAULT_HOLD,
+        Some(ms) => Duration::from_millis(ms.parse().map_err(|_| "hold_ms inválido")?).min(MAX_HOLD),
+    };
+    match input {
+        "key" => press_keys(&param("code").ok_or("falta code")?, hold, bus).await,
+        // The devices, not the config: `slots` only applies at startup and a reload may have changed it
+        "gamepad" => press_gamepad(param, hold, bus.slots()).await,
+        "text" => {
+            let text = param("text").unwrap_or_else(|| String::from_utf8_lossy(body).into_owned());
+            type_text(&text, config.keyboard.layout, bus);
+            Ok(())
+        }
+        other => Err(format!("entrada desconocida '{}'", other)),
+    }
+}
+
+/// `code=KEY_ENTER` o `code=28`; varias con `+` (`KEY_LEFTCTRL+KEY_C`) se
+/// pulsan en orden y se sueltan al revés.
+async fn press_keys(codes: &str, hold: Duration, bus: &SharedBus) -> Result<(), String> {
+    let keys = codes
+        .split('+')
+        .map(|name| match name.parse::<u16>() {
+            Ok(code) => Ok(code),
+            Err(_) => name.parse::<Key>().map(|key| key.0).map_err(|_| format!("tecla desconocida '{}'", name)),
+        })
+        .collect::<Result<Vec<u16>, String>>()?;
+    let press: Vec<InputAction> = keys.iter().map(|&code| InputAction::Key { code, value: 1 }).collect();
+    let release: Vec<InputAction> = keys.iter().rev().map(|&code| InputAction::Key { code, value: 0 }).collect();
+    let _ = bus.emit(Target::Keyboard, &press);
+    tokio::time::sleep(hold).await;
+    let _ = bus.emit(Target::Keyboard, &release);
+    Ok(())
+}
+
+/// `player` (desde 1, por defecto 1), `buttons` separados por comas (`a`,
+/// `b`, `x`, `y`, `lb`, `rb`, `back`, `start`, `guide`, `l3`, `r3`, `lt`,
+/// `rt`, `up`, `down`, `left`, `right`) y ejes `lx`, `ly`, `rx`, `ry` entre
+/// -1 y 1. Se mantiene `hold_ms` y luego se suelta todo.
+async fn press_gamepad(param: &(dyn Fn(&str) -> Option<String> + Sync), hold: Duration, slots: usize) -> Result<(), String> {
+    let player = param("player").unwrap_or_else(|| "1".to_string());
+    let slot = match player.parse::<usize>() {
+        Ok(n) if (1..=slots).contains(&n) => n - 1,
+        _ => return Err(format!("jugador inválido '{}' (1-{})", player, slots)),
+    };
+    let mut buttons = [0u8; 12];
+    let mut axes = [0i16; 8];
+    for name in param("buttons").iter().flat_map(|names| names.split(',')).filter(|name| !name.is_empty()) {
+        match name {
+            "lt" => axes[4] = i16::MAX,
+            "rt" => axes[5] = i16::MAX,
+            "left" => axes[6] = -i16::MAX,
+            "right" => axes[6] = i16::MAX,
+            "up" => axes[7] = -i16::MAX,
+            "down" => axes[7] = i16::MAX,
+            _ => {
+                let index = BUTTONS.iter().position(|button| *button == name);
+                buttons[index.ok_or_else(|| format!("botón desconocido '{}'", name))?] = 1;
+            }
+        }
+    }
+    for (index, name) in ["lx", "ly", "rx", "ry"].into_iter().enumerate() {
+        if let Some(value) = param(name) {
+            let value: f32 = value
+                .parse()
+                .ok()
+                .filter(|value: &f32| (-1.0..=1.0).contains(value))
+                .ok_or_else(|| format!("{} fuera de -1..1", name))?;
+            axes[index] = (value * i16::MAX as f32).round() as i16;
+        }
+    }
+    INJECTOR.send(Injection { slot, buttons, axes }).await?;
+    tokio::time::sleep(hold).await;
+    INJECTOR.send(Injection { slot, buttons: [0; 12], axes: [0; 8] }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn gamepad_player_must_have_a_device() {
+        let player = |value: &'static str| move |name: &str| (name == "player").then(|| value.to_string());
+        assert!(press_gamepad(&player("3"), DEFAULT_HOLD, 2).await.is_err());
+        assert!(press_gamepad(&player("0"), DEFAULT_HOLD, 2).await.is_err());
+        assert!(press_gamepad(&player("uno"), DEFAULT_HOLD, 2).await.is_err());
+    }
+}
@@ -0,0 +1,348 @@
+use crate::identity::ClientKey;
+use crate::logger::{log_detail, Verbosity};
+use std::net::IpAddr;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, watch};
+
+/// Payload de TOKEN_STATE, visto desde la conexión que lo recibe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenState {
+    Free = 0,
+    Held = 1,
+    Other = 2,
+}
+
+struct Holder {
+    connection: u64,
+    client: ClientKey,
+    last_input: Instant,
+}
+
+/// Petición de otro dispositivo para quedarse con el turno, a la espera de
+/// que quien lo tiene (o el socket de administración) responda.
+struct Takeover {
+    connection: u64,
+    client: ClientKey,
+    ip: IpAddr,
+    deadline: Instant,
+    answer: oneshot::Sender<bool>,
+}
+
+impl Takeover {
+    fn alive(&self) -> bool {
+        Instant::now() < self.deadline && !self.answer.is_closed()
+    }
+}
+
+#[derive(Default)]
+struct TokenTable {
+    holder: Option<Holder>,
+    takeover: Option<Takeover>,
+}
+
+/// A quién se le pide ceder el turno y quién lo pide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TakeoverRequest {
+    pub holder: u64,
+    pub ip: IpAddr,
+}
+
+/// Turno del teclado con `[keyboard] arbitration = "token"`: pueden
+/// conectarse varios clientes, pero sólo el que tiene el turno inyecta
+/// teclas. Las conexiones se identifican por su id de sesión TCP.
+pub struct Arbiter {
+    table: Mutex<TokenTable>,
+    // Connection that holds the token, for the tasks that report or react to changes
+    changes: watch::Sender<Option<u64>>,
+    // Pending takeover, so the holder's connection can ask its user
+    takeovers: watch::Sender<Option<TakeoverRequest>>,
+}
+
+pub static ARBITER: LazyLock<Arbiter> = LazyLock::new(Arbiter::new);
+
+impl Arbiter {
+    fn new() -> Self {
+        Self { table: Mutex::default(), changes: watch::Sender::new(None), takeovers: watch::Sender::new(None) }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<Option<u64>> {
+        self.changes.subscribe()
+    }
+
+    pub fn subscribe_takeovers(&self) -> watch::Receiver<Option<TakeoverRequest>> {
+        self.takeovers.subscribe()
+    }
+
+    pub fn state_for(&self, connection: u64) -> TokenState {
+        match &self.table.lock().unwrap().holder {
+            None => TokenState::Free,
+            Some(holder) if holder.connection == connection => TokenState::Held,
+            Some(_) => TokenState::Other,
+        }
+    }
+
+    /// Da el turno a `connection` si está libre o si quien lo tiene lleva
+    /// `idle` sin teclear (`idle` cero = nunca se quita). Devuelve si la
+    /// conexión tiene el turno; si ya lo tenía, sólo anota la actividad.
+    pub fn claim(&self, connection: u64, client: ClientKey, idle: Duration) -> bool {
+        let mut table = self.table.lock().unwrap();
+        let now = Instant::now();
+        let previous = match table.holder.as_mut() {
+            Some(current) if current.connection == connection => {
+                current.last_input = now;
+                return true;
+            }
+            Some(current) if idle.is_zero() || now.duration_since(current.last_input) < idle => return false,
+            Some(current) => Some(current.client),
+            None => None,
+        };
+        // Whoever was asking the old holder has to ask again
+        self.answer_takeover(&mut table, false);
+        table.holder = Some(Holder { connection, client, last_input: now });
+        let detail = match previous {
+            Some(previous) => format!("cliente={} (quitado a {}, inactivo)", client, previous),
+            None => format!("cliente={}", client),
+        };
+        log_detail(Verbosity::Low, "Turno del teclado tomado", &detail);
+        self.changes.send_replace(Some(connection));
+        true
+    }
+
+    /// Suelta el turno si lo tiene `connection` (TOKEN_RELEASE o
+    /// desconexión). Si alguien estaba pidiendo el turno, pasa a él.
+    pub fn release(&self, connection: u64, reason: &str) -> bool {
+        let mut table = self.table.lock().unwrap();
+        let Some(current) = table.holder.take_if(|current| current.connection == connection) else {
+            return false;
+        };
+        log_detail(Verbosity::Low, "Turno del teclado liberado", &format!("cliente={} ({})", current.client, reason));
+        self.hand_over(&mut table);
+        true
+    }
+
+    /// `token release` en el socket de administración: quita el turno a
+    /// quien lo tenga.
+    pub fn revoke(&self) -> Option<ClientKey> {
+        let mut table = self.table.lock().unwrap();
+        let current = table.holder.take()?;
+        log_detail(Verbosity::Low, "Turno del teclado retirado", &format!("cliente={}", current.client));
+        self.hand_over(&mut table);
+        Some(current.client)
+    }
+
+    /// TOKEN_REQUEST con el turno ocupado: se lo pide a quien lo tiene, que
+    /// tiene hasta `wait` para responder. El receptor recibe `true` si
+    /// `connection` se quedó con el turno; `None` si ya hay otra petición
+    /// pendiente o el turno quedó libre entretanto.
+    pub fn request_takeover(
+        &self,
+        connection: u64,
+        client: ClientKey,
+        ip: IpAddr,
+        wait: Duration,
+    ) -> Option<oneshot::Receiver<bool>> {
+        let mut table = self.table.lock().unwrap();
+        let holder = table.holder.as_ref().filter(|holder| holder.connection != connection)?.connection;
+        if table.takeover.as_ref().is_some_and(Takeover::alive) {
+            return None;
+        }
+        let (answer, receiver) = oneshot::channel();
+        table.takeover = Some(Takeover { connection, client, ip, deadline: Instant::now() + wait, answer });
+        log_detail(Verbosity::Low, "Cesión del teclado pedida", &format!("cliente={} ({})", client, ip));
+        self.takeovers.send_replace(Some(TakeoverRequest { holder, ip }));
+        Some(receiver)
+    }
+
+    /// TAKEOVER_ANSWER de quien tiene el turno. Las respuestas de otras
+    /// conexiones se ignoran.
+    pub fn answer(&self, connection: u64, approve: bool) -> bool {
+        let mut table = self.table.lock().unwrap();
+        if table.holder.as_ref().is_none_or(|holder| holder.connection != connection) {
+            return false;
+        }
+        self.answer_takeover(&mut table, approve)
+    }
+
+    /// `takeover approve` / `takeover deny` en el socket de administración.
+    pub fn answer_admin(&self, approve: bool) -> bool {
+        let mut table = self.table.lock().unwrap();
+        self.answer_takeover(&mut table, approve)
+    }
+
+    /// El que pidió el turno dejó de esperar (se agotó el plazo).
+    pub fn withdraw(&self, connection: u64) {
+        let mut table = self.table.lock().unwrap();
+        if let Some(takeover) = table.takeover.take_if(|takeover| takeover.connection == connection) {
+            log_detail(Verbosity::Low, "Cesión del teclado sin respuesta", &format!("cliente={}", takeover.client));
+            self.takeovers.send_replace(None);
+        }
+    }
+
+    fn answer_takeover(&self, table: &mut TokenTable, approve: bool) -> bool {
+        let Some(takeover) = table.takeover.take() else {
+            return false;
+        };
+        self.takeovers.send_replace(None);
+        if !takeover.alive() {
+            return false;
+        }
+        if !approve {
+            log_detail(Verbosity::Low, "Cesión del teclado rechazada", &format!("cliente={}", takeover.client));
+            let _ = takeover.answer.send(false);
+            return true;
+        }
+        if let Some(previous) = table.holder.take() {
+            let detail = format!("cliente={} (cedido)", previous.client);
+            log_detail(Verbosity::Low, "Turno del teclado liberado", &detail);
+        }
+        table.takeover = Some(takeover);
+        self.hand_over(table);
+        true
+    }
+
+    // Gives a free token to whoever is waiting for it, if anyone
+    fn hand_over(&self, table: &mut TokenTable) {
+        let waiting = table.takeover.take().filter(Takeover::alive);
+        self.takeovers.send_replace(None);
+        let Some(takeover) = waiting else {
+            self.changes.send_replace(None);
+            return;
+        };
+        let detail = format!("cliente={} ({})", takeover.client, takeover.ip);
+        log_detail(Verbosity::Low, "Turno del teclado tomado", &detail);
+        table.holder =
+            Some(Holder { connection: takeover.connection, client: takeover.client, last_input: Instant::now() });
+        self.changes.send_replace(Some(takeover.connection));
+        let _ = takeover.answer.send(true);
+    }
+
+    pub fn render(&self) -> String {
+        match &self.table.lock().unwrap().holder {
+            Some(holder) => format!("{} idle={}s", holder.client, holder.last_input.elapsed().as_secs()),
+            None => "free".to_string(),
+        }
+    }
+
+    pub fn render_takeover(&self) -> String {
+        match self.table.lock().unwrap().takeover.as_ref().filter(|takeover| takeover.alive()) {
+            Some(takeover) => format!(
+                "{} {} {}s",
+                takeover.client,
+                takeover.ip,
+                takeover.deadline.saturating_duration_since(Instant::now()).as_secs()
+            ),
+            None => "none".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    const IDLE: Duration = Duration::from_secs(30);
+    const WAIT: Duration = Duration::from_secs(10);
+
+    fn client(port: u16) -> ClientKey {
+        ClientKey::Addr(SocketAddr::from(([192, 168, 1, 20], port)))
+    }
+
+    fn ip() -> IpAddr {
+        IpAddr::from([192, 168, 1, 21])
+    }
+
+    #[test]
+    fn first_to_claim_holds_the_token() {
+        let arbiter = Arbiter::new();
+        assert_eq!(arbiter.state_for(1), TokenState::Free);
+        assert!(arbiter.claim(1, client(1), IDLE));
+        assert!(arbiter.claim(1, client(1), IDLE));
+        assert!(!arbiter.claim(2, client(2), IDLE));
+        assert_eq!(arbiter.state_for(1), TokenState::Held);
+        assert_eq!(arbiter.state_for(2), TokenState::Other);
+        assert_eq!(*arbiter.subscribe().borrow(), Some(1));
+    }
+
+    #[test]
+    fn idle_holder_loses_the_token() {
+        let arbiter = Arbiter::new();
+        assert!(arbiter.claim(1, client(1), IDLE));
+        assert!(!arbiter.claim(2, client(2), Duration::ZERO));
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(arbiter.claim(2, client(2), Duration::from_millis(1)));
+        assert_eq!(arbiter.state_for(1), TokenState::Other);
+    }
+
+    #[test]
+    fn only_the_holder_releases() {
+        let arbiter = Arbiter::new();
+        assert!(arbiter.claim(1, client(1), IDLE));
+        assert!(!arbiter.release(2, "TOKEN_RELEASE"));
+        assert!(arbiter.release(1, "TOKEN_RELEASE"));
+        assert_eq!(arbiter.state_for(1), TokenState::Free);
+        assert_eq!(*arbiter.subscribe().borrow(), None);
+        assert!(arbiter.claim(2, client(2), IDLE));
+    }
+
+    #[test]
+    fn revoke_frees_the_token() {
+        let arbiter = Arbiter::new();
+        assert_eq!(arbiter.revoke(), None);
+        assert!(arbiter.claim(1, client(1), IDLE));
+        assert_eq!(arbiter.revoke(), Some(client(1)));
+        assert_eq!(arbiter.state_for(1), TokenState::Free);
+        assert_eq!(arbiter.render(), "free");
+    }
+
+    #[test]
+    fn approved_takeover_hands_the_token_over() {
+        let arbiter = Arbiter::new();
+        assert!(arbiter.claim(1, client(1), IDLE));
+        let mut answer = arbiter.request_takeover(2, client(2), ip(), WAIT).unwrap();
+        assert_eq!(*arbiter.subscribe_takeovers().borrow(), Some(TakeoverRequest { holder: 1, ip: ip() }));
+        // Someone else's answer does not count
+        assert!(!arbiter.answer(2, true));
+        assert!(arbiter.answer(1, true));
+        assert_eq!(answer.try_recv(), Ok(true));
+        assert_eq!(arbiter.state_for(2), TokenState::Held);
+        assert_eq!(*arbiter.subscribe_takeovers().borrow(), None);
+    }
+
+    #[test]
+    fn denied_takeover_keeps_the_holder() {
+        let arbiter = Arbiter::new();
+        assert!(arbiter.claim(1, client(1), IDLE));
+        let mut answer = arbiter.request_takeover(2, client(2), ip(), WAIT).unwrap();
+        assert!(arbiter.answer_admin(false));
+        assert_eq!(answer.try_recv(), Ok(false));
+        assert_eq!(arbiter.state_for(1), TokenState::Held);
+    }
+
+    #[test]
+    fn release_passes_the_token_to_the_requester() {
+        let arbiter = Arbiter::new();
+        assert!(arbiter.claim(1, client(1), IDLE));
+        let mut answer = arbiter.request_takeover(2, client(2), ip(), WAIT).unwrap();
+        assert!(arbiter.request_takeover(3, client(3), ip(), WAIT).is_none());
+        assert!(arbiter.release(1, "desconexión"));
+        assert_eq!(answer.try_recv(), Ok(true));
+        assert_eq!(arbiter.state_for(2), TokenState::Held);
+        assert_eq!(*arbiter.subscribe().borrow(), Some(2));
+    }
+
+    #[test]
+    fn withdrawn_or_expired_requests_get_nothing() {
+        let arbiter = Arbiter::new();
+        assert!(arbiter.request_takeover(2, client(2), ip(), WAIT).is_none());
+        assert!(arbiter.claim(1, client(1), IDLE));
+        assert!(arbiter.request_takeover(1, client(1), ip(), WAIT).is_none());
+        let _answer = arbiter.request_takeover(2, client(2), ip(), WAIT).unwrap();
+        arbiter.withdraw(2);
+        assert!(!arbiter.answer(1, true));
+        let _expired = arbiter.request_takeover(2, client(2), ip(), Duration::ZERO).unwrap();
+        assert!(arbiter.release(1, "TOKEN_RELEASE"));
+        assert_eq!(arbiter.state_for(2), TokenState::Free);
+    }
+}
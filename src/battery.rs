@@ -0,0 +1,57 @@
+use crate::identity::ClientKey;
+use crate::logger::{log_detail, Verbosity};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// Below this the server warns once per discharge
+const LOW_BATTERY: u8 = 15;
+
+struct Report {
+    slot: Option<usize>,
+    level: u8,
+    charging: bool,
+    at: Instant,
+}
+
+/// Nivel de batería que informa cada cliente. Se consulta con `battery` en
+/// el socket de administración.
+#[derive(Clone, Default)]
+pub struct Batteries {
+    reports: Arc<Mutex<HashMap<ClientKey, Report>>>,
+}
+
+impl Batteries {
+    /// Guarda el informe; `true` si el cliente acaba de entrar en batería baja.
+    pub fn report(&self, key: ClientKey, slot: Option<usize>, level: u8, charging: bool) -> bool {
+        let level = level.min(100);
+        let mut reports = self.reports.lock().unwrap();
+        let was_low = reports.get(&key).is_some_and(|r| r.level <= LOW_BATTERY && !r.charging);
+        let now_low = level <= LOW_BATTERY && !charging && !was_low;
+        if now_low {
+            let who = slot.map_or(key.to_string(), |s| format!("P{} ({})", s + 1, key));
+            log_detail(Verbosity::Low, "🔋 Batería baja", &format!("{} al {}%", who, level));
+        }
+        reports.insert(key, Report { slot, level, charging, at: Instant::now() });
+        now_low
+    }
+
+    pub fn forget(&self, key: &ClientKey) {
+        self.reports.lock().unwrap().remove(key);
+    }
+
+    /// Una línea por cliente: `P1 id:1a2b3c4d 12% charging 30s`.
+    pub fn render(&self) -> String {
+        let reports = self.reports.lock().unwrap();
+        let mut lines: Vec<_> = reports.iter().collect();
+        lines.sort_by_key(|(_, r)| r.slot.unwrap_or(usize::MAX));
+        let mut out = String::new();
+        for (key, report) in lines {
+            let slot = report.slot.map_or("-".to_string(), |s| format!("P{}", s + 1));
+            let state = if report.charging { "charging" } else { "discharging" };
+            let _ = writeln!(out, "{} {} {}% {} {}s", slot, key, report.level, state, report.at.elapsed().as_secs());
+        }
+        out.trim_end().to_string()
+    }
+}
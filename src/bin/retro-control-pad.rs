@@ -0,0 +1,221 @@
+// Shares the wire constants with the server; most of them are server-side only
+#[allow(dead_code)]
+#[path = "../protocol.rs"]
+mod protocol;
+
+use gilrs::{Axis, Button, EventType, Gamepad, GamepadId, Gilrs};
+use protocol::{
+    DEFAULT_GAMEPAD_PORT, DISCOVERY_PORT, HEADER_DISCOVERY, HEADER_GAMEPAD_SNAPSHOT, HEADER_SLOT_ASSIGN,
+    HEADER_SLOT_RELEASE, HEADER_SLOT_REQUEST, MODE_XBOX,
+};
+use std::collections::HashMap;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "\
+Uso: retro-control-pad [opciones]
+
+Envía los mandos USB/Bluetooth de este equipo a un retro-control-server,
+como la app del móvil. Sin --to, espera al anuncio de descubrimiento.
+
+Opciones:
+  --to <host>      servidor al que enviar
+  --port <puerto>  puerto UDP de mando (por defecto el anunciado, o 5558)
+  --slot <n>       jugador que pide el primer mando (los siguientes, n+1...)
+  help             muestra esta ayuda";
+
+// An idle pad resends its snapshot this often, so the server keeps its slot
+const KEEPALIVE: Duration = Duration::from_secs(1);
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Snapshot button order (the server's Xbox360Layout). gilrs names buttons by
+// position, so the Xbox X button is West and Y is North.
+const BUTTONS: [Button; 11] = [
+    Button::South,
+    Button::East,
+    Button::West,
+    Button::North,
+    Button::LeftTrigger,
+    Button::RightTrigger,
+    Button::Select,
+    Button::Start,
+    Button::Mode,
+    Button::LeftThumb,
+    Button::RightThumb,
+];
+
+struct Options {
+    host: Option<String>,
+    port: Option<u16>,
+    slot: Option<u8>,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Self { host: None, port: None, slot: None };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let mut value = || iter.next().ok_or_else(|| format!("falta el valor de {}", arg));
+            match arg.as_str() {
+                "--to" => options.host = Some(value()?.clone()),
+                "--port" => {
+                    let port = value()?;
+                    options.port = Some(port.parse().map_err(|_| format!("puerto inválido: {}", port))?);
+                }
+                "--slot" => {
+                    let slot = value()?.parse().ok().filter(|&slot| slot >= 1);
+                    options.slot = Some(slot.ok_or("--slot empieza en 1")?);
+                }
+                "help" | "--help" | "-h" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                other => return Err(format!("argumento desconocido: {}\n\n{}", other, USAGE)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Un mando conectado: su propio socket, para que el servidor le dé un
+/// jugador distinto a cada uno.
+struct Pad {
+    socket: UdpSocket,
+    last: Vec<u8>,
+    sent_at: Instant,
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = Options::parse(&args)?;
+    let server = match &options.host {
+        Some(host) => (host.as_str(), options.port.unwrap_or(DEFAULT_GAMEPAD_PORT))
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| format!("no se pudo resolver {}", host))?,
+        None => discover(options.port)?,
+    };
+    println!("▶ Enviando mandos a {}", server);
+
+    let mut gilrs = Gilrs::new().map_err(|e| format!("no se pudieron abrir los mandos: {}", e))?;
+    let mut pads: HashMap<GamepadId, Pad> = HashMap::new();
+    let mut next_slot = options.slot;
+    let connected: Vec<GamepadId> = gilrs.gamepads().map(|(id, _)| id).collect();
+    for id in connected {
+        connect(&gilrs, id, server, &mut next_slot, &mut pads)?;
+    }
+    if pads.is_empty() {
+        println!("… Esperando a que se conecte un mando");
+    }
+
+    loop {
+        if let Some(event) = gilrs.next_event_blocking(Some(KEEPALIVE)) {
+            match event.event {
+                EventType::Connected => connect(&gilrs, event.id, server, &mut next_slot, &mut pads)?,
+                EventType::Disconnected => {
+                    if let Some(pad) = pads.remove(&event.id) {
+                        let _ = pad.socket.send(&[HEADER_SLOT_RELEASE]);
+                        println!("✗ {} desconectado", gilrs.gamepad(event.id).name());
+                    }
+                }
+                _ => {}
+            }
+        }
+        for (&id, pad) in pads.iter_mut() {
+            let snapshot = snapshot(&gilrs.gamepad(id));
+            if snapshot != pad.last || pad.sent_at.elapsed() >= KEEPALIVE {
+                // Refused while the server restarts; the next snapshot retries
+                let _ = pad.socket.send(&snapshot);
+                pad.last = snapshot;
+                pad.sent_at = Instant::now();
+            }
+            read_replies(&gilrs.gamepad(id), &pad.socket);
+        }
+    }
+}
+
+fn connect(
+    gilrs: &Gilrs,
+    id: GamepadId,
+    server: SocketAddr,
+    next_slot: &mut Option<u8>,
+    pads: &mut HashMap<GamepadId, Pad>,
+) -> std::io::Result<()> {
+    let socket = UdpSocket::bind(if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+    socket.connect(server)?;
+    socket.set_nonblocking(true)?;
+    if let Some(slot) = next_slot {
+        socket.send(&[HEADER_SLOT_REQUEST, *slot])?;
+        *next_slot = slot.checked_add(1);
+    }
+    println!("✓ {} conectado", gilrs.gamepad(id).name());
+    pads.insert(id, Pad { socket, last: Vec::new(), sent_at: Instant::now() });
+    Ok(())
+}
+
+/// Snapshot `[0x42][modo][botones: u16][ejes: 8 x i16]` con el estado actual
+/// del mando. Los ejes siguen el convenio de evdev: Y crece hacia abajo.
+fn snapshot(gamepad: &Gamepad) -> Vec<u8> {
+    let mut buttons = 0u16;
+    for (index, &button) in BUTTONS.iter().enumerate() {
+        if gamepad.is_pressed(button) {
+            buttons |= 1 << index;
+        }
+    }
+    let stick = |axis, sign: f32| (gamepad.value(axis) * sign * i16::MAX as f32).round() as i16;
+    let trigger = |button| gamepad.button_data(button).map_or(0.0, |data| data.value());
+    let pressed = |negative, positive| gamepad.is_pressed(positive) as i16 - gamepad.is_pressed(negative) as i16;
+    let axes = [
+        stick(Axis::LeftStickX, 1.0),
+        stick(Axis::LeftStickY, -1.0),
+        stick(Axis::RightStickX, 1.0),
+        stick(Axis::RightStickY, -1.0),
+        (trigger(Button::LeftTrigger2) * i16::MAX as f32).round() as i16,
+        (trigger(Button::RightTrigger2) * i16::MAX as f32).round() as i16,
+        pressed(Button::DPadLeft, Button::DPadRight) * i16::MAX,
+        pressed(Button::DPadUp, Button::DPadDown) * i16::MAX,
+    ];
+
+    let mut packet = Vec::with_capacity(20);
+    packet.extend_from_slice(&[HEADER_GAMEPAD_SNAPSHOT, MODE_XBOX]);
+    packet.extend_from_slice(&buttons.to_le_bytes());
+    for axis in axes {
+        packet.extend_from_slice(&axis.to_le_bytes());
+    }
+    packet
+}
+
+// Only SLOT_ASSIGN is shown; haptics and the rest are ignored
+fn read_replies(gamepad: &Gamepad, socket: &UdpSocket) {
+    let mut buf = [0u8; 64];
+    while let Ok(len) = socket.recv(&mut buf) {
+        if len >= 2 && buf[0] == HEADER_SLOT_ASSIGN {
+            match buf[1] {
+                0 => println!("✗ {}: no quedan jugadores libres", gamepad.name()),
+                slot => println!("✓ {}: jugador {}", gamepad.name(), slot),
+            }
+        }
+    }
+}
+
+/// Espera al anuncio de descubrimiento del servidor
+/// (`[0x50][tcp: u16][ratón: u16][mando: u16]`, los puertos en LE).
+fn discover(port: Option<u16>) -> std::io::Result<SocketAddr> {
+    println!("… Buscando un servidor en la red local");
+    let socket = UdpSocket::bind(("0.0.0.0", DISCOVERY_PORT))?;
+    socket.set_read_timeout(Some(DISCOVERY_TIMEOUT))?;
+    let mut buf = [0u8; 64];
+    loop {
+        let (len, from) = socket.recv_from(&mut buf).map_err(|e| match e.kind() {
+            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut => {
+                std::io::Error::new(e.kind(), "no se encontró ningún servidor; usa --to <host>")
+            }
+            _ => e,
+        })?;
+        if len >= 5 && buf[0] == HEADER_DISCOVERY {
+            // Servers that predate the gamepad port in the announcement use the default
+            let announced = (len >= 7).then(|| u16::from_le_bytes([buf[5], buf[6]]));
+            return Ok(SocketAddr::new(from.ip(), port.or(announced).unwrap_or(DEFAULT_GAMEPAD_PORT)));
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use crate::devices::is_consumer_key;
+use crate::devices::lazy::SharedDevice;
+use crate::power;
+use crate::stats::ChannelStats;
+use crate::trace::{self, Span};
+use evdev::{AbsoluteAxisType, EventType, InputEvent, RelativeAxisType};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::broadcast;
+use tokio::time::MissedTickBehavior;
+
+// Frames buffered per observer; a slower one skips ahead
+const OBSERVER_BACKLOG: usize = 256;
+
+/// Entrada ya interpretada por un servidor, todavía sin forma de evdev. Los
+/// servidores sólo producen acciones; el bus las convierte en eventos del
+/// dispositivo de destino.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputAction {
+    /// Tecla o botón (`KEY_*`/`BTN_*`): 0 suelto, 1 pulsado, 2 repetición.
+    Key { code: u16, value: i32 },
+    /// Movimiento relativo del puntero.
+    Move { dx: i32, dy: i32 },
+    /// Rueda vertical, en muescas.
+    Wheel(i32),
+    /// Eje absoluto (`ABS_*`).
+    Axis { code: u16, value: i32 },
+}
+
+impl InputAction {
+    pub fn key(code: u16, pressed: bool) -> Self {
+        Self::Key { code, value: pressed as i32 }
+    }
+
+    /// Si juntarla con `other` en el mismo frame perdería información: una
+    /// tecla o un hat que cambia de valor dentro del tick (un toque corto).
+    fn conflicts_with(&self, other: &Self) -> bool {
+        match (*self, *other) {
+            (Self::Key { code, value }, Self::Key { code: other_code, value: other_value }) => {
+                code == other_code && value != other_value
+            }
+            (Self::Axis { code, value }, Self::Axis { code: other_code, value: other_value }) => {
+                code == other_code && value != other_value && is_hat(code)
+            }
+            _ => false,
+        }
+    }
+
+    fn push_events(&self, events: &mut Vec<InputEvent>) {
+        match *self {
+            Self::Key { code, value } => events.push(InputEvent::new(EventType::KEY, code, value)),
+            Self::Move { dx, dy } => {
+                for (axis, value) in [(RelativeAxisType::REL_X, dx), (RelativeAxisType::REL_Y, dy)] {
+                    if value != 0 {
+                        events.push(InputEvent::new(EventType::RELATIVE, axis.0, value));
+                    }
+                }
+            }
+            Self::Wheel(value) => events.push(InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_WHEEL.0, value)),
+            Self::Axis { code, value } => events.push(InputEvent::new(EventType::ABSOLUTE, code, value)),
+        }
+    }
+}
+
+/// Dispositivo virtual al que va una acción.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Target {
+    Mouse,
+    Keyboard,
+    /// Teclado del modo encoder
+    Encoder,
+    /// Pistola de luz (puntero absoluto)
+    Lightgun,
+    /// Control multimedia; recibe las teclas de ese tipo enviadas al teclado
+    Consumer,
+    /// Mando de un jugador (slot desde 0)
+    Gamepad(usize),
+}
+
+/// Paquete del que salen unas acciones: su canal y cuándo se recibió, para
+/// medir la latencia hasta que el dispositivo las emite, y su span si su
+/// cliente se está trazando.
+#[derive(Clone, Copy)]
+pub struct Origin {
+    pub channel: &'static ChannelStats,
+    pub received: Instant,
+    pub span: Option<Span>,
+}
+
+impl Origin {
+    pub fn now(channel: &'static ChannelStats) -> Self {
+        Self { channel, received: Instant::now(), span: None }
+    }
+
+    /// Como `now`, para un paquete de `client`.
+    pub fn from_client(channel: &'static ChannelStats, client: SocketAddr) -> Self {
+        Self { span: trace::span(client), ..Self::now(channel) }
+    }
+}
+
+// Frames waiting for the next tick, and the packets they came from
+#[derive(Default)]
+struct Queued {
+    frames: Vec<Vec<InputAction>>,
+    origins: Vec<Origin>,
+}
+
+/// Un frame ya emitido, tal como lo ven los observadores.
+#[derive(Debug, Clone)]
+pub struct Emitted {
+    pub at: SystemTime,
+    pub target: Target,
+    pub actions: Vec<InputAction>,
+}
+
+/// Punto único entre los servidores y los dispositivos virtuales: recibe
+/// acciones y las emite en el dispositivo de su `Target`. Todo lo que sale
+/// hacia uinput pasa por aquí, así que es el sitio para observarlo o
+/// desviarlo a otro backend.
+pub struct Bus {
+    mouse: SharedDevice,
+    keyboard: SharedDevice,
+    encoder: SharedDevice,
+    lightgun: SharedDevice,
+    /// Con `[devices] consumer`; sin él, esas teclas se quedan en el teclado.
+    consumer: Option<SharedDevice>,
+    gamepads: Vec<SharedDevice>,
+    observers: broadcast::Sender<Emitted>,
+    /// Frames por emitir en el próximo tick, si hay `[devices] coalesce_ms`.
+    pending: Option<Mutex<HashMap<Target, Queued>>>,
+}
+
+pub type SharedBus = Arc<Bus>;
+
+// Keyboard frames and consumer frames, one of each per original frame
+type SplitFrames = (Vec<Vec<InputAction>>, Vec<Vec<InputAction>>);
+
+impl Bus {
+    pub fn new(
+        mouse: SharedDevice,
+        keyboard: SharedDevice,
+        encoder: SharedDevice,
+        lightgun: SharedDevice,
+        consumer: Option<SharedDevice>,
+        gamepads: Vec<SharedDevice>,
+        coalesce: bool,
+    ) -> SharedBus {
+        let observers = broadcast::channel(OBSERVER_BACKLOG).0;
+        let pending = coalesce.then(Mutex::default);
+        Arc::new(Self { mouse, keyboard, encoder, lightgun, consumer, gamepads, observers, pending })
+    }
+
+    /// Número de jugadores (un mando virtual por slot).
+    pub fn slots(&self) -> usize {
+        self.gamepads.len()
+    }
+
+    /// Recibe una copia de cada frame emitido, después de todo el mapeo.
+    pub fn subscribe(&self) -> broadcast::Receiver<Emitted> {
+        self.observers.subscribe()
+    }
+
+    fn publish(&self, target: Target, frames: &[&[InputAction]]) {
+        // Nothing is copied while no one is watching
+        if self.observers.receiver_count() == 0 {
+            return;
+        }
+        for actions in frames.iter().filter(|f| !f.is_empty()) {
+            let _ = self.observers.send(Emitted { at: SystemTime::now(), target, actions: actions.to_vec() });
+        }
+    }
+
+    pub fn device(&self, target: Target) -> &SharedDevice {
+        match target {
+            Target::Mouse => &self.mouse,
+            Target::Keyboard => &self.keyboard,
+            Target::Encoder => &self.encoder,
+            Target::Lightgun => &self.lightgun,
+            Target::Consumer => self.consumer.as_ref().unwrap_or(&self.keyboard),
+            Target::Gamepad(slot) => &self.gamepads[slot],
+        }
+    }
+
+    /// Emite las acciones como un solo frame (un SYN_REPORT al final),
+    /// creando el dispositivo si hace falta. Sin acciones no emite nada.
+    pub fn emit(&self, target: Target, actions: &[InputAction]) -> std::io::Result<()> {
+        self.emit_frames(target, &[actions])
+    }
+
+    /// Varios frames seguidos, p. ej. la pulsación y la suelta de una tecla,
+    /// para que el frontend no los fusione.
+    pub fn emit_frames(&self, target: Target, frames: &[&[InputAction]]) -> std::io::Result<()> {
+        self.emit_frames_from(None, target, frames)
+    }
+
+    /// Como `emit`, para la respuesta directa a un paquete: al emitirse
+    /// cuenta su latencia en las estadísticas de su canal.
+    pub fn emit_from(&self, origin: Origin, target: Target, actions: &[InputAction]) -> std::io::Result<()> {
+        self.emit_frames_from(Some(origin), target, &[actions])
+    }
+
+    fn emit_frames_from(&self, origin: Option<Origin>, target: Target, frames: &[&[InputAction]]) -> std::io::Result<()> {
+        if target == Target::Keyboard
+            && let Some(frames) = power::divert_keys(frames)
+        {
+            return self.emit_frames_from(origin, target, &as_slices(&frames));
+        }
+        if let Some((keyboard, consumer)) = self.split_consumer(target, frames) {
+            let keyboard_result = self.emit_frames_from(origin, Target::Keyboard, &as_slices(&keyboard));
+            let consumer_result = self.emit_frames_from(origin, Target::Consumer, &as_slices(&consumer));
+            return keyboard_result.and(consumer_result);
+        }
+        if let Some(pending) = &self.pending {
+            let mut pending = pending.lock().unwrap();
+            for frame in frames.iter().filter(|f| !f.is_empty()) {
+                coalesce(&mut pending.entry(target).or_default().frames, frame);
+            }
+            if let Some(origin) = origin
+                && let Some(queued) = pending.get_mut(&target)
+            {
+                queued.origins.push(origin);
+            }
+            return Ok(());
+        }
+        self.send_frames(target, frames, origin.as_slice())
+    }
+
+    fn send_frames(&self, target: Target, frames: &[&[InputAction]], origins: &[Origin]) -> std::io::Result<()> {
+        let mut reports: Vec<Vec<InputEvent>> = frames.iter().filter(|f| !f.is_empty()).map(|f| to_events(f)).collect();
+        if reports.is_empty() {
+            return Ok(());
+        }
+        if let Target::Gamepad(_) = target {
+            // ¡¡ESTO ES CRÍTICO EN BATOCERA!!
+            reports.push(vec![InputEvent::new(EventType::SYNCHRONIZATION, 1, 0)]);
+        }
+        let reports: Vec<&[InputEvent]> = reports.iter().map(Vec::as_slice).collect();
+        let result = self.device(target).emit_reports(&reports);
+        for origin in origins {
+            trace::stage(origin.span, "emit", || match &result {
+                Ok(()) => format!("{:?}: {} frame(s)", target, frames.len()),
+                Err(e) => format!("{:?}: {}", target, e),
+            });
+        }
+        result?;
+        for origin in origins {
+            origin.channel.latency.record(origin.received.elapsed());
+        }
+        self.publish(target, frames);
+        Ok(())
+    }
+
+    /// Como `emit`, pero sin crear el dispositivo: para soltar lo pulsado,
+    /// que no tiene sentido en un dispositivo que nunca existió.
+    pub fn release(&self, target: Target, actions: &[InputAction]) {
+        if actions.is_empty() {
+            return;
+        }
+        if let Some((keyboard, consumer)) = self.split_consumer(target, &[actions]) {
+            self.release(Target::Keyboard, &keyboard.concat());
+            self.release(Target::Consumer, &consumer.concat());
+            return;
+        }
+        if let Some(pending) = &self.pending
+            && let Some(queued) = pending.lock().unwrap().get_mut(&target)
+        {
+            // Behind what is already queued; those frames create the device anyway
+            coalesce(&mut queued.frames, actions);
+            return;
+        }
+        if self.device(target).emit_if_created(&to_events(actions)) {
+            self.publish(target, &[actions]);
+        }
+    }
+
+    /// Frames del teclado con teclas multimedia, separados en los del teclado
+    /// y los del control multimedia. `None` si no hay nada que separar.
+    fn split_consumer(&self, target: Target, frames: &[&[InputAction]]) -> Option<SplitFrames> {
+        let is_consumer =
+            |action: &InputAction| matches!(*action, InputAction::Key { code, .. } if is_consumer_key(code));
+        if target != Target::Keyboard || self.consumer.is_none() || !frames.iter().any(|f| f.iter().any(is_consumer)) {
+            return None;
+        }
+        Some(frames.iter().map(|frame| frame.iter().copied().partition(|action| !is_consumer(action))).unzip())
+    }
+
+    /// Con `[devices] coalesce_ms`, emite cada `tick` lo acumulado: un frame
+    /// por dispositivo (más si una tecla cambió dos veces dentro del tick).
+    pub async fn run_coalescer(self: SharedBus, tick: Duration) {
+        let Some(pending) = &self.pending else {
+            return;
+        };
+        let mut ticker = tokio::time::interval(tick);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let batches = std::mem::take(&mut *pending.lock().unwrap());
+            for (target, queued) in batches {
+                let frames: Vec<&[InputAction]> = queued.frames.iter().map(Vec::as_slice).collect();
+                // Same as the servers: a device that cannot be created has logged why
+                let _ = self.send_frames(target, &frames, &queued.origins);
+            }
+        }
+    }
+}
+
+/// Junta `actions` con el último frame en cola: los movimientos y la rueda
+/// se suman y los ejes se quedan con el último valor. Si algo se perdería
+/// (ver `conflicts_with`), va en un frame propio detrás.
+fn coalesce(queued: &mut Vec<Vec<InputAction>>, actions: &[InputAction]) {
+    let Some(last) = queued.last_mut().filter(|last| !actions.iter().any(|a| last.iter().any(|q| a.conflicts_with(q)))) else {
+        queued.push(actions.to_vec());
+        return;
+    };
+    for &action in actions {
+        let merged = last.iter_mut().find_map(|queued| match (queued, action) {
+            (InputAction::Move { dx, dy }, InputAction::Move { dx: more_x, dy: more_y }) => {
+                *dx = dx.saturating_add(more_x);
+                *dy = dy.saturating_add(more_y);
+                Some(())
+            }
+            (InputAction::Wheel(notches), InputAction::Wheel(more)) => {
+                *notches = notches.saturating_add(more);
+                Some(())
+            }
+            (InputAction::Axis { code, value }, InputAction::Axis { code: new_code, value: new_value }) if *code == new_code => {
+                *value = new_value;
+                Some(())
+            }
+            (queued @ InputAction::Key { .. }, key @ InputAction::Key { .. }) if *queued == key => Some(()),
+            _ => None,
+        });
+        if merged.is_none() {
+            last.push(action);
+        }
+    }
+}
+
+fn is_hat(code: u16) -> bool {
+    (AbsoluteAxisType::ABS_HAT0X.0..=AbsoluteAxisType::ABS_HAT3Y.0).contains(&code)
+}
+
+fn as_slices(frames: &[Vec<InputAction>]) -> Vec<&[InputAction]> {
+    frames.iter().map(Vec::as_slice).collect()
+}
+
+fn to_events(actions: &[InputAction]) -> Vec<InputEvent> {
+    let mut events = Vec::with_capacity(actions.len() + 1);
+    for action in actions {
+        action.push_events(&mut events);
+    }
+    events
+}
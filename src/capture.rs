@@ -0,0 +1,259 @@
+use crate::logger::{log_detail, Verbosity};
+use crate::network::ClientStream;
+use crate::stats::STATS;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+pub const MAGIC: &[u8; 6] = b"RCCAP\x01";
+
+/// Canal de cada registro de la captura.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Mouse = 0,
+    /// Bytes de un stream TCP, en el orden en que se leyeron
+    Keyboard = 1,
+    Gamepad = 2,
+    /// El stream TCP de esa dirección se cerró (registro sin datos)
+    KeyboardClosed = 3,
+}
+
+impl Channel {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Mouse),
+            1 => Some(Self::Keyboard),
+            2 => Some(Self::Gamepad),
+            3 => Some(Self::KeyboardClosed),
+            _ => None,
+        }
+    }
+}
+
+/// Un registro leído de una captura.
+pub struct Record {
+    pub time_us: u64,
+    pub channel: Channel,
+    pub addr: String,
+    pub data: Vec<u8>,
+}
+
+/// Lee una captura completa. Un último registro cortado (el servidor murió
+/// a medias) se descarta sin error.
+pub fn read(path: &Path) -> std::io::Result<Vec<Record>> {
+    let bytes = std::fs::read(path)?;
+    let invalid = |what: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, what.to_string());
+    let mut rest = bytes.strip_prefix(MAGIC).ok_or_else(|| invalid("no es una captura de retro-control-server"))?;
+    let mut records = Vec::new();
+    while rest.len() >= 10 {
+        let time_us = u64::from_le_bytes(rest[..8].try_into().unwrap());
+        let channel = Channel::from_byte(rest[8]).ok_or_else(|| invalid("canal desconocido en la captura"))?;
+        let addr_len = rest[9] as usize;
+        let Some(addr) = rest.get(10..10 + addr_len) else {
+            break;
+        };
+        let Some(len) = rest.get(10 + addr_len..12 + addr_len) else {
+            break;
+        };
+        let start = 12 + addr_len;
+        let end = start + u16::from_le_bytes([len[0], len[1]]) as usize;
+        let Some(data) = rest.get(start..end) else {
+            break;
+        };
+        records.push(Record {
+            time_us,
+            channel,
+            addr: String::from_utf8_lossy(addr).into_owned(),
+            data: data.to_vec(),
+        });
+        rest = &rest[end..];
+    }
+    Ok(records)
+}
+
+/// Todo lo recibido por la red, con marca de tiempo y origen, para que los
+/// desarrolladores de clientes puedan mandar sesiones problemáticas.
+///
+/// Formato: `RCCAP\x01` y luego registros
+/// `[time_us:u64][channel:1][addr_len:1][addr:utf8][len:u16][data:len]`,
+/// enteros little-endian y `time_us` desde el inicio de la captura.
+struct Capture {
+    start: Instant,
+    file: Mutex<BufWriter<File>>,
+}
+
+static CAPTURE: OnceLock<Capture> = OnceLock::new();
+
+pub fn start(path: &Path) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(MAGIC)?;
+    file.flush()?;
+    let _ = CAPTURE.set(Capture { start: Instant::now(), file: Mutex::new(file) });
+    log_detail(Verbosity::Low, "Capturando paquetes", &path.display().to_string());
+    Ok(())
+}
+
+pub fn record(channel: Channel, addr: SocketAddr, data: &[u8]) {
+    let Some(capture) = CAPTURE.get() else {
+        return;
+    };
+    let time_us = capture.start.elapsed().as_micros() as u64;
+    let mut file = capture.file.lock().unwrap();
+    // Flushed per record so a crash still leaves a usable capture
+    if let Err(e) = write_record(&mut *file, time_us, channel, addr, data).and_then(|()| file.flush()) {
+        log_detail(Verbosity::Low, "Error escribiendo captura", &e.to_string());
+    }
+}
+
+fn write_record(
+    out: &mut impl Write,
+    time_us: u64,
+    channel: Channel,
+    addr: SocketAddr,
+    data: &[u8],
+) -> std::io::Result<()> {
+    let addr = addr.to_string();
+    // Our buffers are far smaller, but never overflow the length field
+    let data = &data[..data.len().min(u16::MAX as usize)];
+    out.write_all(&time_us.to_le_bytes())?;
+    out.write_all(&[channel as u8, addr.len() as u8])?;
+    out.write_all(addr.as_bytes())?;
+    out.write_all(&(data.len() as u16).to_le_bytes())?;
+    out.write_all(data)
+}
+
+/// Conexión del teclado que registra en la captura todo lo que se lee de
+/// ella y, con `tee`, además lo copia a otro destino (el relé).
+pub struct CapturedStream {
+    inner: ClientStream,
+    addr: SocketAddr,
+    tee: Option<mpsc::Sender<Vec<u8>>>,
+    // Bytes handed to the reader so far: the offset of the next one in the stream
+    read: u64,
+}
+
+impl CapturedStream {
+    pub fn new(inner: ClientStream, addr: SocketAddr) -> Self {
+        Self { inner, addr, tee: None, read: 0 }
+    }
+
+    /// Bytes leídos del stream hasta ahora.
+    pub fn bytes_read(&self) -> u64 {
+        self.read
+    }
+
+    /// Bytes que ya llegaron y todavía nadie leyó.
+    pub fn queued(&self) -> std::io::Result<u64> {
+        self.inner.queued()
+    }
+
+    pub fn tee(&mut self, tx: mpsc::Sender<Vec<u8>>) {
+        self.tee = Some(tx);
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl AsyncRead for CapturedStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let addr = self.addr;
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result
+            && buf.filled().len() > before
+        {
+            self.read += (buf.filled().len() - before) as u64;
+            record(Channel::Keyboard, addr, &buf.filled()[before..]);
+            if let Some(tee) = &self.tee {
+                // Skipping bytes would garble the stream, so a remote that falls behind is cut off
+                match tee.try_send(buf.filled()[before..].to_vec()) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) => {
+                        STATS.relay.record_dropped(1);
+                        log_detail(Verbosity::Low, "Relé saturado, copia cortada", &addr.to_string());
+                        self.tee = None;
+                    }
+                    Err(TrySendError::Closed(_)) => self.tee = None,
+                }
+            }
+        }
+        result
+    }
+}
+
+impl AsyncWrite for CapturedStream {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr() -> SocketAddr {
+        "192.168.1.20:40000".parse().unwrap()
+    }
+
+    fn capture(records: &[(u64, Channel, &[u8])]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        for &(time_us, channel, data) in records {
+            write_record(&mut bytes, time_us, channel, addr(), data).unwrap();
+        }
+        bytes
+    }
+
+    fn read_bytes(name: &str, bytes: &[u8]) -> std::io::Result<Vec<Record>> {
+        let path = std::env::temp_dir().join(format!("retro-capture-{}-{}.rccap", std::process::id(), name));
+        std::fs::write(&path, bytes).unwrap();
+        let records = read(&path);
+        std::fs::remove_file(&path).unwrap();
+        records
+    }
+
+    #[test]
+    fn records_read_back_as_written() {
+        let bytes = capture(&[(0, Channel::Mouse, &[0x20, 1, 2]), (1500, Channel::KeyboardClosed, &[])]);
+        let records = read_bytes("roundtrip", &bytes).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!((records[0].time_us, records[0].channel), (0, Channel::Mouse));
+        assert_eq!(records[0].addr, "192.168.1.20:40000");
+        assert_eq!(records[0].data, [0x20, 1, 2]);
+        assert_eq!((records[1].time_us, records[1].channel), (1500, Channel::KeyboardClosed));
+        assert!(records[1].data.is_empty());
+    }
+
+    #[test]
+    fn cut_last_record_is_dropped() {
+        let mut bytes = capture(&[(0, Channel::Gamepad, &[0x42; 8]), (10, Channel::Gamepad, &[0x42; 8])]);
+        bytes.truncate(bytes.len() - 3);
+        let records = read_bytes("cut", &bytes).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].data, [0x42; 8]);
+    }
+
+    #[test]
+    fn foreign_files_and_unknown_channels_are_errors() {
+        assert!(read_bytes("magic", b"RCCAP\x02").is_err());
+        let mut bytes = capture(&[(0, Channel::Mouse, &[0x20])]);
+        bytes[MAGIC.len() + 8] = 9;
+        assert!(read_bytes("channel", &bytes).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use crate::identity::{ClientId, ClientKey};
+use crate::logger::{log_detail, Verbosity};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+/// Ejes calibrables del snapshot: sticks (0..3) y gatillos (4, 5). Los ejes
+/// del d-pad ya son digitales.
+pub const CALIBRATED_AXES: usize = 6;
+
+/// Tope de `rumble_intensity`: el doble de lo que pide el juego, para
+/// móviles con vibración floja.
+pub const MAX_RUMBLE_INTENSITY: u8 = 200;
+
+/// Rango real de un eje medido durante la calibración.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AxisCalibration {
+    pub min: i16,
+    pub center: i16,
+    pub max: i16,
+}
+
+impl AxisCalibration {
+    /// Sin corrección: el rango nominal del protocolo.
+    pub const FULL_RANGE: Self = Self { min: -32768, center: 0, max: 32767 };
+
+    /// Reescala `value` para que `min`/`center`/`max` medidos lleguen a
+    /// -32768/0/32767. Un lado sin recorrido (gatillos) queda en 0.
+    pub fn apply(&self, value: i16) -> i16 {
+        let offset = value as i32 - self.center as i32;
+        let span = if offset >= 0 {
+            self.max as i32 - self.center as i32
+        } else {
+            self.center as i32 - self.min as i32
+        };
+        if span <= 0 {
+            return 0;
+        }
+        let full = if offset >= 0 { 32767 } else { 32768 };
+        (offset * full / span).clamp(-32768, 32767) as i16
+    }
+}
+
+/// Ajustes que el servidor recuerda por cliente.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientProfile {
+    pub calibration: Option<Vec<AxisCalibration>>,
+    /// Reenviar al cliente la vibración de los juegos (RUMBLE).
+    pub rumble: bool,
+    /// Intensidad de esa vibración, en porcentaje (0..=200).
+    pub rumble_intensity: u8,
+}
+
+impl Default for ClientProfile {
+    fn default() -> Self {
+        Self {
+            calibration: None,
+            rumble: true,
+            rumble_intensity: 100,
+        }
+    }
+}
+
+impl ClientProfile {
+    pub fn calibrate(&self, axes: &mut [i16; 8]) {
+        if let Some(calibration) = &self.calibration {
+            for (axis, cal) in axes.iter_mut().zip(calibration) {
+                *axis = cal.apply(*axis);
+            }
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct ProfilesFile {
+    #[serde(default)]
+    clients: BTreeMap<String, ClientProfile>,
+}
+
+/// Perfiles por cliente. Los de clientes identificados (HELLO) se guardan en
+/// `[gamepad] profiles_file` si está configurado; los demás sólo viven en
+/// memoria, porque su dirección no sobrevive a una reconexión.
+pub struct ClientProfiles {
+    path: Option<PathBuf>,
+    profiles: HashMap<ClientKey, ClientProfile>,
+}
+
+impl ClientProfiles {
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let mut profiles = HashMap::new();
+        if let Some(path) = &path {
+            match std::fs::read_to_string(path) {
+                Ok(text) => match toml::from_str::<ProfilesFile>(&text) {
+                    Ok(file) => {
+                        for (hex, profile) in file.clients {
+                            match ClientId::from_hex(&hex) {
+                                Some(id) => {
+                                    profiles.insert(ClientKey::Id(id), profile);
+                                }
+                                None => log_detail(Verbosity::Low, "Perfil ignorado", &format!("id inválido: {}", hex)),
+                            }
+                        }
+                    }
+                    Err(e) => log_detail(Verbosity::Low, "Error en perfiles de cliente", &format!("{}: {}", path.display(), e)),
+                },
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => log_detail(Verbosity::Low, "Error en perfiles de cliente", &format!("{}: {}", path.display(), e)),
+            }
+        }
+        Self { path, profiles }
+    }
+
+    pub fn get(&self, key: &ClientKey) -> Option<&ClientProfile> {
+        self.profiles.get(key)
+    }
+
+    /// Modifica el perfil de `key` (creándolo si hace falta) y lo persiste.
+    pub fn update(&mut self, key: ClientKey, change: impl FnOnce(&mut ClientProfile)) {
+        change(self.profiles.entry(key).or_default());
+        if let ClientKey::Id(_) = key {
+            self.save();
+        }
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let file = ProfilesFile {
+            clients: self
+                .profiles
+                .iter()
+                .filter_map(|(key, profile)| match key {
+                    ClientKey::Id(id) => Some((id.to_hex(), profile.clone())),
+                    ClientKey::Addr(_) => None,
+                })
+                .collect(),
+        };
+        let result = toml::to_string(&file)
+            .map_err(std::io::Error::other)
+            .and_then(|text| std::fs::write(path, text));
+        if let Err(e) = result {
+            log_detail(Verbosity::Low, "Error guardando perfiles", &format!("{}: {}", path.display(), e));
+        }
+    }
+}
@@ -0,0 +1,1076 @@
+use crate::admin::READ_ONLY_COMMANDS;
+use crate::protocol::{
+    DEFAULT_GAMEPAD_PORT, DEFAULT_KEYBOARD_PORT, DEFAULT_MOUSE_PORT, DEFAULT_REGISTER_PORT,
+    DISCOVERY_FAST_INTERVAL_MS, DISCOVERY_FAST_PERIOD_SECS, DISCOVERY_INTERVAL_MS, MAX_EXTRA_AXES,
+};
+use crate::devices::LIGHTGUN_MAX;
+use crate::devices::xbox360_layout::Xbox360Layout;
+use crate::locale::{set_locale, Locale};
+use crate::logger::{check_subsystem, log_detail, set_log_format, set_verbosity, LogFormat, Verbosity, VerbositySpec};
+use crate::systemd;
+use crate::keyboard_layout::KeyboardLayout;
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Configuración recargable en caliente (SIGHUP o socket de administración).
+///
+/// Todo lo que vive aquí se puede cambiar sin recrear los dispositivos uinput,
+/// así que los emuladores no pierden el control al recargar.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Nivel de log (0..2). `--verbosity` en la línea de comandos tiene prioridad.
+    pub verbosity: Option<u8>,
+    pub log: LogConfig,
+    pub network: NetworkConfig,
+    pub discovery: DiscoveryConfig,
+    pub security: SecurityConfig,
+    pub sessions: SessionConfig,
+    pub keyboard: KeyboardConfig,
+    pub gamepad: GamepadConfig,
+    pub encoder: EncoderConfig,
+    pub lightgun: LightgunConfig,
+    pub mouse: MouseConfig,
+    pub admin: AdminConfig,
+    pub relay: RelayConfig,
+    pub observer: ObserverConfig,
+    pub api: ApiConfig,
+    pub mqtt: MqttConfig,
+    pub remote_retropad: RemoteRetroPadConfig,
+    pub synergy: SynergyConfig,
+    pub dsu: DsuConfig,
+    pub quic: QuicConfig,
+    pub privileges: PrivilegesConfig,
+    pub scheduling: SchedulingConfig,
+    pub devices: DevicesConfig,
+    pub power: PowerConfig,
+    pub rendezvous: RendezvousConfig,
+    /// Perfiles de mapeo por nombre; ver `profiles::ProfileManager`.
+    pub profiles: BTreeMap<String, MappingProfile>,
+}
+
+/// Sólo se aplica al arrancar; cambiarlo requiere reiniciar el servidor.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    /// IP o nombre de interfaz donde escuchan todos los servidores.
+    pub bind: String,
+    /// Sobrescriben `bind` para un servidor concreto.
+    pub mouse_bind: Option<String>,
+    pub keyboard_bind: Option<String>,
+    pub gamepad_bind: Option<String>,
+    pub mouse_port: u16,
+    pub keyboard_port: u16,
+    pub gamepad_port: u16,
+    /// Cuántos puertos siguientes probar si el configurado está ocupado (0 = ninguno).
+    pub port_fallback: u16,
+    /// SO_RCVBUF de los sockets UDP en bytes (0 = valor del kernel). Sólo
+    /// se aplica al arrancar.
+    pub udp_recv_buffer: usize,
+    /// Sockets SO_REUSEPORT por servidor UDP, cada uno con su tarea de
+    /// recepción (1 = un único socket). Sólo se aplica al arrancar.
+    pub udp_receivers: usize,
+    /// Microsegundos que los receptores UDP reintentan la lectura en bucle
+    /// antes de dormir (0 = nunca). Gasta CPU para arañar latencia.
+    pub busy_poll_us: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    /// Interfaz por la que anunciar el servidor. Sin valor = todas las que
+    /// tengan broadcast de subred.
+    pub interface: Option<String>,
+    /// Cada cuánto volver a enumerar las interfaces de red.
+    pub refresh_interfaces_secs: u64,
+    /// Seguir anunciando con clientes conectados (para que un segundo
+    /// teléfono encuentre el servidor). Por defecto se calla al conectar
+    /// el primer cliente TCP.
+    pub while_connected: bool,
+    /// Intervalo en reposo.
+    pub interval_ms: u64,
+    /// Intervalo rápido tras arrancar o tras perder a todos los clientes,
+    /// para que las apps encuentren el servidor enseguida.
+    pub fast_interval_ms: u64,
+    /// Cuánto dura la fase rápida antes de pasar a `interval_ms`.
+    pub fast_period_secs: u64,
+    /// Puerto UDP donde los clientes de otras redes envían REGISTER para
+    /// recibir el anuncio en unicast (0 = desactivado). Sólo se lee al arrancar.
+    pub register_port: u16,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityConfig {
+    /// Redes/IPs con permiso para enviar input. Vacío = cualquiera.
+    pub allowlist: Vec<IpNet>,
+    /// Paquetes UDP por segundo permitidos por IP (0 = sin límite).
+    pub udp_rate_limit: u32,
+    /// Ráfaga máxima por encima de `udp_rate_limit`.
+    pub udp_rate_burst: u32,
+    /// Paquetes inválidos dentro de `quarantine_window_secs` tras los que se
+    /// ignora a una IP (0 = nunca).
+    pub quarantine_threshold: u32,
+    pub quarantine_window_secs: u64,
+    /// Duración de la cuarentena.
+    pub quarantine_secs: u64,
+    /// Clave compartida con la que los clientes firman sus datagramas de
+    /// ratón y mando (ver `packet_auth`). Sin valor = sin firma.
+    pub packet_key: Option<String>,
+}
+
+/// Límite de clientes simultáneos por canal (teclado, mouse, gamepad).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    pub max_clients: usize,
+    pub policy: SessionPolicy,
+    /// Segundos sin input tras los que se cierra una conexión TCP, soltando
+    /// sus teclas y liberando su hueco (0 = nunca).
+    pub idle_timeout_secs: u64,
+    /// Igual para los clientes UDP, que no tienen desconexión explícita.
+    pub udp_idle_timeout_secs: u64,
+    /// Soltar a la fuerza una tecla o botón pulsado más de estos segundos
+    /// sin que el cliente lo refresque (0 = nunca). Recupera releases
+    /// perdidos sin esperar a que la sesión caduque.
+    pub held_limit_secs: u64,
+}
+
+/// Qué hacer cuando llega un cliente nuevo y no hay hueco.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionPolicy {
+    /// Rechazar al recién llegado (el primero en conectar se queda).
+    RejectNewest,
+    /// Desconectar al cliente más antiguo.
+    EvictOldest,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyboardConfig {
+    /// Pares `[scancode_cliente, código_evdev]` aplicados antes de emitir.
+    pub remap: Vec<(u16, u16)>,
+    /// Autorepetición en el servidor: espera antes de repetir y repeticiones
+    /// por segundo (0 = desactivada; el cliente puede enviar estado 2).
+    pub repeat_delay_ms: u64,
+    pub repeat_rate_hz: u32,
+    /// Distribución de la consola del host, usada para traducir los
+    /// caracteres de los paquetes TEXT.
+    pub layout: KeyboardLayout,
+    /// Teclas pulsadas a la vez como máximo por conexión (0 = sin límite).
+    /// Protege de estados "todo pulsado" por clientes rotos o paquetes corruptos.
+    pub max_held_keys: usize,
+    /// Qué hacer con una pulsación nueva cuando ya se alcanzó el límite.
+    pub rollover: RolloverPolicy,
+    /// Teclas analógicas (paquete ANALOG_KEY) que mueven un eje del mando:
+    /// `[código_evdev, índice_eje, dirección]`, con dirección 1 o -1.
+    pub analog_keys: Vec<(u16, usize, i8)>,
+    /// Jugador (desde 1) cuyo mando mueven las teclas analógicas.
+    pub analog_player: usize,
+    /// Tiempo mínimo entre dos cambios de modo (0 = sin límite). Un cambio
+    /// antes de tiempo no se aplica y cuenta como infracción.
+    pub mode_switch_interval_ms: u64,
+    /// Cómo se reparten el teclado varios clientes conectados a la vez.
+    pub arbitration: KeyboardArbitration,
+    /// Con `arbitration = "token"`, segundos sin teclear tras los que otro
+    /// cliente puede tomar el turno sin que se suelte (0 = nunca).
+    pub token_idle_secs: u64,
+    /// Segundos que tiene quien tiene el turno para aceptar que otro
+    /// dispositivo se lo quite (0 = las peticiones se rechazan sin preguntar).
+    pub takeover_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyboardArbitration {
+    /// Se conectan todos y sólo el que tiene el turno inyecta teclas.
+    Token,
+    /// Una sesión por cliente con el límite y la política de `[sessions]`.
+    Exclusive,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RolloverPolicy {
+    /// Ignorar la tecla nueva; las que ya estaban pulsadas siguen igual.
+    IgnoreNewest,
+    /// Soltar la tecla que lleva más tiempo sin pulsarse ni refrescarse.
+    ReleaseOldest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerMode {
+    Analog,
+    Digital,
+    Both,
+}
+
+/// Eje de `[gamepad] extra_axes`, fuera del layout Xbox.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtraAxis {
+    Throttle,
+    Rudder,
+    Misc,
+    /// Segunda cruceta, horizontal.
+    Hat1x,
+    /// Segunda cruceta, vertical.
+    Hat1y,
+}
+
+impl ExtraAxis {
+    pub fn code(self) -> u16 {
+        match self {
+            Self::Throttle => 6, // ABS_THROTTLE
+            Self::Rudder => 7,   // ABS_RUDDER
+            Self::Misc => 40,    // ABS_MISC
+            Self::Hat1x => 18,   // ABS_HAT1X
+            Self::Hat1y => 19,   // ABS_HAT1Y
+        }
+    }
+
+    /// Las crucetas van de -1 a 1; el resto usa el rango de los sticks.
+    pub fn is_hat(self) -> bool {
+        matches!(self, Self::Hat1x | Self::Hat1y)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GuideMode {
+    /// BTN_MODE en el gamepad virtual.
+    Button,
+    /// Una tecla del teclado virtual (`guide_key`).
+    Key,
+    /// Lo consume el servidor como modificador de `hotkeys`.
+    Hotkey,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GamepadConfig {
+    /// Qué emiten los gatillos: eje analógico (ABS_Z/ABS_RZ), botón digital
+    /// (BTN_TL2/BTN_TR2) o ambos.
+    pub trigger_mode: TriggerMode,
+    /// Valor del snapshot que corresponde al gatillo a fondo; se escala a 0..255.
+    pub trigger_input_max: i16,
+    /// Valor de gatillo (ya en 0..255) a partir del cual se pulsa el botón digital.
+    pub trigger_threshold: i32,
+    /// Desviación del stick izquierdo que cuenta como dirección en modo arcade.
+    pub hat_threshold: i16,
+    /// Fichero donde se guardan los perfiles por cliente (calibración). Sin
+    /// valor, sólo se recuerdan mientras el servidor esté en marcha.
+    pub profiles_file: Option<PathBuf>,
+    /// Qué hace el botón Guide.
+    pub guide: GuideMode,
+    /// Tecla evdev emitida con `guide = "key"` (por defecto KEY_ESC).
+    pub guide_key: u16,
+    /// Con `guide = "hotkey"`: pares `[índice_botón, tecla_evdev]` que se
+    /// emiten al pulsar ese botón mientras se mantiene Guide.
+    pub hotkeys: Vec<(usize, u16)>,
+    /// Número de jugadores; cada uno tiene su gamepad virtual. Sólo se lee
+    /// al arrancar.
+    pub slots: usize,
+    /// Mando estilo DualShock 2: ejes extra con la presión de los botones
+    /// frontales, LB/RB y la cruceta. Sólo se lee al arrancar.
+    pub pressure: bool,
+    /// Ejes que se añaden al gamepad virtual, movidos por el snapshot
+    /// extendido en este orden. Sólo se lee al arrancar.
+    pub extra_axes: Vec<ExtraAxis>,
+    /// Limita el d-pad a 4 direcciones (gana el eje con más desviación).
+    pub four_way: bool,
+    /// Modo navegación: d-pad, A y B se emiten como flechas, Enter y Esc en
+    /// el teclado virtual.
+    pub navigation: bool,
+    /// El stick derecho en vertical mueve la rueda del ratón virtual, más
+    /// rápido cuanto más se inclina, en vez de llegar al gamepad.
+    pub flick_scroll: bool,
+    /// Combinaciones `[[índices_botón], tecla_evdev]`: pulsar todos esos
+    /// botones a la vez emite la tecla en el teclado virtual en su lugar.
+    pub chords: Vec<(Vec<usize>, u16)>,
+    /// Margen para pulsar todos los botones de una combinación.
+    pub chord_window_ms: u64,
+    /// Filtros aplicados a cada snapshot, en orden, antes de emitirlo.
+    pub filters: Vec<FilterConfig>,
+    /// Modo encoder: los botones con tecla en `[encoder] players` se emiten
+    /// en el teclado "Retro Control Encoder" en vez de en el gamepad.
+    pub encoder: bool,
+    /// Las teclas de navegación y del encoder se emiten además de la entrada
+    /// del gamepad, no en su lugar.
+    pub mirror: bool,
+    /// Cada cuánto buscar procesos de `[profiles.*] processes` (0 = nunca).
+    pub process_watch_secs: u64,
+    /// Rangos de los ejes del gamepad virtual; ver `AxisRanges`.
+    pub axis_ranges: AxisRanges,
+}
+
+/// Rangos (AbsInfo) de los sticks y gatillos del gamepad virtual. Por
+/// defecto los de xpad. Los valores del snapshot se reescalan a ellos.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AxisRanges {
+    pub stick_min: i32,
+    pub stick_max: i32,
+    /// Ruido que el kernel filtra en los sticks.
+    pub stick_fuzz: i32,
+    /// Zona que los lectores de joystick tratan como centro.
+    pub stick_flat: i32,
+    pub trigger_min: i32,
+    pub trigger_max: i32,
+    pub trigger_fuzz: i32,
+    pub trigger_flat: i32,
+}
+
+impl AxisRanges {
+    /// Un valor de stick del snapshot (-32768..32767) en `stick_min..stick_max`.
+    pub fn stick(&self, value: i16) -> i32 {
+        let span = self.stick_max as i64 - self.stick_min as i64;
+        (self.stick_min as i64 + (value as i64 - i16::MIN as i64) * span / u16::MAX as i64) as i32
+    }
+
+    /// Un gatillo entre 0 y `max` en `trigger_min..trigger_max`.
+    pub fn trigger(&self, value: i32, max: i32) -> i32 {
+        let span = self.trigger_max as i64 - self.trigger_min as i64;
+        (self.trigger_min as i64 + value.clamp(0, max) as i64 * span / max.max(1) as i64) as i32
+    }
+
+    /// Un valor ya emitido como fracción del recorrido del stick, -1..1.
+    pub fn stick_fraction(&self, value: i32) -> f32 {
+        let span = self.stick_max as f32 - self.stick_min as f32;
+        ((value as f32 - self.stick_min as f32) / span * 2.0 - 1.0).clamp(-1.0, 1.0)
+    }
+
+    /// Un valor ya emitido como fracción del recorrido del gatillo, 0..1.
+    pub fn trigger_fraction(&self, value: i32) -> f32 {
+        let span = self.trigger_max as f32 - self.trigger_min as f32;
+        ((value as f32 - self.trigger_min as f32) / span).clamp(0.0, 1.0)
+    }
+}
+
+/// Una entrada de `[gamepad] filters`; ver `filters::build`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum FilterConfig {
+    /// Zona muerta por eje, con el resto del recorrido reescalado.
+    Deadzone {
+        #[serde(default = "default_deadzone_axes")]
+        axes: Vec<usize>,
+        radius: i16,
+    },
+    /// Autodisparo de esos botones mientras se mantienen pulsados.
+    Turbo { buttons: Vec<usize>, hz: f64 },
+    /// Resolución de direcciones opuestas entre stick izquierdo y d-pad.
+    Socd {
+        mode: SocdMode,
+        #[serde(default = "default_socd_threshold")]
+        threshold: i16,
+    },
+    /// Pares `[índice_origen, índice_destino]` de botones.
+    Remap { buttons: Vec<(usize, usize)> },
+    /// Ejes llevados a otros ejes, con escala; ver `AxisRoute`.
+    AxisRemap { axes: Vec<AxisRoute> },
+    /// Script Rhai con enganches sobre el snapshot; ver `scripting::Script`.
+    Script {
+        path: PathBuf,
+        /// Puerto de comandos de red de RetroArch (`network_cmd_port`).
+        #[serde(default = "default_retroarch_address")]
+        retroarch: SocketAddr,
+    },
+    /// Módulo WebAssembly aislado; ver `plugins::WasmPlugin`.
+    Wasm { path: PathBuf },
+}
+
+/// Una entrada de `axis_remap`: el eje `to` recibe `from * scale + offset`.
+/// Los índices 0-7 son los del snapshot y 8 en adelante los de `extra_axes`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AxisRoute {
+    pub from: usize,
+    pub to: usize,
+    #[serde(default = "default_axis_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub offset: i32,
+}
+
+fn default_axis_scale() -> f64 {
+    1.0
+}
+
+fn default_retroarch_address() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 55355))
+}
+
+fn default_deadzone_axes() -> Vec<usize> {
+    vec![0, 1, 2, 3]
+}
+
+fn default_socd_threshold() -> i16 {
+    20000
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SocdMode {
+    /// Las dos direcciones se anulan.
+    Neutral,
+    /// Gana la última que se pulsó.
+    LastWins,
+    /// En vertical gana arriba; en horizontal se anulan (estilo hitbox).
+    UpPriority,
+}
+
+/// Teclas del modo encoder, con la distribución de un I-PAC por defecto.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EncoderConfig {
+    /// Por jugador, pares `[índice_botón, tecla_evdev]`. Los índices 12-15
+    /// son las direcciones arriba, abajo, izquierda y derecha.
+    pub players: Vec<Vec<(usize, u16)>>,
+}
+
+/// Perfil de mapeo: sólo los campos presentes sustituyen a los de `[gamepad]`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MappingProfile {
+    /// Subcadenas buscadas en la línea de comandos de los procesos para
+    /// activar el perfil automáticamente (p. ej. "mame pacman", "mupen64plus").
+    pub processes: Vec<String>,
+    pub four_way: Option<bool>,
+    pub navigation: Option<bool>,
+    pub flick_scroll: Option<bool>,
+    pub encoder: Option<bool>,
+    pub mirror: Option<bool>,
+    pub chords: Option<Vec<(Vec<usize>, u16)>>,
+    pub filters: Option<Vec<FilterConfig>>,
+    pub trigger_mode: Option<TriggerMode>,
+    pub trigger_threshold: Option<i32>,
+    pub hat_threshold: Option<i16>,
+    pub guide: Option<GuideMode>,
+    pub hotkeys: Option<Vec<(usize, u16)>>,
+    /// Al activarse con otros rangos, los gamepads se vuelven a crear.
+    pub axis_ranges: Option<AxisRanges>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Ruta del socket Unix de administración. Sin valor = deshabilitado.
+    pub socket: Option<PathBuf>,
+}
+
+/// Ver `relay::Relay`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayConfig {
+    /// Otras instancias de retro-control-server por nombre, con sus puertos
+    /// por defecto.
+    pub targets: BTreeMap<String, IpAddr>,
+    /// Emitir también en esta máquina mientras se reenvía.
+    pub local: bool,
+}
+
+/// Ver `observer::run_observer`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ObserverConfig {
+    /// Dirección TCP del canal de observación (se lee al arrancar). Sin
+    /// valor = deshabilitado.
+    pub listen: Option<SocketAddr>,
+    /// Incluir lo emitido en el teclado virtual, que puede ser texto
+    /// tecleado (contraseñas incluidas).
+    pub keyboard: bool,
+    /// Veces por segundo que `/events` envía el estado de los mandos a los
+    /// overlays (1-120).
+    pub overlay_hz: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DevicesConfig {
+    /// Crear cada dispositivo virtual al llegar su primer evento en vez de
+    /// al arrancar (sólo se lee al arrancar). Con `[privileges] user`, ese
+    /// usuario necesita acceso a /dev/uinput (ver `doctor --install`); si no
+    /// lo tiene, el servidor no arranca.
+    pub lazy: bool,
+    /// Destruir un dispositivo tras este tiempo sin eventos (0 = nunca). Hay
+    /// emuladores que no toleran que el mando desaparezca en plena partida.
+    pub teardown_after_secs: u64,
+    /// Ejecutar `modprobe uinput` al arrancar si falta el módulo (como root).
+    pub modprobe: bool,
+    /// Juntar lo recibido en ventanas de este tamaño y emitir un solo frame
+    /// por dispositivo al final de cada una (0 = emitir al momento). Es la
+    /// latencia añadida máxima. Sólo se lee al arrancar.
+    pub coalesce_ms: u64,
+    /// Enviar volumen, reproducción y demás teclas multimedia por un
+    /// dispositivo de control multimedia aparte en vez de por el teclado.
+    /// Sólo se lee al arrancar.
+    pub consumer: bool,
+}
+
+/// Sólo se aplica al arrancar; cambiarlo requiere reiniciar el servidor.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrivilegesConfig {
+    /// Usuario al que cambiar tras crear los dispositivos y abrir los sockets.
+    pub user: Option<String>,
+    /// Grupo; por defecto el grupo primario del usuario.
+    pub group: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Nivel por subsistema (ver `logger::SUBSYSTEMS`), p. ej. `gamepad = 2`.
+    /// `--verbosity gamepad=2` tiene prioridad.
+    pub levels: BTreeMap<String, u8>,
+    /// Idioma de los mensajes: `es` (por defecto) o `en`.
+    pub locale: Locale,
+    /// `pretty` (por defecto), `plain` o `json`. `--log-format` tiene prioridad.
+    pub format: LogFormat,
+}
+
+/// Sólo se aplica al arrancar; ver `scheduling::apply`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulingConfig {
+    /// Prioridad SCHED_FIFO 1..99 (0 = planificación normal).
+    pub realtime_priority: u8,
+    /// Niceness -20..19; se ignora con `realtime_priority`.
+    pub nice: i32,
+    /// Núcleo al que fijar los hilos del servidor (por defecto, cualquiera).
+    pub cpu: Option<usize>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            bind: "0.0.0.0".to_string(),
+            mouse_bind: None,
+            keyboard_bind: None,
+            gamepad_bind: None,
+            mouse_port: DEFAULT_MOUSE_PORT,
+            keyboard_port: DEFAULT_KEYBOARD_PORT,
+            gamepad_port: DEFAULT_GAMEPAD_PORT,
+            port_fallback: 10,
+            udp_recv_buffer: 0,
+            udp_receivers: 1,
+            busy_poll_us: 0,
+        }
+    }
+}
+
+impl Default for RendezvousConfig {
+    fn default() -> Self {
+        Self {
+            server: None,
+            name: String::new(),
+            interval_secs: 15,
+        }
+    }
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            interface: None,
+            refresh_interfaces_secs: 30,
+            while_connected: false,
+            interval_ms: DISCOVERY_INTERVAL_MS,
+            fast_interval_ms: DISCOVERY_FAST_INTERVAL_MS,
+            fast_period_secs: DISCOVERY_FAST_PERIOD_SECS,
+            register_port: DEFAULT_REGISTER_PORT,
+        }
+    }
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            allowlist: Vec::new(),
+            udp_rate_limit: 1000,
+            udp_rate_burst: 200,
+            quarantine_threshold: 50,
+            quarantine_window_secs: 10,
+            quarantine_secs: 300,
+            packet_key: None,
+        }
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            max_clients: 1,
+            policy: SessionPolicy::RejectNewest,
+            idle_timeout_secs: 300,
+            udp_idle_timeout_secs: 30,
+            held_limit_secs: 0,
+        }
+    }
+}
+
+impl SessionConfig {
+    /// `held_limit_secs` como duración; `None` si está desactivado.
+    pub fn held_limit(&self) -> Option<Duration> {
+        (self.held_limit_secs > 0).then(|| Duration::from_secs(self.held_limit_secs))
+    }
+}
+
+impl Default for KeyboardConfig {
+    fn default() -> Self {
+        Self {
+            // Android clients send Android keycodes for some keys
+            remap: vec![
+                (69, 12), // KEYCODE_MINUS  -> KEY_MINUS
+                (70, 13), // KEYCODE_EQUALS -> KEY_EQUAL
+                (81, 78), // KEYCODE_PLUS   -> KEY_KPPLUS
+            ],
+            repeat_delay_ms: 500,
+            repeat_rate_hz: 30,
+            layout: KeyboardLayout::Us,
+            max_held_keys: 16,
+            rollover: RolloverPolicy::IgnoreNewest,
+            // WASD -> left stick
+            analog_keys: vec![(17, 1, -1), (31, 1, 1), (30, 0, -1), (32, 0, 1)],
+            analog_player: 1,
+            mode_switch_interval_ms: 250,
+            arbitration: KeyboardArbitration::Token,
+            token_idle_secs: 30,
+            takeover_timeout_secs: 10,
+        }
+    }
+}
+
+/// Ratón virtual (paquete MOUSE).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MouseConfig {
+    /// Factor aplicado al movimiento mientras el cliente mantiene el modo
+    /// precisión (bit 0x10 de los botones), entre 0 y 1.
+    pub precision_scale: f64,
+}
+
+/// Qué hacer con KEY_POWER y KEY_SLEEP emitidas por el servidor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerKeys {
+    /// Se emiten en el teclado virtual y logind decide (HandlePowerKey,
+    /// HandleSuspendKey).
+    #[default]
+    Emit,
+    /// No se emiten: el servidor pide a logind que suspenda el equipo.
+    Suspend,
+}
+
+/// Suspensión y encendido del equipo; ver `power`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PowerConfig {
+    /// Sólo se lee al arrancar.
+    pub keys: PowerKeys,
+    /// Impedir que el equipo se suspenda mientras haya clientes conectados.
+    pub inhibit: bool,
+    /// Atender los paquetes WAKE enviando el Wake-on-LAN en nombre del cliente.
+    pub wake_relay: bool,
+}
+
+/// Ver `rendezvous::run_rendezvous`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RendezvousConfig {
+    /// `host:puerto` del punto de encuentro (subcomando `rendezvous`). Sin
+    /// valor = desactivado. Sólo se lee al arrancar.
+    pub server: Option<String>,
+    /// Nombre con el que se registran este servidor y sus móviles. Hace de
+    /// contraseña: quien lo conozca puede abrir camino hasta aquí.
+    pub name: String,
+    /// Cada cuánto renovar el registro, que mantiene abierto el NAT.
+    pub interval_secs: u64,
+}
+
+/// Calibración de la pistola de luz (paquete LIGHTGUN).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LightgunConfig {
+    /// Márgenes de overscan, en porcentaje del rango de la pistola que queda
+    /// fuera de la imagen del juego por cada lado. La superficie táctil del
+    /// cliente se reparte sobre lo que queda dentro.
+    pub left: f64,
+    pub right: f64,
+    pub top: f64,
+    pub bottom: f64,
+    /// Cuánto se mantiene el gatillo en un disparo fuera de pantalla.
+    pub offscreen_hold_ms: u64,
+}
+
+impl LightgunConfig {
+    /// Convierte un punto de la superficie táctil (0..65535 en cada eje) en
+    /// la posición de la pistola, dentro del área calibrada.
+    pub fn aim(&self, x: u16, y: u16) -> (i32, i32) {
+        let scale = |value: u16, start: f64, end: f64| {
+            let span = 1.0 - (start + end) / 100.0;
+            let position = start / 100.0 + value as f64 / u16::MAX as f64 * span;
+            (position * LIGHTGUN_MAX as f64).round() as i32
+        };
+        (scale(x, self.left, self.right), scale(y, self.top, self.bottom))
+    }
+}
+
+/// Ver `api::run_api`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    /// Dirección TCP de la API REST (se lee al arrancar). Sin valor =
+    /// deshabilitada.
+    pub listen: Option<SocketAddr>,
+    /// Token que cada petición debe enviar como `Authorization: Bearer`.
+    /// Obligatorio si hay `listen`.
+    pub token: Option<String>,
+}
+
+/// Ver `mqtt::run_mqtt`. Se lee al arrancar.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttConfig {
+    /// `host` o `host:puerto` (1883 por defecto). Sin valor = deshabilitado.
+    pub broker: Option<String>,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Prefijo de todos los topics.
+    pub topic: String,
+    /// Comandos aceptados en `<topic>/command`, sólo de consulta (ver
+    /// `admin::READ_ONLY_COMMANDS`). Vacío = no se escucha ese topic. MQTT
+    /// no autentica los mensajes: cualquiera que publique en el broker vale.
+    pub commands: Vec<String>,
+    /// Aceptar entrada en `<topic>/input/...`; quien pueda publicar en el
+    /// broker controla la máquina.
+    pub input: bool,
+}
+
+/// Ver `remote_retropad::run_remote_retropad`. Se lee al arrancar.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RemoteRetroPadConfig {
+    pub enabled: bool,
+    /// Puerto UDP del jugador 1; el jugador N usa `port + N - 1`.
+    pub port: u16,
+}
+
+/// Ver `synergy::run_synergy`. Se lee al arrancar.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SynergyConfig {
+    /// `host` o `host:puerto` (24800 por defecto) del PC que comparte su
+    /// ratón y teclado. Sin valor = deshabilitado.
+    pub server: Option<String>,
+    /// Nombre de esta pantalla en la configuración del servidor.
+    pub name: String,
+    /// Tamaño de pantalla que se anuncia al servidor.
+    pub width: i16,
+    pub height: i16,
+}
+
+/// Ver `dsu::run_dsu`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DsuConfig {
+    /// Dirección UDP del servidor DSU (se lee al arrancar); lo habitual es
+    /// `127.0.0.1:26760`. Sin valor = deshabilitado.
+    pub listen: Option<SocketAddr>,
+}
+
+/// Ver `quic::run_quic`. Se lee al arrancar.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuicConfig {
+    /// Dirección UDP del listener QUIC, p. ej. `0.0.0.0:5562`. Sin valor =
+    /// deshabilitado.
+    pub listen: Option<SocketAddr>,
+    /// Certificado y clave TLS en PEM. Si no existe ninguno, se crea un par
+    /// autofirmado en estas rutas.
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl Default for QuicConfig {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            cert: PathBuf::from("/var/lib/retro-control/quic-cert.pem"),
+            key: PathBuf::from("/var/lib/retro-control/quic-key.pem"),
+        }
+    }
+}
+
+impl Default for MouseConfig {
+    fn default() -> Self {
+        Self { precision_scale: 0.5 }
+    }
+}
+
+impl Default for LightgunConfig {
+    fn default() -> Self {
+        Self {
+            left: 0.0,
+            right: 0.0,
+            top: 0.0,
+            bottom: 0.0,
+            offscreen_hold_ms: 60,
+        }
+    }
+}
+
+impl Default for ObserverConfig {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            keyboard: false,
+            overlay_hz: 30,
+        }
+    }
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            broker: None,
+            client_id: "retro-control-server".to_string(),
+            username: None,
+            password: None,
+            topic: "retro-control".to_string(),
+            commands: Vec::new(),
+            input: false,
+        }
+    }
+}
+
+impl Default for RemoteRetroPadConfig {
+    fn default() -> Self {
+        Self { enabled: false, port: 55400 }
+    }
+}
+
+impl Default for SynergyConfig {
+    fn default() -> Self {
+        Self {
+            server: None,
+            name: "retro-control".to_string(),
+            width: 1920,
+            height: 1080,
+        }
+    }
+}
+
+impl Default for DevicesConfig {
+    fn default() -> Self {
+        Self {
+            lazy: true,
+            teardown_after_secs: 0,
+            modprobe: false,
+            coalesce_ms: 0,
+            consumer: true,
+        }
+    }
+}
+
+impl Default for GamepadConfig {
+    fn default() -> Self {
+        Self {
+            trigger_mode: TriggerMode::Both,
+            trigger_input_max: 32767,
+            trigger_threshold: 10,
+            hat_threshold: 20000,
+            profiles_file: None,
+            guide: GuideMode::Button,
+            guide_key: 1,
+            hotkeys: Vec::new(),
+            slots: 4,
+            pressure: false,
+            extra_axes: Vec::new(),
+            four_way: false,
+            navigation: false,
+            flick_scroll: false,
+            chords: Vec::new(),
+            chord_window_ms: 50,
+            filters: Vec::new(),
+            encoder: false,
+            mirror: false,
+            process_watch_secs: 2,
+            axis_ranges: AxisRanges::default(),
+        }
+    }
+}
+
+impl Default for AxisRanges {
+    fn default() -> Self {
+        Self {
+            stick_min: Xbox360Layout::STICK_MIN,
+            stick_max: Xbox360Layout::STICK_MAX,
+            stick_fuzz: 16,
+            stick_flat: 128,
+            trigger_min: Xbox360Layout::TRIGGER_MIN,
+            trigger_max: Xbox360Layout::TRIGGER_MAX,
+            trigger_fuzz: 0,
+            trigger_flat: 0,
+        }
+    }
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        Self {
+            players: vec![
+                // P1: arrows, LCtrl, LAlt, Space, LShift, Z, X, 1 = start, 5 = coin
+                vec![
+                    (12, 103), (13, 108), (14, 105), (15, 106),
+                    (0, 29), (1, 56), (2, 57), (3, 42), (4, 44), (5, 45),
+                    (7, 2), (6, 6),
+                ],
+                // P2: R, F, D, G, A, S, Q, W, I, K, 2 = start, 6 = coin
+                vec![
+                    (12, 19), (13, 33), (14, 32), (15, 34),
+                    (0, 30), (1, 31), (2, 16), (3, 17), (4, 23), (5, 37),
+                    (7, 3), (6, 7),
+                ],
+            ],
+        }
+    }
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("no se pudo leer {}: {}", path.display(), e))?;
+        let config: Self = toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        for name in config.log.levels.keys() {
+            check_subsystem(name).map_err(|e| format!("{}: [log] levels: {}", path.display(), e))?;
+        }
+        let gun = &config.lightgun;
+        // Below 50 each, so a full-width touch always spans some of the picture
+        let borders = [gun.left, gun.right, gun.top, gun.bottom];
+        if borders.iter().any(|b| !(0.0..50.0).contains(b)) {
+            return Err(format!("{}: [lightgun] los márgenes van de 0 a 50 (%)", path.display()));
+        }
+        let extra = &config.gamepad.extra_axes;
+        if extra.len() > MAX_EXTRA_AXES || extra.iter().enumerate().any(|(i, axis)| extra[..i].contains(axis)) {
+            return Err(format!("{}: [gamepad] extra_axes: hasta {} ejes distintos", path.display(), MAX_EXTRA_AXES));
+        }
+        // The pressure axes already use ABS_THROTTLE, ABS_RUDDER and ABS_MISC
+        if config.gamepad.pressure && extra.iter().any(|axis| Xbox360Layout::PRESSURE_CODES.contains(&axis.code())) {
+            return Err(format!("{}: [gamepad] extra_axes: con pressure sólo quedan hat1x y hat1y", path.display()));
+        }
+        let ranges = std::iter::once(&config.gamepad.axis_ranges)
+            .chain(config.profiles.values().filter_map(|profile| profile.axis_ranges.as_ref()));
+        for ranges in ranges {
+            if ranges.stick_min >= ranges.stick_max || ranges.trigger_min >= ranges.trigger_max {
+                return Err(format!("{}: axis_ranges: cada mínimo debe ser menor que su máximo", path.display()));
+            }
+        }
+        if !(config.mouse.precision_scale > 0.0 && config.mouse.precision_scale <= 1.0) {
+            return Err(format!("{}: [mouse] precision_scale va de 0 a 1", path.display()));
+        }
+        if config.api.listen.is_some() && config.api.token.as_deref().is_none_or(str::is_empty) {
+            return Err(format!("{}: [api] listen necesita un token", path.display()));
+        }
+        if let Some(command) = config.mqtt.commands.iter().find(|c| !READ_ONLY_COMMANDS.contains(&c.as_str())) {
+            return Err(format!(
+                "{}: [mqtt] commands: '{}' no es un comando de consulta (válidos: {})",
+                path.display(),
+                command,
+                READ_ONLY_COMMANDS.join(", ")
+            ));
+        }
+        if config.security.packet_key.as_deref() == Some("") {
+            return Err(format!("{}: [security] packet_key no puede estar vacía", path.display()));
+        }
+        if config.rendezvous.server.is_some() && config.rendezvous.name.is_empty() {
+            return Err(format!("{}: [rendezvous] server necesita un name", path.display()));
+        }
+        Ok(config)
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        self.security.allowlist.is_empty()
+            || self.security.allowlist.iter().any(|net| net.contains(&ip))
+    }
+
+    pub fn map_keyboard_key(&self, scancode: u16) -> u16 {
+        self.keyboard
+            .remap
+            .iter()
+            .find(|(from, _)| *from == scancode)
+            .map(|(_, to)| *to)
+            // Pass through others (assuming they are already Linux evdev codes)
+            .unwrap_or(scancode)
+    }
+}
+
+/// Handle compartido a la configuración activa.
+///
+/// Los lectores obtienen un `Arc<Config>` inmutable; una recarga sólo cambia
+/// el puntero, nunca muta una configuración que alguien esté leyendo.
+#[derive(Clone)]
+pub struct ConfigHandle {
+    path: Option<PathBuf>,
+    cli_verbosity: VerbositySpec,
+    cli_log_format: Option<LogFormat>,
+    current: Arc<RwLock<Arc<Config>>>,
+}
+
+impl ConfigHandle {
+    pub fn load(
+        path: Option<PathBuf>,
+        cli_verbosity: VerbositySpec,
+        cli_log_format: Option<LogFormat>,
+    ) -> Result<Self, String> {
+        let config = match &path {
+            Some(p) => Config::from_file(p)?,
+            None => Config::default(),
+        };
+        let handle = Self {
+            path,
+            cli_verbosity,
+            cli_log_format,
+            current: Arc::new(RwLock::new(Arc::new(config))),
+        };
+        handle.apply_verbosity();
+        Ok(handle)
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Vuelve a leer el archivo. Si es inválido se conserva la configuración anterior.
+    pub fn reload(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Err("no se indicó archivo de configuración (--config)".to_string());
+        };
+        let config = Config::from_file(path)?;
+        systemd::notify_reloading();
+        *self.current.write().unwrap() = Arc::new(config);
+        self.apply_verbosity();
+        systemd::notify_ready();
+        log_detail(Verbosity::Low, "Configuración recargada", &path.display().to_string());
+        Ok(())
+    }
+
+    fn apply_verbosity(&self) {
+        let config = self.get();
+        let level = self.cli_verbosity.level.or(config.verbosity).unwrap_or(0);
+        let mut subsystems = config.log.levels.clone();
+        subsystems.extend(self.cli_verbosity.subsystems.clone());
+        set_verbosity(Verbosity::from_u8(level), &subsystems);
+        set_locale(config.log.locale);
+        set_log_format(self.cli_log_format.unwrap_or(config.log.format));
+    }
+}
@@ -0,0 +1,146 @@
+use crate::logger::{log_detail, Verbosity};
+use std::sync::{LazyLock, Mutex};
+
+/// Cómo se combinan los ejes del piloto y del copiloto. Los botones siempre
+/// se combinan con OR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AxisMerge {
+    /// Se suman y se recortan al rango de i16.
+    Sum,
+    /// Manda el último que sacó el eje del centro; si vuelve al centro,
+    /// vale lo que tenga el otro.
+    Last,
+}
+
+impl AxisMerge {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "sum" => Some(Self::Sum),
+            "last" => Some(Self::Last),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Sum => "sum",
+            Self::Last => "last",
+        }
+    }
+}
+
+/// Dos jugadores que manejan un solo mando virtual, el del piloto. El mando
+/// del copiloto queda en reposo mientras dure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pairing {
+    pub pilot: usize,
+    pub copilot: usize,
+    pub axes: AxisMerge,
+}
+
+/// Modo co-piloto (accesibilidad): se activa con `copilot <piloto>
+/// <copiloto> [sum|last]` en el socket de administración.
+pub struct CoPilot {
+    pairing: Mutex<Option<Pairing>>,
+}
+
+pub static COPILOT: LazyLock<CoPilot> = LazyLock::new(|| CoPilot { pairing: Mutex::new(None) });
+
+impl CoPilot {
+    pub fn get(&self) -> Option<Pairing> {
+        *self.pairing.lock().unwrap()
+    }
+
+    pub fn set(&self, pairing: Option<Pairing>) {
+        *self.pairing.lock().unwrap() = pairing;
+        let detail = pairing.map_or_else(
+            || "desactivado".to_string(),
+            |p| format!("P{} + P{} ({})", p.pilot + 1, p.copilot + 1, p.axes.name()),
+        );
+        log_detail(Verbosity::Low, "Co-piloto", &detail);
+    }
+
+    pub fn render(&self) -> String {
+        self.get().map_or_else(
+            || "off".to_string(),
+            |p| format!("{} {} {}", p.pilot + 1, p.copilot + 1, p.axes.name()),
+        )
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Side {
+    buttons: [u8; 12],
+    axes: [i16; 8],
+}
+
+/// Estado de la combinación en el bucle del gamepad: el último snapshot de
+/// cada lado y quién movió cada eje por última vez.
+#[derive(Default)]
+pub struct Merger {
+    pairing: Option<Pairing>,
+    sides: [Side; 2],
+    // Side (0 pilot, 1 copilot) that last moved each axis off centre
+    owners: [usize; 8],
+}
+
+impl Merger {
+    /// Ajusta el emparejamiento al activo. Devuelve los mandos que hay que
+    /// soltar: el del nuevo copiloto, que deja de emitir, y el del piloto
+    /// anterior, que podría tener pulsado algo del otro lado.
+    pub fn update(&mut self, pairing: Option<Pairing>) -> Vec<usize> {
+        if pairing == self.pairing {
+            return Vec::new();
+        }
+        let previous = std::mem::replace(self, Self { pairing, ..Self::default() });
+        previous.pairing.map(|p| p.pilot).into_iter().chain(pairing.map(|p| p.copilot)).collect()
+    }
+
+    /// Combina un snapshot de `slot` con el último del otro lado si forma
+    /// parte del emparejamiento. Devuelve el slot cuyo mando debe emitirlo.
+    pub fn apply(&mut self, slot: usize, buttons: &mut [u8; 12], axes: &mut [i16; 8]) -> usize {
+        let Some(pairing) = self.pairing else {
+            return slot;
+        };
+        let side = if slot == pairing.pilot {
+            0
+        } else if slot == pairing.copilot {
+            1
+        } else {
+            return slot;
+        };
+        let before = self.sides[side];
+        self.sides[side] = Side { buttons: *buttons, axes: *axes };
+        let [pilot, copilot] = self.sides;
+
+        for (i, button) in buttons.iter_mut().enumerate() {
+            *button = (pilot.buttons[i] != 0 || copilot.buttons[i] != 0) as u8;
+        }
+        for (i, axis) in axes.iter_mut().enumerate() {
+            *axis = match pairing.axes {
+                AxisMerge::Sum => (pilot.axes[i] as i32 + copilot.axes[i] as i32).clamp(i16::MIN as i32, i16::MAX as i32) as i16,
+                AxisMerge::Last => {
+                    let mine = self.sides[side].axes[i];
+                    if mine != before.axes[i] && mine != 0 {
+                        self.owners[i] = side;
+                    }
+                    let owner = self.sides[self.owners[i]].axes[i];
+                    if owner != 0 { owner } else { self.sides[1 - self.owners[i]].axes[i] }
+                }
+            };
+        }
+        pairing.pilot
+    }
+
+    /// Olvida lo último de `slot` (sesión caducada o hueco liberado), para
+    /// que no quede pulsado en el mando combinado.
+    pub fn forget(&mut self, slot: usize) {
+        if let Some(pairing) = self.pairing {
+            if slot == pairing.pilot {
+                self.sides[0] = Side::default();
+            } else if slot == pairing.copilot {
+                self.sides[1] = Side::default();
+            }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use crate::doctor;
+use crate::logger::{log_detail, Verbosity};
+use evdev::{InputEvent, uinput::VirtualDevice};
+use std::os::fd::{AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+pub type DeviceFactory = Box<dyn Fn() -> Result<VirtualDevice, Box<dyn std::error::Error>> + Send + Sync>;
+
+nix::ioctl_none!(ui_dev_destroy, b'U', 2);
+
+// After a failed creation, don't hit /dev/uinput again on every packet
+const RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Dispositivo uinput que se crea con el primer evento que hay que emitir,
+/// para que el frontend no vea mandos o teclados fantasma mientras no hay
+/// ningún cliente usándolos.
+pub struct LazyDevice {
+    name: String,
+    // `None` with --dry-run: events are logged instead of emitted
+    factory: Option<DeviceFactory>,
+    state: Mutex<State>,
+    created: watch::Sender<bool>,
+}
+
+#[derive(Default)]
+struct State {
+    device: Option<VirtualDevice>,
+    failed_at: Option<Instant>,
+    last_used: Option<Instant>,
+}
+
+pub type SharedDevice = Arc<LazyDevice>;
+
+impl LazyDevice {
+    pub fn new(
+        name: impl Into<String>,
+        factory: impl Fn() -> Result<VirtualDevice, Box<dyn std::error::Error>> + Send + Sync + 'static,
+    ) -> SharedDevice {
+        Arc::new(Self {
+            name: name.into(),
+            factory: Some(Box::new(factory)),
+            state: Mutex::new(State::default()),
+            created: watch::channel(false).0,
+        })
+    }
+
+    /// Dispositivo simulado (`--dry-run`): no toca /dev/uinput y registra en
+    /// el log los eventos que habría emitido.
+    pub fn simulated(name: impl Into<String>) -> SharedDevice {
+        Arc::new(Self {
+            name: name.into(),
+            factory: None,
+            state: Mutex::new(State::default()),
+            created: watch::channel(false).0,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Crea el dispositivo ya (p. ej. con `[devices] lazy = false`).
+    pub fn create(&self) -> std::io::Result<()> {
+        self.emit_reports(&[])
+    }
+
+    /// Emite varios reportes seguidos, creando el dispositivo si todavía no
+    /// existe. Cada `emit` de evdev cierra su reporte con SYN_REPORT.
+    pub fn emit_reports(&self, reports: &[&[InputEvent]]) -> std::io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let Some(factory) = &self.factory else {
+            if state.last_used.is_none() {
+                log_detail(Verbosity::Low, "Dispositivo virtual simulado", &self.name);
+            }
+            state.last_used = Some(Instant::now());
+            self.log_simulated(reports);
+            return Ok(());
+        };
+        if state.device.is_none() {
+            if state.failed_at.is_some_and(|t| t.elapsed() < RETRY_AFTER) {
+                return Err(std::io::Error::other(format!("{} no disponible", self.name)));
+            }
+            match factory() {
+                Ok(device) => {
+                    log_detail(Verbosity::Low, "Dispositivo virtual creado", &self.name);
+                    state.device = Some(device);
+                    state.failed_at = None;
+                    self.created.send_replace(true);
+                }
+                Err(e) => {
+                    let fix = doctor::remediation(e.as_ref());
+                    log_detail(Verbosity::Low, "Error creando dispositivo virtual", &format!("{}: {}", self.name, e));
+                    log_detail(Verbosity::Low, "Solución", &fix);
+                    state.failed_at = Some(Instant::now());
+                    return Err(std::io::Error::other(format!("{}: {} ({})", self.name, e, fix)));
+                }
+            }
+        }
+        state.last_used = Some(Instant::now());
+        let device = state.device.as_mut().unwrap();
+        for events in reports {
+            device.emit(events)?;
+        }
+        Ok(())
+    }
+
+    fn log_simulated(&self, reports: &[&[InputEvent]]) {
+        for events in reports {
+            let described: Vec<String> = events.iter().map(|e| format!("{:?}={}", e.kind(), e.value())).collect();
+            log_detail(Verbosity::Low, &format!("[dry-run] {}", self.name), &described.join(" "));
+        }
+    }
+
+    /// Como `with`, pero sin crear el dispositivo: para neutralizar estado,
+    /// que no tiene sentido en un dispositivo que nunca existió.
+    pub fn if_created<R>(&self, f: impl FnOnce(&mut VirtualDevice) -> R) -> Option<R> {
+        self.state.lock().unwrap().device.as_mut().map(f)
+    }
+
+    /// Destruye el dispositivo si lleva `idle` sin emitir nada. El siguiente
+    /// evento lo vuelve a crear.
+    pub fn destroy_if_idle(&self, idle: Duration) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.device.is_none() || state.last_used.is_some_and(|t| t.elapsed() < idle) {
+            return false;
+        }
+        self.destroy_locked(&mut state, &format!("inactivo {}s", idle.as_secs()));
+        true
+    }
+
+    /// Destruye el dispositivo si existe, para que el siguiente evento lo
+    /// cree de nuevo con lo que la fábrica dé entonces.
+    pub fn recreate(&self, reason: &str) {
+        let mut state = self.state.lock().unwrap();
+        if state.device.is_some() {
+            self.destroy_locked(&mut state, reason);
+        }
+    }
+
+    fn destroy_locked(&self, state: &mut State, reason: &str) {
+        if let Some(device) = state.device.take() {
+            // Explicit, since other descriptors (the LED monitor's) may keep the file open
+            let _ = unsafe { ui_dev_destroy(device.as_raw_fd()) };
+        }
+        self.created.send_replace(false);
+        log_detail(Verbosity::Low, "Dispositivo virtual destruido", &format!("{} {}", self.name, reason));
+    }
+
+    /// Devuelve si el dispositivo existía (y por tanto se emitió).
+    pub fn emit_if_created(&self, events: &[InputEvent]) -> bool {
+        if events.is_empty() {
+            return false;
+        }
+        if self.factory.is_none() {
+            let used = self.state.lock().unwrap().last_used.is_some();
+            if used {
+                self.log_simulated(&[events]);
+            }
+            return used;
+        }
+        self.if_created(|dev| {
+            let _ = dev.emit(events);
+        })
+        .is_some()
+    }
+
+    /// Espera a que el dispositivo exista.
+    pub async fn created(&self) {
+        let mut rx = self.created.subscribe();
+        let _ = rx.wait_for(|created| *created).await;
+    }
+
+    /// Descriptor propio del dispositivo para esperar eventos del kernel
+    /// (LEDs, force feedback) con `AsyncFd`; `None` si no existe.
+    pub fn watch_fd(&self) -> Option<std::io::Result<OwnedFd>> {
+        self.if_created(|dev| watch_fd(dev.as_raw_fd()))
+    }
+
+    /// Espera a que el dispositivo deje de existir.
+    pub async fn destroyed(&self) {
+        let mut rx = self.created.subscribe();
+        let _ = rx.wait_for(|created| !*created).await;
+    }
+}
+
+// Our own descriptor for readiness, so it stays valid if the device is
+// destroyed while we are registered with the reactor. Reads must not block
+// while holding the device lock; uinput writes never block anyway.
+fn watch_fd(fd: RawFd) -> std::io::Result<OwnedFd> {
+    let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+    let flags = nix::fcntl::OFlag::from_bits_truncate(nix::fcntl::fcntl(borrowed, nix::fcntl::F_GETFL)?);
+    nix::fcntl::fcntl(borrowed, nix::fcntl::F_SETFL(flags | nix::fcntl::OFlag::O_NONBLOCK))?;
+    Ok(nix::unistd::dup(borrowed)?)
+}
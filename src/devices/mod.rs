@@ -1,4 +1,10 @@
-use evdev::{AttributeSet, Key, RelativeAxisType, uinput::{VirtualDevice, VirtualDeviceBuilder}};
+use evdev::{
+    AbsInfo, AbsoluteAxisType, AttributeSet, Key, LedType, RelativeAxisType, UinputAbsSetup,
+    uinput::{VirtualDevice, VirtualDeviceBuilder},
+};
+use crate::logger::{log_detail, Verbosity};
+use std::collections::HashSet;
+use std::os::fd::RawFd;
 
 pub fn create_virtual_mouse() -> Result<VirtualDevice, Box<dyn std::error::Error>> {
     let mut keys = AttributeSet::<Key>::new();
@@ -20,20 +26,163 @@ pub fn create_virtual_mouse() -> Result<VirtualDevice, Box<dyn std::error::Error
     Ok(device)
 }
 
+/// Códigos del teclado virtual: todas las teclas KEY_* del kernel, también
+/// las de más de 255 (KEY_OK, KEY_RED, KEY_NUMERIC_0...). Se saltan los
+/// bloques BTN_*, con los que udev lo tomaría por ratón o mando.
+const KEYBOARD_KEYS: [std::ops::Range<u16>; 3] = [1..0x100, 0x160..0x220, 0x230..0x2c0];
+
 pub fn create_virtual_keyboard() -> Result<VirtualDevice, Box<dyn std::error::Error>> {
     let mut keys = AttributeSet::<Key>::new();
 
+    for i in KEYBOARD_KEYS.into_iter().flatten() {
+        keys.insert(Key::new(i));
+    }
+
+    let opened_before = uinput_fds();
+    let builder = VirtualDeviceBuilder::new()?;
+    // Caps/Num/Scroll Lock LEDs, so the console's lock state is reported back to us
+    if let Err(e) = enable_leds(&opened_before, &[LedType::LED_NUML, LedType::LED_CAPSL, LedType::LED_SCROLLL]) {
+        log_detail(Verbosity::Low, "LEDs de teclado no disponibles", &e.to_string());
+    }
+
+    let device = builder
+        .name("Retro Control Keyboard")
+        .with_keys(&keys)?
+        .build()?;
+
+    Ok(device)
+}
+
+/// Teclas de la página "consumer" de HID: volumen, reproducción, navegador
+/// y las del mando a distancia de la TV (KEY_OK a KEY_IMAGES).
+const CONSUMER_KEYS: [Key; 25] = [
+    Key::KEY_MUTE,
+    Key::KEY_VOLUMEDOWN,
+    Key::KEY_VOLUMEUP,
+    Key::KEY_CALC,
+    Key::KEY_WWW,
+    Key::KEY_MAIL,
+    Key::KEY_BOOKMARKS,
+    Key::KEY_BACK,
+    Key::KEY_FORWARD,
+    Key::KEY_EJECTCD,
+    Key::KEY_NEXTSONG,
+    Key::KEY_PLAYPAUSE,
+    Key::KEY_PREVIOUSSONG,
+    Key::KEY_STOPCD,
+    Key::KEY_RECORD,
+    Key::KEY_REWIND,
+    Key::KEY_CONFIG,
+    Key::KEY_HOMEPAGE,
+    Key::KEY_PLAYCD,
+    Key::KEY_PAUSECD,
+    Key::KEY_PLAY,
+    Key::KEY_FASTFORWARD,
+    Key::KEY_SEARCH,
+    Key::KEY_BRIGHTNESSDOWN,
+    Key::KEY_BRIGHTNESSUP,
+];
+// KEY_OK up to KEY_IMAGES, before the line-editing keys
+const CONSUMER_REMOTE_KEYS: std::ops::Range<u16> = 0x160..0x1c0;
+
+/// Si la tecla va al dispositivo de control multimedia (`[devices] consumer`).
+pub fn is_consumer_key(code: u16) -> bool {
+    CONSUMER_REMOTE_KEYS.contains(&code) || CONSUMER_KEYS.iter().any(|key| key.code() == code)
+}
+
+/// Control multimedia aparte del teclado, como el "Consumer Control" de los
+/// teclados USB reales: hay escritorios y Kodi que sólo atienden el volumen y
+/// la reproducción si vienen de un dispositivo así.
+pub fn create_virtual_consumer() -> Result<VirtualDevice, Box<dyn std::error::Error>> {
+    let mut keys = AttributeSet::<Key>::new();
+    for key in CONSUMER_KEYS {
+        keys.insert(key);
+    }
+    for code in CONSUMER_REMOTE_KEYS {
+        keys.insert(Key::new(code));
+    }
+
+    let device = VirtualDeviceBuilder::new()?
+        .name("Retro Control Consumer Control")
+        .with_keys(&keys)?
+        .build()?;
+
+    Ok(device)
+}
+
+/// Teclado aparte para el modo encoder (estilo I-PAC): las máquinas de
+/// MAME configuradas para encoders de teclado lo ven como un dispositivo más,
+/// sin mezclarse con el teclado del cliente.
+pub fn create_virtual_encoder() -> Result<VirtualDevice, Box<dyn std::error::Error>> {
+    let mut keys = AttributeSet::<Key>::new();
     for i in 0..255 {
         keys.insert(Key::new(i));
     }
 
     let device = VirtualDeviceBuilder::new()?
-        .name("Retro Control Keyboard")
+        .name("Retro Control Encoder")
         .with_keys(&keys)?
         .build()?;
 
     Ok(device)
 }
 
+/// Extremo de ABS_X/ABS_Y de la pistola de luz; el mínimo es 0.
+pub const LIGHTGUN_MAX: i32 = 32767;
+
+/// Pistola de luz: un puntero absoluto con gatillo (BTN_LEFT) y dos botones
+/// auxiliares. Sin INPUT_PROP_DIRECT, así udev lo clasifica como ratón
+/// absoluto y los drivers de lightgun de RetroArch y MAME lo aceptan.
+pub fn create_virtual_lightgun() -> Result<VirtualDevice, Box<dyn std::error::Error>> {
+    let mut keys = AttributeSet::<Key>::new();
+    keys.insert(Key::BTN_LEFT);
+    keys.insert(Key::BTN_RIGHT);
+    keys.insert(Key::BTN_MIDDLE);
+
+    let mut builder = VirtualDeviceBuilder::new()?
+        .name("Retro Control Lightgun")
+        .with_keys(&keys)?;
+    for axis in [AbsoluteAxisType::ABS_X, AbsoluteAxisType::ABS_Y] {
+        builder = builder.with_absolute_axis(&UinputAbsSetup::new(axis, AbsInfo::new(0, 0, LIGHTGUN_MAX, 0, 0, 0)))?;
+    }
+
+    Ok(builder.build()?)
+}
+
+// evdev 0.12's builder has no `with_leds`, so the capability bits are set
+// directly on the /dev/uinput descriptor it just opened.
+nix::ioctl_write_int!(ui_set_evbit, b'U', 100);
+nix::ioctl_write_int!(ui_set_ledbit, b'U', 105);
+
+// Descriptors of this process pointing at /dev/uinput
+fn uinput_fds() -> HashSet<RawFd> {
+    let Ok(entries) = std::fs::read_dir("/proc/self/fd") else {
+        return HashSet::new();
+    };
+    entries
+        .flatten()
+        .filter(|e| std::fs::read_link(e.path()).is_ok_and(|target| target == std::path::Path::new("/dev/uinput")))
+        .filter_map(|e| e.file_name().to_str()?.parse().ok())
+        .collect()
+}
+
+// `opened_before` is the set of uinput descriptors that existed before
+// `VirtualDeviceBuilder::new()`; the one that appeared since is the builder's.
+fn enable_leds(opened_before: &HashSet<RawFd>, leds: &[LedType]) -> std::io::Result<()> {
+    let fd = uinput_fds()
+        .difference(opened_before)
+        .copied()
+        .next()
+        .ok_or_else(|| std::io::Error::other("no se encontró el descriptor de /dev/uinput"))?;
+    unsafe {
+        ui_set_evbit(fd, evdev::EventType::LED.0 as nix::sys::ioctl::ioctl_param_type)?;
+        for led in leds {
+            ui_set_ledbit(fd, led.0 as nix::sys::ioctl::ioctl_param_type)?;
+        }
+    }
+    Ok(())
+}
+
+pub mod lazy;
 pub mod xbox360;
 pub mod xbox360_layout;
@@ -1,7 +1,23 @@
 use super::xbox360_layout::Xbox360Layout;
-use evdev::{AbsInfo, AttributeSet, Key, UinputAbsSetup, uinput::{VirtualDevice, VirtualDeviceBuilder}};
+use crate::config::{AxisRanges, ExtraAxis};
+use crate::rumble::MAX_EFFECTS;
+use evdev::{AbsInfo, AttributeSet, FFEffectType, Key, UinputAbsSetup, uinput::{VirtualDevice, VirtualDeviceBuilder}};
+
+/// `slot` empieza en 0. P1 conserva el nombre de siempre para no romper las
+/// asignaciones que el frontend ya tenga guardadas. Con `pressure` se añaden
+/// los ejes de presión de `Xbox360Layout::PRESSURE_CODES`, y después los de
+/// `extra`. Sticks y gatillos toman sus rangos de `ranges`.
+pub fn create_virtual_gamepad(
+    slot: usize,
+    pressure: bool,
+    extra: &[ExtraAxis],
+    ranges: &AxisRanges,
+) -> Result<VirtualDevice, Box<dyn std::error::Error>> {
+    let name = match slot {
+        0 => "RetroControl Virtual Gamepad".to_string(),
+        n => format!("RetroControl Virtual Gamepad P{}", n + 1),
+    };
 
-pub fn create_virtual_gamepad() -> Result<VirtualDevice, Box<dyn std::error::Error>> {
     // Build AttributeSet of keys
     let key_array = [
         Key::BTN_SOUTH,  // A
@@ -15,6 +31,8 @@ pub fn create_virtual_gamepad() -> Result<VirtualDevice, Box<dyn std::error::Err
         Key::BTN_MODE,   // Guide
         Key::BTN_THUMBL, // Left Stick Press
         Key::BTN_THUMBR, // Right Stick Press
+        Key::BTN_TL2,    // Left trigger (digital)
+        Key::BTN_TR2,    // Right trigger (digital)
     ];
     let mut keys = AttributeSet::<Key>::new();
     for &key in &key_array {
@@ -22,17 +40,26 @@ pub fn create_virtual_gamepad() -> Result<VirtualDevice, Box<dyn std::error::Err
     }
 
     let mut builder = VirtualDeviceBuilder::new()?
-        .name("RetroControl Virtual Gamepad")
+        .name(&name)
         .with_keys(&keys)?;
 
     // Add absolute axes individually (evdev version provides `with_absolute_axis`).
+    let stick = AbsInfo::new(ranges.stick(0), ranges.stick_min, ranges.stick_max, ranges.stick_fuzz, ranges.stick_flat, 0);
+    let trigger = AbsInfo::new(
+        ranges.trigger_min,
+        ranges.trigger_min,
+        ranges.trigger_max,
+        ranges.trigger_fuzz,
+        ranges.trigger_flat,
+        0,
+    );
     let axes = [
-        (0, AbsInfo::new(0, Xbox360Layout::STICK_MIN, Xbox360Layout::STICK_MAX, 16, 128, 0)), // ABS_X
-        (1, AbsInfo::new(0, Xbox360Layout::STICK_MIN, Xbox360Layout::STICK_MAX, 16, 128, 0)), // ABS_Y
-        (3, AbsInfo::new(0, Xbox360Layout::STICK_MIN, Xbox360Layout::STICK_MAX, 16, 128, 0)), // ABS_RX
-        (4, AbsInfo::new(0, Xbox360Layout::STICK_MIN, Xbox360Layout::STICK_MAX, 16, 128, 0)), // ABS_RY
-        (2, AbsInfo::new(0, Xbox360Layout::TRIGGER_MIN, Xbox360Layout::TRIGGER_MAX, 0, 0, 0)), // ABS_Z
-        (5, AbsInfo::new(0, Xbox360Layout::TRIGGER_MIN, Xbox360Layout::TRIGGER_MAX, 0, 0, 0)), // ABS_RZ
+        (0, stick), // ABS_X
+        (1, stick), // ABS_Y
+        (3, stick), // ABS_RX
+        (4, stick), // ABS_RY
+        (2, trigger), // ABS_Z
+        (5, trigger), // ABS_RZ
         (16, AbsInfo::new(0, Xbox360Layout::HAT_MIN, Xbox360Layout::HAT_MAX, 0, 0, 0)), // ABS_HAT0X
         (17, AbsInfo::new(0, Xbox360Layout::HAT_MIN, Xbox360Layout::HAT_MAX, 0, 0, 0)), // ABS_HAT0Y
     ];
@@ -54,6 +81,38 @@ pub fn create_virtual_gamepad() -> Result<VirtualDevice, Box<dyn std::error::Err
         builder = builder.with_absolute_axis(&setup)?;
     }
 
+    // Force feedback, for `rumble` to pass on to the player's phone. Besides
+    // plain rumble, the effects games commonly use on pads without motors of
+    // their own; `rumble::Motors` turns them into the two motor strengths.
+    let mut effects = AttributeSet::<FFEffectType>::new();
+    for effect in [
+        FFEffectType::FF_RUMBLE,
+        FFEffectType::FF_PERIODIC,
+        FFEffectType::FF_SQUARE,
+        FFEffectType::FF_TRIANGLE,
+        FFEffectType::FF_SINE,
+        FFEffectType::FF_SAW_UP,
+        FFEffectType::FF_SAW_DOWN,
+        FFEffectType::FF_CONSTANT,
+        FFEffectType::FF_RAMP,
+        FFEffectType::FF_GAIN,
+    ] {
+        effects.insert(effect);
+    }
+    builder = builder.with_ff(&effects)?.with_ff_effects_max(MAX_EFFECTS);
+
+    if pressure {
+        for code in Xbox360Layout::PRESSURE_CODES {
+            let info = AbsInfo::new(0, 0, Xbox360Layout::PRESSURE_MAX, 0, 0, 0);
+            builder = builder.with_absolute_axis(&UinputAbsSetup::new(evdev::AbsoluteAxisType(code), info))?;
+        }
+    }
+
+    for axis in extra {
+        let info = if axis.is_hat() { AbsInfo::new(0, Xbox360Layout::HAT_MIN, Xbox360Layout::HAT_MAX, 0, 0, 0) } else { stick };
+        builder = builder.with_absolute_axis(&UinputAbsSetup::new(evdev::AbsoluteAxisType(axis.code()), info))?;
+    }
+
     let device = builder.build()?;
     Ok(device)
 }
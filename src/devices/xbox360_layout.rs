@@ -54,7 +54,8 @@ impl Xbox360Layout {
         17, // ABS_HAT0Y - dpad vertical   (-1,0,1)
     ];
 
-    // Rangos estándar que usa xpad en Linux (evdev)
+    // Rangos estándar que usa xpad en Linux (evdev); los de sticks y gatillos
+    // son los valores por defecto de `[gamepad] axis_ranges`
     pub const STICK_MIN: i32 = -32768;
     pub const STICK_MAX: i32 = 32767;
 
@@ -64,6 +65,33 @@ impl Xbox360Layout {
     pub const HAT_MIN: i32 = -1;
     pub const HAT_MAX: i32 = 1;
 
+    /// Botones digitales de los gatillos, para frontends que no leen ABS_Z/ABS_RZ.
+    pub const TRIGGER_BUTTON_CODES: [u16; 2] = [
+        312, // BTN_TL2 (LT)
+        313, // BTN_TR2 (RT)
+    ];
+
+    /// Ejes de presión de un mando estilo DualShock 2 (`[gamepad] pressure`),
+    /// 0..255, en el orden del snapshot: A, B, X, Y, LB, RB y la cruceta
+    /// (arriba, abajo, izquierda, derecha). Ni ABS_HAT* (SDL los trata como
+    /// crucetas) ni ABS_MT_* (el dispositivo pasaría por pantalla táctil).
+    pub const PRESSURE_COUNT: usize = 10;
+
+    pub const PRESSURE_CODES: [u16; Self::PRESSURE_COUNT] = [
+        6,  // ABS_THROTTLE - A
+        7,  // ABS_RUDDER   - B
+        8,  // ABS_WHEEL    - X
+        9,  // ABS_GAS      - Y
+        10, // ABS_BRAKE    - LB
+        24, // ABS_PRESSURE - RB
+        25, // ABS_DISTANCE - dpad up
+        26, // ABS_TILT_X   - dpad down
+        27, // ABS_TILT_Y   - dpad left
+        40, // ABS_MISC     - dpad right
+    ];
+
+    pub const PRESSURE_MAX: i32 = 255;
+
     pub fn button_code(idx: usize) -> Option<u16> {
         Self::BUTTON_CODES.get(idx).copied()
     }
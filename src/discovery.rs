@@ -1,39 +1,181 @@
-use crate::protocol::{DISCOVERY_INTERVAL_MS, DISCOVERY_PORT, HEADER_DISCOVERY};
+use crate::config::ConfigHandle;
+use crate::logger::{log_detail, log_lazy, Verbosity};
+use crate::network::{ipv4_interfaces, mac_address};
+use crate::protocol::{DISCOVERY_PORT, HEADER_DISCOVERY, HEADER_REGISTER};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use tokio::net::UdpSocket;
-use tokio::time::{Duration, sleep};
+use tokio::time::{Duration, Instant, sleep_until};
 
+// A registration lasts this long after the client's last REGISTER
+const REGISTRATION_TTL: Duration = Duration::from_secs(300);
+// Unicast announcements are capped, whoever asks
+const MAX_REGISTERED: usize = 64;
+
+/// Puertos reales (tras el fallback) que se anuncian a los clientes.
+#[derive(Debug, Clone, Copy)]
+pub struct DiscoveryPorts {
+    pub tcp: u16,
+    pub mouse: u16,
+    pub gamepad: u16,
+}
+
+/// Anuncia el servidor por broadcast en la LAN y, con `[discovery]
+/// register_port`, también a los clientes de otras redes (VPN, otra VLAN)
+/// que se registran con REGISTER: reciben la respuesta al momento y después
+/// el mismo anuncio en unicast mientras sigan registrándose.
 pub async fn run_discovery_broadcast(
-    tcp_port: u16,
-    udp_port: u16,
+    ports: DiscoveryPorts,
     active_clients: Arc<AtomicUsize>,
+    config: ConfigHandle,
 ) -> std::io::Result<()> {
-    let socket = UdpSocket::bind(("0.0.0.0", 0)).await?;
+    let register_port = config.get().discovery.register_port;
+    let socket = match register_port {
+        0 => UdpSocket::bind(("0.0.0.0", 0)).await?,
+        port => match UdpSocket::bind(("0.0.0.0", port)).await {
+            Ok(socket) => socket,
+            Err(e) => {
+                log_detail(Verbosity::Low, "Registro de clientes no disponible", &format!("puerto {}: {}", port, e));
+                UdpSocket::bind(("0.0.0.0", 0)).await?
+            }
+        },
+    };
     socket.set_broadcast(true)?;
 
-    let mut payload = [0u8; 5];
+    let mut targets = BroadcastTargets::default();
+    let mut registered: HashMap<SocketAddr, Instant> = HashMap::new();
+    let mut buf = [0u8; 16];
+    // Fase rápida: al arrancar y cada vez que se va el último cliente
+    let mut fast_since = Instant::now();
+    let mut had_clients = false;
+    let mut next_announce = Instant::now();
     loop {
-        if active_clients.load(Ordering::SeqCst) == 0 {
-            payload[0] = HEADER_DISCOVERY;
-            payload[1..3].copy_from_slice(&tcp_port.to_le_bytes());
-            payload[3..5].copy_from_slice(&udp_port.to_le_bytes());
-            match socket
-                .send_to(&payload, ("255.255.255.255", DISCOVERY_PORT))
-                .await
-            {
-                Ok(size) => {
-                    let clients = active_clients.load(Ordering::SeqCst);
-                    println!(
-                        "Sent discovery packet ({} bytes) TCP:{} UDP:{} active_clients:{}",
-                        size, tcp_port, udp_port, clients
-                    );
+        tokio::select! {
+            _ = sleep_until(next_announce) => {}
+            received = socket.recv_from(&mut buf) => {
+                let Ok((len, addr)) = received else {
+                    continue;
+                };
+                if len == 0 || buf[0] != HEADER_REGISTER || !config.get().is_allowed(addr.ip()) {
+                    continue;
+                }
+                if !registered.contains_key(&addr) {
+                    if registered.len() >= MAX_REGISTERED {
+                        continue;
+                    }
+                    log_detail(Verbosity::Low, "Cliente registrado", &addr.to_string());
                 }
-                Err(e) => {
-                    eprintln!("Error broadcasting discovery packet: {}", e);
+                registered.insert(addr, Instant::now() + REGISTRATION_TTL);
+                let refresh = Duration::from_secs(config.get().discovery.refresh_interfaces_secs);
+                let mac = primary_mac(targets.get(config.get().discovery.interface.as_deref(), refresh));
+                let _ = socket.send_to(&announcement(ports, mac), addr).await;
+                continue;
+            }
+        }
+
+        let discovery = config.get().discovery.clone();
+        let clients = active_clients.load(Ordering::SeqCst);
+        if had_clients && clients == 0 {
+            fast_since = Instant::now();
+        }
+        had_clients = clients > 0;
+        registered.retain(|addr, expires| {
+            let alive = *expires > Instant::now();
+            if !alive {
+                log_detail(Verbosity::Medium, "Registro caducado", &addr.to_string());
+            }
+            alive
+        });
+
+        if discovery.while_connected || clients == 0 {
+            let refresh = Duration::from_secs(discovery.refresh_interfaces_secs);
+            let broadcast = targets.get(discovery.interface.as_deref(), refresh);
+            let mac = primary_mac(broadcast);
+            let unicast = registered.keys().map(|&addr| (addr, mac));
+            let sends: Vec<(SocketAddr, [u8; 6])> = broadcast
+                .iter()
+                .map(|&(target, mac)| (SocketAddr::from((target, DISCOVERY_PORT)), mac))
+                .chain(unicast)
+                .collect();
+            for (target, mac) in sends {
+                match socket.send_to(&announcement(ports, mac), target).await {
+                    Ok(size) => {
+                        log_lazy(Verbosity::High, || {
+                            format!(
+                                "Descubrimiento enviado ({} bytes) a {} TCP:{} UDP:{} Gamepad:{} clientes:{}",
+                                size, target, ports.tcp, ports.mouse, ports.gamepad, clients
+                            )
+                        });
+                    }
+                    Err(e) => {
+                        log_detail(Verbosity::Low, "Error enviando descubrimiento", &format!("{}: {}", target, e));
+                    }
                 }
             }
         }
-        sleep(Duration::from_millis(DISCOVERY_INTERVAL_MS)).await;
+        let interval = if fast_since.elapsed() < Duration::from_secs(discovery.fast_period_secs) {
+            discovery.fast_interval_ms
+        } else {
+            discovery.interval_ms
+        };
+        next_announce = Instant::now() + Duration::from_millis(interval);
+    }
+}
+
+/// `[header:1][tcp:2][mouse_udp:2][gamepad_udp:2][mac:6]`, puertos u16 LE y
+/// la MAC de la interfaz por la que sale (ceros si no tiene), para WAKE.
+/// Los clientes antiguos sólo leen los primeros 5 o 7 bytes.
+fn announcement(ports: DiscoveryPorts, mac: [u8; 6]) -> [u8; 13] {
+    let mut payload = [0u8; 13];
+    payload[0] = HEADER_DISCOVERY;
+    payload[1..3].copy_from_slice(&ports.tcp.to_le_bytes());
+    payload[3..5].copy_from_slice(&ports.mouse.to_le_bytes());
+    payload[5..7].copy_from_slice(&ports.gamepad.to_le_bytes());
+    payload[7..13].copy_from_slice(&mac);
+    payload
+}
+
+// Registered clients are not behind any one interface; they get the first real MAC
+fn primary_mac(targets: &[(Ipv4Addr, [u8; 6])]) -> [u8; 6] {
+    targets.iter().map(|&(_, mac)| mac).find(|mac| *mac != [0; 6]).unwrap_or_default()
+}
+
+/// Direcciones de broadcast de subred de cada interfaz, con su MAC, recalculadas
+/// periódicamente para detectar Wi-Fi que aparece o cambia de red.
+///
+/// Muchos routers y drivers Wi-Fi descartan 255.255.255.255, así que sólo se
+/// usa como último recurso si ninguna interfaz tiene broadcast propio.
+#[derive(Default)]
+struct BroadcastTargets {
+    targets: Vec<(Ipv4Addr, [u8; 6])>,
+    interface: Option<String>,
+    refreshed_at: Option<Instant>,
+}
+
+impl BroadcastTargets {
+    fn get(&mut self, interface: Option<&str>, refresh: Duration) -> &[(Ipv4Addr, [u8; 6])] {
+        let stale = self.refreshed_at.is_none_or(|t| t.elapsed() >= refresh);
+        if stale || self.interface.as_deref() != interface {
+            let mut targets: Vec<(Ipv4Addr, [u8; 6])> = ipv4_interfaces()
+                .into_iter()
+                .filter(|i| interface.is_none_or(|name| i.name == name))
+                .filter_map(|i| Some((i.broadcast?, mac_address(&i.name).unwrap_or_default())))
+                .collect();
+            targets.sort();
+            targets.dedup();
+            if targets.is_empty() {
+                targets.push((Ipv4Addr::BROADCAST, [0; 6]));
+            }
+            if targets != self.targets {
+                let list: Vec<String> = targets.iter().map(|(t, _)| t.to_string()).collect();
+                log_detail(Verbosity::Medium, "Destinos de descubrimiento", &list.join(", "));
+            }
+            self.targets = targets;
+            self.interface = interface.map(str::to_string);
+            self.refreshed_at = Some(Instant::now());
+        }
+        &self.targets
     }
 }
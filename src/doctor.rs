@@ -0,0 +1,269 @@
+use crate::logger::{log_detail, Verbosity};
+use nix::errno::Errno;
+use nix::unistd::{geteuid, User};
+use std::fs::OpenOptions;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::process::Command;
+
+const UINPUT_NODE: &str = "/dev/uinput";
+const UINPUT_GROUP: &str = "uinput";
+const UDEV_RULE_PATH: &str = "/etc/udev/rules.d/99-retro-control-uinput.rules";
+const MODULES_LOAD_PATH: &str = "/etc/modules-load.d/retro-control-uinput.conf";
+
+pub const UDEV_RULE: &str =
+    "KERNEL==\"uinput\", SUBSYSTEM==\"misc\", MODE=\"0660\", GROUP=\"uinput\", OPTIONS+=\"static_node=uinput\"\n";
+
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    /// Si falla, no se pueden crear dispositivos. Los demás son avisos: el
+    /// nodo puede funcionar aunque el chequeo no lo vea claro.
+    pub required: bool,
+    pub detail: String,
+    /// Qué hacer si el chequeo falla
+    pub fix: Option<String>,
+}
+
+/// Verifica que el proceso actual pueda crear dispositivos uinput.
+pub fn diagnose() -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let builtin = uinput_builtin();
+    let module_loaded = builtin || Path::new("/sys/module/uinput").exists();
+    // Containers and some kernels hide /sys/module and modules.builtin; the node says enough
+    let node_exists = Path::new(UINPUT_NODE).exists();
+    checks.push(Check {
+        name: "Módulo uinput",
+        ok: module_loaded,
+        required: false,
+        detail: match (builtin, module_loaded, node_exists) {
+            (true, _, _) => "incluido en el kernel".to_string(),
+            (false, true, _) => "cargado".to_string(),
+            (false, false, true) => "no visible, pero /dev/uinput existe".to_string(),
+            (false, false, false) => "no cargado".to_string(),
+        },
+        fix: Some("sudo modprobe uinput, o [devices] modprobe = true para que lo cargue el servidor".to_string()),
+    });
+
+    let metadata = std::fs::metadata(UINPUT_NODE);
+    checks.push(Check {
+        name: "Nodo /dev/uinput",
+        ok: metadata.is_ok(),
+        required: true,
+        detail: match &metadata {
+            Ok(m) => format!(
+                "uid={} gid={} modo={:o}",
+                m.uid(),
+                m.gid(),
+                m.permissions().mode() & 0o777
+            ),
+            Err(e) => e.to_string(),
+        },
+        fix: Some(if in_container() {
+            "el proceso corre en un contenedor: expón el dispositivo (p. ej. --device /dev/uinput)".to_string()
+        } else {
+            "sudo modprobe uinput (el nodo aparece al cargar el módulo)".to_string()
+        }),
+    });
+
+    if metadata.is_ok() {
+        let writable = open_for_writing();
+        checks.push(Check {
+            name: "Permiso de escritura",
+            ok: writable.is_ok(),
+            required: true,
+            detail: match &writable {
+                Ok(_) => "ok".to_string(),
+                Err(e) => e.to_string(),
+            },
+            fix: Some(format!(
+                "sudo retro-control-server doctor --install --user {} (y volver a iniciar sesión)",
+                std::env::var("USER").unwrap_or_else(|_| "<usuario>".to_string())
+            )),
+        });
+    }
+
+    checks
+}
+
+/// Abre /dev/uinput como lo hará evdev al crear un dispositivo.
+pub fn open_for_writing() -> std::io::Result<std::fs::File> {
+    OpenOptions::new().write(true).open(UINPUT_NODE)
+}
+
+/// Un kernel con CONFIG_INPUT_UINPUT=y no tiene /sys/module/uinput.
+fn uinput_builtin() -> bool {
+    let Ok(release) = std::fs::read_to_string("/proc/sys/kernel/osrelease") else {
+        return false;
+    };
+    std::fs::read_to_string(format!("/lib/modules/{}/modules.builtin", release.trim()))
+        .map(|builtin| builtin.lines().any(|l| l.ends_with("/uinput.ko")))
+        .unwrap_or(false)
+}
+
+/// Carga el módulo uinput si falta (`[devices] modprobe`). Sólo como root;
+/// espera un momento a que udev cree /dev/uinput.
+pub fn load_module() -> bool {
+    if Path::new(UINPUT_NODE).exists() {
+        return true;
+    }
+    if !geteuid().is_root() {
+        log_detail(Verbosity::Low, "No se puede cargar uinput", "modprobe necesita root");
+        return false;
+    }
+    match Command::new("modprobe").arg("uinput").status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => {
+            log_detail(Verbosity::Low, "modprobe uinput falló", &status.to_string());
+            return false;
+        }
+        Err(e) => {
+            log_detail(Verbosity::Low, "modprobe uinput falló", &e.to_string());
+            return false;
+        }
+    }
+    for _ in 0..20 {
+        if Path::new(UINPUT_NODE).exists() {
+            log_detail(Verbosity::Low, "Módulo uinput cargado", "modprobe uinput");
+            return true;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+    log_detail(Verbosity::Low, "modprobe uinput falló", "el módulo cargó pero no apareció /dev/uinput");
+    false
+}
+
+/// Causa concreta de un fallo al crear un dispositivo virtual y qué hacer,
+/// para no dejar al usuario con un error genérico.
+pub fn remediation(error: &(dyn std::error::Error + 'static)) -> String {
+    let errno = error
+        .downcast_ref::<std::io::Error>()
+        .and_then(std::io::Error::raw_os_error)
+        .map(Errno::from_raw);
+    let user = User::from_uid(geteuid()).ok().flatten().map_or_else(|| geteuid().to_string(), |u| u.name);
+    match errno {
+        Some(Errno::ENOENT | Errno::ENODEV | Errno::ENXIO) if in_container() => {
+            "el contenedor no expone /dev/uinput: añade --device /dev/uinput (o el equivalente de tu runtime)"
+                .to_string()
+        }
+        Some(Errno::ENOENT | Errno::ENODEV | Errno::ENXIO) => {
+            if Path::new("/sys/module/uinput").exists() || uinput_builtin() {
+                "uinput está cargado pero falta /dev/uinput: revisa udev (sudo udevadm trigger --name-match=uinput)"
+                    .to_string()
+            } else {
+                "el módulo uinput no está cargado: sudo modprobe uinput, o [devices] modprobe = true".to_string()
+            }
+        }
+        Some(Errno::EACCES | Errno::EPERM) if in_container() => {
+            "el contenedor bloquea /dev/uinput: permite el dispositivo en su cgroup (--device /dev/uinput) \
+             y ejecútalo con acceso de escritura"
+                .to_string()
+        }
+        Some(Errno::EACCES | Errno::EPERM) => format!(
+            "el usuario {} no puede escribir /dev/uinput: sudo retro-control-server doctor --install --user {} \
+             (y volver a iniciar sesión)",
+            user, user
+        ),
+        Some(Errno::EROFS) => "/dev está montado como sólo lectura (¿contenedor o sandbox?)".to_string(),
+        _ => "ejecuta `retro-control-server doctor` para ver qué falta".to_string(),
+    }
+}
+
+fn in_container() -> bool {
+    Path::new("/.dockerenv").exists()
+        || Path::new("/run/.containerenv").exists()
+        || std::fs::read_to_string("/proc/1/cgroup")
+            .map(|c| c.contains("docker") || c.contains("lxc") || c.contains("kubepods"))
+            .unwrap_or(false)
+}
+
+pub fn print_checks(checks: &[Check]) {
+    for check in checks {
+        let mark = match (check.ok, check.required) {
+            (true, _) => "✓",
+            (false, true) => "✗",
+            (false, false) => "⚠",
+        };
+        println!("{} {}: {}", mark, check.name, check.detail);
+        if !check.ok
+            && let Some(fix) = &check.fix
+        {
+            println!("  └─ solución: {}", fix);
+        }
+    }
+}
+
+/// `retro-control-server doctor [--udev-rule] [--install [--user NAME]]`
+pub fn run_doctor(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut install = false;
+    let mut print_rule = false;
+    let mut user = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--install" => install = true,
+            "--udev-rule" => print_rule = true,
+            "--user" => user = iter.next().cloned(),
+            other => return Err(format!("argumento desconocido para doctor: {}", other).into()),
+        }
+    }
+
+    if print_rule {
+        print!("{}", UDEV_RULE);
+        return Ok(());
+    }
+
+    if install {
+        install_udev_setup(user.as_deref())?;
+        println!();
+    }
+
+    let checks = diagnose();
+    print_checks(&checks);
+    if checks.iter().all(|c| c.ok || !c.required) {
+        println!("\nTodo listo: el servidor puede crear dispositivos virtuales.");
+        Ok(())
+    } else {
+        Err("el sistema no está listo para uinput".into())
+    }
+}
+
+fn install_udev_setup(user: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+    if !group_exists(UINPUT_GROUP) {
+        run("groupadd", &["--system", UINPUT_GROUP])?;
+    }
+    if let Some(user) = user {
+        run("usermod", &["-aG", UINPUT_GROUP, user])?;
+    }
+
+    std::fs::write(UDEV_RULE_PATH, UDEV_RULE)
+        .map_err(|e| format!("no se pudo escribir {}: {} (¿falta sudo?)", UDEV_RULE_PATH, e))?;
+    println!("✓ Regla udev escrita en {}", UDEV_RULE_PATH);
+    std::fs::write(MODULES_LOAD_PATH, "uinput\n")
+        .map_err(|e| format!("no se pudo escribir {}: {}", MODULES_LOAD_PATH, e))?;
+    println!("✓ uinput se cargará al arrancar ({})", MODULES_LOAD_PATH);
+
+    run("modprobe", &["uinput"])?;
+    run("udevadm", &["control", "--reload-rules"])?;
+    run("udevadm", &["trigger", "--name-match=uinput"])?;
+    Ok(())
+}
+
+fn group_exists(name: &str) -> bool {
+    std::fs::read_to_string("/etc/group")
+        .map(|groups| groups.lines().any(|l| l.split(':').next() == Some(name)))
+        .unwrap_or(false)
+}
+
+fn run(program: &str, args: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+    let status = Command::new(program)
+        .args(args)
+        .status()
+        .map_err(|e| format!("no se pudo ejecutar {}: {}", program, e))?;
+    if !status.success() {
+        return Err(format!("{} {} falló ({})", program, args.join(" "), status).into());
+    }
+    println!("✓ {} {}", program, args.join(" "));
+    Ok(())
+}
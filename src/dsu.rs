@@ -0,0 +1,237 @@
+use crate::config::ConfigHandle;
+use crate::logger::{log_detail, Verbosity};
+use crate::stats::STATS;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::LazyLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::Duration;
+
+// DSU only knows four controllers
+const DSU_SLOTS: usize = 4;
+const PROTOCOL_VERSION: u16 = 1001;
+const HEADER_LEN: usize = 16;
+const MSG_VERSION: u32 = 0x100000;
+const MSG_PORTS: u32 = 0x100001;
+const MSG_DATA: u32 = 0x100002;
+// Clients repeat their data request; one that stops is dropped after this
+const SUBSCRIPTION_TIMEOUT: Duration = Duration::from_secs(5);
+// A slot without motion for this long is reported as disconnected
+const IDLE_SLOT: Duration = Duration::from_secs(5);
+// Samples buffered for the DSU server; a slow one skips ahead
+const MOTION_BACKLOG: usize = 64;
+
+/// Lectura de los sensores del móvil (paquete MOTION), en las unidades y
+/// ejes de DSU: acelerómetro en g y giroscopio en grados/s (pitch, yaw, roll).
+#[derive(Debug, Clone, Copy)]
+pub struct MotionSample {
+    pub accel: [f32; 3],
+    pub gyro: [f32; 3],
+}
+
+/// Reparte las lecturas de movimiento del servidor de mando al servidor DSU.
+pub struct Motion {
+    started: Instant,
+    samples: broadcast::Sender<(usize, u64, MotionSample)>,
+}
+
+pub static MOTION: LazyLock<Motion> = LazyLock::new(|| Motion {
+    started: Instant::now(),
+    samples: broadcast::channel(MOTION_BACKLOG).0,
+});
+
+impl Motion {
+    /// Registra una lectura del jugador `slot`, con la hora de llegada.
+    pub fn record(&self, slot: usize, sample: MotionSample) {
+        let at_us = self.started.elapsed().as_micros() as u64;
+        // Nobody listening unless [dsu] is enabled
+        let _ = self.samples.send((slot, at_us, sample));
+    }
+}
+
+/// `[0x4A][acelerómetro: 3 x f32][giroscopio: 3 x f32]`, little-endian.
+pub fn parse_motion(buf: &[u8]) -> Option<MotionSample> {
+    let value = |i: usize| {
+        let at = 1 + i * 4;
+        buf.get(at..at + 4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).filter(|v| v.is_finite())
+    };
+    Some(MotionSample {
+        accel: [value(0)?, value(1)?, value(2)?],
+        gyro: [value(3)?, value(4)?, value(5)?],
+    })
+}
+
+struct Subscription {
+    slots: [bool; DSU_SLOTS],
+    expires: Instant,
+}
+
+/// Servidor DSU (protocolo "cemuhook", `[dsu] listen`): Dolphin, Citra,
+/// Yuzu/Ryujinx y Cemu leen de aquí el giroscopio y el acelerómetro del
+/// móvil de los jugadores 1 a 4, junto al mando virtual. Sólo lleva el
+/// movimiento; los botones y sticks siguen llegando por el mando virtual.
+pub async fn run_dsu(socket: UdpSocket, config: ConfigHandle) -> std::io::Result<()> {
+    let server_id = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.subsec_nanos()) ^ std::process::id();
+    let mut samples = MOTION.samples.subscribe();
+    let mut subscribers: HashMap<SocketAddr, Subscription> = HashMap::new();
+    let mut last_sample: [Option<Instant>; DSU_SLOTS] = [None; DSU_SLOTS];
+    let mut packet_numbers = [0u32; DSU_SLOTS];
+    let mut buf = [0u8; 128];
+
+    loop {
+        tokio::select! {
+            received = socket.recv_from(&mut buf) => {
+                let (len, addr) = received?;
+                if !config.get().is_allowed(addr.ip()) {
+                    continue;
+                }
+                let Some((message, payload)) = parse_request(&buf[..len]) else {
+                    continue;
+                };
+                let connected = last_sample.map(|at| at.is_some_and(|at| at.elapsed() < IDLE_SLOT));
+                match message {
+                    MSG_VERSION => {
+                        let reply = packet(server_id, MSG_VERSION, &PROTOCOL_VERSION.to_le_bytes());
+                        socket.send_to(&reply, addr).await?;
+                    }
+                    MSG_PORTS => {
+                        // [count: i32][slot: u8 x count]
+                        let count = payload.get(..4).map_or(0, |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+                        for &slot in payload.iter().skip(4).take(count.clamp(0, DSU_SLOTS as i32) as usize) {
+                            let slot = slot as usize;
+                            if slot < DSU_SLOTS {
+                                let mut info = slot_header(slot, connected[slot]).to_vec();
+                                info.push(0);
+                                socket.send_to(&packet(server_id, MSG_PORTS, &info), addr).await?;
+                            }
+                        }
+                    }
+                    MSG_DATA => {
+                        // [flags: u8][slot: u8][mac: 6]; no flags = every slot
+                        let flags = payload.first().copied().unwrap_or(0);
+                        let slot = payload.get(1).map_or(usize::MAX, |&slot| slot as usize);
+                        let mac = payload.get(2..8);
+                        let mut slots = [flags == 0; DSU_SLOTS];
+                        for (i, wanted) in slots.iter_mut().enumerate() {
+                            *wanted |= flags & 1 != 0 && slot == i;
+                            *wanted |= flags & 2 != 0 && mac == Some(&mac_for(i)[..]);
+                        }
+                        if !subscribers.contains_key(&addr) {
+                            log_detail(Verbosity::Low, "Cliente DSU", &addr.to_string());
+                        }
+                        let expires = Instant::now() + SUBSCRIPTION_TIMEOUT;
+                        let subscription = subscribers.entry(addr).or_insert(Subscription { slots, expires });
+                        for (kept, wanted) in subscription.slots.iter_mut().zip(slots) {
+                            *kept |= wanted;
+                        }
+                        subscription.expires = expires;
+                    }
+                    _ => {}
+                }
+            }
+            sample = samples.recv() => {
+                let (slot, at_us, sample) = match sample {
+                    Ok(sample) => sample,
+                    // Only the latest reading matters; the oldest ones were dropped
+                    Err(RecvError::Lagged(missed)) => {
+                        STATS.dsu.record_dropped(missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+                if slot >= DSU_SLOTS {
+                    continue;
+                }
+                last_sample[slot] = Some(Instant::now());
+                subscribers.retain(|_, subscription| subscription.expires > Instant::now());
+                packet_numbers[slot] = packet_numbers[slot].wrapping_add(1);
+                let data = packet(server_id, MSG_DATA, &data_payload(slot, packet_numbers[slot], at_us, &sample));
+                for (addr, subscription) in &subscribers {
+                    if subscription.slots[slot] {
+                        // One unreachable emulator must not stop the others
+                        let _ = socket.send_to(&data, addr).await;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Valida cabecera y CRC de un paquete "DSUC" y devuelve su tipo y el resto.
+fn parse_request(buf: &[u8]) -> Option<(u32, &[u8])> {
+    if buf.len() < HEADER_LEN + 4 || &buf[..4] != b"DSUC" {
+        return None;
+    }
+    let len = u16::from_le_bytes([buf[6], buf[7]]) as usize;
+    let body = buf.get(HEADER_LEN..HEADER_LEN + len)?;
+    let crc = u32::from_le_bytes([buf[8], buf[9], buf[10], buf[11]]);
+    let mut unsigned = buf[..HEADER_LEN + len].to_vec();
+    unsigned[8..12].fill(0);
+    if crc32(&unsigned) != crc || body.len() < 4 {
+        return None;
+    }
+    Some((u32::from_le_bytes([body[0], body[1], body[2], body[3]]), &body[4..]))
+}
+
+/// `[DSUS][versión: u16][longitud: u16][crc32][id: u32][tipo: u32][payload]`
+fn packet(server_id: u32, message: u32, payload: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_LEN + 4 + payload.len());
+    packet.extend_from_slice(b"DSUS");
+    packet.extend_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+    packet.extend_from_slice(&((payload.len() + 4) as u16).to_le_bytes());
+    packet.extend_from_slice(&[0; 4]);
+    packet.extend_from_slice(&server_id.to_le_bytes());
+    packet.extend_from_slice(&message.to_le_bytes());
+    packet.extend_from_slice(payload);
+    let crc = crc32(&packet);
+    packet[8..12].copy_from_slice(&crc.to_le_bytes());
+    packet
+}
+
+fn mac_for(slot: usize) -> [u8; 6] {
+    [0, 0, 0, 0, 0, slot as u8 + 1]
+}
+
+/// `[slot][estado: 2 conectado][modelo: 2 giroscopio completo][conexión: 1 USB][mac: 6][batería]`
+fn slot_header(slot: usize, connected: bool) -> [u8; 11] {
+    let mut header = [0u8; 11];
+    header[0] = slot as u8;
+    if connected {
+        header[1..4].copy_from_slice(&[2, 2, 1]);
+        header[4..10].copy_from_slice(&mac_for(slot));
+    }
+    header
+}
+
+fn data_payload(slot: usize, number: u32, at_us: u64, sample: &MotionSample) -> Vec<u8> {
+    let mut data = slot_header(slot, true).to_vec();
+    data.push(1);
+    data.extend_from_slice(&number.to_le_bytes());
+    // Buttons, home, touch button; the virtual gamepad carries those
+    data.extend_from_slice(&[0; 4]);
+    // Sticks centred
+    data.extend_from_slice(&[128; 4]);
+    // Analog d-pad, face and shoulder buttons
+    data.extend_from_slice(&[0; 12]);
+    // Two touch points, inactive
+    data.extend_from_slice(&[0; 12]);
+    data.extend_from_slice(&at_us.to_le_bytes());
+    for value in sample.accel.iter().chain(&sample.gyro) {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data
+}
+
+// CRC-32 (IEEE, as zlib), bit by bit; packets are under 100 bytes
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
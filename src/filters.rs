@@ -0,0 +1,402 @@
+use crate::config::{AxisRoute, FilterConfig, SocdMode};
+use crate::plugins::WasmPlugin;
+use crate::protocol::MAX_EXTRA_AXES;
+use crate::scripting::Script;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Snapshot ya parseado (y calibrado) de un mando, tal como lo ven los filtros.
+pub struct PadInput {
+    pub buttons: [u8; 12],
+    pub axes: [i16; 8],
+    /// Ejes de `[gamepad] extra_axes` en su orden; 0 si el cliente no los envía.
+    pub extra: [i16; MAX_EXTRA_AXES],
+    /// Eventos de teclado `(código, valor)` que piden los filtros, para el
+    /// teclado virtual.
+    pub keys: Vec<(u16, i32)>,
+}
+
+impl PadInput {
+    /// Un eje por índice: 0-7 los del snapshot, 8 en adelante los extra.
+    pub fn axis(&self, index: usize) -> Option<i16> {
+        match index.checked_sub(self.axes.len()) {
+            None => self.axes.get(index).copied(),
+            Some(extra) => self.extra.get(extra).copied(),
+        }
+    }
+
+    pub fn axis_mut(&mut self, index: usize) -> Option<&mut i16> {
+        match index.checked_sub(self.axes.len()) {
+            None => self.axes.get_mut(index),
+            Some(extra) => self.extra.get_mut(extra),
+        }
+    }
+}
+
+/// Un paso del pipeline de `[gamepad] filters`. Cada slot tiene sus propias
+/// instancias, así que un filtro puede guardar estado entre snapshots.
+pub trait InputFilter: Send {
+    fn apply(&mut self, input: &mut PadInput, now: Instant);
+}
+
+/// Registro de filtros: construye el filtro que describe una entrada de
+/// `[gamepad] filters`. Un tipo nuevo de filtro se añade aquí y en `FilterConfig`.
+pub fn build(config: &FilterConfig) -> Box<dyn InputFilter> {
+    match config {
+        FilterConfig::Deadzone { axes, radius } => Box::new(Deadzone { axes: axes.clone(), radius: *radius }),
+        FilterConfig::Turbo { buttons, hz } => Box::new(Turbo::new(buttons.clone(), *hz)),
+        FilterConfig::Socd { mode, threshold } => Box::new(Socd::new(*mode, *threshold)),
+        FilterConfig::Remap { buttons } => Box::new(Remap { pairs: buttons.clone() }),
+        FilterConfig::AxisRemap { axes } => Box::new(AxisRemap { routes: axes.clone() }),
+        FilterConfig::Script { path, retroarch } => Box::new(Script::new(path.clone(), *retroarch)),
+        FilterConfig::Wasm { path } => Box::new(WasmPlugin::new(path.clone())),
+    }
+}
+
+/// Los filtros de un slot, en el orden de la configuración. Se reconstruyen
+/// (perdiendo su estado) cuando una recarga o un perfil cambian la lista.
+#[derive(Default)]
+pub struct Pipeline {
+    config: Vec<FilterConfig>,
+    filters: Vec<Box<dyn InputFilter>>,
+}
+
+impl Pipeline {
+    pub fn run(&mut self, config: &[FilterConfig], input: &mut PadInput) {
+        if self.config != config {
+            self.config = config.to_vec();
+            self.filters = config.iter().map(build).collect();
+        }
+        let now = Instant::now();
+        for filter in &mut self.filters {
+            filter.apply(input, now);
+        }
+    }
+}
+
+/// Archivo de un filtro externo (script o módulo WASM) que se vuelve a
+/// cargar cuando cambia. Se mira como mucho una vez por segundo.
+pub struct WatchedFile {
+    pub path: PathBuf,
+    checked: Option<Instant>,
+    // Modification time of the file at the last load attempt
+    attempted: Option<Option<SystemTime>>,
+}
+
+impl WatchedFile {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, checked: None, attempted: None }
+    }
+
+    /// Si toca intentar (re)cargarlo: la primera vez y cada vez que cambie.
+    pub fn changed(&mut self, now: Instant) -> bool {
+        if self.checked.is_some_and(|checked| now.duration_since(checked) < Duration::from_secs(1)) {
+            return false;
+        }
+        self.checked = Some(now);
+        let modified = std::fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok();
+        self.attempted.replace(modified) != Some(modified)
+    }
+}
+
+/// Ignora la desviación por debajo de `radius` en cada eje y reescala el
+/// resto, para que el recorrido útil siga llegando a los extremos.
+struct Deadzone {
+    axes: Vec<usize>,
+    radius: i16,
+}
+
+impl InputFilter for Deadzone {
+    fn apply(&mut self, input: &mut PadInput, _now: Instant) {
+        let radius = self.radius.clamp(0, i16::MAX - 1) as i32;
+        for &index in &self.axes {
+            let Some(axis) = input.axes.get_mut(index) else {
+                continue;
+            };
+            let value = *axis as i32;
+            let magnitude = value.abs() - radius;
+            *axis = if magnitude <= 0 {
+                0
+            } else {
+                (value.signum() * magnitude * i16::MAX as i32 / (i16::MAX as i32 - radius)).clamp(-32768, 32767) as i16
+            };
+        }
+    }
+}
+
+/// Autodisparo: mientras el botón está pulsado se alterna pulsado/suelto
+/// `hz` veces por segundo. Sólo avanza con cada snapshot recibido.
+struct Turbo {
+    buttons: Vec<usize>,
+    half_period: Duration,
+    pressed_at: [Option<Instant>; 12],
+}
+
+impl Turbo {
+    fn new(buttons: Vec<usize>, hz: f64) -> Self {
+        Self {
+            buttons,
+            half_period: Duration::from_secs_f64(0.5 / hz.max(0.1)),
+            pressed_at: [None; 12],
+        }
+    }
+}
+
+impl InputFilter for Turbo {
+    fn apply(&mut self, input: &mut PadInput, now: Instant) {
+        for &index in &self.buttons {
+            let Some(button) = input.buttons.get_mut(index) else {
+                continue;
+            };
+            if *button == 0 {
+                self.pressed_at[index] = None;
+                continue;
+            }
+            let since = *self.pressed_at[index].get_or_insert(now);
+            let phase = now.duration_since(since).as_nanos() / self.half_period.as_nanos().max(1);
+            *button = phase.is_multiple_of(2) as u8;
+        }
+    }
+}
+
+/// Direcciones opuestas a la vez (SOCD) entre el stick izquierdo y el
+/// d-pad: resuelve el conflicto en cada eje antes de que llegue al juego.
+struct Socd {
+    mode: SocdMode,
+    threshold: i16,
+    // Per axis (x, y), the last source that changed: false = stick, true = d-pad
+    last_dpad: [bool; 2],
+    previous: [(i32, i32); 2],
+}
+
+impl Socd {
+    fn new(mode: SocdMode, threshold: i16) -> Self {
+        Self { mode, threshold, last_dpad: [false; 2], previous: [(0, 0); 2] }
+    }
+}
+
+impl InputFilter for Socd {
+    fn apply(&mut self, input: &mut PadInput, _now: Instant) {
+        let threshold = self.threshold;
+        let digital = |v: i16| if v <= -threshold { -1 } else if v >= threshold { 1 } else { 0 };
+        // (stick axis, d-pad axis) for x and y
+        for (i, (stick, dpad)) in [(0, 6), (1, 7)].into_iter().enumerate() {
+            let current = (digital(input.axes[stick]), input.axes[dpad].signum() as i32);
+            if current.1 != self.previous[i].1 && current.1 != 0 {
+                self.last_dpad[i] = true;
+            } else if current.0 != self.previous[i].0 && current.0 != 0 {
+                self.last_dpad[i] = false;
+            }
+            self.previous[i] = current;
+            if current.0 == 0 || current.1 == 0 || current.0 == current.1 {
+                continue;
+            }
+            let keep_dpad = match self.mode {
+                SocdMode::Neutral => {
+                    input.axes[stick] = 0;
+                    input.axes[dpad] = 0;
+                    continue;
+                }
+                SocdMode::LastWins => self.last_dpad[i],
+                // Negative y is up
+                SocdMode::UpPriority if i == 1 => current.1 < 0,
+                SocdMode::UpPriority => {
+                    input.axes[stick] = 0;
+                    input.axes[dpad] = 0;
+                    continue;
+                }
+            };
+            if keep_dpad {
+                input.axes[stick] = 0;
+            } else {
+                input.axes[dpad] = 0;
+            }
+        }
+    }
+}
+
+/// Cambia botones de sitio: cada par `[origen, destino]` hace que pulsar el
+/// origen pulse el destino. Un botón que es origen deja de pulsarse a sí
+/// mismo, salvo que otro par lo tenga como destino.
+struct Remap {
+    pairs: Vec<(usize, usize)>,
+}
+
+impl InputFilter for Remap {
+    fn apply(&mut self, input: &mut PadInput, _now: Instant) {
+        let source = input.buttons;
+        for &(from, _) in &self.pairs {
+            if let Some(button) = input.buttons.get_mut(from) {
+                *button = 0;
+            }
+        }
+        for &(from, to) in &self.pairs {
+            if let (Some(&value), Some(target)) = (source.get(from), input.buttons.get_mut(to)) {
+                *target |= value;
+            }
+        }
+    }
+}
+
+/// Lleva ejes a otros ejes (p. ej. stick derecho a gatillos, d-pad a stick
+/// izquierdo). Como en `Remap`, un eje origen queda en reposo salvo que otra
+/// ruta lo tenga como destino. Si varias rutas, o el propio eje, mueven el
+/// mismo destino, gana el valor más alejado del reposo.
+struct AxisRemap {
+    routes: Vec<AxisRoute>,
+}
+
+impl InputFilter for AxisRemap {
+    fn apply(&mut self, input: &mut PadInput, _now: Instant) {
+        let values: Vec<(usize, i16)> = self
+            .routes
+            .iter()
+            .filter_map(|route| {
+                let scaled = (input.axis(route.from)? as f64 * route.scale) as i64 + route.offset as i64;
+                Some((route.to, scaled.clamp(i16::MIN as i64, i16::MAX as i64) as i16))
+            })
+            .collect();
+        for route in &self.routes {
+            if let Some(axis) = input.axis_mut(route.from) {
+                *axis = 0;
+            }
+        }
+        for (to, value) in values {
+            if let Some(axis) = input.axis_mut(to)
+                && value.unsigned_abs() > axis.unsigned_abs()
+            {
+                *axis = value;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pad() -> PadInput {
+        PadInput { buttons: [0; 12], axes: [0; 8], extra: [0; MAX_EXTRA_AXES], keys: Vec::new() }
+    }
+
+    fn socd(mode: SocdMode) -> Box<dyn InputFilter> {
+        build(&FilterConfig::Socd { mode, threshold: 20000 })
+    }
+
+    #[test]
+    fn deadzone_zeroes_the_centre_and_rescales_the_rest() {
+        let mut filter = build(&FilterConfig::Deadzone { axes: vec![0, 1, 2], radius: 8000 });
+        let mut input = pad();
+        input.axes[..4].copy_from_slice(&[4000, -32768, 20000, 4000]);
+        filter.apply(&mut input, Instant::now());
+        assert_eq!(input.axes[..4], [0, -32768, 15876, 4000]);
+    }
+
+    #[test]
+    fn turbo_alternates_while_held() {
+        let mut filter = build(&FilterConfig::Turbo { buttons: vec![0], hz: 10.0 });
+        let start = Instant::now();
+        let mut held = |since: Duration| {
+            let mut input = pad();
+            input.buttons[0] = 1;
+            input.buttons[1] = 1;
+            filter.apply(&mut input, start + since);
+            (input.buttons[0], input.buttons[1])
+        };
+        assert_eq!(held(Duration::ZERO), (1, 1));
+        assert_eq!(held(Duration::from_millis(60)), (0, 1));
+        assert_eq!(held(Duration::from_millis(110)), (1, 1));
+    }
+
+    #[test]
+    fn turbo_restarts_on_each_press() {
+        let mut filter = build(&FilterConfig::Turbo { buttons: vec![0], hz: 10.0 });
+        let start = Instant::now();
+        let mut input = pad();
+        input.buttons[0] = 1;
+        filter.apply(&mut input, start);
+        input.buttons[0] = 0;
+        filter.apply(&mut input, start + Duration::from_millis(30));
+        assert_eq!(input.buttons[0], 0);
+        input.buttons[0] = 1;
+        filter.apply(&mut input, start + Duration::from_millis(60));
+        assert_eq!(input.buttons[0], 1);
+    }
+
+    #[test]
+    fn socd_neutral_cancels_both() {
+        let mut filter = socd(SocdMode::Neutral);
+        let mut input = pad();
+        input.axes[0] = -30000;
+        input.axes[6] = 1;
+        filter.apply(&mut input, Instant::now());
+        assert_eq!((input.axes[0], input.axes[6]), (0, 0));
+    }
+
+    #[test]
+    fn socd_last_input_wins() {
+        let mut filter = socd(SocdMode::LastWins);
+        let mut input = pad();
+        input.axes[0] = -30000;
+        filter.apply(&mut input, Instant::now());
+        input.axes[6] = 1;
+        filter.apply(&mut input, Instant::now());
+        assert_eq!((input.axes[0], input.axes[6]), (0, 1));
+    }
+
+    #[test]
+    fn socd_up_priority_keeps_up_and_cancels_left_right() {
+        let mut filter = socd(SocdMode::UpPriority);
+        let mut input = pad();
+        input.axes[1] = 30000;
+        input.axes[7] = -1;
+        filter.apply(&mut input, Instant::now());
+        assert_eq!((input.axes[1], input.axes[7]), (0, -1));
+
+        let mut input = pad();
+        input.axes[1] = -30000;
+        input.axes[7] = 1;
+        input.axes[0] = 30000;
+        input.axes[6] = -1;
+        filter.apply(&mut input, Instant::now());
+        assert_eq!(input.axes, [0, -30000, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn socd_ignores_stick_below_threshold() {
+        let mut filter = socd(SocdMode::Neutral);
+        let mut input = pad();
+        input.axes[0] = -10000;
+        input.axes[6] = 1;
+        filter.apply(&mut input, Instant::now());
+        assert_eq!((input.axes[0], input.axes[6]), (-10000, 1));
+    }
+
+    #[test]
+    fn remap_swaps_and_moves_buttons() {
+        let mut filter = build(&FilterConfig::Remap { buttons: vec![(0, 1), (1, 0), (2, 3)] });
+        let mut input = pad();
+        input.buttons[0] = 1;
+        input.buttons[2] = 1;
+        filter.apply(&mut input, Instant::now());
+        assert_eq!(input.buttons[..4], [0, 1, 0, 1]);
+    }
+
+    #[test]
+    fn axis_remap_scales_and_keeps_the_furthest_value() {
+        let routes = vec![
+            AxisRoute { from: 2, to: 4, scale: 0.5, offset: 0 },
+            AxisRoute { from: 8, to: 0, scale: 1.0, offset: 0 },
+            AxisRoute { from: 3, to: 5, scale: 2.0, offset: 100 },
+        ];
+        let mut filter = build(&FilterConfig::AxisRemap { axes: routes });
+        let mut input = pad();
+        input.axes[0] = 20000;
+        input.axes[2] = 20000;
+        input.axes[3] = 30000;
+        input.extra[0] = -5000;
+        filter.apply(&mut input, Instant::now());
+        assert_eq!(input.axes, [20000, 0, 0, 0, 10000, 32767, 0, 0]);
+        assert_eq!(input.extra[0], 0);
+    }
+}
@@ -0,0 +1,19 @@
+use crate::protocol::HEADER_HAPTIC;
+
+/// Patrones de vibración que el servidor pide al cliente para confirmar
+/// algo que pasó en el servidor. El cliente decide cómo reproducirlos.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Haptic {
+    /// Confirmación breve (cambio de modo)
+    Tick = 1,
+    /// Dos pulsos cortos (combinación reconocida)
+    DoubleBuzz = 2,
+    /// Aviso largo (batería baja)
+    Long = 3,
+}
+
+impl Haptic {
+    pub fn packet(self) -> [u8; 2] {
+        [HEADER_HAPTIC, self as u8]
+    }
+}
@@ -0,0 +1,191 @@
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+
+// Longest request accepted, head and body together; clients only send short commands
+const MAX_REQUEST: usize = 8192;
+// A connection that has not sent its whole request by then is dropped, so idle
+// sockets cannot hold the listeners' permits
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Petición HTTP/1.1 mínima: lo justo para los endpoints del servidor
+/// (overlays y API REST), sin keep-alive ni chunked.
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    query: String,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub async fn read(stream: &mut TcpStream) -> std::io::Result<Self> {
+        timeout(READ_TIMEOUT, Self::read_untimed(stream))
+            .await
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "petición HTTP sin terminar"))?
+    }
+
+    async fn read_untimed(stream: &mut (impl AsyncRead + Unpin)) -> std::io::Result<Self> {
+        let invalid = |reason: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, reason.to_string());
+        let mut data = Vec::new();
+        let mut buf = [0u8; 1024];
+        let head_len = loop {
+            if let Some(end) = data.windows(4).position(|w| w == b"\r\n\r\n") {
+                break end + 4;
+            }
+            let len = stream.read(&mut buf).await?;
+            if len == 0 || data.len() + len > MAX_REQUEST {
+                return Err(invalid("petición HTTP incompleta"));
+            }
+            data.extend_from_slice(&buf[..len]);
+        };
+
+        let head = String::from_utf8_lossy(&data[..head_len]).into_owned();
+        let mut lines = head.lines();
+        // "POST /input/key?code=KEY_ENTER HTTP/1.1"
+        let mut request_line = lines.next().unwrap_or("").split_whitespace();
+        let method = request_line.next().unwrap_or("").to_string();
+        let target = request_line.next().unwrap_or("/");
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let headers: Vec<(String, String)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+
+        let mut request = Self { method, path: path.to_string(), query: query.to_string(), headers, body: Vec::new() };
+        let body_len: usize = request.header("content-length").and_then(|len| len.parse().ok()).unwrap_or(0);
+        if head_len + body_len > MAX_REQUEST {
+            return Err(invalid("petición HTTP demasiado grande"));
+        }
+        request.body = data.split_off(head_len);
+        while request.body.len() < body_len {
+            let len = stream.read(&mut buf).await?;
+            if len == 0 {
+                return Err(invalid("petición HTTP incompleta"));
+            }
+            request.body.extend_from_slice(&buf[..len]);
+        }
+        request.body.truncate(body_len);
+        Ok(request)
+    }
+
+    /// Cabecera por nombre, sin distinguir mayúsculas.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    /// Parámetro de la query string o de un cuerpo
+    /// `application/x-www-form-urlencoded`, ya decodificado.
+    pub fn param(&self, name: &str) -> Option<String> {
+        let form = self
+            .header("content-type")
+            .is_some_and(|kind| kind.starts_with("application/x-www-form-urlencoded"))
+            .then(|| String::from_utf8_lossy(&self.body).into_owned());
+        [Some(self.query.as_str()), form.as_deref()].into_iter().flatten().find_map(|pairs| query_param(pairs, name))
+    }
+}
+
+/// Valor decodificado de `name` en `a=1&b=2`.
+pub fn query_param(pairs: &str, name: &str) -> Option<String> {
+    pairs
+        .split('&')
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+        .find(|(key, _)| percent_decode(key) == name)
+        .map(|(_, value)| percent_decode(value))
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(byte) = text.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            decoded.push(byte);
+            i += 3;
+            continue;
+        }
+        decoded.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Responde y deja que el cliente cierre; todas las respuestas llevan CORS
+/// abierto para que las usen páginas servidas desde otro origen.
+pub async fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> std::io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\n\
+         Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await
+}
+
+/// Compara sin salir antes en el primer byte distinto, para que el tiempo
+/// de respuesta no revele cuánto del token acertó el cliente.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(raw: &[u8]) -> std::io::Result<Request> {
+        Request::read_untimed(&mut &raw[..]).await
+    }
+
+    #[tokio::test]
+    async fn request_line_headers_and_query() {
+        let request = parse(b"POST /input/key?code=KEY_ENTER&state=1 HTTP/1.1\r\nAuthorization: Bearer abc\r\n\r\n")
+            .await
+            .unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/input/key");
+        assert_eq!(request.header("authorization"), Some("Bearer abc"));
+        assert_eq!(request.param("code").as_deref(), Some("KEY_ENTER"));
+        assert_eq!(request.param("state").as_deref(), Some("1"));
+        assert_eq!(request.param("missing"), None);
+    }
+
+    #[tokio::test]
+    async fn form_body_is_read_up_to_content_length() {
+        let raw = b"POST /input/text HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\
+                    Content-Length: 14\r\n\r\ntext=hola+mu%C3%B1o";
+        let request = parse(raw).await.unwrap();
+        assert_eq!(request.body, b"text=hola+mu%C");
+        let raw = b"POST /input/text HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\
+                    Content-Length: 19\r\n\r\ntext=hola+mu%C3%B1o";
+        let request = parse(raw).await.unwrap();
+        assert_eq!(request.param("text").as_deref(), Some("hola muño"));
+    }
+
+    #[tokio::test]
+    async fn incomplete_and_oversized_requests_fail() {
+        assert!(parse(b"GET / HTTP/1.1\r\nHost: x\r\n").await.is_err());
+        assert!(parse(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nabc").await.is_err());
+        assert!(parse(b"POST / HTTP/1.1\r\nContent-Length: 9000\r\n\r\n").await.is_err());
+        let mut huge = b"GET / HTTP/1.1\r\nX: ".to_vec();
+        huge.resize(MAX_REQUEST + 1, b'a');
+        assert!(parse(&huge).await.is_err());
+    }
+
+    #[test]
+    fn query_params_are_percent_decoded() {
+        assert_eq!(query_param("a=1&b%20c=x%2By+z", "b c").as_deref(), Some("x+y z"));
+        assert_eq!(query_param("flag&a=1", "flag").as_deref(), Some(""));
+        assert_eq!(query_param("a=%zz", "a").as_deref(), Some("%zz"));
+    }
+
+    #[test]
+    fn token_comparison() {
+        assert!(constant_time_eq(b"secreto", b"secreto"));
+        assert!(!constant_time_eq(b"secreto", b"secretx"));
+        assert!(!constant_time_eq(b"secreto", b"secret"));
+    }
+}
@@ -0,0 +1,146 @@
+use crate::logger::{log_detail, Verbosity};
+use crate::protocol::{HEADER_HELLO, HEADER_HELLO_ACK};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+pub const CLIENT_ID_LEN: usize = 16;
+
+/// Token opaco que el servidor entrega al cliente en el HELLO. El cliente lo
+/// guarda y lo vuelve a presentar, así su sesión sobrevive a cambios de IP.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClientId(pub [u8; CLIENT_ID_LEN]);
+
+impl ClientId {
+    pub fn generate() -> std::io::Result<Self> {
+        let mut bytes = [0u8; CLIENT_ID_LEN];
+        std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+        Ok(Self(bytes))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        bytes.try_into().ok().map(Self)
+    }
+
+    /// Token completo en hexadecimal, para guardarlo en disco.
+    pub fn to_hex(self) -> String {
+        self.0.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != CLIENT_ID_LEN * 2 || !hex.is_ascii() {
+            return None;
+        }
+        let mut bytes = [0u8; CLIENT_ID_LEN];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+        }
+        Some(Self(bytes))
+    }
+}
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The first 4 bytes are plenty to tell clients apart in logs
+        for b in &self.0[..4] {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ClientId({})", self)
+    }
+}
+
+/// Clave de sesión: el token si el cliente se identificó; si no, su
+/// dirección completa (IP y puerto), para que dos dispositivos detrás de la
+/// misma IP (NAT, repetidor, emulador + teléfono) no se pisen.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum ClientKey {
+    Addr(SocketAddr),
+    Id(ClientId),
+}
+
+impl fmt::Display for ClientKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientKey::Addr(addr) => write!(f, "{}", addr),
+            ClientKey::Id(id) => write!(f, "id:{}", id),
+        }
+    }
+}
+
+struct Binding {
+    channel: &'static str,
+    id: ClientId,
+}
+
+/// Qué identidad usa cada dirección, según los HELLO recibidos (TCP o UDP).
+/// Cada canal de un cliente tiene su propio puerto de origen, así que se
+/// guarda una asociación por canal.
+#[derive(Clone, Default)]
+pub struct Identities {
+    bindings: Arc<Mutex<HashMap<SocketAddr, Binding>>>,
+}
+
+impl Identities {
+    pub fn bind(&self, channel: &'static str, addr: SocketAddr, id: ClientId) {
+        let mut bindings = self.bindings.lock().unwrap();
+        // Only the latest address per (client, channel) is kept
+        bindings.retain(|_, b| !(b.id == id && b.channel == channel));
+        bindings.insert(addr, Binding { channel, id });
+    }
+
+    /// Sin HELLO desde esta dirección se recurre a la IP, siempre que sólo
+    /// un cliente identificado la use; si no, se distingue por puerto.
+    pub fn key_for(&self, addr: SocketAddr) -> ClientKey {
+        let bindings = self.bindings.lock().unwrap();
+        if let Some(binding) = bindings.get(&addr) {
+            return ClientKey::Id(binding.id);
+        }
+        let mut ids = bindings
+            .iter()
+            .filter(|(a, _)| a.ip() == addr.ip())
+            .map(|(_, b)| b.id);
+        match ids.next() {
+            Some(first) if ids.all(|id| id == first) => ClientKey::Id(first),
+            _ => ClientKey::Addr(addr),
+        }
+    }
+
+    /// Procesa un HELLO (`[len][token]`, sin la cabecera) y devuelve el token
+    /// asignado. Un token ausente o mal formado recibe uno nuevo.
+    pub fn hello(&self, channel: &'static str, addr: SocketAddr, token: &[u8]) -> std::io::Result<ClientId> {
+        let id = match ClientId::from_bytes(token) {
+            Some(id) => id,
+            None => {
+                let id = ClientId::generate()?;
+                log_detail(Verbosity::Low, "Identidad nueva", &format!("{} {} id={}", channel, addr, id));
+                id
+            }
+        };
+        self.bind(channel, addr, id);
+        Ok(id)
+    }
+}
+
+/// `[HELLO_ACK][token:16]`
+pub fn hello_ack(id: ClientId) -> [u8; 1 + CLIENT_ID_LEN] {
+    let mut ack = [0u8; 1 + CLIENT_ID_LEN];
+    ack[0] = HEADER_HELLO_ACK;
+    ack[1..].copy_from_slice(&id.0);
+    ack
+}
+
+/// Extrae el token de un HELLO recibido por UDP: `[HELLO][len][token:len]`.
+pub fn parse_udp_hello(buf: &[u8]) -> Option<&[u8]> {
+    if buf.len() < 2 || buf[0] != HEADER_HELLO {
+        return None;
+    }
+    buf.get(2..2 + buf[1] as usize)
+}
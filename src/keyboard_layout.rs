@@ -0,0 +1,265 @@
+use evdev::Key;
+use serde::Deserialize;
+
+/// Distribución de teclado configurada en la consola del host. Los caracteres
+/// recibidos en un paquete TEXT se traducen a la tecla física (y
+/// modificadores) que los produce con esa distribución.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyboardLayout {
+    #[default]
+    Us,
+    Es,
+    De,
+    Fr,
+}
+
+const NONE: u8 = 0;
+const SHIFT: u8 = 1;
+const ALTGR: u8 = 2;
+
+/// Tecla física más modificadores necesarios para un carácter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stroke {
+    pub key: Key,
+    pub shift: bool,
+    pub altgr: bool,
+}
+
+impl Stroke {
+    fn new(key: Key, mods: u8) -> Self {
+        Self {
+            key,
+            shift: mods & SHIFT != 0,
+            altgr: mods & ALTGR != 0,
+        }
+    }
+}
+
+// Symbols on the number row and the right side of the keyboard. Dead keys
+// (accents, ^ and ¨ on es/de/fr) are left out: they need a second keystroke.
+const US_SYMBOLS: &[(char, Key, u8)] = &[
+    ('!', Key::KEY_1, SHIFT), ('@', Key::KEY_2, SHIFT), ('#', Key::KEY_3, SHIFT),
+    ('$', Key::KEY_4, SHIFT), ('%', Key::KEY_5, SHIFT), ('^', Key::KEY_6, SHIFT),
+    ('&', Key::KEY_7, SHIFT), ('*', Key::KEY_8, SHIFT), ('(', Key::KEY_9, SHIFT),
+    (')', Key::KEY_0, SHIFT),
+    ('-', Key::KEY_MINUS, NONE), ('_', Key::KEY_MINUS, SHIFT),
+    ('=', Key::KEY_EQUAL, NONE), ('+', Key::KEY_EQUAL, SHIFT),
+    ('[', Key::KEY_LEFTBRACE, NONE), ('{', Key::KEY_LEFTBRACE, SHIFT),
+    (']', Key::KEY_RIGHTBRACE, NONE), ('}', Key::KEY_RIGHTBRACE, SHIFT),
+    (';', Key::KEY_SEMICOLON, NONE), (':', Key::KEY_SEMICOLON, SHIFT),
+    ('\'', Key::KEY_APOSTROPHE, NONE), ('"', Key::KEY_APOSTROPHE, SHIFT),
+    ('`', Key::KEY_GRAVE, NONE), ('~', Key::KEY_GRAVE, SHIFT),
+    ('\\', Key::KEY_BACKSLASH, NONE), ('|', Key::KEY_BACKSLASH, SHIFT),
+    (',', Key::KEY_COMMA, NONE), ('<', Key::KEY_COMMA, SHIFT),
+    ('.', Key::KEY_DOT, NONE), ('>', Key::KEY_DOT, SHIFT),
+    ('/', Key::KEY_SLASH, NONE), ('?', Key::KEY_SLASH, SHIFT),
+];
+
+const ES_SYMBOLS: &[(char, Key, u8)] = &[
+    ('!', Key::KEY_1, SHIFT), ('|', Key::KEY_1, ALTGR),
+    ('"', Key::KEY_2, SHIFT), ('@', Key::KEY_2, ALTGR),
+    ('·', Key::KEY_3, SHIFT), ('#', Key::KEY_3, ALTGR),
+    ('$', Key::KEY_4, SHIFT), ('~', Key::KEY_4, ALTGR),
+    ('%', Key::KEY_5, SHIFT),
+    ('&', Key::KEY_6, SHIFT), ('¬', Key::KEY_6, ALTGR),
+    ('/', Key::KEY_7, SHIFT), ('(', Key::KEY_8, SHIFT),
+    (')', Key::KEY_9, SHIFT), ('=', Key::KEY_0, SHIFT),
+    ('\'', Key::KEY_MINUS, NONE), ('?', Key::KEY_MINUS, SHIFT),
+    ('¡', Key::KEY_EQUAL, NONE), ('¿', Key::KEY_EQUAL, SHIFT),
+    ('[', Key::KEY_LEFTBRACE, ALTGR),
+    ('+', Key::KEY_RIGHTBRACE, NONE), ('*', Key::KEY_RIGHTBRACE, SHIFT),
+    (']', Key::KEY_RIGHTBRACE, ALTGR),
+    ('ñ', Key::KEY_SEMICOLON, NONE), ('Ñ', Key::KEY_SEMICOLON, SHIFT),
+    ('{', Key::KEY_APOSTROPHE, ALTGR),
+    ('º', Key::KEY_GRAVE, NONE), ('ª', Key::KEY_GRAVE, SHIFT), ('\\', Key::KEY_GRAVE, ALTGR),
+    ('ç', Key::KEY_BACKSLASH, NONE), ('Ç', Key::KEY_BACKSLASH, SHIFT), ('}', Key::KEY_BACKSLASH, ALTGR),
+    ('<', Key::KEY_102ND, NONE), ('>', Key::KEY_102ND, SHIFT),
+    (',', Key::KEY_COMMA, NONE), (';', Key::KEY_COMMA, SHIFT),
+    ('.', Key::KEY_DOT, NONE), (':', Key::KEY_DOT, SHIFT),
+    ('-', Key::KEY_SLASH, NONE), ('_', Key::KEY_SLASH, SHIFT),
+    ('€', Key::KEY_E, ALTGR),
+];
+
+const DE_SYMBOLS: &[(char, Key, u8)] = &[
+    ('!', Key::KEY_1, SHIFT), ('"', Key::KEY_2, SHIFT), ('²', Key::KEY_2, ALTGR),
+    ('§', Key::KEY_3, SHIFT), ('³', Key::KEY_3, ALTGR),
+    ('$', Key::KEY_4, SHIFT), ('%', Key::KEY_5, SHIFT), ('&', Key::KEY_6, SHIFT),
+    ('/', Key::KEY_7, SHIFT), ('{', Key::KEY_7, ALTGR),
+    ('(', Key::KEY_8, SHIFT), ('[', Key::KEY_8, ALTGR),
+    (')', Key::KEY_9, SHIFT), (']', Key::KEY_9, ALTGR),
+    ('=', Key::KEY_0, SHIFT), ('}', Key::KEY_0, ALTGR),
+    ('ß', Key::KEY_MINUS, NONE), ('?', Key::KEY_MINUS, SHIFT), ('\\', Key::KEY_MINUS, ALTGR),
+    ('ü', Key::KEY_LEFTBRACE, NONE), ('Ü', Key::KEY_LEFTBRACE, SHIFT),
+    ('+', Key::KEY_RIGHTBRACE, NONE), ('*', Key::KEY_RIGHTBRACE, SHIFT), ('~', Key::KEY_RIGHTBRACE, ALTGR),
+    ('ö', Key::KEY_SEMICOLON, NONE), ('Ö', Key::KEY_SEMICOLON, SHIFT),
+    ('ä', Key::KEY_APOSTROPHE, NONE), ('Ä', Key::KEY_APOSTROPHE, SHIFT),
+    ('°', Key::KEY_GRAVE, SHIFT),
+    ('#', Key::KEY_BACKSLASH, NONE), ('\'', Key::KEY_BACKSLASH, SHIFT),
+    ('<', Key::KEY_102ND, NONE), ('>', Key::KEY_102ND, SHIFT), ('|', Key::KEY_102ND, ALTGR),
+    (',', Key::KEY_COMMA, NONE), (';', Key::KEY_COMMA, SHIFT),
+    ('.', Key::KEY_DOT, NONE), (':', Key::KEY_DOT, SHIFT),
+    ('-', Key::KEY_SLASH, NONE), ('_', Key::KEY_SLASH, SHIFT),
+    ('@', Key::KEY_Q, ALTGR), ('€', Key::KEY_E, ALTGR),
+];
+
+const FR_SYMBOLS: &[(char, Key, u8)] = &[
+    ('&', Key::KEY_1, NONE), ('é', Key::KEY_2, NONE), ('~', Key::KEY_2, ALTGR),
+    ('"', Key::KEY_3, NONE), ('#', Key::KEY_3, ALTGR),
+    ('\'', Key::KEY_4, NONE), ('{', Key::KEY_4, ALTGR),
+    ('(', Key::KEY_5, NONE), ('[', Key::KEY_5, ALTGR),
+    ('-', Key::KEY_6, NONE), ('|', Key::KEY_6, ALTGR),
+    ('è', Key::KEY_7, NONE), ('`', Key::KEY_7, ALTGR),
+    ('_', Key::KEY_8, NONE), ('\\', Key::KEY_8, ALTGR),
+    ('ç', Key::KEY_9, NONE), ('^', Key::KEY_9, ALTGR),
+    ('à', Key::KEY_0, NONE), ('@', Key::KEY_0, ALTGR),
+    (')', Key::KEY_MINUS, NONE), ('°', Key::KEY_MINUS, SHIFT), (']', Key::KEY_MINUS, ALTGR),
+    ('=', Key::KEY_EQUAL, NONE), ('+', Key::KEY_EQUAL, SHIFT), ('}', Key::KEY_EQUAL, ALTGR),
+    ('$', Key::KEY_RIGHTBRACE, NONE), ('£', Key::KEY_RIGHTBRACE, SHIFT),
+    ('ù', Key::KEY_APOSTROPHE, NONE), ('%', Key::KEY_APOSTROPHE, SHIFT),
+    ('²', Key::KEY_GRAVE, NONE),
+    ('*', Key::KEY_BACKSLASH, NONE), ('µ', Key::KEY_BACKSLASH, SHIFT),
+    ('<', Key::KEY_102ND, NONE), ('>', Key::KEY_102ND, SHIFT),
+    (',', Key::KEY_M, NONE), ('?', Key::KEY_M, SHIFT),
+    (';', Key::KEY_COMMA, NONE), ('.', Key::KEY_COMMA, SHIFT),
+    (':', Key::KEY_DOT, NONE), ('/', Key::KEY_DOT, SHIFT),
+    ('!', Key::KEY_SLASH, NONE), ('§', Key::KEY_SLASH, SHIFT),
+    ('€', Key::KEY_E, ALTGR),
+];
+
+// QWERTY positions of a..z
+const LETTERS: [Key; 26] = [
+    Key::KEY_A, Key::KEY_B, Key::KEY_C, Key::KEY_D, Key::KEY_E, Key::KEY_F, Key::KEY_G,
+    Key::KEY_H, Key::KEY_I, Key::KEY_J, Key::KEY_K, Key::KEY_L, Key::KEY_M, Key::KEY_N,
+    Key::KEY_O, Key::KEY_P, Key::KEY_Q, Key::KEY_R, Key::KEY_S, Key::KEY_T, Key::KEY_U,
+    Key::KEY_V, Key::KEY_W, Key::KEY_X, Key::KEY_Y, Key::KEY_Z,
+];
+
+const DIGITS: [Key; 10] = [
+    Key::KEY_0, Key::KEY_1, Key::KEY_2, Key::KEY_3, Key::KEY_4,
+    Key::KEY_5, Key::KEY_6, Key::KEY_7, Key::KEY_8, Key::KEY_9,
+];
+
+impl KeyboardLayout {
+    /// Tecla que produce `c`, o `None` si la distribución no lo tiene (o
+    /// sólo mediante tecla muerta).
+    pub fn stroke(self, c: char) -> Option<Stroke> {
+        match c {
+            ' ' => return Some(Stroke::new(Key::KEY_SPACE, NONE)),
+            '\n' | '\r' => return Some(Stroke::new(Key::KEY_ENTER, NONE)),
+            '\t' => return Some(Stroke::new(Key::KEY_TAB, NONE)),
+            '\u{8}' => return Some(Stroke::new(Key::KEY_BACKSPACE, NONE)),
+            _ => {}
+        }
+
+        if c.is_ascii_alphabetic() {
+            let key = self.letter_key(c.to_ascii_lowercase());
+            let mods = if c.is_ascii_uppercase() { SHIFT } else { NONE };
+            return Some(Stroke::new(key, mods));
+        }
+
+        if let Some(digit) = c.to_digit(10) {
+            let key = DIGITS[digit as usize];
+            // AZERTY types digits with Shift
+            let mods = if self == KeyboardLayout::Fr { SHIFT } else { NONE };
+            return Some(Stroke::new(key, mods));
+        }
+
+        self.symbols()
+            .iter()
+            .find(|(symbol, _, _)| *symbol == c)
+            .map(|&(_, key, mods)| Stroke::new(key, mods))
+    }
+
+    fn letter_key(self, lower: char) -> Key {
+        let swapped = match (self, lower) {
+            (KeyboardLayout::De, 'y') => 'z',
+            (KeyboardLayout::De, 'z') => 'y',
+            (KeyboardLayout::Fr, 'a') => 'q',
+            (KeyboardLayout::Fr, 'q') => 'a',
+            (KeyboardLayout::Fr, 'z') => 'w',
+            (KeyboardLayout::Fr, 'w') => 'z',
+            (KeyboardLayout::Fr, 'm') => return Key::KEY_SEMICOLON,
+            _ => lower,
+        };
+        LETTERS[(swapped as u8 - b'a') as usize]
+    }
+
+    fn symbols(self) -> &'static [(char, Key, u8)] {
+        match self {
+            KeyboardLayout::Us => US_SYMBOLS,
+            KeyboardLayout::Es => ES_SYMBOLS,
+            KeyboardLayout::De => DE_SYMBOLS,
+            KeyboardLayout::Fr => FR_SYMBOLS,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use KeyboardLayout::{De, Es, Fr, Us};
+
+    const LAYOUTS: [KeyboardLayout; 4] = [Us, Es, De, Fr];
+
+    fn stroke(layout: KeyboardLayout, c: char) -> Stroke {
+        layout.stroke(c).unwrap()
+    }
+
+    #[test]
+    fn letters_follow_the_layout() {
+        assert_eq!(stroke(KeyboardLayout::Us, 'y'), Stroke::new(Key::KEY_Y, NONE));
+        assert_eq!(stroke(KeyboardLayout::De, 'y'), Stroke::new(Key::KEY_Z, NONE));
+        assert_eq!(stroke(KeyboardLayout::De, 'Z'), Stroke::new(Key::KEY_Y, SHIFT));
+        assert_eq!(stroke(KeyboardLayout::Fr, 'a'), Stroke::new(Key::KEY_Q, NONE));
+        assert_eq!(stroke(KeyboardLayout::Fr, 'W'), Stroke::new(Key::KEY_Z, SHIFT));
+        assert_eq!(stroke(KeyboardLayout::Fr, 'm'), Stroke::new(Key::KEY_SEMICOLON, NONE));
+    }
+
+    #[test]
+    fn digits_need_shift_only_on_azerty() {
+        assert_eq!(stroke(KeyboardLayout::Es, '7'), Stroke::new(Key::KEY_7, NONE));
+        assert_eq!(stroke(KeyboardLayout::Fr, '7'), Stroke::new(Key::KEY_7, SHIFT));
+    }
+
+    #[test]
+    fn symbols_use_their_modifiers() {
+        assert_eq!(stroke(KeyboardLayout::Us, '@'), Stroke::new(Key::KEY_2, SHIFT));
+        assert_eq!(stroke(KeyboardLayout::Es, '@'), Stroke::new(Key::KEY_2, ALTGR));
+        assert_eq!(stroke(KeyboardLayout::De, '@'), Stroke::new(Key::KEY_Q, ALTGR));
+        assert_eq!(stroke(KeyboardLayout::Fr, '@'), Stroke::new(Key::KEY_0, ALTGR));
+        assert_eq!(stroke(KeyboardLayout::Es, 'ñ'), Stroke::new(Key::KEY_SEMICOLON, NONE));
+    }
+
+    #[test]
+    fn control_characters_are_the_same_everywhere() {
+        for layout in LAYOUTS {
+            assert_eq!(stroke(layout, ' '), Stroke::new(Key::KEY_SPACE, NONE));
+            assert_eq!(stroke(layout, '\n'), Stroke::new(Key::KEY_ENTER, NONE));
+            assert_eq!(stroke(layout, '\t'), Stroke::new(Key::KEY_TAB, NONE));
+        }
+    }
+
+    #[test]
+    fn dead_keys_and_unknown_characters_have_no_stroke() {
+        assert_eq!(KeyboardLayout::Es.stroke('^'), None);
+        assert_eq!(KeyboardLayout::Us.stroke('ñ'), None);
+        assert_eq!(KeyboardLayout::De.stroke('→'), None);
+    }
+
+    #[test]
+    fn each_character_appears_once_per_layout() {
+        for layout in LAYOUTS {
+            let symbols = layout.symbols();
+            for (i, (symbol, _, _)) in symbols.iter().enumerate() {
+                assert!(
+                    !symbols[i + 1..].iter().any(|(other, _, _)| other == symbol),
+                    "{:?} repite '{}'",
+                    layout,
+                    symbol
+                );
+            }
+        }
+    }
+}
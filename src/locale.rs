@@ -0,0 +1,460 @@
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::LazyLock;
+
+/// Idioma de los mensajes de log (`[log] locale`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Locale {
+    /// Los mensajes tal como están escritos en el código
+    #[default]
+    Es,
+    En,
+}
+
+static CURRENT_LOCALE: AtomicU8 = AtomicU8::new(Locale::Es as u8);
+
+pub fn set_locale(locale: Locale) {
+    CURRENT_LOCALE.store(locale as u8, Ordering::Relaxed);
+}
+
+/// Traducciones al inglés de los mensajes de log. `{}` marca un valor que
+/// se copia del mensaje original; en la traducción, `{1}`, `{2}`... lo
+/// reordenan si hace falta. Lo que no está aquí se imprime sin traducir.
+const EN: &[(&str, &str)] = &[
+    // Arranque y ciclo de vida
+    ("🚀 Iniciando Retro Control Server...", "🚀 Starting Retro Control Server..."),
+    ("Trazando los paquetes de", "Tracing packets from"),
+    ("✓ Dispositivos virtuales creados", "✓ Virtual devices created"),
+    ("✓ Dispositivos virtuales bajo demanda", "✓ Virtual devices created on demand"),
+    ("⚠ --dry-run: no se crean dispositivos uinput, los eventos sólo se registran", "⚠ --dry-run: no uinput devices are created, events are only logged"),
+    ("✓ Servidores de red iniciados", "✓ Network servers started"),
+    ("   - Teclado TCP: {}", "   - Keyboard TCP: {}"),
+    ("   - Observador TCP: {}", "   - Observer TCP: {}"),
+    ("   - API REST: {}", "   - REST API: {}"),
+    ("   - Remote RetroPad UDP: {}", "   - Remote RetroPad UDP: {}"),
+    ("   - DSU UDP: {}", "   - DSU UDP: {}"),
+    ("   - QUIC: {}", "   - QUIC: {}"),
+    ("Esperando conexiones...", "Waiting for connections..."),
+    ("\nApagando Retro Control Server...", "\nShutting down Retro Control Server..."),
+    ("Error en broadcast de descubrimiento: {}", "Discovery broadcast error: {}"),
+    ("Error en socket de administración: {}", "Admin socket error: {}"),
+    ("Error en canal de observación: {}", "Observer channel error: {}"),
+    ("Error en la API REST: {}", "REST API error: {}"),
+    ("Error en Remote RetroPad: {}", "Remote RetroPad error: {}"),
+    ("Error en servidor DSU: {}", "DSU server error: {}"),
+    ("Error en listener QUIC: {}", "QUIC listener error: {}"),
+    ("Error en servidor UDP Mouse: {}", "Mouse UDP server error: {}"),
+    ("Error en servidor TCP Teclado: {}", "Keyboard TCP server error: {}"),
+    ("Error en servidor UDP Gamepad: {}", "Gamepad UDP server error: {}"),
+    ("no se pudo abrir QUIC en {}: {}", "could not open QUIC on {}: {}"),
+    ("Error recargando configuración: {}", "Error reloading configuration: {}"),
+    ("Configuración recargada", "Configuration reloaded"),
+    ("no se pudo crear la captura {}: {}", "could not create capture {}: {}"),
+    ("Capturando paquetes", "Capturing packets"),
+    ("Error escribiendo captura", "Error writing capture"),
+    ("uinput no está disponible; ejecuta `retro-control-server doctor`", "uinput is not available; run `retro-control-server doctor`"),
+    ("no se indicó archivo de configuración (--config)", "no configuration file given (--config)"),
+    ("no se pudo leer {}: {}", "could not read {}: {}"),
+    ("{}: [log] levels: {}", "{}: [log] levels: {}"),
+    ("subsistema de log desconocido: {} (válidos: {})", "unknown log subsystem: {} (valid: {})"),
+    ("nivel de log inválido: {}", "invalid log level: {}"),
+    ("--log-format acepta plain, pretty o json, no '{}'", "--log-format accepts plain, pretty or json, not '{}'"),
+    ("{}: [lightgun] los márgenes van de 0 a 50 (%)", "{}: [lightgun] margins go from 0 to 50 (%)"),
+    ("{}: [gamepad] extra_axes: hasta {} ejes distintos", "{}: [gamepad] extra_axes: up to {} distinct axes"),
+    ("{}: [gamepad] extra_axes: con pressure sólo quedan hat1x y hat1y", "{}: [gamepad] extra_axes: with pressure only hat1x and hat1y are left"),
+    ("{}: axis_ranges: cada mínimo debe ser menor que su máximo", "{}: axis_ranges: each minimum must be below its maximum"),
+    ("{}: [mouse] precision_scale va de 0 a 1", "{}: [mouse] precision_scale goes from 0 to 1"),
+    ("{}: [api] listen necesita un token", "{}: [api] listen needs a token"),
+    ("{}: [mqtt] commands: '{}' no es un comando de consulta (válidos: {})", "{}: [mqtt] commands: '{}' is not a query command (valid: {})"),
+    ("{}: [security] packet_key no puede estar vacía", "{}: [security] packet_key cannot be empty"),
+    ("{}: [rendezvous] server necesita un name", "{}: [rendezvous] server needs a name"),
+    // Privilegios, systemd y planificación
+    ("Ejecutando como root", "Running as root"),
+    ("configura [privileges] user para abandonar root tras el arranque", "set [privileges] user to drop root after startup"),
+    ("Sin privilegios que abandonar", "No privileges to drop"),
+    ("Privilegios abandonados", "Privileges dropped"),
+    ("no se pudo buscar el usuario {}: {}", "could not look up user {}: {}"),
+    ("el usuario {} no existe", "user {} does not exist"),
+    ("no se pudo buscar el grupo {}: {}", "could not look up group {}: {}"),
+    ("el grupo {} no existe", "group {} does not exist"),
+    ("se pudo recuperar root tras setuid; abortando", "root could be regained after setuid; aborting"),
+    ("usuario={} uid={} gid={}", "user={} uid={} gid={}"),
+    ("Socket TCP heredado de systemd", "TCP socket inherited from systemd"),
+    ("Watchdog de systemd activo", "systemd watchdog active"),
+    ("ping cada {}", "ping every {}"),
+    ("No se pudo ajustar la planificación", "Could not adjust scheduling"),
+    ("Planificación ajustada", "Scheduling adjusted"),
+    ("{}, {} ({} hilos)", "{}, {} ({} threads)"),
+    ("cualquier núcleo", "any core"),
+    ("núcleo {}", "core {}"),
+    // Red
+    ("Puerto {} ocupado", "{} port busy"),
+    ("{} en uso, usando {}", "{} in use, using {}"),
+    ("Buffer de recepción {} recortado", "{} receive buffer capped"),
+    ("Buffer de recepción {}", "{} receive buffer"),
+    ("pedido {} bytes, el kernel reserva {}", "requested {} bytes, the kernel reserves {}"),
+    ("{} bytes; sube net.core.rmem_max o arranca como root", "{} bytes; raise net.core.rmem_max or start as root"),
+    ("Receptores {}", "{} receivers"),
+    ("{} sockets SO_REUSEPORT en {}", "{} SO_REUSEPORT sockets on {}"),
+    ("Error en receptor {} #{}", "Error in {} receiver #{}"),
+    ("Datagrama demasiado grande", "Datagram too large"),
+    ("{} desde {}", "{} from {}"),
+    ("Datagrama no autenticado", "Unauthenticated datagram"),
+    ("Destinos de descubrimiento", "Discovery targets"),
+    ("Descubrimiento enviado ({} bytes) a {} TCP:{} UDP:{} Gamepad:{} clientes:{}", "Discovery sent ({} bytes) to {} TCP:{} UDP:{} Gamepad:{} clients:{}"),
+    ("Error enviando descubrimiento", "Error sending discovery"),
+    ("puerto {}: {}", "port {}: {}"),
+    ("'{}' no es una IP ni una interfaz con IPv4", "'{}' is neither an IP nor an interface with IPv4"),
+    ("los receptores UDP terminaron", "the UDP receivers stopped"),
+    ("no se pudo enlazar {} en el puerto {}: {}", "could not bind {} on port {}: {}"),
+    ("Teclado TCP", "Keyboard TCP"),
+    ("Teclado", "Keyboard"),
+    // Sesiones, identidades y seguridad
+    ("Conexión TCP aceptada", "TCP connection accepted"),
+    ("Conexión TCP rechazada", "TCP connection rejected"),
+    ("Conexión TCP terminada", "TCP connection closed"),
+    ("Error en conexión TCP", "TCP connection error"),
+    ("Error en HELLO TCP", "TCP HELLO error"),
+    ("HELLO sin terminar", "unfinished HELLO"),
+    ("Conexión QUIC aceptada", "QUIC connection accepted"),
+    ("Conexión QUIC rechazada", "QUIC connection rejected"),
+    ("Conexión QUIC cerrada", "QUIC connection closed"),
+    ("Error en conexión QUIC", "QUIC connection error"),
+    ("Datagrama QUIC descartado", "QUIC datagram dropped"),
+    ("Certificado QUIC creado", "QUIC certificate created"),
+    ("ip={}: ya hay {}", "ip={}: {} already connected"),
+    ("{}: canal {}", "{}: channel {}"),
+    ("{}: certificado QUIC no válido: {}", "{}: invalid QUIC certificate: {}"),
+    ("no se pudo crear el certificado QUIC: {}", "could not create the QUIC certificate: {}"),
+    ("Error en HELLO UDP", "UDP HELLO error"),
+    ("Cliente {} registrado", "{} client registered"),
+    ("Cliente {} rechazado", "{} client rejected"),
+    ("Cliente {} desconectado", "{} client disconnected"),
+    ("Sesión {} terminada", "{} session ended"),
+    ("cliente={} sesión cerrada por el servidor", "client={} session closed by the server"),
+    ("cliente={} límite={} ocupado por {}", "client={} limit={} taken by {}"),
+    ("cliente={} sesiones={}", "client={} sessions={}"),
+    ("cliente={} sin snapshots", "client={} without snapshots"),
+    ("cliente={} ejes={}", "client={} axes={}"),
+    ("cliente={} ({})", "client={} ({})"),
+    ("cliente={}", "client={}"),
+    ("reconexión del mismo cliente", "same client reconnected"),
+    ("desalojado por {}", "evicted by {}"),
+    ("inactiva {}s", "idle for {}s"),
+    ("desconexión", "disconnection"),
+    ("Identidad nueva", "New identity"),
+    ("id inválido: {}", "invalid id: {}"),
+    ("ip={} fuera de la allowlist", "ip={} not in the allowlist"),
+    ("ip={} en cuarentena", "ip={} quarantined"),
+    ("Fuente en cuarentena", "Source quarantined"),
+    ("{} ({}): {} paquetes inválidos en {}s, ignorada {}s", "{} ({}): {} invalid packets in {}s, ignored for {}s"),
+    ("Cuarentena terminada", "Quarantine over"),
+    ("sin contador ni etiqueta", "no counter or tag"),
+    ("etiqueta no válida", "invalid tag"),
+    ("contador repetido o atrasado", "repeated or stale counter"),
+    ("no se pudo generar el nonce", "could not generate the nonce"),
+    ("en cuarentena", "quarantined"),
+    ("límite de tasa", "rate limited"),
+    ("paquete no reconocido", "unrecognized packet"),
+    // Dispositivos
+    ("Dispositivo virtual creado", "Virtual device created"),
+    ("Dispositivo virtual simulado", "Virtual device simulated"),
+    ("Dispositivo virtual destruido", "Virtual device destroyed"),
+    ("Error creando dispositivo virtual", "Error creating virtual device"),
+    ("{} no disponible", "{} unavailable"),
+    ("no se encontró el descriptor de /dev/uinput", "the /dev/uinput descriptor was not found"),
+    ("{} inactivo {}s", "{} idle for {}s"),
+    ("inactivo {}s", "idle for {}s"),
+    ("por cambio de rangos de los ejes", "because the axis ranges changed"),
+    ("Solución", "Fix"),
+    ("No se puede cargar uinput", "Cannot load uinput"),
+    ("modprobe necesita root", "modprobe needs root"),
+    ("modprobe uinput falló", "modprobe uinput failed"),
+    ("Módulo uinput cargado", "uinput module loaded"),
+    ("⚠ Módulo uinput", "⚠ uinput module"),
+    ("Sin acceso a /dev/uinput tras abandonar root", "No /dev/uinput access after dropping root"),
+    ("{}: los mandos P2 en adelante y los dispositivos auxiliares no se podrán crear", "{}: gamepads from P2 on and auxiliary devices cannot be created"),
+    ("el módulo cargó pero no apareció /dev/uinput", "the module loaded but /dev/uinput did not appear"),
+    ("el contenedor no expone /dev/uinput: añade --device /dev/uinput (o el equivalente de tu runtime)", "the container does not expose /dev/uinput: add --device /dev/uinput (or your runtime's equivalent)"),
+    ("uinput está cargado pero falta /dev/uinput: revisa udev (sudo udevadm trigger --name-match=uinput)", "uinput is loaded but /dev/uinput is missing: check udev (sudo udevadm trigger --name-match=uinput)"),
+    ("el módulo uinput no está cargado: sudo modprobe uinput, o [devices] modprobe = true", "the uinput module is not loaded: sudo modprobe uinput, or [devices] modprobe = true"),
+    ("el contenedor bloquea /dev/uinput: permite el dispositivo en su cgroup (--device /dev/uinput) y ejecútalo con acceso de escritura", "the container blocks /dev/uinput: allow the device in its cgroup (--device /dev/uinput) with write access"),
+    ("el usuario {} no puede escribir /dev/uinput: sudo retro-control-server doctor --install --user {} (y volver a iniciar sesión)", "user {} cannot write /dev/uinput: sudo retro-control-server doctor --install --user {} (then log in again)"),
+    ("/dev está montado como sólo lectura (¿contenedor o sandbox?)", "/dev is mounted read-only (container or sandbox?)"),
+    ("ejecuta `retro-control-server doctor` para ver qué falta", "run `retro-control-server doctor` to see what is missing"),
+    ("Monitorizando", "Monitoring"),
+    ("Error abriendo nodo", "Error opening node"),
+    ("Error buscando nodos del dispositivo", "Error looking up device nodes"),
+    ("{} en {}", "{} on {}"),
+    ("Snapshot descartado", "Snapshot dropped"),
+    // Teclado
+    ("Modo cambiado", "Mode changed"),
+    ("a gamepad", "to gamepad"),
+    ("a mouse+teclado", "to mouse+keyboard"),
+    ("Cambio de modo demasiado seguido", "Mode switch too soon"),
+    ("sin esperar el ack anterior", "without waiting for the previous ack"),
+    ("antes del intervalo mínimo", "before the minimum interval"),
+    ("Turno del teclado tomado", "Keyboard token taken"),
+    ("Turno del teclado liberado", "Keyboard token released"),
+    ("Turno del teclado retirado", "Keyboard token revoked"),
+    ("Cesión del teclado pedida", "Keyboard takeover requested"),
+    ("Cesión del teclado rechazada", "Keyboard takeover refused"),
+    ("Cesión del teclado sin respuesta", "Keyboard takeover timed out"),
+    ("cliente={} (quitado a {}, inactivo)", "client={} (taken from {}, idle)"),
+    ("cedido por el cliente", "released by the client"),
+    ("cedido", "handed over"),
+    ("servidor del teclado detenido", "keyboard server stopped"),
+    ("Teclas liberadas", "Keys released"),
+    ("{} teclas seguían pulsadas", "{} keys were still held"),
+    ("Modificadores enganchados", "Modifiers latched"),
+    ("Modificador pegajoso ignorado", "Sticky modifier ignored"),
+    ("código {} no es un modificador", "code {} is not a modifier"),
+    ("Carácter sin tecla", "Character without a key"),
+    ("{} en distribución {}", "{} in layout {}"),
+    ("LEDs de teclado no disponibles", "Keyboard LEDs unavailable"),
+    ("LEDs de teclado", "Keyboard LEDs"),
+    ("Error leyendo LEDs del teclado", "Error reading keyboard LEDs"),
+    ("Error leyendo force feedback", "Error reading force feedback"),
+    ("Tecla soltada por el watchdog", "Key released by the watchdog"),
+    ("Tecla ignorada", "Key ignored"),
+    ("código {} con {} teclas ya pulsadas", "code {} with {} keys already held"),
+    ("Tecla soltada por rollover", "Key released by rollover"),
+    ("código {} para pulsar {}", "code {} to press {}"),
+    ("código {} pulsada más de {}s sin refresco", "code {} held over {}s without a refresh"),
+    ("Botones del ratón soltados por el watchdog", "Mouse buttons released by the watchdog"),
+    ("{} pulsados más de {}s sin refresco", "{} held over {}s without a refresh"),
+    // Gamepad y perfiles
+    ("Modo de gamepad detectado: {}", "Gamepad mode detected: {}"),
+    ("Modo de gamepad: {}", "Gamepad mode: {}"),
+    ("ARCADE (8 direcciones)", "ARCADE (8 directions)"),
+    ("XBOX (analógico)", "XBOX (analog)"),
+    ("Evento: {}", "Event: {}"),
+    ("{} derecha", "{} right"),
+    ("{} izquierda", "{} left"),
+    ("{} arriba", "{} up"),
+    ("{} abajo", "{} down"),
+    ("(sin acción)", "(no action)"),
+    ("Jugador asignado", "Player assigned"),
+    ("Jugador liberado", "Player released"),
+    ("cliente={} sin jugador libre", "client={} without a free player"),
+    ("cliente={} jugador={} mode={} buttons={} axes={}", "client={} player={} mode={} buttons={} axes={}"),
+    ("jugador={} buttons={} axes={} teclas={}", "player={} buttons={} axes={} keys={}"),
+    ("{} teclado={}", "{} keyboard={}"),
+    ("Mando soltado por el watchdog", "Gamepad released by the watchdog"),
+    ("jugador {} con botones pulsados más de {}s sin refresco", "player {} had buttons held over {}s without a refresh"),
+    ("🔋 Batería baja", "🔋 Battery low"),
+    ("{} al {}%", "{} at {}%"),
+    ("Calibración iniciada", "Calibration started"),
+    ("Calibración guardada", "Calibration saved"),
+    ("Calibración borrada", "Calibration cleared"),
+    ("Calibración fallida", "Calibration failed"),
+    ("Error en perfiles de cliente", "Client profiles error"),
+    ("Error guardando perfiles", "Error saving profiles"),
+    ("Perfil de mapeo", "Mapping profile"),
+    ("cliente", "client"),
+    ("proceso detectado", "process detected"),
+    ("socket de administración", "admin socket"),
+    ("Perfil ignorado", "Profile ignored"),
+    ("Perfil rechazado", "Profile rejected"),
+    ("Error cambiando perfil", "Error switching profile"),
+    ("perfil desconocido '{}'", "unknown profile '{}'"),
+    ("Script cargado", "Script loaded"),
+    ("Error en script", "Script error"),
+    ("on_snapshot debe devolver el mapa del mando", "on_snapshot must return the gamepad map"),
+    ("Error enviando comando a RetroArch", "Error sending command to RetroArch"),
+    ("no se pudo abrir un socket UDP", "could not open a UDP socket"),
+    ("Módulo WASM cargado", "WASM module loaded"),
+    ("Error en módulo WASM", "WASM module error"),
+    ("no exporta memory", "does not export memory"),
+    // Administración
+    ("Socket de administración", "Admin socket"),
+    ("Error en cliente de administración", "Admin client error"),
+    ("Relé", "Relay"),
+    ("desactivado", "off"),
+    ("Error en el relé", "Relay error"),
+    ("Relé saturado, copia cortada", "Relay backed up, copy cut off"),
+    ("destino de relé desconocido '{}'", "unknown relay target '{}'"),
+    ("Pantallas detectadas", "Screens detected"),
+    ("Pantalla de la pistola", "Lightgun screen"),
+    ("Suspendiendo el equipo", "Suspending the host"),
+    ("Error al suspender", "Suspend failed"),
+    ("Suspensión bloqueada", "Sleep inhibited"),
+    ("Suspensión permitida", "Sleep allowed"),
+    ("Error bloqueando la suspensión", "Could not inhibit sleep"),
+    ("Wake-on-LAN enviado", "Wake-on-LAN sent"),
+    ("{} cliente(s)", "{} client(s)"),
+    ("{}: [power] wake_relay desactivado", "{}: [power] wake_relay is off"),
+    ("Wake-on-LAN rechazado", "Wake-on-LAN refused"),
+    ("Error enviando Wake-on-LAN", "Error sending Wake-on-LAN"),
+    ("Registro de clientes no disponible", "Client registration unavailable"),
+    ("Cliente registrado", "Client registered"),
+    ("Registro caducado", "Registration expired"),
+    ("Error en el punto de encuentro", "Rendezvous error"),
+    ("Punto de encuentro no encontrado", "Rendezvous not found"),
+    ("Registrado en el punto de encuentro", "Registered with the rendezvous"),
+    ("Abriendo camino al cliente", "Punching through to client"),
+    ("Punto de encuentro en {}", "Rendezvous on {}"),
+    ("Co-piloto", "Co-pilot"),
+    ("Observador conectado", "Observer connected"),
+    ("Observador desconectado", "Observer disconnected"),
+    ("Observador rechazado", "Observer rejected"),
+    ("{} (máximo {})", "{} (limit {})"),
+    ("Error en cliente de la API", "API client error"),
+    ("API: petición sin autorización", "API: unauthorized request"),
+    ("Entrada por API", "Input from API"),
+    ("petición HTTP sin terminar", "unfinished HTTP request"),
+    ("petición HTTP incompleta", "incomplete HTTP request"),
+    ("petición HTTP demasiado grande", "HTTP request too large"),
+    ("MQTT conectado", "MQTT connected"),
+    ("MQTT desconectado", "MQTT disconnected"),
+    ("Error conectando a MQTT", "MQTT connection error"),
+    ("Entrada por MQTT", "Input from MQTT"),
+    ("Cliente Remote RetroPad", "Remote RetroPad client"),
+    ("puerto fuera de rango", "port out of range"),
+    ("jugador {} desde {}", "player {} from {}"),
+    ("Synergy conectado", "Synergy connected"),
+    ("Synergy desconectado", "Synergy disconnected"),
+    ("Error conectando a Synergy", "Synergy connection error"),
+    ("{} como '{}'", "{} as '{}'"),
+    ("no es un servidor Synergy/Barrier", "not a Synergy/Barrier server"),
+    ("versión de protocolo incompatible", "incompatible protocol version"),
+    ("ya hay una pantalla '{}' conectada", "a screen '{}' is already connected"),
+    ("el servidor no tiene una pantalla '{}'", "the server has no screen '{}'"),
+    ("el servidor rechazó un mensaje", "the server rejected a message"),
+    ("Cliente DSU", "DSU client"),
+    ("cerrada por el servidor", "closed by the server"),
+    // Separators, matched last: they only split a message so each part is translated
+    ("{}: {}", "{}: {}"),
+    ("{} ({})", "{} ({})"),
+    ("{}, {}", "{}, {}"),
+];
+
+// Most literal text first, so "cliente={} sesiones={}" wins over "cliente={}"
+static CATALOG: LazyLock<Vec<(&str, &str)>> = LazyLock::new(|| {
+    let mut catalog = EN.to_vec();
+    catalog.sort_by_key(|(es, _)| std::cmp::Reverse(es.len() - es.matches("{}").count() * 2));
+    catalog
+});
+
+/// Traduce un mensaje al idioma activo.
+pub fn tr(text: &str) -> Cow<'_, str> {
+    if CURRENT_LOCALE.load(Ordering::Relaxed) == Locale::Es as u8 {
+        return Cow::Borrowed(text);
+    }
+    to_english(text)
+}
+
+fn to_english(text: &str) -> Cow<'_, str> {
+    for (es, en) in CATALOG.iter() {
+        if let Some(values) = match_template(es, text) {
+            let values: Vec<Cow<str>> = values.into_iter().map(to_english).collect();
+            return Cow::Owned(fill_template(en, &values));
+        }
+    }
+    Cow::Borrowed(text)
+}
+
+/// Los valores de cada `{}` si `text` encaja con `template`.
+fn match_template<'a>(template: &str, text: &'a str) -> Option<Vec<&'a str>> {
+    let mut parts = template.split("{}");
+    let mut rest = text.strip_prefix(parts.next()?)?;
+    let parts: Vec<&str> = parts.collect();
+    let mut values = Vec::with_capacity(parts.len());
+    for (i, literal) in parts.iter().enumerate() {
+        let end = if i + 1 == parts.len() {
+            // The last literal anchors at the end ("" takes the rest)
+            rest.strip_suffix(literal)?.len()
+        } else {
+            rest.find(literal).filter(|_| !literal.is_empty())?
+        };
+        values.push(&rest[..end]);
+        rest = &rest[end + literal.len()..];
+    }
+    (rest.is_empty()).then_some(values)
+}
+
+fn fill_template(template: &str, values: &[Cow<str>]) -> String {
+    let mut out = String::with_capacity(template.len() + 16);
+    let mut next = 0;
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let index = match &rest[start + 1..start + len] {
+            "" => {
+                next += 1;
+                next - 1
+            }
+            n => n.parse::<usize>().ok().and_then(|n| n.checked_sub(1)).unwrap_or(usize::MAX),
+        };
+        out.push_str(values.get(index).map_or("", |v| v.as_ref()));
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn template_captures_each_value() {
+        assert_eq!(match_template("{} desde {}", "12 desde 10.0.0.2:5555"), Some(vec!["12", "10.0.0.2:5555"]));
+        assert_eq!(match_template("Cliente {} registrado", "Cliente Mouse registrado"), Some(vec!["Mouse"]));
+        assert_eq!(match_template("cliente={}", "cliente="), Some(vec![""]));
+        assert_eq!(match_template("Configuración recargada", "Configuración recargada"), Some(vec![]));
+    }
+
+    #[test]
+    fn template_must_cover_the_whole_text() {
+        assert_eq!(match_template("Cliente {} registrado", "Cliente Mouse registrado ya"), None);
+        assert_eq!(match_template("Cliente {} registrado", "El cliente Mouse registrado"), None);
+        assert_eq!(match_template("Configuración recargada", "Configuración"), None);
+        // Shorter than the literal only counting bytes of a multi-byte char
+        assert_eq!(match_template("{} abajo", "ñandú"), None);
+    }
+
+    #[test]
+    fn fill_reorders_numbered_values() {
+        let values = [Cow::Borrowed("Mouse"), Cow::Borrowed("5555")];
+        assert_eq!(fill_template("{} in use, using {}", &values), "Mouse in use, using 5555");
+        assert_eq!(fill_template("{2} for {1}", &values), "5555 for Mouse");
+    }
+
+    #[test]
+    fn most_literal_template_wins() {
+        assert_eq!(to_english("cliente=10.0.0.2:5555 sesiones=1/2"), "client=10.0.0.2:5555 sessions=1/2");
+        assert_eq!(to_english("cliente=10.0.0.2:5555"), "client=10.0.0.2:5555");
+    }
+
+    #[test]
+    fn values_are_translated_too() {
+        assert_eq!(to_english("10.0.0.2:5555: etiqueta no válida"), "10.0.0.2:5555: invalid tag");
+        assert_eq!(to_english("Puerto Teclado TCP ocupado"), "Keyboard TCP port busy");
+        assert_eq!(to_english("BTN.A, dpad arriba"), "BTN.A, dpad up");
+        assert_eq!(to_english("texto sin traducción"), "texto sin traducción");
+    }
+
+    // Each Spanish template, filled in as the code would, must come out as
+    // its own translation: no other entry may shadow it.
+    #[test]
+    fn every_template_translates_its_spanish_source() {
+        for (es, en) in EN {
+            let values: Vec<Cow<str>> =
+                (1..=es.matches("{}").count()).map(|n| Cow::Owned(format!("⟨{}⟩", n))).collect();
+            let source = fill_template(es, &values);
+            assert_eq!(to_english(&source), fill_template(en, &values), "plantilla {:?}", es);
+        }
+    }
+
+    #[test]
+    fn spanish_is_left_untouched() {
+        set_locale(Locale::Es);
+        assert_eq!(tr("Configuración recargada"), "Configuración recargada");
+    }
+}
@@ -1,4 +1,11 @@
+use crate::locale::tr;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::panic::Location;
+use std::path::Path;
 use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::RwLock;
 
 #[derive(Clone, Copy, PartialEq, PartialOrd)]
 pub enum Verbosity {
@@ -18,50 +25,320 @@ impl Verbosity {
     }
 }
 
+/// Subsistemas que admiten un nivel propio. Cada mensaje pertenece al del
+/// archivo que lo emite: `servers/gamepad_server.rs` es `gamepad`,
+/// cualquier archivo de `devices/` es `devices` y el resto, su nombre.
+pub const SUBSYSTEMS: &[&str] = &[
+    "mouse",
+    "keyboard",
+    "gamepad",
+    "discovery",
+    "network",
+    "session",
+    "admin",
+    "devices",
+    "monitor",
+    "config",
+    "serve",
+    "capture",
+    "quarantine",
+    "battery",
+    "profiles",
+    "client_profile",
+    "identity",
+    "slots",
+    "privileges",
+    "scheduling",
+    "systemd",
+    "process_watch",
+    "scripting",
+    "plugins",
+    "relay",
+    "copilot",
+    "observer",
+    "api",
+    "mqtt",
+    "remote_retropad",
+    "synergy",
+    "dsu",
+    "rumble",
+    "screens",
+    "power",
+    "rendezvous",
+    "arbitration",
+    "quic",
+];
+
 static CURRENT_VERBOSITY: AtomicU8 = AtomicU8::new(0);
+// Highest level any subsystem prints, so disabled messages return early
+static MAX_VERBOSITY: AtomicU8 = AtomicU8::new(0);
+static SUBSYSTEM_VERBOSITY: RwLock<Vec<(String, u8)>> = RwLock::new(Vec::new());
 static EVENT_COUNTER: AtomicU64 = AtomicU64::new(0);
 
-pub fn set_verbosity(level: Verbosity) {
+/// Nivel general y niveles por subsistema (que tienen prioridad).
+pub fn set_verbosity(level: Verbosity, subsystems: &BTreeMap<String, u8>) {
+    let mut overrides = SUBSYSTEM_VERBOSITY.write().unwrap();
+    *overrides = subsystems.iter().map(|(name, level)| (name.clone(), *level)).collect();
+    let max = overrides.iter().map(|(_, level)| *level).fold(level as u8, u8::max);
     CURRENT_VERBOSITY.store(level as u8, Ordering::SeqCst);
+    MAX_VERBOSITY.store(max, Ordering::SeqCst);
 }
 
-pub fn log(level: Verbosity, message: &str) {
-    if level <= Verbosity::from_u8(CURRENT_VERBOSITY.load(Ordering::SeqCst)) {
-        match level {
-            Verbosity::Low => println!("ℹ️  {}", message),
-            Verbosity::Medium => println!("🔍 {}", message),
-            Verbosity::High => println!("🐛 {}", message),
+pub fn check_subsystem(name: &str) -> Result<(), String> {
+    if SUBSYSTEMS.contains(&name) {
+        Ok(())
+    } else {
+        Err(format!("subsistema de log desconocido: {} (válidos: {})", name, SUBSYSTEMS.join(", ")))
+    }
+}
+
+fn subsystem_of(file: &str) -> &str {
+    let path = Path::new(file);
+    if path.parent().and_then(Path::file_name).is_some_and(|dir| dir == "devices") {
+        return "devices";
+    }
+    let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("");
+    stem.strip_suffix("_server").unwrap_or(stem)
+}
+
+/// Si un mensaje de este nivel se imprimiría desde el archivo que llama.
+/// Las rutas calientes lo consultan (o usan `log_lazy`) para no formatear
+/// nada que se va a tirar.
+#[track_caller]
+pub fn enabled(level: Verbosity) -> bool {
+    let level = level as u8;
+    if level > MAX_VERBOSITY.load(Ordering::Relaxed) {
+        return false;
+    }
+    let overrides = SUBSYSTEM_VERBOSITY.read().unwrap();
+    if !overrides.is_empty() {
+        let subsystem = subsystem_of(Location::caller().file());
+        if let Some((_, max)) = overrides.iter().find(|(name, _)| name == subsystem) {
+            return level <= *max;
+        }
+    }
+    level <= CURRENT_VERBOSITY.load(Ordering::Relaxed)
+}
+
+/// `--verbosity`: un nivel (`2`), niveles por subsistema
+/// (`gamepad=2,discovery=0`) o ambos (`0,gamepad=2`).
+#[derive(Debug, Clone, Default)]
+pub struct VerbositySpec {
+    pub level: Option<u8>,
+    pub subsystems: BTreeMap<String, u8>,
+}
+
+impl VerbositySpec {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        let level = |value: &str| value.trim().parse::<u8>().map_err(|_| format!("nivel de log inválido: {}", value));
+        for part in spec.split(',').filter(|part| !part.trim().is_empty()) {
+            match part.split_once('=') {
+                Some((name, value)) => {
+                    let name = name.trim();
+                    check_subsystem(name)?;
+                    parsed.subsystems.insert(name.to_string(), level(value)?);
+                }
+                None => parsed.level = Some(level(part)?),
+            }
         }
+        Ok(parsed)
     }
 }
 
+#[track_caller]
+pub fn log(level: Verbosity, message: &str) {
+    if enabled(level) {
+        emit(level, &tr(message), None);
+    }
+}
+
+/// Como `log`, pero el mensaje sólo se construye si el nivel está activo.
+#[track_caller]
+pub fn log_lazy(level: Verbosity, message: impl FnOnce() -> String) {
+    if enabled(level) {
+        log(level, &message());
+    }
+}
+
+#[track_caller]
 pub fn log_data(level: Verbosity, title: &str, data: &[u8]) {
-    if level <= Verbosity::from_u8(CURRENT_VERBOSITY.load(Ordering::SeqCst)) {
-        let hex = data.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ");
-        println!("🐛 {}", title);
-        println!("  └─ {}", hex);
-        println!();
+    if enabled(level) {
+        let mut hex = String::with_capacity(data.len() * 3);
+        for (i, b) in data.iter().enumerate() {
+            let separator = if i == 0 { "" } else { " " };
+            let _ = write!(hex, "{}{:02X}", separator, b);
+        }
+        emit(level, &tr(title), Some(&hex));
     }
 }
 
+#[track_caller]
 pub fn log_detail(level: Verbosity, title: &str, detail: &str) {
-    if level <= Verbosity::from_u8(CURRENT_VERBOSITY.load(Ordering::SeqCst)) {
+    if enabled(level) {
+        emit(level, &tr(title), Some(&tr(detail)));
+    }
+}
+
+/// Ignora la verbosidad: son las líneas que pide `--trace-client`.
+#[track_caller]
+pub fn log_trace(title: &str, detail: &str) {
+    emit(Verbosity::Low, &tr(title), Some(&tr(detail)));
+}
+
+/// Bloque numerado con una línea por campo; `lines` sólo se llama si el
+/// nivel está activo.
+#[track_caller]
+pub fn log_block(level: Verbosity, title: &str, lines: impl FnOnce() -> Vec<String>) {
+    if !enabled(level) {
+        return;
+    }
+    let event_num = EVENT_COUNTER.fetch_add(1, Ordering::SeqCst);
+    let title = tr(title);
+    let lines = lines();
+    match log_format() {
+        LogFormat::Pretty => {
+            println!("╭── Event #{}  [{}]", event_num, title);
+            for line in lines {
+                println!("│   {}", line);
+            }
+            println!("╰──────────────────────────────────");
+            println!();
+        }
+        LogFormat::Plain | LogFormat::Json => {
+            emit(level, &format!("{} #{}", title, event_num), Some(&lines.join(" ")));
+        }
+    }
+}
+
+/// Formato de salida (`--log-format` o `[log] format`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Emojis, bloques y líneas en blanco, para la consola
+    #[default]
+    Pretty,
+    /// Una línea por mensaje con su nivel, para journald
+    Plain,
+    /// Un objeto JSON por línea, para recolectores de logs
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "pretty" => Ok(Self::Pretty),
+            "plain" => Ok(Self::Plain),
+            "json" => Ok(Self::Json),
+            other => Err(format!("--log-format acepta plain, pretty o json, no '{}'", other)),
+        }
+    }
+}
+
+static LOG_FORMAT: AtomicU8 = AtomicU8::new(LogFormat::Pretty as u8);
+
+pub fn set_log_format(format: LogFormat) {
+    LOG_FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+fn log_format() -> LogFormat {
+    match LOG_FORMAT.load(Ordering::Relaxed) {
+        1 => LogFormat::Plain,
+        2 => LogFormat::Json,
+        _ => LogFormat::Pretty,
+    }
+}
+
+#[track_caller]
+fn emit(level: Verbosity, title: &str, detail: Option<&str>) {
+    let format = log_format();
+    if format == LogFormat::Pretty {
         match level {
             Verbosity::Low => println!("ℹ️  {}", title),
             Verbosity::Medium => println!("🔍 {}", title),
             Verbosity::High => println!("🐛 {}", title),
         }
-        println!("  └─ {}", detail);
-        println!();
+        if let Some(detail) = detail {
+            println!("  └─ {}", detail);
+            println!();
+        }
+        return;
     }
+    let label = match level {
+        Verbosity::Low => "info",
+        Verbosity::Medium => "debug",
+        Verbosity::High => "trace",
+    };
+    // Decorations are for people: leading emoji, bullets and blank lines go
+    let title = title.trim_start_matches(|c: char| !c.is_alphanumeric() && c != '[').trim_end();
+    let subsystem = subsystem_of(Location::caller().file());
+    if format == LogFormat::Plain {
+        match detail {
+            Some(detail) => println!("{} {}: {}: {}", label.to_uppercase(), subsystem, title, detail),
+            None => println!("{} {}: {}", label.to_uppercase(), subsystem, title),
+        }
+        return;
+    }
+    let mut line = format!(
+        "{{\"level\":\"{}\",\"subsystem\":\"{}\",\"message\":\"{}\"",
+        label,
+        subsystem,
+        json_escape(title)
+    );
+    if let Some(detail) = detail {
+        let _ = write!(line, ",\"detail\":\"{}\"", json_escape(detail));
+    }
+    line.push('}');
+    println!("{}", line);
 }
 
-pub fn log_block(title: &str, lines: Vec<String>) {
-    let event_num = EVENT_COUNTER.fetch_add(1, Ordering::SeqCst);
-    println!("╭── Event #{}  [{}]", event_num, title);
-    for line in lines {
-        println!("│   {}", line);
+pub fn json_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbosity_level_alone() {
+        let spec = VerbositySpec::parse("2").unwrap();
+        assert_eq!(spec.level, Some(2));
+        assert!(spec.subsystems.is_empty());
+    }
+
+    #[test]
+    fn verbosity_per_subsystem() {
+        let spec = VerbositySpec::parse("0, gamepad=2,discovery = 1,").unwrap();
+        assert_eq!(spec.level, Some(0));
+        assert_eq!(spec.subsystems.get("gamepad"), Some(&2));
+        assert_eq!(spec.subsystems.get("discovery"), Some(&1));
+        assert_eq!(spec.subsystems.len(), 2);
+    }
+
+    #[test]
+    fn verbosity_rejects_unknown_subsystems_and_levels() {
+        assert!(VerbositySpec::parse("joystick=2").is_err());
+        assert!(VerbositySpec::parse("gamepad=alto").is_err());
+        assert!(VerbositySpec::parse("-1").is_err());
+    }
+
+    #[test]
+    fn empty_verbosity_changes_nothing() {
+        let spec = VerbositySpec::parse("").unwrap();
+        assert_eq!(spec.level, None);
+        assert!(spec.subsystems.is_empty());
     }
-    println!("╰──────────────────────────────────");
-    println!();
-}
\ No newline at end of file
+}
@@ -1,91 +1,112 @@
+mod admin;
+mod arbitration;
+mod api;
+mod battery;
+mod bus;
+mod capture;
+mod client_profile;
+mod config;
+mod copilot;
 mod devices;
 mod discovery;
+mod doctor;
+mod dsu;
+mod filters;
+mod http;
+mod haptics;
+mod identity;
+mod serve;
 mod servers;
+mod session;
+mod share;
+mod slots;
+mod stats;
+mod stress;
+mod synergy;
 mod input_mode;
+mod keyboard_layout;
+mod locale;
 mod logger;
+mod monitor;
+mod mqtt;
+mod network;
+mod observer;
+mod overlay;
+mod packet_auth;
+mod plugins;
+mod power;
+mod process_watch;
+mod profiles;
+mod privileges;
 mod protocol;
+mod quarantine;
+mod quic;
+mod rate_limit;
+mod relay;
+mod remote_retropad;
+mod rendezvous;
+mod replay;
+mod rumble;
+mod scheduling;
+mod screens;
+mod scripting;
+mod self_test;
+mod systemd;
+mod trace;
 
-use devices::{create_virtual_keyboard, create_virtual_mouse};
-use discovery::run_discovery_broadcast;
-use devices::xbox360::create_virtual_gamepad;
-use servers::gamepad_server::run_udp_gamepad_server;
-use input_mode::InputMode;
-use servers::keyboard_server::run_tcp_keyboard_server;
-use logger::{log, set_verbosity, Verbosity};
-use servers::mouse_server::run_udp_mouse_server;
-use std::sync::atomic::AtomicUsize;
-use std::sync::{Arc, Mutex};
-use tokio::sync::RwLock;
+const USAGE: &str = "\
+Uso: retro-control-server [subcomando] [opciones]
 
-const UDP_PORT: u16 = 5555;
-const TCP_PORT: u16 = 5556;
-const GAMEPAD_UDP_PORT: u16 = 5558;
+Subcomandos:
+  serve     servidor de control remoto (por defecto)
+            --config <ruta>  --verbosity <0-2>[,subsistema=<0-2>...]  --dry-run
+            --capture <fichero>  --log-format <pretty|plain|json>
+            --trace-client <ip>
+  monitor   como serve, imprimiendo además los eventos que entrega el kernel
+  record    graba lo que envían los clientes, sin dispositivos (serve --capture --dry-run)
+            <captura> [opciones de serve]
+  doctor    comprueba que uinput esté disponible
+            --install [--user <nombre>]  --udev-rule
+  replay    reenvía una captura a un servidor con el ritmo original
+            <captura> --to <host>  --loss <%>  --delay <ms>  --jitter <ms>
+            --speed <factor>  --seed <n>  --mouse-port/--keyboard-port/--gamepad-port <puerto>
+  stress    inunda un servidor con paquetes aleatorios y mide pérdidas y latencia
+            --to <host>  --channel <mouse|gamepad|all>  --clients <n>  --rate <pps>
+            --duration <s>  --admin <socket>  --seed <n>
+  share     envía mandos, ratones y teclados físicos de esta máquina a otro servidor
+            --to <host>  --device <ruta>...  --grab
+            --mouse-port/--keyboard-port/--gamepad-port <puerto>
+  rendezvous punto de encuentro para conectar móviles a servidores tras un NAT
+            --listen <dirección:puerto>
+  test      reproduce un patrón en dispositivos virtuales, sin red
+            --only <gamepad|mouse|keyboard>
+  help      muestra esta ayuda";
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = std::env::args().collect();
-    let verbosity = if args.len() > 1 && args[1] == "--verbosity" && args.len() > 2 {
-        args[2].parse::<u8>().unwrap_or(0)
-    } else {
-        0
-    };
-    set_verbosity(Verbosity::from_u8(verbosity));
-
-    log(Verbosity::Low, "🚀 Iniciando Retro Control Server...");
-
-    let mouse = Arc::new(Mutex::new(create_virtual_mouse()?));
-    let keyboard = Arc::new(Mutex::new(create_virtual_keyboard()?));
-    let gamepad = Arc::new(Mutex::new(create_virtual_gamepad()?));
-    let input_mode = Arc::new(RwLock::new(InputMode::MouseKeyboard));
-
-    println!("✓ Dispositivos virtuales creados");
-
-    let connected_clients = Arc::new(AtomicUsize::new(0));
-    let mouse_clone = mouse.clone();
-    tokio::spawn(async move {
-        if let Err(e) = run_udp_mouse_server(UDP_PORT, mouse_clone).await {
-            log(Verbosity::Low, &format!("Error en servidor UDP Mouse: {}", e));
-        }
-    });
-
-    let keyboard_clone = keyboard.clone();
-    let mode_clone = input_mode.clone();
-    let tcp_clients_clone = connected_clients.clone();
-    tokio::spawn(async move {
-        if let Err(e) = run_tcp_keyboard_server(
-            TCP_PORT,
-            keyboard_clone,
-            mode_clone,
-            tcp_clients_clone,
-        )
-        .await
-        {
-            log(Verbosity::Low, &format!("Error en servidor TCP Teclado: {}", e));
+    let argv: Vec<String> = std::env::args().skip(1).collect();
+    let result = match argv.first().map(String::as_str) {
+        // Without a subcommand (or with bare options) it serves, as it always did
+        None => serve::run_serve(&[], false).await,
+        Some(arg) if arg.starts_with("--") && arg != "--help" => serve::run_serve(&argv, false).await,
+        Some("serve") => serve::run_serve(&argv[1..], false).await,
+        Some("monitor") => serve::run_serve(&argv[1..], true).await,
+        Some("record") => serve::run_record(&argv[1..]).await,
+        Some("doctor") => doctor::run_doctor(&argv[1..]),
+        Some("test") => self_test::run_self_test(&argv[1..]).await,
+        Some("replay") => replay::run_replay(&argv[1..]).await,
+        Some("stress") => stress::run_stress(&argv[1..]).await,
+        Some("share") => share::run_share(&argv[1..]).await,
+        Some("rendezvous") => rendezvous::run_rendezvous_server(&argv[1..]).await,
+        Some("help" | "--help" | "-h") => {
+            println!("{}", USAGE);
+            Ok(())
         }
-    });
-
-    let gamepad_clone = gamepad.clone();
-    tokio::spawn(async move {
-        if let Err(e) = run_udp_gamepad_server(GAMEPAD_UDP_PORT, gamepad_clone).await {
-            log(Verbosity::Low, &format!("Error en servidor UDP Gamepad: {}", e));
-        }
-    });
-
-    let discovery_clients = connected_clients.clone();
-    tokio::spawn(async move {
-        if let Err(e) = run_discovery_broadcast(TCP_PORT, UDP_PORT, discovery_clients).await {
-            log(Verbosity::Low, &format!("Error en broadcast de descubrimiento: {}", e));
+        Some(other) => {
+            eprintln!("{}", USAGE);
+            Err(format!("subcomando desconocido: {}", other).into())
         }
-    });
-
-    log(Verbosity::Low, "✓ Servidores de red iniciados");
-    log(Verbosity::Low, &format!("   - Mouse UDP: 0.0.0.0:{}", UDP_PORT));
-    log(Verbosity::Low, &format!("   - Teclado TCP: 0.0.0.0:{}", TCP_PORT));
-    log(Verbosity::Low, &format!("   - Gamepad UDP: 0.0.0.0:{}", GAMEPAD_UDP_PORT));
-    log(Verbosity::Low, "Esperando conexiones...");
-
-    tokio::signal::ctrl_c().await?;
-    log(Verbosity::Low, "\nApagando Retro Control Server...");
-
-    Ok(())
-}
\ No newline at end of file
+    };
+    // Once serve has read [log] locale, its errors follow it too
+    result.map_err(|e| locale::tr(&e.to_string()).into_owned().into())
+}
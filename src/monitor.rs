@@ -0,0 +1,71 @@
+use crate::devices::lazy::SharedDevice;
+use crate::logger::{log_detail, Verbosity};
+use evdev::{Device, EventType};
+use std::path::PathBuf;
+use std::time::Duration;
+
+// devtmpfs creates the node right away, but its permissions may still be
+// changing under udev
+const OPEN_ATTEMPTS: u32 = 20;
+const OPEN_RETRY: Duration = Duration::from_millis(100);
+
+/// Subcomando `monitor`: vuelve a abrir cada dispositivo virtual por su nodo
+/// `/dev/input/eventN`, como lo haría un emulador, e imprime lo que entrega
+/// el kernel junto a los paquetes recibidos.
+pub fn spawn_kernel_monitor(device: SharedDevice) {
+    tokio::spawn(async move {
+        loop {
+            // Devices appear with their first event and may be torn down later
+            device.created().await;
+            let nodes = device.if_created(|dev| -> std::io::Result<Vec<PathBuf>> {
+                dev.enumerate_dev_nodes_blocking()?.collect()
+            });
+            match nodes {
+                Some(Ok(nodes)) => {
+                    for node in nodes {
+                        let name = device.name().to_string();
+                        tokio::task::spawn_blocking(move || read_node(name, node));
+                    }
+                }
+                Some(Err(e)) => log_detail(Verbosity::Low, "Error buscando nodos del dispositivo", &e.to_string()),
+                None => continue,
+            }
+            device.destroyed().await;
+        }
+    });
+}
+
+// Blocking reads on purpose: one thread per node, ending when the node goes away
+fn read_node(name: String, node: PathBuf) {
+    let mut attempts = 0;
+    let mut input = loop {
+        match Device::open(&node) {
+            Ok(input) => break input,
+            Err(_) if attempts < OPEN_ATTEMPTS => {
+                attempts += 1;
+                std::thread::sleep(OPEN_RETRY);
+            }
+            Err(e) => {
+                log_detail(Verbosity::Low, "Error abriendo nodo", &format!("{}: {}", node.display(), e));
+                return;
+            }
+        }
+    };
+    log_detail(Verbosity::Low, "Monitorizando", &format!("{} en {}", name, node.display()));
+
+    let title = format!("[kernel] {}", name);
+    loop {
+        let events = match input.fetch_events() {
+            Ok(events) => events,
+            // ENODEV once the virtual device is destroyed
+            Err(_) => return,
+        };
+        let described: Vec<String> = events
+            .filter(|e| e.event_type() != EventType::SYNCHRONIZATION)
+            .map(|e| format!("{:?}={}", e.kind(), e.value()))
+            .collect();
+        if !described.is_empty() {
+            log_detail(Verbosity::Low, &title, &described.join(" "));
+        }
+    }
+}
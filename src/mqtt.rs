@@ -0,0 +1,244 @@
+use crate::admin::handle_command;
+use crate::api::{inject, INPUTS};
+use crate::battery::Batteries;
+use crate::bus::SharedBus;
+use crate::config::{ConfigHandle, MqttConfig};
+use crate::http::query_param;
+use crate::input_mode::InputMode;
+use crate::logger::{json_escape, log_detail, Verbosity};
+use crate::profiles::{ProfileManager, DEFAULT_PROFILE};
+use crate::stats::STATS;
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Packet, Publish, QoS};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::{watch, Semaphore};
+use tokio::time::{interval, sleep, Duration};
+
+const DEFAULT_PORT: u16 = 1883;
+// Requests waiting for the event loop; publishes use try_publish so a full queue drops them
+const REQUEST_QUEUE: usize = 32;
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+// The state is checked this often and published only when it changed
+const STATE_INTERVAL: Duration = Duration::from_secs(1);
+const RETRY: Duration = Duration::from_secs(5);
+// Inputs held at once (each may hold its keys for seconds); more are refused
+const MAX_INPUTS_IN_FLIGHT: usize = 8;
+
+static INPUTS_IN_FLIGHT: Semaphore = Semaphore::const_new(MAX_INPUTS_IN_FLIGHT);
+
+/// Cliente MQTT (`[mqtt] broker`), pensado para Home Assistant. Con
+/// `topic = "retro-control"`:
+///
+/// - `retro-control/status`: `online`/`offline` (retenido, con last will).
+/// - `retro-control/state`: `{"clients":1,"mode":"gamepad","profile":"default"}`
+///   (retenido), publicado al cambiar.
+/// - `retro-control/command`: los comandos de consulta del socket de
+///   administración permitidos en `[mqtt] commands`; la respuesta sale en
+///   `retro-control/reply`.
+/// - `retro-control/input/<key|gamepad|text>`: con `[mqtt] input`, inyecta
+///   entrada como la API REST; ver `api::inject`.
+///
+/// Los mensajes no llevan autenticación ni van cifrados (sin TLS): las ACL
+/// del broker son la única protección de esos topics.
+pub async fn run_mqtt(
+    bus: SharedBus,
+    config: ConfigHandle,
+    profiles: ProfileManager,
+    batteries: Batteries,
+    input_mode: watch::Receiver<InputMode>,
+    clients: Arc<AtomicUsize>,
+) {
+    let settings = config.get().mqtt.clone();
+    let Some(broker) = settings.broker.clone() else {
+        return;
+    };
+    let (host, port) = match broker.rsplit_once(':').map(|(host, port)| (host, port.parse())) {
+        Some((host, Ok(port))) => (host.to_string(), port),
+        _ => (broker.clone(), DEFAULT_PORT),
+    };
+    let topic = settings.topic.trim_end_matches('/').to_string();
+    let mut options = MqttOptions::new(settings.client_id.clone(), host, port);
+    options.set_keep_alive(KEEP_ALIVE);
+    options.set_last_will(LastWill::new(format!("{}/status", topic), "offline", QoS::AtLeastOnce, true));
+    if let Some(username) = settings.username.clone() {
+        options.set_credentials(username, settings.password.clone().unwrap_or_default());
+    }
+    let (client, mut events) = AsyncClient::new(options, REQUEST_QUEUE);
+
+    let mut ticker = interval(STATE_INTERVAL);
+    let mut published = String::new();
+    let mut connected = false;
+    loop {
+        tokio::select! {
+            event = events.poll() => match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    connected = true;
+                    log_detail(Verbosity::Low, "MQTT conectado", &broker);
+                    if !settings.commands.is_empty() {
+                        let _ = client.try_subscribe(format!("{}/command", topic), QoS::AtLeastOnce);
+                    }
+                    if settings.input {
+                        let _ = client.try_subscribe(format!("{}/input/+", topic), QoS::AtLeastOnce);
+                    }
+                    let _ = client.try_publish(format!("{}/status", topic), QoS::AtLeastOnce, true, "online");
+                    // Republish on the next tick; the broker may have lost it
+                    published.clear();
+                }
+                Ok(Event::Incoming(Packet::Publish(message))) => {
+                    handle_message(message, &topic, &client, &bus, &config, &profiles, &batteries);
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    if std::mem::take(&mut connected) {
+                        log_detail(Verbosity::Low, "MQTT desconectado", &e.to_string());
+                    } else {
+                        log_detail(Verbosity::Medium, "Error conectando a MQTT", &format!("{}: {}", broker, e));
+                    }
+                    // The next poll() reconnects
+                    sleep(RETRY).await;
+                }
+            },
+            _ = ticker.tick() => {
+                let state = render_state(&input_mode, &clients, &profiles);
+                if connected
+                    && state != published
+                    && client.try_publish(format!("{}/state", topic), QoS::AtLeastOnce, true, state.clone()).is_ok()
+                {
+                    published = state;
+                }
+            }
+        }
+    }
+}
+
+fn render_state(input_mode: &watch::Receiver<InputMode>, clients: &AtomicUsize, profiles: &ProfileManager) -> String {
+    let mode = match *input_mode.borrow() {
+        InputMode::Gamepad => "gamepad",
+        InputMode::MouseKeyboard => "mouse_keyboard",
+    };
+    let profile = profiles.active().unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+    format!(
+        "{{\"clients\":{},\"mode\":\"{}\",\"profile\":\"{}\"}}",
+        clients.load(Ordering::SeqCst),
+        mode,
+        json_escape(&profile)
+    )
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Route<'a> {
+    /// Comando de consulta permitido en `[mqtt] commands`.
+    Command,
+    /// Comando que no está en la lista: se responde con un error.
+    Refused,
+    /// `<topic>/input/<tipo>` con `[mqtt] input`.
+    Input(&'a str),
+}
+
+/// Qué hacer con un mensaje; `None` = ignorarlo. Los topics no habilitados
+/// se ignoran aunque el broker los entregue (otra suscripción, un ACL laxo).
+fn route<'a>(prefix: &str, topic: &'a str, payload: &str, settings: &MqttConfig) -> Option<Route<'a>> {
+    let subtopic = topic.strip_prefix(prefix)?;
+    if subtopic == "/command" {
+        if settings.commands.is_empty() {
+            return None;
+        }
+        // Only queries: anyone who can publish to the broker reaches this
+        let allowed = settings.commands.iter().any(|command| command == payload);
+        return Some(if allowed { Route::Command } else { Route::Refused });
+    }
+    let input = subtopic.strip_prefix("/input/").filter(|input| INPUTS.contains(input))?;
+    settings.input.then_some(Route::Input(input))
+}
+
+fn handle_message(
+    message: Publish,
+    topic: &str,
+    client: &AsyncClient,
+    bus: &SharedBus,
+    config: &ConfigHandle,
+    profiles: &ProfileManager,
+    batteries: &Batteries,
+) {
+    let payload = String::from_utf8_lossy(&message.payload).trim().to_string();
+    let reply_topic = format!("{}/reply", topic);
+    let input = match route(topic, &message.topic, &payload, &config.get().mqtt) {
+        Some(Route::Command) => {
+            let reply = handle_command(&payload, config, profiles, batteries);
+            let _ = client.try_publish(reply_topic, QoS::AtLeastOnce, false, reply);
+            return;
+        }
+        Some(Route::Refused) => {
+            let reply = format!("error: '{}' no está en [mqtt] commands", payload);
+            let _ = client.try_publish(reply_topic, QoS::AtLeastOnce, false, reply);
+            return;
+        }
+        Some(Route::Input(input)) => input.to_string(),
+        None => return,
+    };
+    // Only the kind: a text payload may be a password
+    log_detail(Verbosity::Medium, "Entrada por MQTT", &input);
+    let Ok(permit) = INPUTS_IN_FLIGHT.try_acquire() else {
+        STATS.mqtt.record_dropped(1);
+        let _ = client.try_publish(reply_topic, QoS::AtLeastOnce, false, "error: demasiadas entradas en curso");
+        return;
+    };
+    let (bus, config, client) = (bus.clone(), config.get(), client.clone());
+    // Holding a key must not stall the event loop
+    tokio::spawn(async move {
+        let _permit = permit;
+        // A bare payload is the main parameter: "KEY_ENTER", "start" or the text itself
+        let main = match input.as_str() {
+            "key" => "code",
+            "gamepad" => "buttons",
+            _ => "text",
+        };
+        let param = |name: &str| {
+            if payload.contains('=') && input != "text" {
+                query_param(&payload, name)
+            } else {
+                (name == main).then(|| payload.clone())
+            }
+        };
+        if let Err(e) = inject(&input, &param, payload.as_bytes(), &bus, &config).await {
+            let _ = client.try_publish(reply_topic, QoS::AtLeastOnce, false, format!("error: {}", e));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(commands: &[&str], input: bool) -> MqttConfig {
+        MqttConfig { commands: commands.iter().map(|c| c.to_string()).collect(), input, ..MqttConfig::default() }
+    }
+
+    #[test]
+    fn commands_are_off_by_default() {
+        assert_eq!(route("rc", "rc/command", "stats", &MqttConfig::default()), None);
+    }
+
+    #[test]
+    fn only_listed_commands_run() {
+        let settings = settings(&["stats", "ping"], false);
+        assert_eq!(route("rc", "rc/command", "stats", &settings), Some(Route::Command));
+        assert_eq!(route("rc", "rc/command", "unban all", &settings), Some(Route::Refused));
+    }
+
+    #[test]
+    fn input_needs_opt_in() {
+        assert_eq!(route("rc", "rc/input/key", "KEY_ENTER", &MqttConfig::default()), None);
+        let settings = settings(&[], true);
+        assert_eq!(route("rc", "rc/input/key", "KEY_ENTER", &settings), Some(Route::Input("key")));
+        assert_eq!(route("rc", "rc/input/mouse", "", &settings), None);
+    }
+
+    #[test]
+    fn foreign_topics_are_ignored() {
+        let settings = settings(&["stats"], true);
+        assert_eq!(route("rc", "other/command", "stats", &settings), None);
+        assert_eq!(route("rc", "rc/state", "", &settings), None);
+        assert_eq!(route("rc", "rc/commands", "stats", &settings), None);
+    }
+}
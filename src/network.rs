@@ -0,0 +1,637 @@
+use crate::logger::{log_detail, Verbosity};
+use crate::quic::{self, QuicStream};
+use nix::ifaddrs::getifaddrs;
+use nix::net::if_::InterfaceFlags;
+use std::io::ErrorKind;
+use crate::config::ConfigHandle;
+use crate::protocol::MAX_DATAGRAM;
+use crate::stats::ChannelStats;
+use nix::sys::socket::{
+    bind, getsockopt, recvmmsg, setsockopt, socket, sockopt, AddressFamily, MsgFlags, MultiHeaders, SockFlag,
+    SockType, SockaddrStorage,
+};
+use std::io::IoSliceMut;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::os::fd::{AsFd, AsRawFd, RawFd};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, Interest, ReadBuf};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+
+// Datagrams drained per recvmmsg call
+const BATCH: usize = 32;
+// Datagrams queued between the SO_REUSEPORT receivers and the server
+const RECEIVER_QUEUE: usize = 1024;
+// QUIC streams waiting for the keyboard server to accept them
+const STREAM_QUEUE: usize = 16;
+
+/// Dirección IPv4 de una interfaz de red.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceV4 {
+    pub name: String,
+    pub address: Ipv4Addr,
+    pub broadcast: Option<Ipv4Addr>,
+}
+
+/// Interfaces activas con dirección IPv4 (incluye loopback).
+pub fn ipv4_interfaces() -> Vec<InterfaceV4> {
+    let Ok(addrs) = getifaddrs() else {
+        return Vec::new();
+    };
+    addrs
+        .filter(|ifa| ifa.flags.contains(InterfaceFlags::IFF_UP))
+        .filter_map(|ifa| {
+            let address = ifa.address.as_ref()?.as_sockaddr_in()?.ip();
+            let broadcast = ifa
+                .broadcast
+                .as_ref()
+                .and_then(|b| b.as_sockaddr_in())
+                .map(|b| b.ip());
+            Some(InterfaceV4 {
+                name: ifa.interface_name,
+                address,
+                broadcast,
+            })
+        })
+        .collect()
+}
+
+pub fn find_interface(name: &str) -> Option<InterfaceV4> {
+    ipv4_interfaces().into_iter().find(|i| i.name == name)
+}
+
+/// MAC de una interfaz, para que los clientes puedan despertar el equipo
+/// con Wake-on-LAN. `None` sin MAC (loopback, túneles).
+pub fn mac_address(interface: &str) -> Option<[u8; 6]> {
+    let text = std::fs::read_to_string(format!("/sys/class/net/{}/address", interface)).ok()?;
+    let mut mac = [0u8; 6];
+    let mut parts = text.trim().split(':');
+    for byte in &mut mac {
+        *byte = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    (parts.next().is_none() && mac != [0; 6]).then_some(mac)
+}
+
+/// Acepta una IP (`0.0.0.0`, `192.168.1.10`) o un nombre de interfaz (`eth0`).
+pub fn resolve_bind_address(spec: &str) -> std::io::Result<IpAddr> {
+    if let Ok(ip) = spec.parse::<IpAddr>() {
+        return Ok(ip);
+    }
+    find_interface(spec)
+        .map(|i| IpAddr::V4(i.address))
+        .ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("'{}' no es una IP ni una interfaz con IPv4", spec),
+            )
+        })
+}
+
+/// Ajusta SO_RCVBUF para absorber ráfagas (0 = valor del kernel). Como root
+/// usa SO_RCVBUFFORCE, que no está limitado por `net.core.rmem_max`.
+pub fn set_recv_buffer(name: &str, socket: &UdpSocket, bytes: usize) -> std::io::Result<()> {
+    if bytes == 0 {
+        return Ok(());
+    }
+    let fd = socket.as_fd();
+    if setsockopt(&fd, sockopt::RcvBufForce, &bytes).is_err() {
+        setsockopt(&fd, sockopt::RcvBuf, &bytes)?;
+    }
+    // The kernel reports twice the requested size (bookkeeping overhead)
+    let effective = getsockopt(&fd, sockopt::RcvBuf)?;
+    log_detail(
+        Verbosity::Medium,
+        &format!("Buffer de recepción {}", name),
+        &format!("pedido {} bytes, el kernel reserva {}", bytes, effective),
+    );
+    if effective < bytes {
+        log_detail(
+            Verbosity::Low,
+            &format!("Buffer de recepción {} recortado", name),
+            &format!("{} bytes; sube net.core.rmem_max o arranca como root", effective),
+        );
+    }
+    Ok(())
+}
+
+/// Decide si un datagrama atrasado sobra porque el siguiente del mismo
+/// cliente, que puede modificar, ya lo contiene.
+pub type Coalesce = fn(&[u8], &mut [u8]) -> bool;
+
+/// Recepción por lotes con `recvmmsg`: una ráfaga de paquetes se vacía con
+/// una sola llamada al sistema y se entrega de uno en uno. En una Pi Zero con
+/// el ratón a 250 Hz el coste por syscall era lo que dominaba la CPU.
+///
+/// Con `busy_poll` distinto de cero, antes de dormir en epoll reintenta la
+/// lectura en bucle durante ese tiempo: ahorra la latencia de despertar al
+/// hilo a cambio de un núcleo ocupado. Los aciertos, fallos y el tiempo
+/// gastado girando se ven en `stats`.
+///
+/// Si un lote trae atraso, `coalesce` junta cada datagrama con el siguiente
+/// del mismo cliente cuando sólo difieren en movimiento (ejes, puntero), así
+/// una ráfaga de ruido del stick no retrasa la pulsación que viene detrás.
+/// Con `[security] packet_key` no junta nada: todavía no se comprobó la
+/// firma, y un datagrama falsificado podría ocupar el lugar de uno auténtico.
+pub struct DatagramBatch {
+    buffers: Vec<[u8; MAX_DATAGRAM + 1]>,
+    received: Vec<(usize, SocketAddr)>,
+    // Datagrams absorbed by a later one, skipped on delivery
+    absorbed: Vec<bool>,
+    next: usize,
+    busy_poll: Duration,
+    coalesce: Coalesce,
+    stats: &'static ChannelStats,
+    config: ConfigHandle,
+}
+
+impl DatagramBatch {
+    pub fn new(busy_poll: Duration, coalesce: Coalesce, stats: &'static ChannelStats, config: ConfigHandle) -> Self {
+        Self {
+            buffers: vec![[0u8; MAX_DATAGRAM + 1]; BATCH],
+            received: Vec::with_capacity(BATCH),
+            absorbed: Vec::with_capacity(BATCH),
+            next: 0,
+            busy_poll,
+            coalesce,
+            stats,
+            config,
+        }
+    }
+
+    /// Como `UdpSocket::recv_from`, copiando el siguiente datagrama del lote
+    /// a `buf`. Se puede cancelar en `select!` sin perder paquetes.
+    pub async fn recv_from(&mut self, socket: &UdpSocket, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        if self.skip_absorbed() == self.received.len() && !self.busy_poll.is_zero() {
+            self.spin(socket.as_raw_fd())?;
+        }
+        while self.skip_absorbed() == self.received.len() {
+            socket.readable().await?;
+            let fd = socket.as_raw_fd();
+            match socket.try_io(Interest::READABLE, || self.fill(fd)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let (len, addr) = self.received[self.next];
+        let len = len.min(buf.len());
+        buf[..len].copy_from_slice(&self.buffers[self.next][..len]);
+        self.next += 1;
+        Ok((len, addr))
+    }
+
+    // Index of the next datagram to deliver
+    fn skip_absorbed(&mut self) -> usize {
+        while self.absorbed.get(self.next) == Some(&true) {
+            self.next += 1;
+        }
+        self.next
+    }
+
+    /// Reintenta `recvmmsg` hasta recibir algo o agotar `busy_poll`.
+    fn spin(&mut self, fd: RawFd) -> std::io::Result<()> {
+        let start = Instant::now();
+        let result = loop {
+            // Straight to the syscall: tokio's readiness flag is stale while spinning
+            match self.fill(fd) {
+                Ok(()) if !self.received.is_empty() => break Ok(true),
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => break Err(e),
+            }
+            if start.elapsed() >= self.busy_poll {
+                break Ok(false);
+            }
+            std::hint::spin_loop();
+        };
+        self.stats.record_busy_poll(result.as_ref().is_ok_and(|hit| *hit), start.elapsed());
+        result.map(|_| ())
+    }
+
+    fn fill(&mut self, fd: RawFd) -> std::io::Result<()> {
+        self.received.clear();
+        self.absorbed.clear();
+        self.next = 0;
+        // Rebuilt per call: the headers hold raw pointers and would make the server future !Send
+        let mut headers = MultiHeaders::<SockaddrStorage>::preallocate(BATCH, None);
+        let mut slices: Vec<[IoSliceMut; 1]> = self.buffers.iter_mut().map(|b| [IoSliceMut::new(b)]).collect();
+        let messages = recvmmsg(fd, &mut headers, slices.iter_mut(), MsgFlags::MSG_DONTWAIT, None)?;
+        for message in messages {
+            let addr = message.address.and_then(|a| match (a.as_sockaddr_in(), a.as_sockaddr_in6()) {
+                (Some(v4), _) => Some(SocketAddr::V4((*v4).into())),
+                (_, Some(v6)) => Some(SocketAddr::V6((*v6).into())),
+                _ => None,
+            });
+            // Slots keep their index so received[i] matches buffers[i]
+            let addr = addr.unwrap_or(SocketAddr::from(([0, 0, 0, 0], 0)));
+            self.received.push((message.bytes, addr));
+        }
+        self.absorb_backlog();
+        Ok(())
+    }
+
+    fn absorb_backlog(&mut self) {
+        self.absorbed.resize(self.received.len(), false);
+        if self.config.get().security.packet_key.is_some() {
+            return;
+        }
+        for earlier in 0..self.received.len() {
+            let (earlier_len, addr) = self.received[earlier];
+            // Only the client's next datagram can absorb it, or the order would change
+            let Some(later) = (earlier + 1..self.received.len()).find(|&i| self.received[i].1 == addr) else {
+                continue;
+            };
+            let later_len = self.received[later].0;
+            if earlier_len > MAX_DATAGRAM || later_len > MAX_DATAGRAM {
+                continue;
+            }
+            let (before, after) = self.buffers.split_at_mut(later);
+            if (self.coalesce)(&before[earlier][..earlier_len], &mut after[0][..later_len]) {
+                self.absorbed[earlier] = true;
+                self.stats.record_coalesced();
+            }
+        }
+    }
+}
+
+/// Por dónde llegó un datagrama. La dirección de origen de UDP se puede
+/// falsificar; sólo `Quic` garantiza que vino de la conexión del cliente.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Carrier {
+    Udp,
+    Quic,
+}
+
+/// Un datagrama leído por un receptor SO_REUSEPORT o llegado por QUIC.
+pub struct Datagram {
+    data: [u8; MAX_DATAGRAM + 1],
+    len: usize,
+    addr: SocketAddr,
+    carrier: Carrier,
+}
+
+impl Datagram {
+    /// Copia `bytes`; uno de más basta para que el servidor lo descarte por
+    /// demasiado grande.
+    pub fn new(bytes: &[u8], addr: SocketAddr, carrier: Carrier) -> Self {
+        let mut data = [0u8; MAX_DATAGRAM + 1];
+        let len = bytes.len().min(data.len());
+        data[..len].copy_from_slice(&bytes[..len]);
+        Self { data, len, addr, carrier }
+    }
+
+    fn copy_to(&self, buf: &mut [u8]) -> (usize, SocketAddr, Carrier) {
+        let len = self.len.min(buf.len());
+        buf[..len].copy_from_slice(&self.data[..len]);
+        (len, self.addr, self.carrier)
+    }
+}
+
+enum SocketSource {
+    // From the server's own socket, in batches
+    Direct(DatagramBatch),
+    // From the queue fed by several SO_REUSEPORT receivers
+    Queue(mpsc::Receiver<Datagram>),
+}
+
+/// De dónde lee un servidor UDP sus datagramas: su socket y, con `[quic]
+/// listen`, los datagramas QUIC de su canal.
+pub struct DatagramSource {
+    socket: SocketSource,
+    quic: Option<mpsc::Receiver<Datagram>>,
+}
+
+impl DatagramSource {
+    /// Como `UdpSocket::recv_from`, diciendo además por dónde llegó el
+    /// datagrama. Se puede cancelar en `select!`.
+    pub async fn recv_from(
+        &mut self,
+        socket: &UdpSocket,
+        buf: &mut [u8],
+    ) -> std::io::Result<(usize, SocketAddr, Carrier)> {
+        let Self { socket: source, quic } = self;
+        let quic = async {
+            match quic {
+                Some(queue) => queue.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        let datagram = tokio::select! {
+            result = async {
+                match source {
+                    SocketSource::Direct(batch) => {
+                        batch.recv_from(socket, buf).await.map(|(len, addr)| (len, addr, Carrier::Udp))
+                    }
+                    SocketSource::Queue(queue) => {
+                        let datagram =
+                            queue.recv().await.ok_or_else(|| std::io::Error::other("los receptores UDP terminaron"))?;
+                        Ok(datagram.copy_to(buf))
+                    }
+                }
+            } => return result,
+            Some(datagram) = quic => datagram,
+        };
+        Ok(datagram.copy_to(buf))
+    }
+}
+
+/// Socket de un servidor UDP: `socket` para responder y `source` para leer.
+pub struct UdpEndpoint {
+    pub socket: ReplySocket,
+    pub source: DatagramSource,
+}
+
+impl UdpEndpoint {
+    /// Hace que el servidor reciba también los datagramas QUIC de su canal,
+    /// que el listener QUIC envía por el `Sender` devuelto.
+    pub fn attach_quic(&mut self) -> mpsc::Sender<Datagram> {
+        let (tx, rx) = mpsc::channel(RECEIVER_QUEUE);
+        self.source.quic = Some(rx);
+        tx
+    }
+}
+
+/// Por dónde responde un servidor UDP: a los clientes QUIC por su conexión
+/// (ver `quic`), a los demás por el socket.
+#[derive(Clone)]
+pub struct ReplySocket {
+    udp: Arc<UdpSocket>,
+    // QUIC datagram channel of this server
+    channel: u8,
+}
+
+impl ReplySocket {
+    pub fn udp(&self) -> &Arc<UdpSocket> {
+        &self.udp
+    }
+
+    /// Como `UdpSocket::send_to`.
+    pub async fn send_to(&self, data: &[u8], addr: SocketAddr) -> std::io::Result<usize> {
+        match quic::send_datagram(self.channel, data, addr) {
+            Some(result) => result.map(|()| data.len()),
+            None => self.udp.send_to(data, addr).await,
+        }
+    }
+}
+
+/// Conexión de un cliente del teclado: TCP o un stream QUIC.
+pub enum ClientStream {
+    Tcp(TcpStream),
+    Quic(QuicStream),
+}
+
+nix::ioctl_read_bad!(fionread, nix::libc::FIONREAD, nix::libc::c_int);
+
+impl ClientStream {
+    /// Como `TcpStream::peek`: lo leído se vuelve a entregar después.
+    pub async fn peek(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.peek(buf).await,
+            Self::Quic(stream) => stream.peek(buf).await,
+        }
+    }
+
+    /// Bytes que ya llegaron y todavía nadie leyó.
+    pub fn queued(&self) -> std::io::Result<u64> {
+        match self {
+            Self::Tcp(stream) => {
+                let mut queued: nix::libc::c_int = 0;
+                unsafe { fionread(stream.as_raw_fd(), &mut queued) }?;
+                Ok(queued.max(0) as u64)
+            }
+            Self::Quic(stream) => Ok(stream.queued()),
+        }
+    }
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Quic(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Quic(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Quic(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Quic(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Listener del teclado: acepta conexiones TCP y, con `[quic] listen`, los
+/// streams QUIC que le pasa el listener QUIC.
+pub struct StreamListener {
+    tcp: TcpListener,
+    quic: Option<mpsc::Receiver<(QuicStream, SocketAddr)>>,
+}
+
+impl StreamListener {
+    pub fn new(tcp: TcpListener) -> Self {
+        Self { tcp, quic: None }
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.tcp.local_addr()
+    }
+
+    /// Como [`UdpEndpoint::attach_quic`], para los streams.
+    pub fn attach_quic(&mut self) -> mpsc::Sender<(QuicStream, SocketAddr)> {
+        let (tx, rx) = mpsc::channel(STREAM_QUEUE);
+        self.quic = Some(rx);
+        tx
+    }
+
+    pub async fn accept(&mut self) -> std::io::Result<(ClientStream, SocketAddr)> {
+        let Self { tcp, quic } = self;
+        let quic = async {
+            match quic {
+                Some(queue) => queue.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            result = tcp.accept() => result.map(|(stream, addr)| (ClientStream::Tcp(stream), addr)),
+            Some((stream, addr)) = quic => Ok((ClientStream::Quic(stream), addr)),
+        }
+    }
+}
+
+/// Prepara la recepción de un servidor UDP. Con `receivers > 1` abre
+/// `receivers - 1` sockets más en el mismo puerto con SO_REUSEPORT (el de
+/// `socket` ya debe tenerlo) y lanza una tarea por socket; el kernel reparte
+/// los clientes entre ellas y todas alimentan la misma cola, así el estado
+/// del servidor y la emisión siguen en una sola tarea. `coalesce` es la
+/// regla del protocolo del servidor para juntar datagramas atrasados.
+///
+/// Debe llamarse antes de abandonar root: el kernel sólo comparte el puerto
+/// entre sockets del mismo usuario.
+pub fn udp_endpoint(
+    name: &str,
+    socket: UdpSocket,
+    config: &ConfigHandle,
+    coalesce: Coalesce,
+    stats: &'static ChannelStats,
+    quic_channel: u8,
+) -> std::io::Result<UdpEndpoint> {
+    let network = config.get().network.clone();
+    let receivers = network.udp_receivers;
+    let busy_poll = Duration::from_micros(network.busy_poll_us);
+    set_recv_buffer(name, &socket, network.udp_recv_buffer)?;
+    let socket = Arc::new(socket);
+    let endpoint = |source| UdpEndpoint {
+        socket: ReplySocket { udp: socket.clone(), channel: quic_channel },
+        source: DatagramSource { socket: source, quic: None },
+    };
+    if receivers <= 1 {
+        let batch = DatagramBatch::new(busy_poll, coalesce, stats, config.clone());
+        return Ok(endpoint(SocketSource::Direct(batch)));
+    }
+    let addr = socket.local_addr()?;
+    let mut sockets = vec![socket.clone()];
+    for _ in 1..receivers {
+        let extra = bind_reuse_port(addr).map_err(|e| bind_error(name, addr.port(), e))?;
+        set_recv_buffer(name, &extra, network.udp_recv_buffer)?;
+        sockets.push(Arc::new(extra));
+    }
+    let (tx, rx) = mpsc::channel(RECEIVER_QUEUE);
+    for (index, receiver) in sockets.into_iter().enumerate() {
+        let tx = tx.clone();
+        let name = name.to_string();
+        let config = config.clone();
+        tokio::spawn(async move {
+            let mut batch = DatagramBatch::new(busy_poll, coalesce, stats, config);
+            loop {
+                let mut data = [0u8; MAX_DATAGRAM + 1];
+                let (len, addr) = match batch.recv_from(&receiver, &mut data).await {
+                    Ok(received) => received,
+                    Err(e) => {
+                        log_detail(Verbosity::Low, &format!("Error en receptor {} #{}", name, index), &e.to_string());
+                        return;
+                    }
+                };
+                if tx.send(Datagram { data, len, addr, carrier: Carrier::Udp }).await.is_err() {
+                    return;
+                }
+            }
+        });
+    }
+    log_detail(
+        Verbosity::Medium,
+        &format!("Receptores {}", name),
+        &format!("{} sockets SO_REUSEPORT en {}", receivers, addr),
+    );
+    Ok(endpoint(SocketSource::Queue(rx)))
+}
+
+fn bind_reuse_port(addr: SocketAddr) -> std::io::Result<UdpSocket> {
+    let family = if addr.is_ipv4() { AddressFamily::Inet } else { AddressFamily::Inet6 };
+    let fd = socket(family, SockType::Datagram, SockFlag::SOCK_NONBLOCK | SockFlag::SOCK_CLOEXEC, None)?;
+    setsockopt(&fd, sockopt::ReusePort, &true)?;
+    bind(fd.as_raw_fd(), &SockaddrStorage::from(addr))?;
+    UdpSocket::from_std(std::net::UdpSocket::from(fd))
+}
+
+/// Datagramas que el kernel descartó en `port` por tener lleno el buffer de
+/// recepción (columna `drops` de /proc/net/udp).
+pub fn udp_kernel_drops(port: u16) -> Option<u64> {
+    let local_port = format!(":{:04X}", port);
+    let mut found = None;
+    for table in ["/proc/net/udp", "/proc/net/udp6"] {
+        let Ok(text) = std::fs::read_to_string(table) else {
+            continue;
+        };
+        for line in text.lines().skip(1) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.get(1).is_some_and(|local| local.ends_with(&local_port))
+                && let Some(drops) = fields.last().and_then(|d| d.parse::<u64>().ok())
+            {
+                *found.get_or_insert(0) += drops;
+            }
+        }
+    }
+    found
+}
+
+/// Enlaza un socket UDP en `port`, probando los siguientes `fallback` puertos
+/// si está ocupado. Con `reuse_port` lo abre con SO_REUSEPORT para que
+/// [`udp_endpoint`] pueda añadirle receptores.
+pub async fn bind_udp(
+    name: &str,
+    addr: IpAddr,
+    port: u16,
+    fallback: u16,
+    reuse_port: bool,
+) -> std::io::Result<UdpSocket> {
+    let mut last_err = None;
+    for candidate in candidate_ports(port, fallback) {
+        let result = if reuse_port {
+            bind_reuse_port(SocketAddr::new(addr, candidate))
+        } else {
+            UdpSocket::bind((addr, candidate)).await
+        };
+        match result {
+            Ok(socket) => {
+                announce_fallback(name, port, candidate);
+                return Ok(socket);
+            }
+            Err(e) if e.kind() == ErrorKind::AddrInUse => last_err = Some(e),
+            Err(e) => return Err(bind_error(name, candidate, e)),
+        }
+    }
+    Err(bind_error(name, port, last_err.unwrap()))
+}
+
+/// Igual que [`bind_udp`] pero para el listener TCP.
+pub async fn bind_tcp(name: &str, addr: IpAddr, port: u16, fallback: u16) -> std::io::Result<TcpListener> {
+    let mut last_err = None;
+    for candidate in candidate_ports(port, fallback) {
+        match TcpListener::bind((addr, candidate)).await {
+            Ok(listener) => {
+                announce_fallback(name, port, candidate);
+                return Ok(listener);
+            }
+            Err(e) if e.kind() == ErrorKind::AddrInUse => last_err = Some(e),
+            Err(e) => return Err(bind_error(name, candidate, e)),
+        }
+    }
+    Err(bind_error(name, port, last_err.unwrap()))
+}
+
+fn candidate_ports(port: u16, fallback: u16) -> impl Iterator<Item = u16> {
+    (0..=fallback).filter_map(move |offset| port.checked_add(offset))
+}
+
+fn announce_fallback(name: &str, requested: u16, actual: u16) {
+    if requested != actual {
+        log_detail(
+            Verbosity::Low,
+            &format!("Puerto {} ocupado", name),
+            &format!("{} en uso, usando {}", requested, actual),
+        );
+    }
+}
+
+fn bind_error(name: &str, port: u16, e: std::io::Error) -> std::io::Error {
+    std::io::Error::new(e.kind(), format!("no se pudo enlazar {} en el puerto {}: {}", name, port, e))
+}
@@ -0,0 +1,134 @@
+use crate::bus::{Emitted, InputAction, SharedBus, Target};
+use crate::config::ConfigHandle;
+use crate::logger::{log_detail, Verbosity};
+use crate::overlay::{serve_http, Pads};
+use crate::stats::STATS;
+use evdev::{AbsoluteAxisType, Key};
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::UNIX_EPOCH;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{timeout, Duration};
+
+// Observers connected at once; more are turned away
+const MAX_OBSERVERS: usize = 8;
+// Browsers send their GET at once; raw observers usually send nothing
+const HTTP_WAIT: Duration = Duration::from_millis(300);
+
+/// Canal de observación (`[observer] listen`): cada conexión TCP recibe, en
+/// JSON con una línea por frame, lo que el servidor emite en los
+/// dispositivos virtuales ya mapeado. Es de sólo lectura: lo que envíe el
+/// cliente se descarta. Para overlays, visores de entrada y depuración.
+///
+/// ```text
+/// {"time_ms":1700000000123,"device":"gamepad","player":1,"events":[{"type":"key","code":304,"name":"BTN_SOUTH","value":1}]}
+/// ```
+///
+/// Si la conexión empieza con una petición `GET`, se atiende como HTTP para
+/// overlays de streaming; ver `overlay::serve_http`.
+pub async fn run_observer(listener: TcpListener, bus: SharedBus, config: ConfigHandle) -> std::io::Result<()> {
+    let connected = Arc::new(AtomicUsize::new(0));
+    let pads = Pads::track(&bus, config.clone());
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        if !config.get().is_allowed(addr.ip()) {
+            continue;
+        }
+        if connected.load(Ordering::SeqCst) >= MAX_OBSERVERS {
+            log_detail(Verbosity::Medium, "Observador rechazado", &format!("{} (máximo {})", addr, MAX_OBSERVERS));
+            continue;
+        }
+        connected.fetch_add(1, Ordering::SeqCst);
+        log_detail(Verbosity::Low, "Observador conectado", &addr.to_string());
+        let frames = bus.subscribe();
+        let config = config.clone();
+        let connected = connected.clone();
+        let pads = pads.clone();
+        tokio::spawn(async move {
+            let mut first = [0u8; 4];
+            let _ = match timeout(HTTP_WAIT, stream.peek(&mut first)).await {
+                Ok(Ok(4)) if &first == b"GET " => serve_http(stream, pads, config.get().observer.overlay_hz).await,
+                _ => stream_frames(stream, frames, config).await,
+            };
+            connected.fetch_sub(1, Ordering::SeqCst);
+            log_detail(Verbosity::Low, "Observador desconectado", &addr.to_string());
+        });
+    }
+}
+
+async fn stream_frames(
+    mut stream: TcpStream,
+    mut frames: broadcast::Receiver<Emitted>,
+    config: ConfigHandle,
+) -> std::io::Result<()> {
+    let (mut reader, mut writer) = stream.split();
+    let mut discard = [0u8; 64];
+    loop {
+        let line = tokio::select! {
+            frame = frames.recv() => match frame {
+                Ok(frame) => {
+                    if frame.target == Target::Keyboard && !config.get().observer.keyboard {
+                        continue;
+                    }
+                    to_json(&frame)
+                }
+                // Frames a slow observer missed are skipped, never queued without bound
+                Err(RecvError::Lagged(missed)) => {
+                    STATS.observer.record_dropped(missed);
+                    format!("{{\"lagged\":{}}}\n", missed)
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+            read = reader.read(&mut discard) => match read? {
+                0 => return Ok(()),
+                _ => continue,
+            },
+        };
+        writer.write_all(line.as_bytes()).await?;
+    }
+}
+
+fn to_json(frame: &Emitted) -> String {
+    let time_ms = frame.at.duration_since(UNIX_EPOCH).map_or(0, |t| t.as_millis());
+    let (device, player) = match frame.target {
+        Target::Mouse => ("mouse", None),
+        Target::Keyboard => ("keyboard", None),
+        Target::Encoder => ("encoder", None),
+        Target::Lightgun => ("lightgun", None),
+        Target::Consumer => ("consumer", None),
+        Target::Gamepad(slot) => ("gamepad", Some(slot + 1)),
+    };
+    let mut line = format!("{{\"time_ms\":{},\"device\":\"{}\"", time_ms, device);
+    if let Some(player) = player {
+        let _ = write!(line, ",\"player\":{}", player);
+    }
+    line.push_str(",\"events\":[");
+    for (i, action) in frame.actions.iter().enumerate() {
+        if i > 0 {
+            line.push(',');
+        }
+        let _ = match *action {
+            InputAction::Key { code, value } => write!(
+                line,
+                "{{\"type\":\"key\",\"code\":{},\"name\":\"{:?}\",\"value\":{}}}",
+                code,
+                Key(code),
+                value
+            ),
+            InputAction::Axis { code, value } => write!(
+                line,
+                "{{\"type\":\"axis\",\"code\":{},\"name\":\"{:?}\",\"value\":{}}}",
+                code,
+                AbsoluteAxisType(code),
+                value
+            ),
+            InputAction::Move { dx, dy } => write!(line, "{{\"type\":\"move\",\"dx\":{},\"dy\":{}}}", dx, dy),
+            InputAction::Wheel(value) => write!(line, "{{\"type\":\"wheel\",\"value\":{}}}", value),
+        };
+    }
+    line.push_str("]}\n");
+    line
+}
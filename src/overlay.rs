@@ -0,0 +1,176 @@
+use crate::bus::{Emitted, InputAction, SharedBus, Target};
+use crate::config::{AxisRanges, ConfigHandle};
+use crate::http::{respond, Request};
+use evdev::{AbsoluteAxisType, Key};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+const PAGE: &str = include_str!("overlay.html");
+
+/// Estado de un mando con la forma de la API Gamepad del navegador
+/// ("standard mapping"), que es lo que leen casi todos los visores de
+/// entrada: 17 botones de 0 a 1 y 4 ejes de -1 a 1 (Y hacia abajo).
+#[derive(Clone, Copy, Default)]
+struct PadState {
+    active: bool,
+    buttons: [f32; 17],
+    axes: [f32; 4],
+    // Seen ABS_Z/ABS_RZ, which then win over BTN_TL2/BTN_TR2
+    analog_triggers: [bool; 2],
+}
+
+impl PadState {
+    fn apply(&mut self, frame: &Emitted, ranges: &AxisRanges) {
+        self.active = true;
+        for action in &frame.actions {
+            match *action {
+                InputAction::Key { code, value } => self.key(code, value != 0),
+                InputAction::Axis { code, value } => self.axis(code, value, ranges),
+                _ => {}
+            }
+        }
+    }
+
+    fn key(&mut self, code: u16, pressed: bool) {
+        let index = match Key(code) {
+            Key::BTN_SOUTH => 0,
+            Key::BTN_EAST => 1,
+            // Xbox X and Y, as xpad reports them
+            Key::BTN_NORTH => 2,
+            Key::BTN_WEST => 3,
+            Key::BTN_TL => 4,
+            Key::BTN_TR => 5,
+            Key::BTN_TL2 if !self.analog_triggers[0] => 6,
+            Key::BTN_TR2 if !self.analog_triggers[1] => 7,
+            Key::BTN_SELECT => 8,
+            Key::BTN_START => 9,
+            Key::BTN_THUMBL => 10,
+            Key::BTN_THUMBR => 11,
+            Key::BTN_MODE => 16,
+            _ => return,
+        };
+        self.buttons[index] = pressed as u8 as f32;
+    }
+
+    fn axis(&mut self, code: u16, value: i32, ranges: &AxisRanges) {
+        let stick = |value: i32| ranges.stick_fraction(value);
+        let trigger = |value: i32| ranges.trigger_fraction(value);
+        match AbsoluteAxisType(code) {
+            AbsoluteAxisType::ABS_X => self.axes[0] = stick(value),
+            AbsoluteAxisType::ABS_Y => self.axes[1] = stick(value),
+            AbsoluteAxisType::ABS_RX => self.axes[2] = stick(value),
+            AbsoluteAxisType::ABS_RY => self.axes[3] = stick(value),
+            AbsoluteAxisType::ABS_Z => {
+                self.analog_triggers[0] = true;
+                self.buttons[6] = trigger(value);
+            }
+            AbsoluteAxisType::ABS_RZ => {
+                self.analog_triggers[1] = true;
+                self.buttons[7] = trigger(value);
+            }
+            AbsoluteAxisType::ABS_HAT0Y => {
+                self.buttons[12] = (value < 0) as u8 as f32;
+                self.buttons[13] = (value > 0) as u8 as f32;
+            }
+            AbsoluteAxisType::ABS_HAT0X => {
+                self.buttons[14] = (value < 0) as u8 as f32;
+                self.buttons[15] = (value > 0) as u8 as f32;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Último estado de cada jugador, mantenido a partir de lo que emite el bus.
+#[derive(Clone)]
+pub struct Pads(Arc<Mutex<Vec<PadState>>>);
+
+impl Pads {
+    /// Empieza a seguir los mandos virtuales del bus. Los ejes se leen con
+    /// los rangos de `[gamepad] axis_ranges`, no con los de un perfil.
+    pub fn track(bus: &SharedBus, config: ConfigHandle) -> Self {
+        let pads = Self(Arc::new(Mutex::new(vec![PadState::default(); bus.slots()])));
+        let mut frames = bus.subscribe();
+        let tracked = pads.clone();
+        tokio::spawn(async move {
+            loop {
+                match frames.recv().await {
+                    Ok(frame) => {
+                        if let Target::Gamepad(slot) = frame.target
+                            && let Some(pad) = tracked.0.lock().unwrap().get_mut(slot)
+                        {
+                            pad.apply(&frame, &config.get().gamepad.axis_ranges);
+                        }
+                    }
+                    // Only the latest state matters, so missed frames are harmless
+                    Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => return,
+                }
+            }
+        });
+        pads
+    }
+
+    /// `{"players":[{"player":1,"active":true,"buttons":[...],"axes":[...]}]}`
+    fn render(&self) -> String {
+        let pads = self.0.lock().unwrap().clone();
+        let mut json = String::from("{\"players\":[");
+        for (slot, pad) in pads.iter().enumerate() {
+            if slot > 0 {
+                json.push(',');
+            }
+            let _ = write!(json, "{{\"player\":{},\"active\":{},\"buttons\":", slot + 1, pad.active);
+            push_values(&mut json, &pad.buttons);
+            json.push_str(",\"axes\":");
+            push_values(&mut json, &pad.axes);
+            json.push('}');
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+fn push_values(json: &mut String, values: &[f32]) {
+    json.push('[');
+    for (i, value) in values.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        // Three decimals are plenty for a display and keep the lines short
+        let _ = write!(json, "{}", (value * 1000.0).round() / 1000.0);
+    }
+    json.push(']');
+}
+
+/// Atiende una petición HTTP en el puerto del observador:
+///
+/// - `/`: página para una fuente de navegador de OBS (`?player=N`).
+/// - `/state`: el estado actual de todos los jugadores en JSON.
+/// - `/events`: el mismo JSON como Server-Sent Events, `rate_hz` veces por
+///   segundo.
+pub async fn serve_http(mut stream: TcpStream, pads: Pads, rate_hz: u32) -> std::io::Result<()> {
+    let request = Request::read(&mut stream).await?;
+    match request.path.as_str() {
+        "/" => respond(&mut stream, "200 OK", "text/html; charset=utf-8", PAGE).await,
+        "/state" => respond(&mut stream, "200 OK", "application/json", &pads.render()).await,
+        "/events" => {
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+                      Access-Control-Allow-Origin: *\r\n\r\n",
+                )
+                .await?;
+            let mut ticker = interval(Duration::from_secs_f64(1.0 / rate_hz.clamp(1, 120) as f64));
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            loop {
+                ticker.tick().await;
+                stream.write_all(format!("data: {}\n\n", pads.render()).as_bytes()).await?;
+            }
+        }
+        _ => respond(&mut stream, "404 Not Found", "text/plain", "not found").await,
+    }
+}
@@ -0,0 +1,241 @@
+use crate::protocol::{AUTH_NONCE_LEN, AUTH_TAG_LEN, HEADER_AUTH_CHALLENGE, HEADER_AUTH_NONCE};
+use crate::network::Carrier;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::io::Read;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const COUNTER_LEN: usize = 8;
+const TRAILER_LEN: usize = COUNTER_LEN + AUTH_TAG_LEN;
+// Counters this far behind the highest one accepted may still arrive out of order
+const WINDOW: u64 = 64;
+// Clients remembered per server; a new one displaces the one idle the longest
+const MAX_CLIENTS: usize = 256;
+
+/// Nonce y ventana de contadores de un cliente.
+struct ClientWindow {
+    nonce: [u8; AUTH_NONCE_LEN],
+    highest: Option<u64>,
+    // Bit n set = counter `highest - n` already accepted
+    seen: u64,
+    last_used: Instant,
+}
+
+impl ClientWindow {
+    /// Anota `counter` si no se aceptó antes y no quedó fuera de la ventana.
+    fn accept(&mut self, counter: u64) -> bool {
+        let Some(highest) = self.highest else {
+            self.highest = Some(counter);
+            self.seen = 1;
+            return true;
+        };
+        if counter > highest {
+            let ahead = counter - highest;
+            self.seen = if ahead >= WINDOW { 1 } else { (self.seen << ahead) | 1 };
+            self.highest = Some(counter);
+            return true;
+        }
+        let behind = highest - counter;
+        if behind >= WINDOW || self.seen & (1 << behind) != 0 {
+            return false;
+        }
+        self.seen |= 1 << behind;
+        true
+    }
+}
+
+/// Qué hacer con un datagrama según `PacketAuth::check`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    /// Datagrama auténtico; esta es su longitud sin contador ni etiqueta.
+    Accepted(usize),
+    /// Responder con este AUTH_NONCE y descartar el datagrama.
+    Challenge([u8; 1 + AUTH_NONCE_LEN]),
+    /// Etiqueta falsa, contador repetido o demasiado atrasado.
+    Rejected(&'static str),
+}
+
+/// Autenticación de los datagramas de ratón y mando con `[security]
+/// packet_key`. Cada cliente pide un nonce con AUTH_CHALLENGE y firma cada
+/// datagrama con él y un contador creciente; un datagrama capturado no
+/// vale dos veces ni sirve tras un nuevo nonce. Cada servidor UDP tiene la
+/// suya y la usa desde su bucle de recepción, como `RateLimiter`.
+#[derive(Default)]
+pub struct PacketAuth {
+    clients: HashMap<SocketAddr, ClientWindow>,
+}
+
+impl PacketAuth {
+    /// `key` `None` acepta todo tal cual. Lo llegado por QUIC tampoco va
+    /// firmado: TLS ya autentica la conexión. La dirección no basta para
+    /// saberlo, porque un datagrama UDP puede falsificarla.
+    pub fn check(&mut self, key: Option<&str>, packet: &[u8], addr: SocketAddr, carrier: Carrier) -> Verdict {
+        let Some(key) = key else {
+            return Verdict::Accepted(packet.len());
+        };
+        if carrier == Carrier::Quic {
+            return Verdict::Accepted(packet.len());
+        }
+        if packet == [HEADER_AUTH_CHALLENGE] {
+            return self.challenge(addr);
+        }
+        let Some(len) = packet.len().checked_sub(TRAILER_LEN).filter(|&len| len > 0) else {
+            return Verdict::Rejected("sin contador ni etiqueta");
+        };
+        // Tagged with a nonce this server no longer knows, e.g. after a restart
+        let Some(client) = self.clients.get_mut(&addr) else {
+            return self.challenge(addr);
+        };
+        let (datagram, trailer) = packet.split_at(len);
+        let (counter, tag) = trailer.split_at(COUNTER_LEN);
+        let mut mac = HmacSha256::new_from_slice(key.as_bytes()).expect("HMAC acepta claves de cualquier longitud");
+        mac.update(&client.nonce);
+        mac.update(counter);
+        mac.update(datagram);
+        if mac.verify_truncated_left(tag).is_err() {
+            return Verdict::Rejected("etiqueta no válida");
+        }
+        if !client.accept(u64::from_le_bytes(counter.try_into().unwrap())) {
+            return Verdict::Rejected("contador repetido o atrasado");
+        }
+        client.last_used = Instant::now();
+        Verdict::Accepted(len)
+    }
+
+    // A fresh nonce voids everything signed with the previous one
+    fn challenge(&mut self, addr: SocketAddr) -> Verdict {
+        let mut nonce = [0u8; AUTH_NONCE_LEN];
+        if std::fs::File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut nonce)).is_err() {
+            return Verdict::Rejected("no se pudo generar el nonce");
+        }
+        if !self.clients.contains_key(&addr)
+            && self.clients.len() >= MAX_CLIENTS
+            && let Some(oldest) = self.clients.iter().min_by_key(|(_, client)| client.last_used).map(|(addr, _)| *addr)
+        {
+            self.clients.remove(&oldest);
+        }
+        self.clients.insert(addr, ClientWindow { nonce, highest: None, seen: 0, last_used: Instant::now() });
+        let mut reply = [HEADER_AUTH_NONCE; 1 + AUTH_NONCE_LEN];
+        reply[1..].copy_from_slice(&nonce);
+        Verdict::Challenge(reply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigHandle;
+    use crate::logger::VerbositySpec;
+    use crate::network::{self, Datagram};
+    use crate::protocol::{MAX_DATAGRAM, QUIC_CHANNEL_MOUSE};
+    use crate::stats::STATS;
+    use tokio::net::UdpSocket;
+
+    const KEY: &str = "secreto";
+
+    fn addr() -> SocketAddr {
+        "192.168.1.20:40000".parse().unwrap()
+    }
+
+    fn nonce(auth: &mut PacketAuth) -> [u8; AUTH_NONCE_LEN] {
+        match auth.check(Some(KEY), &[HEADER_AUTH_CHALLENGE], addr(), Carrier::Udp) {
+            Verdict::Challenge(reply) => reply[1..].try_into().unwrap(),
+            other => panic!("se esperaba un AUTH_NONCE: {:?}", other),
+        }
+    }
+
+    fn sign(key: &str, nonce: &[u8], counter: u64, datagram: &[u8]) -> Vec<u8> {
+        let mut mac = HmacSha256::new_from_slice(key.as_bytes()).unwrap();
+        mac.update(nonce);
+        mac.update(&counter.to_le_bytes());
+        mac.update(datagram);
+        let mut packet = datagram.to_vec();
+        packet.extend_from_slice(&counter.to_le_bytes());
+        packet.extend_from_slice(&mac.finalize().into_bytes()[..AUTH_TAG_LEN]);
+        packet
+    }
+
+    #[test]
+    fn without_key_everything_passes() {
+        let mut auth = PacketAuth::default();
+        assert_eq!(auth.check(None, &[0x20, 0, 1, 0], addr(), Carrier::Udp), Verdict::Accepted(4));
+    }
+
+    #[test]
+    fn signed_datagram_is_accepted_once() {
+        let mut auth = PacketAuth::default();
+        let nonce = nonce(&mut auth);
+        let packet = sign(KEY, &nonce, 7, &[0x20, 0, 1, 0]);
+        assert_eq!(auth.check(Some(KEY), &packet, addr(), Carrier::Udp), Verdict::Accepted(4));
+        assert!(matches!(auth.check(Some(KEY), &packet, addr(), Carrier::Udp), Verdict::Rejected(_)));
+    }
+
+    #[test]
+    fn wrong_key_and_tampered_datagrams_are_rejected() {
+        let mut auth = PacketAuth::default();
+        let nonce = nonce(&mut auth);
+        let forged = sign("otra", &nonce, 1, &[0x20, 0, 1, 0]);
+        assert!(matches!(auth.check(Some(KEY), &forged, addr(), Carrier::Udp), Verdict::Rejected(_)));
+        let mut tampered = sign(KEY, &nonce, 2, &[0x20, 0, 1, 0]);
+        tampered[2] = 0x7F;
+        assert!(matches!(auth.check(Some(KEY), &tampered, addr(), Carrier::Udp), Verdict::Rejected(_)));
+        assert!(matches!(auth.check(Some(KEY), &[0x20, 0, 1, 0], addr(), Carrier::Udp), Verdict::Rejected(_)));
+    }
+
+    #[test]
+    fn new_nonce_voids_old_datagrams() {
+        let mut auth = PacketAuth::default();
+        let old = sign(KEY, &nonce(&mut auth), 1, &[0x42]);
+        nonce(&mut auth);
+        assert!(matches!(auth.check(Some(KEY), &old, addr(), Carrier::Udp), Verdict::Rejected(_)));
+    }
+
+    #[tokio::test]
+    async fn udp_datagram_from_a_quic_address_needs_a_tag() {
+        let config = ConfigHandle::load(None, VerbositySpec::default(), None).unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server = socket.local_addr().unwrap();
+        let endpoint = network::udp_endpoint("Test", socket, &config, |_, _| false, &STATS.mouse, QUIC_CHANNEL_MOUSE);
+        let mut endpoint = endpoint.unwrap();
+        let quic = endpoint.attach_quic();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        // The QUIC client and the spoofed UDP datagrams share the address
+        let peer = client.local_addr().unwrap();
+        let mut auth = PacketAuth::default();
+        let mut buf = [0u8; MAX_DATAGRAM + 1];
+
+        quic.send(Datagram::new(&[0x20, 0, 1, 0], peer, Carrier::Quic)).await.unwrap();
+        let (len, from, carrier) = endpoint.source.recv_from(endpoint.socket.udp(), &mut buf).await.unwrap();
+        assert_eq!(from, peer);
+        assert_eq!(auth.check(Some(KEY), &buf[..len], from, carrier), Verdict::Accepted(4));
+
+        client.send_to(&[0x20, 0, 1, 0], server).await.unwrap();
+        let (len, from, carrier) = endpoint.source.recv_from(endpoint.socket.udp(), &mut buf).await.unwrap();
+        assert_eq!(from, peer);
+        assert!(matches!(auth.check(Some(KEY), &buf[..len], from, carrier), Verdict::Rejected(_)));
+    }
+
+    #[test]
+    fn unknown_client_gets_a_nonce() {
+        let mut auth = PacketAuth::default();
+        let packet = sign(KEY, &[0; AUTH_NONCE_LEN], 1, &[0x42]);
+        assert!(matches!(auth.check(Some(KEY), &packet, addr(), Carrier::Udp), Verdict::Challenge(_)));
+    }
+
+    #[test]
+    fn window_accepts_late_counters_once() {
+        let mut window = ClientWindow { nonce: [0; AUTH_NONCE_LEN], highest: None, seen: 0, last_used: Instant::now() };
+        assert!(window.accept(10));
+        assert!(window.accept(12));
+        assert!(window.accept(11));
+        assert!(!window.accept(11));
+        assert!(!window.accept(10));
+        assert!(window.accept(100));
+        assert!(!window.accept(100 - WINDOW));
+        assert!(window.accept(100 - WINDOW + 1));
+    }
+}
@@ -0,0 +1,133 @@
+use crate::filters::{InputFilter, PadInput, WatchedFile};
+use crate::logger::{log_detail, Verbosity};
+use std::path::PathBuf;
+use std::time::Instant;
+use wasmi::{Caller, Config, Engine, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc};
+
+/// Instrucciones WASM que un módulo puede ejecutar por snapshot.
+const FUEL: u64 = 1_000_000;
+/// Memoria lineal máxima de un módulo.
+const MEMORY_LIMIT: usize = 16 << 20;
+/// `[buttons: 12 x u8][axes: 8 x i16 LE]`, como el snapshot antiguo sin cabecera.
+const BUFFER_LEN: usize = 28;
+
+struct HostState {
+    limits: StoreLimits,
+    keys: Vec<(u16, i32)>,
+}
+
+struct Instance {
+    store: Store<HostState>,
+    memory: Memory,
+    buffer: usize,
+    apply: TypedFunc<i64, ()>,
+}
+
+/// Filtro `wasm` del pipeline: un módulo WebAssembly aislado, sin acceso a
+/// archivos ni a la red. Interfaz que debe exportar:
+///
+/// - `memory`: su memoria lineal.
+/// - `input() -> i32`: dirección de un búfer de `BUFFER_LEN` bytes.
+/// - `apply(now_ms: i64)`: transforma el búfer en sitio; `now_ms` cuenta
+///   desde que se cargó el módulo.
+///
+/// La única importación permitida es `env.key(code: i32, value: i32)`, que
+/// manda un evento al teclado virtual. Un módulo que falla se registra y se
+/// salta hasta que el archivo cambie.
+pub struct WasmPlugin {
+    file: WatchedFile,
+    engine: Engine,
+    instance: Option<Instance>,
+    loaded_at: Instant,
+}
+
+impl WasmPlugin {
+    pub fn new(path: PathBuf) -> Self {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        Self {
+            file: WatchedFile::new(path),
+            engine: Engine::new(&config),
+            instance: None,
+            loaded_at: Instant::now(),
+        }
+    }
+
+    fn load(&self) -> Result<Instance, String> {
+        let wasm = std::fs::read(&self.file.path).map_err(|e| e.to_string())?;
+        let module = Module::new(&self.engine, &wasm[..]).map_err(|e| e.to_string())?;
+        let state = HostState { limits: StoreLimitsBuilder::new().memory_size(MEMORY_LIMIT).build(), keys: Vec::new() };
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(FUEL).map_err(|e| e.to_string())?;
+        let mut linker = Linker::new(&self.engine);
+        linker
+            .func_wrap("env", "key", |mut caller: Caller<'_, HostState>, code: i32, value: i32| {
+                caller.data_mut().keys.push((code as u16, value));
+            })
+            .map_err(|e| e.to_string())?;
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(|e| e.to_string())?;
+        let memory = instance.get_memory(&store, "memory").ok_or("no exporta memory")?;
+        let input = instance.get_typed_func::<(), i32>(&store, "input").map_err(|e| format!("input: {}", e))?;
+        let apply = instance.get_typed_func::<i64, ()>(&store, "apply").map_err(|e| format!("apply: {}", e))?;
+        store.set_fuel(FUEL).map_err(|e| e.to_string())?;
+        let buffer = input.call(&mut store, ()).map_err(|e| e.to_string())? as u32 as usize;
+        Ok(Instance { store, memory, buffer, apply })
+    }
+
+    fn fail(&mut self, error: &str) {
+        log_detail(Verbosity::Low, "Error en módulo WASM", &format!("{}: {}", self.file.path.display(), error));
+        self.instance = None;
+    }
+
+    fn run(&mut self, input: &mut PadInput, now: Instant) -> Result<(), String> {
+        let Some(instance) = self.instance.as_mut() else {
+            return Ok(());
+        };
+        let mut buffer = [0u8; BUFFER_LEN];
+        buffer[..12].copy_from_slice(&input.buttons);
+        for (chunk, axis) in buffer[12..].chunks_exact_mut(2).zip(input.axes) {
+            chunk.copy_from_slice(&axis.to_le_bytes());
+        }
+        let store = &mut instance.store;
+        instance.memory.write(&mut *store, instance.buffer, &buffer).map_err(|e| e.to_string())?;
+        store.set_fuel(FUEL).map_err(|e| e.to_string())?;
+        let now_ms = now.duration_since(self.loaded_at).as_millis() as i64;
+        instance.apply.call(&mut *store, now_ms).map_err(|e| e.to_string())?;
+        instance.memory.read(&*store, instance.buffer, &mut buffer).map_err(|e| e.to_string())?;
+        input.buttons.copy_from_slice(&buffer[..12]);
+        for (axis, chunk) in input.axes.iter_mut().zip(buffer[12..].chunks_exact(2)) {
+            *axis = i16::from_le_bytes([chunk[0], chunk[1]]);
+        }
+        input.keys.append(&mut store.data_mut().keys);
+        Ok(())
+    }
+}
+
+impl InputFilter for WasmPlugin {
+    fn apply(&mut self, input: &mut PadInput, now: Instant) {
+        if self.file.changed(now) {
+            match self.load() {
+                Ok(instance) => {
+                    log_detail(Verbosity::Low, "Módulo WASM cargado", &self.file.path.display().to_string());
+                    self.instance = Some(instance);
+                    self.loaded_at = now;
+                }
+                Err(e) => self.fail(&e),
+            }
+        }
+        // Work on a copy so a trap halfway through leaves the snapshot untouched
+        let mut output = PadInput { buttons: input.buttons, axes: input.axes, extra: input.extra, keys: Vec::new() };
+        match self.run(&mut output, now) {
+            Ok(()) => {
+                input.buttons = output.buttons;
+                input.axes = output.axes;
+                input.keys.append(&mut output.keys);
+            }
+            Err(e) => self.fail(&e),
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use crate::bus::InputAction;
+use crate::config::{ConfigHandle, PowerConfig, PowerKeys};
+use crate::logger::{log, log_detail, Verbosity};
+use crate::network::ipv4_interfaces;
+use evdev::Key;
+use std::net::Ipv4Addr;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::time::{interval, Duration};
+
+// How often the inhibitor checks whether anyone is connected
+const INHIBIT_CHECK: Duration = Duration::from_secs(2);
+// Wake-on-LAN listeners use the discard port
+const WOL_PORT: u16 = 9;
+
+// `[power] keys = "suspend"`, read at startup
+static SUSPEND_KEYS: AtomicBool = AtomicBool::new(false);
+
+/// Aplica la parte de `[power]` que sólo se lee al arrancar.
+pub fn apply(config: &PowerConfig) {
+    SUSPEND_KEYS.store(config.keys == PowerKeys::Suspend, Ordering::Relaxed);
+}
+
+fn is_power_key(code: u16) -> bool {
+    code == Key::KEY_POWER.code() || code == Key::KEY_SLEEP.code()
+}
+
+/// Con `[power] keys = "suspend"`, quita KEY_POWER y KEY_SLEEP de los frames
+/// del teclado y suspende el equipo al pulsarlas. `None` si no hay nada que
+/// quitar.
+pub fn divert_keys(frames: &[&[InputAction]]) -> Option<Vec<Vec<InputAction>>> {
+    let is_power = |action: &InputAction| matches!(*action, InputAction::Key { code, .. } if is_power_key(code));
+    if !SUSPEND_KEYS.load(Ordering::Relaxed) || !frames.iter().any(|frame| frame.iter().any(is_power)) {
+        return None;
+    }
+    let pressed = |action: &&InputAction| matches!(**action, InputAction::Key { value: 1, .. }) && is_power(action);
+    if frames.iter().flat_map(|frame| frame.iter()).any(|action| pressed(&action)) {
+        suspend();
+    }
+    Some(frames.iter().map(|frame| frame.iter().copied().filter(|action| !is_power(action)).collect()).collect())
+}
+
+/// Pide a logind que suspenda el equipo (`systemctl suspend`). Sin root,
+/// polkit tiene que permitírselo al usuario del servidor.
+fn suspend() {
+    log(Verbosity::Low, "Suspendiendo el equipo");
+    tokio::task::spawn_blocking(|| match Command::new("systemctl").arg("suspend").output() {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => {
+            log_detail(Verbosity::Low, "Error al suspender", String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Err(e) => log_detail(Verbosity::Low, "Error al suspender", &e.to_string()),
+    });
+}
+
+/// Con `[power] inhibit`, mantiene un bloqueo de logind contra la
+/// suspensión mientras haya clientes conectados. El bloqueo lo tiene un
+/// `systemd-inhibit` hijo esperando en su stdin: si el servidor muere, el
+/// pipe se cierra y el bloqueo desaparece con él.
+pub async fn run_inhibitor(clients: Arc<AtomicUsize>, config: ConfigHandle) {
+    let mut inhibitor: Option<Child> = None;
+    let mut ticker = interval(INHIBIT_CHECK);
+    loop {
+        ticker.tick().await;
+        let connected = clients.load(Ordering::SeqCst);
+        let wanted = config.get().power.inhibit && connected > 0;
+        // An inhibitor that exited on its own (logind restarted, polkit said no) is taken again
+        if let Some(child) = &mut inhibitor
+            && child.try_wait().ok().flatten().is_some()
+        {
+            inhibitor = None;
+        }
+        match (wanted, inhibitor.take()) {
+            (true, None) => match inhibit() {
+                Ok(child) => {
+                    log_detail(Verbosity::Medium, "Suspensión bloqueada", &format!("{} cliente(s)", connected));
+                    inhibitor = Some(child);
+                }
+                Err(e) => log_detail(Verbosity::Low, "Error bloqueando la suspensión", &e.to_string()),
+            },
+            (false, Some(child)) => {
+                release(child);
+                log(Verbosity::Medium, "Suspensión permitida");
+            }
+            (_, child) => inhibitor = child,
+        }
+    }
+}
+
+fn inhibit() -> std::io::Result<Child> {
+    Command::new("systemd-inhibit")
+        .args(["--what=sleep:idle", "--who=Retro Control Server", "--why=Hay clientes conectados", "--mode=block"])
+        .arg("cat")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+}
+
+fn release(mut child: Child) {
+    // Closing stdin ends `cat`, and with it the lock
+    drop(child.stdin.take());
+    if child.try_wait().ok().flatten().is_none() {
+        let _ = child.kill();
+    }
+    let _ = child.wait();
+}
+
+/// Paquete mágico de Wake-on-LAN: 6 bytes 0xFF y la MAC 16 veces.
+fn magic_packet(mac: [u8; 6]) -> [u8; 102] {
+    let mut packet = [0xFF; 102];
+    for chunk in packet[6..].chunks_mut(6) {
+        chunk.copy_from_slice(&mac);
+    }
+    packet
+}
+
+/// Con `[power] wake_relay`, despierta otro equipo en nombre de un cliente:
+/// envía el paquete mágico al broadcast de cada interfaz. Así el móvil puede
+/// encender la máquina retro a través de otra instancia que siga despierta,
+/// aunque su propio broadcast no llegue (aislamiento de clientes Wi-Fi, VPN).
+pub async fn wake(mac: [u8; 6], config: &PowerConfig) -> bool {
+    let mac_text = mac.map(|b| format!("{:02x}", b)).join(":");
+    if !config.wake_relay {
+        log_detail(Verbosity::Low, "Wake-on-LAN rechazado", &format!("{}: [power] wake_relay desactivado", mac_text));
+        return false;
+    }
+    let mut targets: Vec<Ipv4Addr> = ipv4_interfaces().into_iter().filter_map(|i| i.broadcast).collect();
+    targets.sort();
+    targets.dedup();
+    if targets.is_empty() {
+        targets.push(Ipv4Addr::BROADCAST);
+    }
+    let result = async {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+        socket.set_broadcast(true)?;
+        let packet = magic_packet(mac);
+        for &target in &targets {
+            socket.send_to(&packet, (target, WOL_PORT)).await?;
+        }
+        std::io::Result::Ok(())
+    }
+    .await;
+    let list: Vec<String> = targets.iter().map(|t| t.to_string()).collect();
+    match result {
+        Ok(()) => {
+            log_detail(Verbosity::Low, "Wake-on-LAN enviado", &format!("{} -> {}", mac_text, list.join(", ")));
+            true
+        }
+        Err(e) => {
+            log_detail(Verbosity::Low, "Error enviando Wake-on-LAN", &format!("{}: {}", mac_text, e));
+            false
+        }
+    }
+}
+
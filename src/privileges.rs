@@ -0,0 +1,56 @@
+use crate::config::PrivilegesConfig;
+use crate::logger::{log_detail, Verbosity};
+use nix::unistd::{Gid, Group, Uid, User, geteuid, initgroups, setgid, setuid};
+use std::ffi::CString;
+
+/// Abandona root una vez abiertos /dev/uinput y los sockets.
+///
+/// Los descriptores ya abiertos siguen siendo válidos tras el cambio de
+/// usuario, así que el servidor funciona igual pero sin privilegios.
+pub fn drop_privileges(config: &PrivilegesConfig) -> Result<(), String> {
+    let Some(user_name) = &config.user else {
+        if geteuid().is_root() {
+            log_detail(
+                Verbosity::Low,
+                "Ejecutando como root",
+                "configura [privileges] user para abandonar root tras el arranque",
+            );
+        }
+        return Ok(());
+    };
+
+    if !geteuid().is_root() {
+        log_detail(Verbosity::Medium, "Sin privilegios que abandonar", &format!("uid={}", geteuid()));
+        return Ok(());
+    }
+
+    let user = User::from_name(user_name)
+        .map_err(|e| format!("no se pudo buscar el usuario {}: {}", user_name, e))?
+        .ok_or_else(|| format!("el usuario {} no existe", user_name))?;
+    let gid: Gid = match &config.group {
+        Some(group_name) => {
+            Group::from_name(group_name)
+                .map_err(|e| format!("no se pudo buscar el grupo {}: {}", group_name, e))?
+                .ok_or_else(|| format!("el grupo {} no existe", group_name))?
+                .gid
+        }
+        None => user.gid,
+    };
+
+    let c_name = CString::new(user.name.as_str()).map_err(|e| e.to_string())?;
+    initgroups(&c_name, gid).map_err(|e| format!("initgroups: {}", e))?;
+    setgid(gid).map_err(|e| format!("setgid({}): {}", gid, e))?;
+    setuid(user.uid).map_err(|e| format!("setuid({}): {}", user.uid, e))?;
+
+    // Paranoia: if root can be regained, the drop did not really happen
+    if setuid(Uid::from_raw(0)).is_ok() {
+        return Err("se pudo recuperar root tras setuid; abortando".to_string());
+    }
+
+    log_detail(
+        Verbosity::Low,
+        "Privilegios abandonados",
+        &format!("usuario={} uid={} gid={}", user.name, user.uid, gid),
+    );
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use crate::config::{Config, ConfigHandle};
+use crate::logger::{log_detail, Verbosity};
+use crate::profiles::ProfileManager;
+use tokio::time::{sleep, Duration};
+
+/// Activa el perfil cuyo `processes` coincide con algún proceso en marcha
+/// (el emulador o core lanzado por el frontend) y vuelve a la base cuando
+/// termina. Sólo actúa cuando cambia lo detectado, así que una selección
+/// manual dura hasta el siguiente cambio de juego.
+pub async fn run_process_watcher(config: ConfigHandle, profiles: ProfileManager) {
+    let mut detected: Option<String> = None;
+    loop {
+        let interval_secs = config.get().gamepad.process_watch_secs;
+        sleep(Duration::from_secs(interval_secs.max(1))).await;
+
+        let current = config.get();
+        if interval_secs == 0 || current.profiles.values().all(|p| p.processes.is_empty()) {
+            continue;
+        }
+        let found = detect_profile(&current);
+        if found != detected {
+            if let Err(e) = profiles.select(&current, found.as_deref(), "proceso detectado") {
+                log_detail(Verbosity::Low, "Error cambiando perfil", &e);
+            }
+            detected = found;
+        }
+    }
+}
+
+fn detect_profile(config: &Config) -> Option<String> {
+    let commands = running_commands();
+    config
+        .profiles
+        .iter()
+        .find(|(_, profile)| {
+            profile
+                .processes
+                .iter()
+                .any(|pattern| commands.iter().any(|command| command.contains(pattern.as_str())))
+        })
+        .map(|(name, _)| name.clone())
+}
+
+// Command lines of every process, arguments separated by spaces
+fn running_commands() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|e| e.file_name().to_str().is_some_and(|n| n.bytes().all(|b| b.is_ascii_digit())))
+        .filter_map(|e| std::fs::read(e.path().join("cmdline")).ok())
+        .filter(|cmdline| !cmdline.is_empty())
+        .map(|cmdline| String::from_utf8_lossy(&cmdline).replace('\0', " "))
+        .collect()
+}
@@ -0,0 +1,91 @@
+use crate::config::{Config, GamepadConfig};
+use crate::logger::{log_detail, Verbosity};
+use std::sync::{Arc, RwLock};
+
+/// Nombre reservado que vuelve a la configuración base de `[gamepad]`.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Perfil de mapeo activo (`[profiles.<nombre>]`). Lo cambian el vigilante de
+/// procesos, el socket de administración o el propio cliente; el servidor de
+/// gamepad lo aplica en cada snapshot.
+#[derive(Clone, Default)]
+pub struct ProfileManager {
+    active: Arc<RwLock<Option<String>>>,
+}
+
+impl ProfileManager {
+    pub fn active(&self) -> Option<String> {
+        self.active.read().unwrap().clone()
+    }
+
+    /// Activa `name` (o la configuración base con `None`/"default").
+    /// `origin` sólo sirve para el log.
+    pub fn select(&self, config: &Config, name: Option<&str>, origin: &str) -> Result<(), String> {
+        let name = name.filter(|n| *n != DEFAULT_PROFILE);
+        if let Some(name) = name
+            && !config.profiles.contains_key(name)
+        {
+            return Err(format!("perfil desconocido '{}'", name));
+        }
+        let mut active = self.active.write().unwrap();
+        if active.as_deref() != name {
+            *active = name.map(str::to_string);
+            log_detail(
+                Verbosity::Low,
+                "Perfil de mapeo",
+                &format!("{} ({})", name.unwrap_or(DEFAULT_PROFILE), origin),
+            );
+        }
+        Ok(())
+    }
+
+    /// `[gamepad]` con los ajustes del perfil activo encima. Si una recarga
+    /// eliminó el perfil, se usa la base.
+    pub fn gamepad_config(&self, config: &Config) -> GamepadConfig {
+        let mut gamepad = config.gamepad.clone();
+        let active = self.active.read().unwrap();
+        let Some(profile) = active.as_deref().and_then(|name| config.profiles.get(name)) else {
+            return gamepad;
+        };
+        if let Some(four_way) = profile.four_way {
+            gamepad.four_way = four_way;
+        }
+        if let Some(navigation) = profile.navigation {
+            gamepad.navigation = navigation;
+        }
+        if let Some(flick_scroll) = profile.flick_scroll {
+            gamepad.flick_scroll = flick_scroll;
+        }
+        if let Some(encoder) = profile.encoder {
+            gamepad.encoder = encoder;
+        }
+        if let Some(mirror) = profile.mirror {
+            gamepad.mirror = mirror;
+        }
+        if let Some(mode) = profile.trigger_mode {
+            gamepad.trigger_mode = mode;
+        }
+        if let Some(threshold) = profile.trigger_threshold {
+            gamepad.trigger_threshold = threshold;
+        }
+        if let Some(threshold) = profile.hat_threshold {
+            gamepad.hat_threshold = threshold;
+        }
+        if let Some(guide) = profile.guide {
+            gamepad.guide = guide;
+        }
+        if let Some(hotkeys) = &profile.hotkeys {
+            gamepad.hotkeys = hotkeys.clone();
+        }
+        if let Some(chords) = &profile.chords {
+            gamepad.chords = chords.clone();
+        }
+        if let Some(filters) = &profile.filters {
+            gamepad.filters = filters.clone();
+        }
+        if let Some(ranges) = profile.axis_ranges {
+            gamepad.axis_ranges = ranges;
+        }
+        gamepad
+    }
+}
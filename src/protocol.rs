@@ -1,15 +1,149 @@
 // Network packet headers
 pub const HEADER_MOUSE: u8 = 0x20;
+// [0x21][action:1][buttons:1] action 1 = begin drag (buttons stay down), 0 = end drag
+pub const HEADER_MOUSE_DRAG: u8 = 0x21;
+// [0x22][buttons:1][count:1] click `count` times (2 = double click) with server-side timing
+pub const HEADER_MOUSE_CLICK: u8 = 0x22;
+// [0x23][x:u16][y:u16][buttons:1] absolute aim for the lightgun, little-endian, 0..65535
+// across the client's touch surface; bit 0 trigger, 1 and 2 aux, 3 = shoot off-screen
+pub const HEADER_LIGHTGUN: u8 = 0x23;
+// [0x24]                client -> server, ask for the connected screens
+// [0x25][count:1][x:i16][y:i16][width:u16][height:u16][flags:1] x count, server -> client,
+//        desktop pixels; flags bit 0 = primary, bit 1 = the one the lightgun aims at.
+//        Also sent after HELLO_ACK on the mouse channel.
+// [0x26][screen:1]      client -> server, confine the lightgun to screen N (1-based,
+//        0 = whole desktop); answered with SCREENS
+pub const HEADER_SCREENS_QUERY: u8 = 0x24;
+pub const HEADER_SCREENS: u8 = 0x25;
+pub const HEADER_SCREEN_SELECT: u8 = 0x26;
+// [0x27][x:u16][y:u16] client -> server, put the lightgun at this raw position, 0..65535
+//        over its whole range with no calibration or screen applied; [0x27] alone centers it
+// [0x28]                client -> server, ask where the lightgun is
+// [0x29][x:u16][y:u16] server -> client, its last emitted position, same scale as 0x27
+pub const HEADER_POINTER_WARP: u8 = 0x27;
+pub const HEADER_POINTER_QUERY: u8 = 0x28;
+pub const HEADER_POINTER_POSITION: u8 = 0x29;
 pub const HEADER_KEYBOARD: u8 = 0x10;
+// [0x11][scancode:1] latches a modifier for the next key press
+pub const HEADER_STICKY_MODIFIER: u8 = 0x11;
+// [0x12][leds:1] server -> client, bit 0 Num Lock, bit 1 Caps Lock, bit 2 Scroll Lock
+pub const HEADER_LED_STATE: u8 = 0x12;
+// [0x13][len:1][utf8:len] characters typed as text, translated with [keyboard] layout
+pub const HEADER_TEXT: u8 = 0x13;
+// [0x14][scancode:1][value:1] analog key, 0 = released .. 255 = fully pressed;
+// keys in [keyboard] analog_keys move a gamepad axis, others are plain key presses
+pub const HEADER_ANALOG_KEY: u8 = 0x14;
+// [0x15][scancode:u16][state:1] as 0x10, for codes above 255
+pub const HEADER_KEYBOARD_WIDE: u8 = 0x15;
 pub const HEADER_MODE_SWITCH: u8 = 0x30;
 pub const HEADER_MODE_ACK: u8 = 0x31;
+// [0x32][len:1][name:len] client -> server, "default" = base [gamepad] settings
+// [0x33][status:1][len:1][name:len] server -> client, status 1 = active, 0 = unknown profile
+pub const HEADER_PROFILE_SELECT: u8 = 0x32;
+pub const HEADER_PROFILE_ACK: u8 = 0x33;
+// [0x34][mac:6] client -> server, send a Wake-on-LAN magic packet to that MAC ([power] wake_relay)
+// [0x35][status:1] server -> client, 1 = sent, 0 = disabled or failed
+pub const HEADER_WAKE: u8 = 0x34;
+pub const HEADER_WAKE_ACK: u8 = 0x35;
+// [0x36] client -> server, take the keyboard input token ([keyboard] arbitration = "token")
+// [0x37] client -> server, give it up
+// [0x38][state:1] server -> client, 0 = free, 1 = this connection holds it, 2 = another client does
+pub const HEADER_TOKEN_REQUEST: u8 = 0x36;
+pub const HEADER_TOKEN_RELEASE: u8 = 0x37;
+pub const HEADER_TOKEN_STATE: u8 = 0x38;
+// [0x39][len:1][ip:len] server -> token holder, another device asks for the token; len 0 = request withdrawn
+// [0x3A][approve:1] token holder -> server, 1 = hand the token over, 0 = keep it
+pub const HEADER_TAKEOVER_REQUEST: u8 = 0x39;
+pub const HEADER_TAKEOVER_ANSWER: u8 = 0x3A;
+// [0x42][mode:1][buttons:u16][axes: 8 x i16] mode 1 = arcade, 2 = xbox, 0 = let the server guess
+// Legacy, still accepted: [0x42][buttons: 12 x u8][axes: 8 x i16], 29 bytes, mode guessed
 pub const HEADER_GAMEPAD_SNAPSHOT: u8 = 0x42;
+// [0x43][action:1] client -> server: 1 = start, 0 = finish and store, 2 = clear
+// [0x44][action:1][status:1] server -> client, status 1 = ok, 0 = failed
+pub const HEADER_CALIBRATE: u8 = 0x43;
+pub const HEADER_CALIBRATE_ACK: u8 = 0x44;
+// [0x45][slot:1] client -> server, request player slot (1-based, 0 = any free)
+// [0x46]         client -> server, release the slot
+// [0x47][slot:1] server -> client, slot given to the client (0 = none)
+pub const HEADER_SLOT_REQUEST: u8 = 0x45;
+pub const HEADER_SLOT_RELEASE: u8 = 0x46;
+pub const HEADER_SLOT_ASSIGN: u8 = 0x47;
+// [0x48][level:1][charging:1] client -> server, battery percentage 0-100
+pub const HEADER_BATTERY: u8 = 0x48;
+// [0x49][pattern:1] server -> client, 1 = tick, 2 = double buzz, 3 = long
+// (also on TCP, but only to clients that sent HELLO)
+pub const HEADER_HAPTIC: u8 = 0x49;
+// [0x4A][accel xyz: 3 x f32][gyro pitch/yaw/roll: 3 x f32] client -> server, little-endian,
+// g and deg/s in DSU axes; served to emulators by [dsu]
+pub const HEADER_MOTION: u8 = 0x4A;
+// [0x4B][strong:1][weak:1] server -> client, motor strengths 0-255 of the game's
+// force feedback for the client's slot, sent on every change (0, 0 = stop)
+// [0x4C][enabled:1][intensity:1] client -> server, rumble settings stored in the
+// client's profile; intensity in percent, 0-200
+pub const HEADER_RUMBLE: u8 = 0x4B;
+pub const HEADER_RUMBLE_SETTINGS: u8 = 0x4C;
+// [0x4D][mode:1][buttons:u16][axes: 8 x i16][count:1][extra: count x i16], optionally
+// [pressure:10]: a snapshot that also drives the [gamepad] extra_axes, in their order
+pub const HEADER_GAMEPAD_SNAPSHOT_EXTENDED: u8 = 0x4D;
+pub const MAX_EXTRA_AXES: usize = 5;
+
+// Calibration actions
+pub const CALIBRATE_FINISH: u8 = 0x00;
+pub const CALIBRATE_START: u8 = 0x01;
+pub const CALIBRATE_CLEAR: u8 = 0x02;
 pub const HEADER_DISCOVERY: u8 = 0x50;
+// [0x51] client -> server on [discovery] register_port, from a client outside the LAN;
+// answered with the DISCOVERY announcement, which then keeps coming in unicast for 5 min
+pub const HEADER_REGISTER: u8 = 0x51;
+pub const DEFAULT_REGISTER_PORT: u16 = 5559;
+// Rendezvous (see rendezvous.rs), UDP:
+// [0x70][role:1][channel:1][len:1][name:len] to the rendezvous, role 0 = server, 1 = client;
+//        channel 0 = control socket, 1 = mouse UDP socket, 2 = gamepad UDP socket
+// [0x71][channel:1][ip:4][port:u16] rendezvous -> control socket, the other side's public address
+// [0x72] from a data socket to the other side's, only to open the NAT; ignored
+pub const HEADER_RENDEZVOUS_REGISTER: u8 = 0x70;
+pub const HEADER_RENDEZVOUS_PEER: u8 = 0x71;
+pub const HEADER_PUNCH: u8 = 0x72;
+pub const DEFAULT_RENDEZVOUS_PORT: u16 = 5560;
+// [0x60][len:1][token:len] client -> server (len 0 = request a new token)
+// [0x61][token:16]         server -> client
+pub const HEADER_HELLO: u8 = 0x60;
+pub const HEADER_HELLO_ACK: u8 = 0x61;
+// With [security] packet_key, every mouse/gamepad UDP datagram ends in
+// [counter:u64][tag:16], tag = first 16 bytes of HMAC-SHA256(key, nonce || counter || datagram).
+// [0x62]          client -> server, ask for a nonce (the only datagram sent without a tag)
+// [0x63][nonce:8] server -> client, also sent when a tagged datagram arrives from an unknown address
+pub const HEADER_AUTH_CHALLENGE: u8 = 0x62;
+pub const HEADER_AUTH_NONCE: u8 = 0x63;
+pub const AUTH_NONCE_LEN: usize = 8;
+pub const AUTH_TAG_LEN: usize = 16;
 
 // Input mode identifiers
 pub const MODE_MOUSE_KEYBOARD: u8 = 0x01;
 pub const MODE_GAMEPAD: u8 = 0x02;
 
+// Snapshot processing modes, as sent in the snapshot's mode byte
+pub const MODE_ARCADE: u8 = 1;   // Arcade layout (snap to 8 directions + -32768)
+pub const MODE_XBOX: u8 = 2;     // Xbox layout with real intermediate values
+
+// Largest datagram accepted on the UDP ports; bigger ones are dropped whole
+// instead of being parsed truncated. Leaves room for protocol extensions.
+pub const MAX_DATAGRAM: usize = 512;
+
+// Default server ports (see [network] in the config)
+pub const DEFAULT_MOUSE_PORT: u16 = 5555;
+pub const DEFAULT_KEYBOARD_PORT: u16 = 5556;
+pub const DEFAULT_GAMEPAD_PORT: u16 = 5558;
+
+// QUIC ([quic] listen, ALPN "retro-control"): each bidirectional stream carries the
+// keyboard protocol of one TCP connection; datagrams are [channel:1][datagram] both ways,
+// the datagram being what the mouse (0x01) or gamepad (0x02) UDP port carries
+pub const QUIC_ALPN: &[u8] = b"retro-control";
+pub const QUIC_CHANNEL_MOUSE: u8 = 0x01;
+pub const QUIC_CHANNEL_GAMEPAD: u8 = 0x02;
+
 // Discovery broadcast configuration
 pub const DISCOVERY_PORT: u16 = 5557;
 pub const DISCOVERY_INTERVAL_MS: u64 = 2000;
+pub const DISCOVERY_FAST_INTERVAL_MS: u64 = 500;
+pub const DISCOVERY_FAST_PERIOD_SECS: u64 = 60;
@@ -0,0 +1,169 @@
+use crate::config::SecurityConfig;
+use crate::logger::{log_detail, Verbosity};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+struct Offender {
+    /// Violaciones dentro de la ventana actual
+    violations: u32,
+    window_start: Instant,
+    total: u64,
+    banned_until: Option<Instant>,
+    channel: &'static str,
+}
+
+/// Fuentes que envían paquetes que no respetan el protocolo (ruido de la
+/// LAN, escáneres, clientes rotos). Pasado `[security] quarantine_threshold`
+/// dentro de la ventana, sus paquetes se ignoran durante `quarantine_secs`.
+/// Se consulta con `bans` y se limpia con `unban` en el socket de administración.
+#[derive(Default)]
+pub struct Quarantine {
+    offenders: Mutex<HashMap<IpAddr, Offender>>,
+}
+
+pub static QUARANTINE: LazyLock<Quarantine> = LazyLock::new(Quarantine::default);
+
+impl Quarantine {
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        let mut offenders = self.offenders.lock().unwrap();
+        let Some(offender) = offenders.get_mut(&ip) else {
+            return false;
+        };
+        match offender.banned_until {
+            Some(until) if Instant::now() < until => true,
+            Some(_) => {
+                offender.banned_until = None;
+                offender.violations = 0;
+                log_detail(Verbosity::Low, "Cuarentena terminada", &ip.to_string());
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Anota un paquete inválido de `ip`.
+    pub fn violation(&self, ip: IpAddr, channel: &'static str, security: &SecurityConfig) {
+        if security.quarantine_threshold == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let window = Duration::from_secs(security.quarantine_window_secs.max(1));
+        let mut offenders = self.offenders.lock().unwrap();
+        let offender = offenders.entry(ip).or_insert(Offender {
+            violations: 0,
+            window_start: now,
+            total: 0,
+            banned_until: None,
+            channel,
+        });
+        if now.duration_since(offender.window_start) > window {
+            offender.violations = 0;
+            offender.window_start = now;
+        }
+        offender.violations += 1;
+        offender.total += 1;
+        offender.channel = channel;
+        if offender.banned_until.is_none() && offender.violations >= security.quarantine_threshold {
+            offender.banned_until = Some(now + Duration::from_secs(security.quarantine_secs));
+            log_detail(
+                Verbosity::Low,
+                "Fuente en cuarentena",
+                &format!(
+                    "{} ({}): {} paquetes inválidos en {}s, ignorada {}s",
+                    ip,
+                    channel,
+                    offender.violations,
+                    window.as_secs(),
+                    security.quarantine_secs
+                ),
+            );
+        }
+    }
+
+    /// Una línea por fuente: `ip canal violaciones=N baneada=Ns`.
+    pub fn render(&self) -> String {
+        let offenders = self.offenders.lock().unwrap();
+        let now = Instant::now();
+        let mut out = String::new();
+        for (ip, offender) in offenders.iter() {
+            let remaining = offender.banned_until.map_or(0, |until| until.saturating_duration_since(now).as_secs());
+            let _ = writeln!(out, "{} {} violations={} banned={}s", ip, offender.channel, offender.total, remaining);
+        }
+        out.trim_end().to_string()
+    }
+
+    /// Olvida `ip` (o todas con `None`). Devuelve cuántas fuentes se limpiaron.
+    pub fn clear(&self, ip: Option<IpAddr>) -> usize {
+        let mut offenders = self.offenders.lock().unwrap();
+        match ip {
+            Some(ip) => offenders.remove(&ip).is_some() as usize,
+            None => {
+                let count = offenders.len();
+                offenders.clear();
+                count
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 168, 1, last])
+    }
+
+    fn security(threshold: u32, secs: u64) -> SecurityConfig {
+        SecurityConfig { quarantine_threshold: threshold, quarantine_secs: secs, ..SecurityConfig::default() }
+    }
+
+    #[test]
+    fn threshold_bans_the_source() {
+        let quarantine = Quarantine::default();
+        let security = security(3, 300);
+        quarantine.violation(ip(1), "Mouse", &security);
+        quarantine.violation(ip(1), "Mouse", &security);
+        assert!(!quarantine.is_banned(ip(1)));
+        quarantine.violation(ip(1), "Mouse", &security);
+        assert!(quarantine.is_banned(ip(1)));
+        assert!(!quarantine.is_banned(ip(2)));
+        assert!(quarantine.render().starts_with("192.168.1.1 Mouse violations=3 banned=29"));
+    }
+
+    #[test]
+    fn zero_threshold_never_bans() {
+        let quarantine = Quarantine::default();
+        let security = security(0, 300);
+        for _ in 0..100 {
+            quarantine.violation(ip(1), "Mouse", &security);
+        }
+        assert!(!quarantine.is_banned(ip(1)));
+        assert_eq!(quarantine.render(), "");
+    }
+
+    #[test]
+    fn ban_ends_after_quarantine_secs() {
+        let quarantine = Quarantine::default();
+        quarantine.violation(ip(1), "Gamepad", &security(1, 0));
+        assert!(!quarantine.is_banned(ip(1)));
+        assert_eq!(quarantine.render(), "192.168.1.1 Gamepad violations=1 banned=0s");
+    }
+
+    #[test]
+    fn clear_forgets_one_or_all() {
+        let quarantine = Quarantine::default();
+        let security = security(1, 300);
+        quarantine.violation(ip(1), "Mouse", &security);
+        quarantine.violation(ip(2), "Mouse", &security);
+        quarantine.violation(ip(3), "Mouse", &security);
+        assert_eq!(quarantine.clear(Some(ip(1))), 1);
+        assert_eq!(quarantine.clear(Some(ip(1))), 0);
+        assert!(!quarantine.is_banned(ip(1)));
+        assert_eq!(quarantine.clear(None), 2);
+        assert!(!quarantine.is_banned(ip(2)));
+    }
+}
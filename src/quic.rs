@@ -0,0 +1,235 @@
+use crate::config::{ConfigHandle, QuicConfig};
+use crate::logger::{log_detail, Verbosity};
+use crate::network::{Carrier, Datagram};
+use crate::protocol::{QUIC_ALPN, QUIC_CHANNEL_GAMEPAD, QUIC_CHANNEL_MOUSE};
+use crate::quarantine::QUARANTINE;
+use quinn::crypto::rustls::QuicServerConfig;
+use quinn::{Connection, Endpoint, RecvStream, SendStream, TransportConfig};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::os::unix::fs::OpenOptionsExt;
+use std::pin::Pin;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc;
+
+// Open QUIC connections at once; further handshakes are refused
+const MAX_CONNECTIONS: usize = 16;
+// Phones drop idle NAT mappings quickly; the keep-alive also spots a vanished client
+const KEEP_ALIVE: Duration = Duration::from_secs(5);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Conexiones abiertas, por la dirección con la que llegaron. Los servidores
+/// conocen al cliente por esa dirección aunque después migre de red, y les
+/// responden a través de la conexión (ver `network::ReplySocket`).
+static PEERS: LazyLock<Mutex<HashMap<SocketAddr, Connection>>> = LazyLock::new(Mutex::default);
+
+/// Adónde entrega el listener QUIC lo que recibe: los streams al servidor
+/// del teclado y cada datagrama al servidor UDP de su canal.
+pub struct QuicRoutes {
+    pub streams: mpsc::Sender<(QuicStream, SocketAddr)>,
+    pub mouse: mpsc::Sender<Datagram>,
+    pub gamepad: mpsc::Sender<Datagram>,
+}
+
+/// Stream bidireccional QUIC: una conexión del teclado, con el mismo
+/// protocolo que por TCP.
+pub struct QuicStream {
+    send: SendStream,
+    recv: RecvStream,
+    // Byte read by `peek`, handed out again by the next read
+    peeked: Option<u8>,
+}
+
+impl QuicStream {
+    /// Como `TcpStream::peek`, de a un byte.
+    pub async fn peek(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some(first) = buf.first_mut() else {
+            return Ok(0);
+        };
+        if self.peeked.is_none() {
+            let mut byte = [0u8; 1];
+            match self.recv.read(&mut byte).await? {
+                Some(1) => self.peeked = Some(byte[0]),
+                _ => return Ok(0),
+            }
+        }
+        *first = self.peeked.unwrap();
+        Ok(1)
+    }
+
+    /// Bytes que ya llegaron y nadie leyó. Sólo cuenta el de `peek`: quinn
+    /// no dice cuánto tiene guardado.
+    pub fn queued(&self) -> u64 {
+        self.peeked.is_some() as u64
+    }
+}
+
+impl AsyncRead for QuicStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let stream = self.get_mut();
+        if buf.remaining() > 0
+            && let Some(byte) = stream.peeked.take()
+        {
+            buf.put_slice(&[byte]);
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut stream.recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        AsyncWrite::poll_write(Pin::new(&mut self.get_mut().send), cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().send).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.get_mut().send).poll_shutdown(cx)
+    }
+}
+
+/// Envía `data` como datagrama del canal `channel` si `addr` es un cliente
+/// QUIC. `None` si no lo es y hay que usar el socket UDP.
+pub fn send_datagram(channel: u8, data: &[u8], addr: SocketAddr) -> Option<std::io::Result<()>> {
+    let connection = PEERS.lock().unwrap().get(&addr)?.clone();
+    let mut packet = Vec::with_capacity(data.len() + 1);
+    packet.push(channel);
+    packet.extend_from_slice(data);
+    Some(connection.send_datagram(packet.into()).map_err(std::io::Error::other))
+}
+
+/// Abre el listener de `[quic] listen` con el certificado de `cert` y `key`.
+/// Si no existe ninguno de los dos, crea ahí un par autofirmado que los
+/// clientes fijan la primera vez. Como los demás sockets, se abre antes de
+/// abandonar root.
+pub fn bind(addr: SocketAddr, config: &QuicConfig) -> Result<Endpoint, String> {
+    let (certs, key) = load_identity(config)?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut tls = rustls::ServerConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(|e| e.to_string())?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("{}: certificado QUIC no válido: {}", config.cert.display(), e))?;
+    tls.alpn_protocols = vec![QUIC_ALPN.to_vec()];
+    let crypto = QuicServerConfig::try_from(tls).map_err(|e| e.to_string())?;
+    let mut transport = TransportConfig::default();
+    transport.keep_alive_interval(Some(KEEP_ALIVE));
+    transport.max_idle_timeout(Some(IDLE_TIMEOUT.try_into().unwrap()));
+    let mut server = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    server.transport_config(Arc::new(transport));
+    Endpoint::server(server, addr).map_err(|e| format!("no se pudo abrir QUIC en {}: {}", addr, e))
+}
+
+fn load_identity(config: &QuicConfig) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), String> {
+    if !config.cert.exists() && !config.key.exists() {
+        create_identity(config).map_err(|e| format!("no se pudo crear el certificado QUIC: {}", e))?;
+    }
+    let certs = CertificateDer::pem_file_iter(&config.cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("no se pudo leer {}: {}", config.cert.display(), e))?;
+    let key =
+        PrivateKeyDer::from_pem_file(&config.key).map_err(|e| format!("no se pudo leer {}: {}", config.key.display(), e))?;
+    Ok((certs, key))
+}
+
+fn create_identity(config: &QuicConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let generated = rcgen::generate_simple_self_signed(vec!["retro-control".to_string()])?;
+    for path in [&config.cert, &config.key] {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+    }
+    // Only root (or the unprivileged user after `chown`) may read the key
+    let mut key = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&config.key)?;
+    key.write_all(generated.signing_key.serialize_pem().as_bytes())?;
+    std::fs::write(&config.cert, generated.cert.pem())?;
+    log_detail(Verbosity::Low, "Certificado QUIC creado", &config.cert.display().to_string());
+    Ok(())
+}
+
+/// Atiende el listener QUIC. Cada conexión lleva los mismos protocolos que
+/// los puertos de siempre: cada stream bidireccional es una conexión del
+/// teclado y los datagramas, `[canal][datagrama]`, van al ratón o al mando.
+/// QUIC añade cifrado, control de congestión y un solo puerto para todo.
+pub async fn run_quic(endpoint: Endpoint, routes: QuicRoutes, config: ConfigHandle) -> std::io::Result<()> {
+    let routes = Arc::new(routes);
+    while let Some(incoming) = endpoint.accept().await {
+        let ip = incoming.remote_address().ip();
+        if !config.get().is_allowed(ip) {
+            log_detail(Verbosity::Low, "Conexión QUIC rechazada", &format!("ip={} fuera de la allowlist", ip));
+            incoming.ignore();
+            continue;
+        }
+        if QUARANTINE.is_banned(ip) {
+            log_detail(Verbosity::Medium, "Conexión QUIC rechazada", &format!("ip={} en cuarentena", ip));
+            incoming.ignore();
+            continue;
+        }
+        if PEERS.lock().unwrap().len() >= MAX_CONNECTIONS {
+            log_detail(Verbosity::Low, "Conexión QUIC rechazada", &format!("ip={}: ya hay {}", ip, MAX_CONNECTIONS));
+            incoming.refuse();
+            continue;
+        }
+        let (routes, config) = (routes.clone(), config.clone());
+        tokio::spawn(async move {
+            match incoming.await {
+                Ok(connection) => serve_connection(connection, &routes, &config).await,
+                Err(e) => log_detail(Verbosity::Medium, "Error en conexión QUIC", &format!("{}: {}", ip, e)),
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn serve_connection(connection: Connection, routes: &QuicRoutes, config: &ConfigHandle) {
+    let addr = connection.remote_address();
+    log_detail(Verbosity::Medium, "Conexión QUIC aceptada", &format!("ip={}", addr.ip()));
+    PEERS.lock().unwrap().insert(addr, connection.clone());
+    let reason = loop {
+        tokio::select! {
+            stream = connection.accept_bi() => match stream {
+                Ok((send, recv)) => {
+                    let stream = QuicStream { send, recv, peeked: None };
+                    if routes.streams.send((stream, addr)).await.is_err() {
+                        break "servidor del teclado detenido".to_string();
+                    }
+                }
+                Err(e) => break e.to_string(),
+            },
+            datagram = connection.read_datagram() => match datagram {
+                Ok(datagram) => route_datagram(&datagram, addr, routes, config).await,
+                Err(e) => break e.to_string(),
+            },
+        }
+    };
+    PEERS.lock().unwrap().remove(&addr);
+    log_detail(Verbosity::Medium, "Conexión QUIC cerrada", &format!("{}: {}", addr, reason));
+}
+
+async fn route_datagram(datagram: &[u8], addr: SocketAddr, routes: &QuicRoutes, config: &ConfigHandle) {
+    let Some((&channel, payload)) = datagram.split_first() else {
+        return;
+    };
+    let queue = match channel {
+        QUIC_CHANNEL_MOUSE => &routes.mouse,
+        QUIC_CHANNEL_GAMEPAD => &routes.gamepad,
+        other => {
+            log_detail(Verbosity::Medium, "Datagrama QUIC descartado", &format!("{}: canal {:#04x}", addr, other));
+            QUARANTINE.violation(addr.ip(), "QUIC", &config.get().security);
+            return;
+        }
+    };
+    // Waits like the SO_REUSEPORT receivers do; quinn drops datagrams while its buffer is full
+    let _ = queue.send(Datagram::new(payload, addr, Carrier::Quic)).await;
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+// Buckets not touched for this long are forgotten
+const BUCKET_IDLE_TTL: Duration = Duration::from_secs(60);
+
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token bucket por IP de origen. Cada servidor UDP tiene el suyo y lo usa
+/// desde su bucle de recepción, así que no necesita locks.
+pub struct RateLimiter {
+    buckets: HashMap<IpAddr, Bucket>,
+    last_prune: Instant,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self {
+            buckets: HashMap::new(),
+            last_prune: Instant::now(),
+        }
+    }
+
+    /// `rate` = paquetes/s sostenidos, `burst` = ráfaga máxima. `rate == 0`
+    /// desactiva el límite.
+    pub fn allow(&mut self, ip: IpAddr, rate: u32, burst: u32) -> bool {
+        if rate == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        if now.duration_since(self.last_prune) >= BUCKET_IDLE_TTL {
+            self.buckets
+                .retain(|_, b| now.duration_since(b.last_refill) < BUCKET_IDLE_TTL);
+            self.last_prune = now;
+        }
+
+        let capacity = burst.max(1) as f64;
+        let bucket = self.buckets.entry(ip).or_insert(Bucket {
+            tokens: capacity,
+            last_refill: now,
+        });
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate as f64).min(capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 168, 1, last])
+    }
+
+    #[test]
+    fn zero_rate_never_limits() {
+        let mut limiter = RateLimiter::new();
+        assert!((0..1000).all(|_| limiter.allow(ip(1), 0, 1)));
+    }
+
+    #[test]
+    fn burst_then_refused() {
+        let mut limiter = RateLimiter::new();
+        assert!((0..5).all(|_| limiter.allow(ip(1), 1, 5)));
+        assert!(!limiter.allow(ip(1), 1, 5));
+        // Each address has its own bucket
+        assert!(limiter.allow(ip(2), 1, 5));
+    }
+
+    #[test]
+    fn tokens_refill_over_time() {
+        let mut limiter = RateLimiter::new();
+        assert!(limiter.allow(ip(1), 1000, 1));
+        assert!(!limiter.allow(ip(1), 1000, 1));
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(limiter.allow(ip(1), 1000, 1));
+    }
+}
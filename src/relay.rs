@@ -0,0 +1,182 @@
+use crate::config::{Config, RelayConfig};
+use crate::logger::{log_detail, Verbosity};
+use crate::network::{ClientStream, ReplySocket};
+use crate::protocol::{DEFAULT_GAMEPAD_PORT, DEFAULT_KEYBOARD_PORT, DEFAULT_MOUSE_PORT, MAX_DATAGRAM};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{LazyLock, Mutex};
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+// Relayed UDP clients kept at once; the least recently used one is dropped
+const MAX_LINKS: usize = 64;
+// Chunks of a relayed keyboard stream waiting for a slow remote
+const TEE_BACKLOG: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RelayChannel {
+    Mouse,
+    Gamepad,
+}
+
+/// Servidor remoto al que se reenvía la entrada.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayTarget {
+    pub name: String,
+    pub ip: IpAddr,
+}
+
+struct Link {
+    // Sent to directly: a fresh tokio socket reports WouldBlock until the reactor polls it
+    socket: std::net::UdpSocket,
+    replies: JoinHandle<()>,
+    last_used: Instant,
+}
+
+/// Modo relé: la entrada recibida se reenvía tal cual, con el mismo
+/// protocolo, a otra instancia de retro-control-server (`[relay] targets`).
+/// El destino activo se elige con `relay <nombre>` en el socket de
+/// administración.
+pub struct Relay {
+    active: watch::Sender<Option<RelayTarget>>,
+    // One socket per relayed UDP client, so the remote tells them apart
+    links: Mutex<HashMap<(RelayChannel, SocketAddr), Link>>,
+}
+
+pub static RELAY: LazyLock<Relay> =
+    LazyLock::new(|| Relay { active: watch::channel(None).0, links: Mutex::default() });
+
+impl Relay {
+    pub fn active(&self) -> Option<RelayTarget> {
+        self.active.borrow().clone()
+    }
+
+    /// Activa el destino `name` de `[relay] targets` (`None` = no reenviar).
+    pub fn select(&self, config: &Config, name: Option<&str>) -> Result<(), String> {
+        let target = match name {
+            Some(name) => {
+                let ip = config.relay.targets.get(name).ok_or_else(|| format!("destino de relé desconocido '{}'", name))?;
+                Some(RelayTarget { name: name.to_string(), ip: *ip })
+            }
+            None => None,
+        };
+        if self.active.send_if_modified(|active| std::mem::replace(active, target.clone()) != target) {
+            for (_, link) in self.links.lock().unwrap().drain() {
+                link.replies.abort();
+            }
+            log_detail(Verbosity::Low, "Relé", target.as_ref().map_or("desactivado", |t| t.name.as_str()));
+        }
+        Ok(())
+    }
+
+    /// Reenvía un datagrama de `client` si hay un destino activo. Devuelve si
+    /// el servidor local debe procesarlo también. Sin `[relay] local`, las
+    /// respuestas del remoto vuelven al cliente a través de `reply`.
+    pub fn forward_datagram(
+        &self,
+        channel: RelayChannel,
+        client: SocketAddr,
+        data: &[u8],
+        reply: &ReplySocket,
+        config: &RelayConfig,
+    ) -> bool {
+        let Some(target) = self.active() else {
+            return true;
+        };
+        let port = match channel {
+            RelayChannel::Mouse => DEFAULT_MOUSE_PORT,
+            RelayChannel::Gamepad => DEFAULT_GAMEPAD_PORT,
+        };
+        let mut links = self.links.lock().unwrap();
+        if !links.contains_key(&(channel, client)) {
+            if links.len() >= MAX_LINKS
+                && let Some(oldest) = links.iter().min_by_key(|(_, link)| link.last_used).map(|(key, _)| *key)
+                && let Some(link) = links.remove(&oldest)
+            {
+                link.replies.abort();
+            }
+            match open_link(SocketAddr::new(target.ip, port), client, reply.clone(), config.local) {
+                Ok(link) => {
+                    links.insert((channel, client), link);
+                }
+                Err(e) => {
+                    log_detail(Verbosity::Medium, "Error en el relé", &format!("{}: {}", target.name, e));
+                    return config.local;
+                }
+            }
+        }
+        let link = links.get_mut(&(channel, client)).unwrap();
+        link.last_used = Instant::now();
+        let _ = link.socket.send(data);
+        config.local
+    }
+
+    /// Para una conexión TCP nueva: a qué puerto de teclado reenviarla, si
+    /// hay un destino activo.
+    pub fn stream_target(&self) -> Option<SocketAddr> {
+        self.active().map(|target| SocketAddr::new(target.ip, DEFAULT_KEYBOARD_PORT))
+    }
+
+    /// Se resuelve cuando cambia el destino activo.
+    pub async fn changed(&self) {
+        let _ = self.active.subscribe().changed().await;
+    }
+}
+
+fn open_link(remote: SocketAddr, client: SocketAddr, reply: ReplySocket, local: bool) -> std::io::Result<Link> {
+    let unspecified: SocketAddr = if remote.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { ([0u16; 8], 0).into() };
+    let socket = std::net::UdpSocket::bind(unspecified)?;
+    socket.connect(remote)?;
+    socket.set_nonblocking(true)?;
+    let reader = UdpSocket::from_std(socket.try_clone()?)?;
+    let replies = tokio::spawn(async move {
+        let mut buf = [0u8; MAX_DATAGRAM];
+        while let Ok(len) = reader.recv(&mut buf).await {
+            // With local emission on, the local server already answers the client
+            if !local {
+                let _ = reply.send_to(&buf[..len], client).await;
+            }
+        }
+    });
+    Ok(Link { socket, replies, last_used: Instant::now() })
+}
+
+/// Reenvía una conexión de teclado entera a `remote` hasta que cualquiera
+/// de los dos lados cierre o cambie el destino del relé.
+pub async fn proxy_stream(mut client: ClientStream, remote: SocketAddr) -> std::io::Result<()> {
+    let mut upstream = TcpStream::connect(remote).await?;
+    tokio::select! {
+        result = tokio::io::copy_bidirectional(&mut client, &mut upstream) => result.map(drop),
+        _ = RELAY.changed() => Ok(()),
+    }
+}
+
+/// Copia al remoto los bytes que el servidor local lee de una conexión
+/// (`[relay] local = true`). Las respuestas del remoto se descartan.
+pub fn spawn_stream_tee(remote: SocketAddr) -> mpsc::Sender<Vec<u8>> {
+    let (tx, mut rx) = mpsc::channel::<Vec<u8>>(TEE_BACKLOG);
+    tokio::spawn(async move {
+        let mut upstream = match TcpStream::connect(remote).await {
+            Ok(upstream) => upstream,
+            Err(e) => {
+                log_detail(Verbosity::Medium, "Error en el relé", &format!("{}: {}", remote, e));
+                return;
+            }
+        };
+        let mut discard = [0u8; 256];
+        loop {
+            tokio::select! {
+                chunk = rx.recv() => match chunk {
+                    Some(chunk) if upstream.write_all(&chunk).await.is_ok() => {}
+                    _ => break,
+                },
+                read = upstream.read(&mut discard) => if !matches!(read, Ok(1..)) { break },
+                _ = RELAY.changed() => break,
+            }
+        }
+    });
+    tx
+}
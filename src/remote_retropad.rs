@@ -0,0 +1,102 @@
+use crate::api::{Injection, INJECTOR};
+use crate::config::ConfigHandle;
+use crate::logger::{log_detail, Verbosity};
+use std::net::{IpAddr, SocketAddr};
+use tokio::net::UdpSocket;
+use tokio::task::JoinSet;
+
+// libretro device types
+const RETRO_DEVICE_JOYPAD: i32 = 1;
+const RETRO_DEVICE_ANALOG: i32 = 5;
+// struct remote_message { int port, device, index, id; uint16_t state; } without the trailing padding
+const MESSAGE_LEN: usize = 18;
+
+/// Dónde cae cada botón de la RetroPad (`RETRO_DEVICE_ID_JOYPAD_*`, por
+/// posición: B abajo, A derecha) en el snapshot.
+enum Control {
+    Button(usize),
+    Axis(usize, i16),
+}
+
+const JOYPAD: [Control; 16] = [
+    Control::Button(0),           // B      -> A
+    Control::Button(2),           // Y      -> X
+    Control::Button(6),           // SELECT -> Back
+    Control::Button(7),           // START
+    Control::Axis(7, -i16::MAX),  // UP
+    Control::Axis(7, i16::MAX),   // DOWN
+    Control::Axis(6, -i16::MAX),  // LEFT
+    Control::Axis(6, i16::MAX),   // RIGHT
+    Control::Button(1),           // A      -> B
+    Control::Button(3),           // X      -> Y
+    Control::Button(4),           // L      -> LB
+    Control::Button(5),           // R      -> RB
+    Control::Axis(4, i16::MAX),   // L2     -> LT
+    Control::Axis(5, i16::MAX),   // R2     -> RT
+    Control::Button(9),           // L3
+    Control::Button(10),          // R3
+];
+
+/// Abre un puerto UDP por jugador, como RetroArch: el jugador N escucha en
+/// `base + N - 1`.
+pub async fn bind_remote_retropad(addr: IpAddr, base: u16, slots: usize) -> std::io::Result<Vec<UdpSocket>> {
+    let mut sockets = Vec::with_capacity(slots);
+    for slot in 0..slots {
+        let port = base.checked_add(slot as u16).ok_or_else(|| std::io::Error::other("puerto fuera de rango"))?;
+        sockets.push(UdpSocket::bind((addr, port)).await?);
+    }
+    Ok(sockets)
+}
+
+/// Servidor compatible con "Remote RetroPad", el mando en red de RetroArch
+/// (núcleo netretropad y `input_remote`): cada datagrama cambia un botón o
+/// un eje del jugador del puerto. El estado resultante entra en el pipeline
+/// del mando como un snapshot inyectado, con filtros, perfiles y demás.
+pub async fn run_remote_retropad(sockets: Vec<UdpSocket>, config: ConfigHandle) -> std::io::Result<()> {
+    let mut players = JoinSet::new();
+    for (slot, socket) in sockets.into_iter().enumerate() {
+        players.spawn(run_player(slot, socket, config.clone()));
+    }
+    match players.join_next().await {
+        Some(Ok(result)) => result,
+        Some(Err(e)) => Err(std::io::Error::other(e)),
+        None => Ok(()),
+    }
+}
+
+async fn run_player(slot: usize, socket: UdpSocket, config: ConfigHandle) -> std::io::Result<()> {
+    let mut buf = [0u8; 64];
+    let mut buttons = [0u8; 12];
+    let mut axes = [0i16; 8];
+    let mut client: Option<SocketAddr> = None;
+    loop {
+        let (len, addr) = socket.recv_from(&mut buf).await?;
+        if !config.get().is_allowed(addr.ip()) || len < MESSAGE_LEN {
+            continue;
+        }
+        if client != Some(addr) {
+            client = Some(addr);
+            log_detail(Verbosity::Low, "Cliente Remote RetroPad", &format!("jugador {} desde {}", slot + 1, addr));
+        }
+        // Native byte order on the sender; every platform RetroArch ships on is little-endian
+        let field = |at: usize| i32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]);
+        let (device, index, id) = (field(4), field(8), field(12));
+        let state = u16::from_le_bytes([buf[16], buf[17]]);
+        match (device, index, id) {
+            (RETRO_DEVICE_JOYPAD, _, 0..=15) => match JOYPAD[id as usize] {
+                Control::Button(button) => buttons[button] = (state != 0) as u8,
+                Control::Axis(axis, value) => {
+                    if state != 0 {
+                        axes[axis] = value;
+                    } else if axes[axis] == value {
+                        axes[axis] = 0;
+                    }
+                }
+            },
+            // Left stick is index 0, right stick index 1; id 0 is X and 1 is Y
+            (RETRO_DEVICE_ANALOG, 0..=1, 0..=1) => axes[(index * 2 + id) as usize] = state as i16,
+            _ => continue,
+        }
+        INJECTOR.send(Injection { slot, buttons, axes }).await.map_err(std::io::Error::other)?;
+    }
+}
@@ -0,0 +1,201 @@
+use crate::config::ConfigHandle;
+use crate::logger::{log, log_detail, Verbosity};
+use crate::protocol::{DEFAULT_RENDEZVOUS_PORT, HEADER_PUNCH, HEADER_RENDEZVOUS_PEER, HEADER_RENDEZVOUS_REGISTER};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::net::{lookup_host, UdpSocket};
+use tokio::time::{interval, Duration, Instant, MissedTickBehavior};
+
+// Who sends a REGISTER
+const ROLE_SERVER: u8 = 0;
+const ROLE_CLIENT: u8 = 1;
+// Which socket it came from: control, then the UDP mouse and gamepad ports
+const CHANNEL_CONTROL: usize = 0;
+const CHANNEL_MOUSE: usize = 1;
+const CHANNEL_GAMEPAD: usize = 2;
+const CHANNELS: usize = 3;
+// Punches sent to each new peer endpoint; the first ones may die in our own NAT
+const PUNCHES: usize = 3;
+// The rendezvous forgets an endpoint not refreshed for this long
+const ENDPOINT_TTL: Duration = Duration::from_secs(60);
+// Names the rendezvous keeps at once, whoever registers them
+const MAX_NAMES: usize = 1024;
+
+/// `[REGISTER][rol:1][canal:1][len:1][nombre:len]`.
+fn register_message(role: u8, channel: usize, name: &str) -> Vec<u8> {
+    let name = &name.as_bytes()[..name.len().min(u8::MAX as usize)];
+    let mut message = vec![HEADER_RENDEZVOUS_REGISTER, role, channel as u8, name.len() as u8];
+    message.extend_from_slice(name);
+    message
+}
+
+fn parse_register(buf: &[u8]) -> Option<(u8, usize, &[u8])> {
+    let (&[HEADER_RENDEZVOUS_REGISTER, role, channel, len], rest) = buf.split_first_chunk::<4>()? else {
+        return None;
+    };
+    let name = rest.get(..len as usize).filter(|name| !name.is_empty())?;
+    let known = (role == ROLE_SERVER || role == ROLE_CLIENT) && (channel as usize) < CHANNELS;
+    known.then_some((role, channel as usize, name))
+}
+
+/// `[PEER][canal:1][ip:4][puerto:u16]`: dirección pública del otro extremo
+/// para ese canal. Sólo IPv4, que es donde hay NAT.
+fn peer_message(channel: usize, addr: SocketAddr) -> Option<[u8; 8]> {
+    let IpAddr::V4(ip) = addr.ip() else {
+        return None;
+    };
+    let mut message = [0u8; 8];
+    message[0] = HEADER_RENDEZVOUS_PEER;
+    message[1] = channel as u8;
+    message[2..6].copy_from_slice(&ip.octets());
+    message[6..8].copy_from_slice(&addr.port().to_le_bytes());
+    Some(message)
+}
+
+fn parse_peer(buf: &[u8]) -> Option<(usize, SocketAddr)> {
+    let &[HEADER_RENDEZVOUS_PEER, channel, a, b, c, d, port_low, port_high] = buf else {
+        return None;
+    };
+    let addr = SocketAddr::from((Ipv4Addr::new(a, b, c, d), u16::from_le_bytes([port_low, port_high])));
+    ((channel as usize) < CHANNELS).then_some((channel as usize, addr))
+}
+
+/// Con `[rendezvous] server`, se registra en el punto de encuentro desde el
+/// socket de control y desde los puertos UDP de ratón y mando, para que vea
+/// sus direcciones públicas tras el NAT. Cuando un móvil se registra con el
+/// mismo nombre, el punto de encuentro da a cada uno las direcciones del
+/// otro y ambos se envían paquetes: así los dos NAT abren el camino sin
+/// redirigir puertos en el router.
+pub async fn run_rendezvous(mouse: Arc<UdpSocket>, gamepad: Arc<UdpSocket>, config: ConfigHandle) {
+    let control = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            log_detail(Verbosity::Low, "Error en el punto de encuentro", &e.to_string());
+            return;
+        }
+    };
+    let mut ticker = interval(Duration::from_secs(config.get().rendezvous.interval_secs.max(1)));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut rendezvous: Option<SocketAddr> = None;
+    let mut peers: [Option<SocketAddr>; CHANNELS] = [None; CHANNELS];
+    let mut buf = [0u8; 64];
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                let settings = config.get().rendezvous.clone();
+                let Some(server) = settings.server else {
+                    continue;
+                };
+                // Looked up every time: a home DDNS name may move
+                let found = lookup_host(&server).await.ok().and_then(|mut addrs| addrs.find(SocketAddr::is_ipv4));
+                let Some(addr) = found else {
+                    log_detail(Verbosity::Low, "Punto de encuentro no encontrado", &server);
+                    continue;
+                };
+                if rendezvous != Some(addr) {
+                    let detail = format!("{} ({})", server, addr);
+                    log_detail(Verbosity::Low, "Registrado en el punto de encuentro", &detail);
+                    rendezvous = Some(addr);
+                }
+                let sockets = [(CHANNEL_CONTROL, &control), (CHANNEL_MOUSE, &*mouse), (CHANNEL_GAMEPAD, &*gamepad)];
+                for (channel, socket) in sockets {
+                    let message = register_message(ROLE_SERVER, channel, &settings.name);
+                    if let Err(e) = socket.send_to(&message, addr).await {
+                        log_detail(Verbosity::Medium, "Error en el punto de encuentro", &format!("{}: {}", addr, e));
+                    }
+                }
+            }
+            received = control.recv_from(&mut buf) => {
+                let Ok((len, from)) = received else {
+                    continue;
+                };
+                let Some((channel, peer)) = parse_peer(&buf[..len]).filter(|_| Some(from) == rendezvous) else {
+                    continue;
+                };
+                let socket = match channel {
+                    CHANNEL_MOUSE => &*mouse,
+                    CHANNEL_GAMEPAD => &*gamepad,
+                    _ => continue,
+                };
+                if peers[channel] != Some(peer) {
+                    log_detail(Verbosity::Low, "Abriendo camino al cliente", &peer.to_string());
+                    peers[channel] = Some(peer);
+                }
+                // Again on every introduction, which also keeps our side of the NAT open
+                for _ in 0..PUNCHES {
+                    let _ = socket.send_to(&[HEADER_PUNCH], peer).await;
+                }
+            }
+        }
+    }
+}
+
+struct Endpoints {
+    at: [Option<(SocketAddr, Instant)>; CHANNELS],
+}
+
+impl Endpoints {
+    fn fresh(&self, channel: usize) -> Option<SocketAddr> {
+        self.at[channel].filter(|(_, seen)| seen.elapsed() < ENDPOINT_TTL).map(|(addr, _)| addr)
+    }
+
+    fn expired(&self) -> bool {
+        (0..CHANNELS).all(|channel| self.fresh(channel).is_none())
+    }
+}
+
+/// Subcomando `rendezvous`: el punto de encuentro, para un VPS o cualquier
+/// máquina con un puerto UDP accesible desde internet. No reenvía input;
+/// sólo presenta servidores y móviles que se registran con el mismo nombre.
+pub async fn run_rendezvous_server(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut listen = SocketAddr::from((Ipv4Addr::UNSPECIFIED, DEFAULT_RENDEZVOUS_PORT));
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--listen" => {
+                let value = iter.next().ok_or("falta el valor de --listen")?;
+                listen = value.parse().map_err(|_| format!("dirección inválida para --listen: {}", value))?;
+            }
+            other => return Err(format!("argumento desconocido para rendezvous: {}", other).into()),
+        }
+    }
+    let socket = UdpSocket::bind(listen).await?;
+    log(Verbosity::Low, &format!("Punto de encuentro en {}", socket.local_addr()?));
+
+    let mut registered: HashMap<(Vec<u8>, u8), Endpoints> = HashMap::new();
+    let mut buf = [0u8; 512];
+    loop {
+        let (len, from) = socket.recv_from(&mut buf).await?;
+        let Some((role, channel, name)) = parse_register(&buf[..len]) else {
+            continue;
+        };
+        registered.retain(|_, endpoints| !endpoints.expired());
+        let key = (name.to_vec(), role);
+        if !registered.contains_key(&key) && registered.len() >= MAX_NAMES {
+            continue;
+        }
+        let endpoints = registered.entry(key).or_insert(Endpoints { at: [None; CHANNELS] });
+        endpoints.at[channel] = Some((from, Instant::now()));
+        if role != ROLE_CLIENT {
+            continue;
+        }
+
+        // Clients refresh often, so each of their registrations introduces both sides again
+        let (Some(server), Some(client)) =
+            (registered.get(&(name.to_vec(), ROLE_SERVER)), registered.get(&(name.to_vec(), ROLE_CLIENT)))
+        else {
+            continue;
+        };
+        for (from_side, to_side) in [(server, client), (client, server)] {
+            let Some(control) = to_side.fresh(CHANNEL_CONTROL) else {
+                continue;
+            };
+            for channel in [CHANNEL_MOUSE, CHANNEL_GAMEPAD] {
+                if let Some(message) = from_side.fresh(channel).and_then(|addr| peer_message(channel, addr)) {
+                    let _ = socket.send_to(&message, control).await;
+                }
+            }
+        }
+    }
+}
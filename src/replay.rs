@@ -0,0 +1,248 @@
+use crate::capture::{self, Channel};
+use crate::protocol::{DEFAULT_GAMEPAD_PORT, DEFAULT_KEYBOARD_PORT, DEFAULT_MOUSE_PORT};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::{sleep_until, Instant};
+
+struct Options {
+    capture: PathBuf,
+    host: String,
+    mouse_port: u16,
+    keyboard_port: u16,
+    gamepad_port: u16,
+    /// Probabilidad (0..1) de perder cada datagrama
+    loss: f64,
+    delay: Duration,
+    jitter: Duration,
+    speed: f64,
+    seed: u64,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Self {
+            capture: PathBuf::new(),
+            host: "127.0.0.1".to_string(),
+            mouse_port: DEFAULT_MOUSE_PORT,
+            keyboard_port: DEFAULT_KEYBOARD_PORT,
+            gamepad_port: DEFAULT_GAMEPAD_PORT,
+            loss: 0.0,
+            delay: Duration::ZERO,
+            jitter: Duration::ZERO,
+            speed: 1.0,
+            seed: 1,
+        };
+        let mut capture = None;
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let mut value = || iter.next().ok_or_else(|| format!("falta el valor de {}", arg));
+            let number = |v: &String| v.parse::<f64>().map_err(|_| format!("valor inválido para {}: {}", arg, v));
+            match arg.as_str() {
+                "--to" => options.host = value()?.clone(),
+                "--mouse-port" => options.mouse_port = number(value()?)? as u16,
+                "--keyboard-port" => options.keyboard_port = number(value()?)? as u16,
+                "--gamepad-port" => options.gamepad_port = number(value()?)? as u16,
+                "--loss" => options.loss = (number(value()?)? / 100.0).clamp(0.0, 1.0),
+                "--delay" => options.delay = Duration::from_secs_f64(number(value()?)?.max(0.0) / 1000.0),
+                "--jitter" => options.jitter = Duration::from_secs_f64(number(value()?)?.max(0.0) / 1000.0),
+                "--speed" => options.speed = number(value()?)?.max(0.01),
+                "--seed" => options.seed = number(value()?)? as u64,
+                other if other.starts_with("--") => return Err(format!("argumento desconocido para replay: {}", other)),
+                path => capture = Some(PathBuf::from(path)),
+            }
+        }
+        options.capture = capture.ok_or("uso: replay <captura> [--to <host>] [--loss <%>] [--delay <ms>] [--jitter <ms>]")?;
+        Ok(options)
+    }
+}
+
+/// Generador pseudoaleatorio con semilla fija: la misma semilla pierde y
+/// retrasa los mismos paquetes en cada ejecución.
+pub struct XorShift(u64);
+
+impl XorShift {
+    pub fn new(seed: u64) -> Self {
+        // Zero is a fixed point of xorshift
+        Self(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Uniforme en [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Cuándo se envía cada registro (índice en `records`) y cuántos datagramas
+/// se pierden a propósito.
+fn schedule(records: &[capture::Record], options: &Options) -> (Vec<(Duration, usize)>, usize) {
+    let mut rng = XorShift::new(options.seed);
+    // Loss only applies to datagrams; TCP keeps its order and just arrives late
+    let mut schedule = Vec::with_capacity(records.len());
+    let mut tcp_due: HashMap<&str, Duration> = HashMap::new();
+    let mut lost = 0;
+    for (index, record) in records.iter().enumerate() {
+        let is_tcp = matches!(record.channel, Channel::Keyboard | Channel::KeyboardClosed);
+        if !is_tcp && rng.next_f64() < options.loss {
+            lost += 1;
+            continue;
+        }
+        let original = Duration::from_micros(record.time_us).div_f64(options.speed);
+        let mut due = original + options.delay + options.jitter.mul_f64(rng.next_f64());
+        if is_tcp {
+            let last = tcp_due.entry(&record.addr).or_default();
+            due = due.max(*last);
+            *last = due;
+        }
+        schedule.push((due, index));
+    }
+    // Stable, so records due at the same instant keep their capture order
+    schedule.sort_by_key(|&(due, _)| due);
+    (schedule, lost)
+}
+
+/// Subcomando `replay`: reenvía una captura (`serve --capture`) a un
+/// servidor con el ritmo original, opcionalmente con pérdida, retardo y
+/// jitter artificiales para reproducir en el banco fallos de Wi-Fi.
+pub async fn run_replay(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let options = Options::parse(args)?;
+    let records = capture::read(&options.capture)?;
+    let (schedule, lost) = schedule(&records, &options);
+
+    println!(
+        "▶ {} registros de {} hacia {} ({} perdidos a propósito)",
+        records.len(),
+        options.capture.display(),
+        options.host,
+        lost
+    );
+
+    let mut datagrams: HashMap<(u8, &str), UdpSocket> = HashMap::new();
+    let mut streams: HashMap<&str, TcpStream> = HashMap::new();
+    let start = Instant::now();
+    let mut sent = 0;
+    for (due, index) in schedule {
+        sleep_until(start + due).await;
+        let record = &records[index];
+        let result = match record.channel {
+            Channel::Mouse | Channel::Gamepad => {
+                let port = if record.channel == Channel::Mouse { options.mouse_port } else { options.gamepad_port };
+                // One socket per original source, so the server sees the same sessions
+                let key = (record.channel as u8, record.addr.as_str());
+                let socket = match datagrams.get(&key) {
+                    Some(socket) => socket,
+                    None => {
+                        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+                        socket.connect((options.host.as_str(), port)).await?;
+                        datagrams.entry(key).or_insert(socket)
+                    }
+                };
+                socket.send(&record.data).await.map(|_| ())
+            }
+            Channel::Keyboard => {
+                let stream = match streams.get_mut(record.addr.as_str()) {
+                    Some(stream) => stream,
+                    None => {
+                        let stream = TcpStream::connect((options.host.as_str(), options.keyboard_port)).await?;
+                        stream.set_nodelay(true)?;
+                        streams.entry(&record.addr).or_insert(stream)
+                    }
+                };
+                stream.write_all(&record.data).await
+            }
+            Channel::KeyboardClosed => {
+                streams.remove(record.addr.as_str());
+                Ok(())
+            }
+        };
+        match result {
+            Ok(()) => sent += 1,
+            Err(e) => eprintln!("✗ {} {}: {}", record.addr, record.time_us, e),
+        }
+    }
+    println!("✓ Reproducción terminada: {} enviados en {:.1}s", sent, start.elapsed().as_secs_f64());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    fn record(time_us: u64, channel: Channel, addr: &str) -> capture::Record {
+        capture::Record { time_us, channel, addr: addr.to_string(), data: vec![0] }
+    }
+
+    #[test]
+    fn options_default_to_localhost_without_impairments() {
+        let options = parse(&["sesion.rccap"]).unwrap();
+        assert_eq!(options.capture, PathBuf::from("sesion.rccap"));
+        assert_eq!((options.host.as_str(), options.mouse_port), ("127.0.0.1", DEFAULT_MOUSE_PORT));
+        assert_eq!((options.loss, options.delay, options.speed), (0.0, Duration::ZERO, 1.0));
+    }
+
+    #[test]
+    fn options_read_impairments() {
+        let options =
+            parse(&["--loss", "25", "sesion.rccap", "--delay", "40", "--jitter", "10", "--to", "10.0.0.2"]).unwrap();
+        assert_eq!(options.loss, 0.25);
+        assert_eq!(options.delay, Duration::from_millis(40));
+        assert_eq!(options.jitter, Duration::from_millis(10));
+        assert_eq!(options.host, "10.0.0.2");
+    }
+
+    #[test]
+    fn options_reject_bad_arguments() {
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["sesion.rccap", "--loss"]).is_err());
+        assert!(parse(&["sesion.rccap", "--loss", "mucho"]).is_err());
+        assert!(parse(&["sesion.rccap", "--lossy", "5"]).is_err());
+    }
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let (mut a, mut b) = (XorShift::new(0), XorShift::new(0));
+        for _ in 0..100 {
+            let value = a.next_f64();
+            assert_eq!(value, b.next_f64());
+            assert!((0.0..1.0).contains(&value));
+        }
+        assert_ne!(XorShift::new(1).next_u64(), XorShift::new(2).next_u64());
+    }
+
+    #[test]
+    fn loss_spares_the_keyboard_stream() {
+        let mut options = parse(&["sesion.rccap", "--loss", "100"]).unwrap();
+        options.delay = Duration::from_millis(5);
+        let records =
+            [record(0, Channel::Mouse, "a"), record(10, Channel::Keyboard, "b"), record(20, Channel::Gamepad, "a")];
+        let (schedule, lost) = schedule(&records, &options);
+        assert_eq!(lost, 2);
+        assert_eq!(schedule, [(Duration::from_micros(5010), 1)]);
+    }
+
+    #[test]
+    fn jitter_keeps_each_stream_in_order() {
+        let options = parse(&["sesion.rccap", "--jitter", "50", "--seed", "7"]).unwrap();
+        let records: Vec<_> = (0..50).map(|i| record(i * 100, Channel::Keyboard, ["a", "b"][i as usize % 2])).collect();
+        let (schedule, lost) = schedule(&records, &options);
+        assert_eq!(lost, 0);
+        for addr in ["a", "b"] {
+            let order: Vec<usize> =
+                schedule.iter().map(|&(_, index)| index).filter(|&index| records[index].addr == addr).collect();
+            assert!(order.is_sorted());
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use crate::devices::lazy::SharedDevice;
+use crate::logger::{log_detail, Verbosity};
+use evdev::uinput::VirtualDevice;
+use evdev::{EventType, FFEffectData, FFEffectKind, FFEffectType, UInputEventType};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::os::fd::OwnedFd;
+use tokio::io::unix::AsyncFd;
+use tokio::sync::mpsc;
+use tokio::time::{sleep_until, Duration, Instant};
+
+// Periodic effects faster than this go to the weak (small, fast) motor
+const FAST_PERIOD_MS: u16 = 50;
+// Effects a game can have uploaded at once on each virtual gamepad
+pub const MAX_EFFECTS: u32 = 16;
+
+/// Fuerza de los dos motores de un mando, 0..=0xFFFF: el grande (fuerte) y
+/// el pequeño (débil), lo único que entienden los clientes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Motors {
+    pub strong: u16,
+    pub weak: u16,
+}
+
+impl Motors {
+    /// El efecto traducido a los dos motores. Las condiciones (muelle,
+    /// fricción, amortiguación, inercia) dependen de la posición del stick y
+    /// no se pueden reproducir con vibración, así que quedan en silencio.
+    fn of(effect: &FFEffectKind) -> Self {
+        match *effect {
+            FFEffectKind::Rumble { strong_magnitude, weak_magnitude } => Self { strong: strong_magnitude, weak: weak_magnitude },
+            // A steady push is felt best on the big motor
+            FFEffectKind::Constant { level, .. } => Self { strong: level.unsigned_abs().saturating_mul(2), weak: 0 },
+            FFEffectKind::Ramp { start_level, end_level, .. } => {
+                let level = start_level.unsigned_abs().max(end_level.unsigned_abs());
+                Self { strong: level.saturating_mul(2), weak: 0 }
+            }
+            FFEffectKind::Periodic { period, magnitude, offset, .. } => {
+                let level = magnitude.unsigned_abs().saturating_add(offset.unsigned_abs()).saturating_mul(2);
+                if period < FAST_PERIOD_MS {
+                    Self { strong: 0, weak: level }
+                } else {
+                    Self { strong: level, weak: 0 }
+                }
+            }
+            _ => Self::default(),
+        }
+    }
+
+    /// Escala a 0..255 aplicando `percent` (la intensidad del cliente).
+    pub fn scaled(self, percent: u8) -> [u8; 2] {
+        let scale = |value: u16| (value as u32 * percent as u32 / 100 / 257).min(u8::MAX as u32) as u8;
+        [scale(self.strong), scale(self.weak)]
+    }
+}
+
+// An effect the game started: it plays from `from` until `until` (forever if None)
+struct Playing {
+    from: Instant,
+    until: Option<Instant>,
+}
+
+/// Force feedback de un gamepad virtual: atiende las subidas y borrados de
+/// efectos que hacen los juegos, sigue cuáles están sonando y envía por
+/// `tx` la fuerza resultante de los motores del slot cada vez que cambia.
+pub fn spawn_rumble_monitor(slot: usize, device: SharedDevice, tx: mpsc::Sender<(usize, Motors)>) {
+    tokio::spawn(async move {
+        if let Err(e) = monitor_rumble(slot, &device, &tx).await {
+            log_detail(Verbosity::Low, "Error leyendo force feedback", &format!("{}: {}", device.name(), e));
+        }
+    });
+}
+
+async fn monitor_rumble(slot: usize, device: &SharedDevice, tx: &mpsc::Sender<(usize, Motors)>) -> std::io::Result<()> {
+    loop {
+        // Gamepads appear with their player's first snapshot and may be torn down later
+        device.created().await;
+        let fd = match device.watch_fd() {
+            Some(fd) => fd?,
+            None => continue,
+        };
+        let async_fd = AsyncFd::new(fd)?;
+        tokio::select! {
+            result = forward_rumble(slot, device, &async_fd, tx) => result?,
+            _ = device.destroyed() => {}
+        }
+        // Effects die with the device
+        let _ = tx.send((slot, Motors::default())).await;
+    }
+}
+
+async fn forward_rumble(
+    slot: usize,
+    device: &SharedDevice,
+    async_fd: &AsyncFd<OwnedFd>,
+    tx: &mpsc::Sender<(usize, Motors)>,
+) -> std::io::Result<()> {
+    let mut effects: HashMap<i16, FFEffectData> = HashMap::new();
+    let mut playing: HashMap<i16, Playing> = HashMap::new();
+    let mut gain = u16::MAX;
+    let mut sent = Motors::default();
+    loop {
+        let now = Instant::now();
+        // Wake up when an effect starts after its delay or runs out
+        let next_change = playing
+            .values()
+            .flat_map(|p| [Some(p.from), p.until])
+            .flatten()
+            .filter(|&at| at > now)
+            .min();
+        tokio::select! {
+            guard = async_fd.readable() => {
+                let mut guard = guard?;
+                match device.if_created(|dev| handle_requests(dev, &mut effects, &mut playing, &mut gain)) {
+                    None => return Ok(()),
+                    Some(Ok(())) => {}
+                    Some(Err(e)) if e.kind() == ErrorKind::WouldBlock => guard.clear_ready(),
+                    Some(Err(e)) => return Err(e),
+                }
+            }
+            _ = sleep_until(next_change.unwrap_or(now)), if next_change.is_some() => {}
+        }
+
+        let now = Instant::now();
+        playing.retain(|_, p| p.until.is_none_or(|until| until > now));
+        let mut motors = Motors::default();
+        for (id, p) in &playing {
+            if let Some(effect) = effects.get(id)
+                && p.from <= now
+            {
+                let effect = Motors::of(&effect.kind);
+                motors.strong = motors.strong.max(effect.strong);
+                motors.weak = motors.weak.max(effect.weak);
+            }
+        }
+        let motors = Motors {
+            strong: (motors.strong as u32 * gain as u32 / u16::MAX as u32) as u16,
+            weak: (motors.weak as u32 * gain as u32 / u16::MAX as u32) as u16,
+        };
+        if motors != sent {
+            sent = motors;
+            if tx.send((slot, motors)).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// Answers the kernel's upload/erase requests (the game's ioctl waits for them)
+// and records EV_FF play/stop and gain changes. Runs with the device locked.
+fn handle_requests(
+    dev: &mut VirtualDevice,
+    effects: &mut HashMap<i16, FFEffectData>,
+    playing: &mut HashMap<i16, Playing>,
+    gain: &mut u16,
+) -> std::io::Result<()> {
+    let events: Vec<_> = dev.fetch_events()?.collect();
+    for event in events {
+        match (event.event_type(), event.code()) {
+            (EventType::UINPUT, code) if code == UInputEventType::UI_FF_UPLOAD.0 => {
+                // Dropping the upload event completes it with retval 0
+                let upload = dev.process_ff_upload(event).map_err(std::io::Error::other)?;
+                effects.insert(upload.effect_id(), upload.effect());
+            }
+            (EventType::UINPUT, code) if code == UInputEventType::UI_FF_ERASE.0 => {
+                let erase = dev.process_ff_erase(event).map_err(std::io::Error::other)?;
+                let id = erase.effect_id() as i16;
+                effects.remove(&id);
+                playing.remove(&id);
+            }
+            (EventType::FORCEFEEDBACK, code) if code == FFEffectType::FF_GAIN.0 => {
+                *gain = event.value().clamp(0, u16::MAX as i32) as u16;
+            }
+            (EventType::FORCEFEEDBACK, code) => {
+                let id = code as i16;
+                // The value is how many times to play it; 0 stops it
+                match (event.value(), effects.get(&id)) {
+                    (count @ 1.., Some(effect)) => {
+                        let from = Instant::now() + Duration::from_millis(effect.replay.delay as u64);
+                        let until = (effect.replay.length > 0)
+                            .then(|| from + Duration::from_millis(effect.replay.length as u64) * count as u32);
+                        playing.insert(id, Playing { from, until });
+                    }
+                    _ => {
+                        playing.remove(&id);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use crate::config::SchedulingConfig;
+use crate::logger::{log_detail, Verbosity};
+use nix::libc;
+use nix::sched::{sched_setaffinity, CpuSet};
+use nix::unistd::Pid;
+
+/// Aplica `[scheduling]` a todos los hilos del proceso, para que la carga de
+/// audio y vídeo del HTPC no meta picos de latencia en la entrada.
+///
+/// Los servidores reciben y emiten desde los hilos del runtime de tokio, así
+/// que se cambian todos los que existen (los hilos que se creen después
+/// heredan la política de quien los crea). Necesita root o CAP_SYS_NICE: hay
+/// que llamarlo antes de abandonar privilegios. Un fallo se avisa pero no
+/// detiene el servidor.
+pub fn apply(config: &SchedulingConfig) {
+    if config.realtime_priority == 0 && config.nice == 0 && config.cpu.is_none() {
+        return;
+    }
+    let threads = process_threads();
+    for &tid in &threads {
+        if let Err(e) = apply_to_thread(config, tid) {
+            log_detail(Verbosity::Low, "No se pudo ajustar la planificación", &e);
+            return;
+        }
+    }
+    let policy = if config.realtime_priority > 0 {
+        format!("SCHED_FIFO {}", config.realtime_priority)
+    } else {
+        format!("nice {}", config.nice)
+    };
+    let cpu = config.cpu.map_or_else(|| "cualquier núcleo".to_string(), |cpu| format!("núcleo {}", cpu));
+    log_detail(
+        Verbosity::Medium,
+        "Planificación ajustada",
+        &format!("{}, {} ({} hilos)", policy, cpu, threads.len()),
+    );
+}
+
+fn apply_to_thread(config: &SchedulingConfig, tid: i32) -> Result<(), String> {
+    if config.realtime_priority > 0 {
+        let param = libc::sched_param { sched_priority: config.realtime_priority.min(99) as i32 };
+        // SAFETY: plain syscall on a thread id of this process with a valid sched_param
+        if unsafe { libc::sched_setscheduler(tid, libc::SCHED_FIFO, &param) } != 0 {
+            return Err(format!("SCHED_FIFO: {}", std::io::Error::last_os_error()));
+        }
+    } else if config.nice != 0 {
+        // On Linux PRIO_PROCESS with a thread id only affects that thread
+        // SAFETY: plain syscall, no pointers involved
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, config.nice.clamp(-20, 19)) } != 0 {
+            return Err(format!("nice: {}", std::io::Error::last_os_error()));
+        }
+    }
+    if let Some(cpu) = config.cpu {
+        let mut set = CpuSet::new();
+        set.set(cpu).map_err(|e| format!("núcleo {}: {}", cpu, e))?;
+        sched_setaffinity(Pid::from_raw(tid), &set).map_err(|e| format!("núcleo {}: {}", cpu, e))?;
+    }
+    Ok(())
+}
+
+fn process_threads() -> Vec<i32> {
+    let Ok(entries) = std::fs::read_dir("/proc/self/task") else {
+        return vec![0];
+    };
+    entries.flatten().filter_map(|entry| entry.file_name().to_str()?.parse().ok()).collect()
+}
@@ -0,0 +1,224 @@
+use crate::logger::{log_detail, Verbosity};
+use crate::protocol::HEADER_SCREENS;
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Outputs are looked up again at most this often
+const REFRESH: Duration = Duration::from_secs(5);
+// [x:i16][y:i16][width:u16][height:u16][flags:1] per screen in SCREENS
+const SCREEN_LEN: usize = 9;
+const FLAG_PRIMARY: u8 = 0x01;
+const FLAG_SELECTED: u8 = 0x02;
+
+/// Una pantalla conectada, con su posición en el escritorio en píxeles.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screen {
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub primary: bool,
+}
+
+static CACHE: Mutex<Option<(Instant, Vec<Screen>)>> = Mutex::new(None);
+
+/// Las pantallas conectadas. Con `DISPLAY` se preguntan a X (`xrandr`), con
+/// `WAYLAND_DISPLAY` al compositor (`wlr-randr`), y si no, a DRM en
+/// /sys/class/drm, que no sabe dónde está cada una: se suponen en fila, de
+/// izquierda a derecha en el orden de los conectores.
+pub async fn screens() -> Vec<Screen> {
+    if let Some((at, screens)) = &*CACHE.lock().unwrap()
+        && at.elapsed() < REFRESH
+    {
+        return screens.clone();
+    }
+    let screens = tokio::task::spawn_blocking(detect).await.unwrap_or_default();
+    let mut cache = CACHE.lock().unwrap();
+    if cache.as_ref().is_none_or(|(_, previous)| *previous != screens) {
+        log_detail(Verbosity::Medium, "Pantallas detectadas", &describe(&screens));
+    }
+    *cache = Some((Instant::now(), screens.clone()));
+    screens
+}
+
+fn detect() -> Vec<Screen> {
+    let from_x = || std::env::var_os("DISPLAY").map(|_| parse_xrandr(&run("xrandr", &["--query"])));
+    let from_wayland = || std::env::var_os("WAYLAND_DISPLAY").map(|_| parse_wlr_randr(&run("wlr-randr", &[])));
+    from_x()
+        .filter(|screens| !screens.is_empty())
+        .or_else(|| from_wayland().filter(|screens| !screens.is_empty()))
+        .unwrap_or_else(from_drm)
+}
+
+fn run(program: &str, args: &[&str]) -> String {
+    Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+        .unwrap_or_default()
+}
+
+// "HDMI-1 connected primary 1920x1080+0+0 (normal left inverted ...) 527mm x 296mm"
+fn parse_xrandr(text: &str) -> Vec<Screen> {
+    let mut screens = Vec::new();
+    for line in text.lines().filter(|line| !line.starts_with(char::is_whitespace)) {
+        let mut words = line.split_whitespace();
+        let (Some(name), Some("connected")) = (words.next(), words.next()) else {
+            continue;
+        };
+        let mut primary = false;
+        for word in words {
+            if word == "primary" {
+                primary = true;
+            } else if let Some((width, height, x, y)) = parse_geometry(word) {
+                screens.push(Screen { name: name.to_string(), x, y, width, height, primary });
+                break;
+            }
+        }
+    }
+    screens
+}
+
+// WIDTHxHEIGHT+X+Y
+fn parse_geometry(word: &str) -> Option<(u32, u32, i32, i32)> {
+    let (size, position) = word.split_once('+')?;
+    let (width, height) = size.split_once('x')?;
+    let (x, y) = position.split_once('+')?;
+    Some((width.parse().ok()?, height.parse().ok()?, x.parse().ok()?, y.parse().ok()?))
+}
+
+// An unindented line per output, then "Enabled: yes", the modes with
+// "1920x1080 px, 60.000000 Hz (preferred, current)" and "Position: 0,0"
+fn parse_wlr_randr(text: &str) -> Vec<Screen> {
+    let mut screens: Vec<Screen> = Vec::new();
+    let mut enabled = Vec::new();
+    for line in text.lines() {
+        if !line.starts_with(char::is_whitespace) {
+            if let Some(name) = line.split_whitespace().next() {
+                let primary = screens.is_empty();
+                screens.push(Screen { name: name.to_string(), x: 0, y: 0, width: 0, height: 0, primary });
+                enabled.push(true);
+            }
+            continue;
+        }
+        let (Some(screen), Some(on)) = (screens.last_mut(), enabled.last_mut()) else {
+            continue;
+        };
+        let line = line.trim();
+        if let Some(value) = line.strip_prefix("Enabled:") {
+            *on = value.trim() == "yes";
+        } else if let Some(value) = line.strip_prefix("Position:")
+            && let Some((x, y)) = value.trim().split_once(',')
+        {
+            screen.x = x.parse().unwrap_or(0);
+            screen.y = y.parse().unwrap_or(0);
+        } else if line.contains("current")
+            && let Some((width, height)) = line.split_whitespace().next().and_then(|size| size.split_once('x'))
+        {
+            screen.width = width.parse().unwrap_or(0);
+            screen.height = height.parse().unwrap_or(0);
+        }
+    }
+    screens.into_iter().zip(enabled).filter(|(screen, on)| *on && screen.width > 0).map(|(screen, _)| screen).collect()
+}
+
+// /sys/class/drm/card0-HDMI-A-1/{status,modes}; the first mode is the preferred one
+fn from_drm() -> Vec<Screen> {
+    let Ok(entries) = std::fs::read_dir("/sys/class/drm") else {
+        return Vec::new();
+    };
+    let mut connectors: Vec<_> = entries.flatten().map(|entry| entry.path()).collect();
+    connectors.sort();
+    let mut screens = Vec::new();
+    let mut x = 0;
+    for path in connectors {
+        let read = |file: &str| std::fs::read_to_string(path.join(file)).unwrap_or_default();
+        if read("status").trim() != "connected" {
+            continue;
+        }
+        let modes = read("modes");
+        let Some((width, height)) = modes.lines().next().and_then(|mode| mode.split_once('x')) else {
+            continue;
+        };
+        // "1920x1080i" for interlaced modes
+        let height = height.trim_end_matches(|c: char| !c.is_ascii_digit());
+        let (Ok(width), Ok(height)) = (width.parse::<u32>(), height.parse::<u32>()) else {
+            continue;
+        };
+        let file_name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let name = file_name.split_once('-').map_or(file_name.as_str(), |(_, connector)| connector).to_string();
+        screens.push(Screen { name, x, y: 0, width, height, primary: screens.is_empty() });
+        x += width as i32;
+    }
+    screens
+}
+
+fn describe(screens: &[Screen]) -> String {
+    if screens.is_empty() {
+        return "ninguna".to_string();
+    }
+    let names: Vec<String> =
+        screens.iter().map(|s| format!("{} {}x{}+{}+{}", s.name, s.width, s.height, s.x, s.y)).collect();
+    names.join(", ")
+}
+
+/// `[SCREENS][count:1]` y por pantalla `[x:i16][y:i16][width:u16][height:u16][flags:1]`,
+/// flags bit 0 = principal, bit 1 = la que apunta la pistola (`selected`,
+/// empezando en 0).
+pub fn screens_message(screens: &[Screen], selected: Option<usize>) -> Vec<u8> {
+    let count = screens.len().min(u8::MAX as usize);
+    let mut message = Vec::with_capacity(2 + count * SCREEN_LEN);
+    message.extend_from_slice(&[HEADER_SCREENS, count as u8]);
+    for (i, screen) in screens.iter().take(count).enumerate() {
+        let clamp_i16 = |value: i32| value.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        let clamp_u16 = |value: u32| value.min(u16::MAX as u32) as u16;
+        message.extend_from_slice(&clamp_i16(screen.x).to_le_bytes());
+        message.extend_from_slice(&clamp_i16(screen.y).to_le_bytes());
+        message.extend_from_slice(&clamp_u16(screen.width).to_le_bytes());
+        message.extend_from_slice(&clamp_u16(screen.height).to_le_bytes());
+        let mut flags = 0;
+        if screen.primary {
+            flags |= FLAG_PRIMARY;
+        }
+        if selected == Some(i) {
+            flags |= FLAG_SELECTED;
+        }
+        message.push(flags);
+    }
+    message
+}
+
+/// Parte del escritorio que ocupa una pantalla, en fracciones del rectángulo
+/// que engloba todas: `(izquierda, arriba, derecha, abajo)`.
+#[derive(Debug, Clone, Copy)]
+pub struct Region(f64, f64, f64, f64);
+
+impl Region {
+    pub fn of(screens: &[Screen], index: usize) -> Option<Self> {
+        let screen = screens.get(index)?;
+        let left = screens.iter().map(|s| s.x).min()?;
+        let top = screens.iter().map(|s| s.y).min()?;
+        let right = screens.iter().map(|s| s.x + s.width as i32).max()?;
+        let bottom = screens.iter().map(|s| s.y + s.height as i32).max()?;
+        let (width, height) = ((right - left).max(1) as f64, (bottom - top).max(1) as f64);
+        Some(Self(
+            (screen.x - left) as f64 / width,
+            (screen.y - top) as f64 / height,
+            (screen.x + screen.width as i32 - left) as f64 / width,
+            (screen.y + screen.height as i32 - top) as f64 / height,
+        ))
+    }
+
+    /// Lleva una posición de `0..=max` sobre todo el escritorio a la misma
+    /// posición relativa dentro de la pantalla.
+    pub fn confine(&self, (x, y): (i32, i32), max: i32) -> (i32, i32) {
+        let scale = |value: i32, start: f64, end: f64| {
+            ((start + value as f64 / max as f64 * (end - start)) * max as f64).round() as i32
+        };
+        (scale(x, self.0, self.2), scale(y, self.1, self.3))
+    }
+}
@@ -0,0 +1,173 @@
+use crate::filters::{InputFilter, PadInput, WatchedFile};
+use crate::logger::{log_detail, Verbosity};
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Map, Scope, AST};
+use std::net::{SocketAddr, UdpSocket};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Límite de operaciones por llamada, para que un bucle infinito en un
+/// script no congele el bucle del gamepad.
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// Puntos de enganche que un script puede definir (todos opcionales):
+///
+/// - `on_snapshot(pad)`: recibe `#{buttons, axes, state}` y devuelve el
+///   mapa, modificado o no. `state` se conserva entre llamadas.
+/// - `on_press(index)` / `on_release(index)`: un botón cambió de estado.
+///
+/// Acciones disponibles en cualquiera de ellos: `key(code, value)` manda un
+/// evento al teclado virtual y `retroarch("COMANDO")` envía un comando de
+/// red de RetroArch (`FAST_FORWARD`, `SAVE_STATE`...).
+const HOOKS: [&str; 3] = ["on_snapshot", "on_press", "on_release"];
+
+#[derive(Default)]
+struct Actions {
+    keys: Vec<(u16, i32)>,
+    commands: Vec<String>,
+}
+
+struct Loaded {
+    ast: AST,
+    hooks: [bool; 3],
+}
+
+/// Filtro `script` del pipeline: un script Rhai con los enganches de `HOOKS`.
+/// Un error de carga o de ejecución se registra y deja pasar la entrada
+/// sin tocar hasta que el archivo cambie.
+pub struct Script {
+    file: WatchedFile,
+    retroarch: SocketAddr,
+    engine: Engine,
+    actions: Arc<Mutex<Actions>>,
+    loaded: Option<Loaded>,
+    state: Dynamic,
+    previous: [u8; 12],
+    socket: Option<UdpSocket>,
+}
+
+impl Script {
+    pub fn new(path: PathBuf, retroarch: SocketAddr) -> Self {
+        let actions = Arc::new(Mutex::new(Actions::default()));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let keys = Arc::clone(&actions);
+        engine.register_fn("key", move |code: i64, value: i64| {
+            keys.lock().unwrap().keys.push((code as u16, value as i32));
+        });
+        let commands = Arc::clone(&actions);
+        engine.register_fn("retroarch", move |command: &str| {
+            commands.lock().unwrap().commands.push(command.to_string());
+        });
+        Self {
+            file: WatchedFile::new(path),
+            retroarch,
+            engine,
+            actions,
+            loaded: None,
+            state: Dynamic::from_map(Map::new()),
+            previous: [0; 12],
+            socket: None,
+        }
+    }
+
+    fn reload_if_changed(&mut self, now: Instant) {
+        if !self.file.changed(now) {
+            return;
+        }
+        match self.engine.compile_file(self.file.path.clone()) {
+            Ok(ast) => {
+                let hooks = HOOKS.map(|hook| ast.iter_functions().any(|f| f.name == hook));
+                log_detail(Verbosity::Low, "Script cargado", &self.file.path.display().to_string());
+                self.loaded = Some(Loaded { ast, hooks });
+                self.state = Dynamic::from_map(Map::new());
+            }
+            Err(e) => self.fail(&e.to_string()),
+        }
+    }
+
+    fn fail(&mut self, error: &str) {
+        log_detail(Verbosity::Low, "Error en script", &format!("{}: {}", self.file.path.display(), error));
+        self.loaded = None;
+    }
+
+    fn call(&mut self, hook: usize, args: impl rhai::FuncArgs) -> Option<Dynamic> {
+        let loaded = self.loaded.as_ref()?;
+        if !loaded.hooks[hook] {
+            return None;
+        }
+        let options = CallFnOptions::new().eval_ast(false);
+        let result = self.engine.call_fn_with_options(options, &mut Scope::new(), &loaded.ast, HOOKS[hook], args);
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.fail(&e.to_string());
+                None
+            }
+        }
+    }
+
+    fn run_hooks(&mut self, input: &mut PadInput) {
+        let previous = std::mem::replace(&mut self.previous, input.buttons);
+        for (index, (&now, &before)) in input.buttons.iter().zip(&previous).enumerate() {
+            if (now != 0) != (before != 0) {
+                self.call(if now != 0 { 1 } else { 2 }, (index as i64,));
+            }
+        }
+
+        let mut pad = Map::new();
+        pad.insert("buttons".into(), input.buttons.iter().map(|&b| Dynamic::from_int(b as i64)).collect::<Array>().into());
+        pad.insert("axes".into(), input.axes.iter().map(|&a| Dynamic::from_int(a as i64)).collect::<Array>().into());
+        pad.insert("state".into(), self.state.clone());
+        let Some(result) = self.call(0, (pad,)) else {
+            return;
+        };
+        let Some(mut pad) = result.try_cast::<Map>() else {
+            self.fail("on_snapshot debe devolver el mapa del mando");
+            return;
+        };
+        if let Some(state) = pad.remove("state") {
+            self.state = state;
+        }
+        read_into(&pad, "buttons", &mut input.buttons, |v| v.clamp(0, u8::MAX as i64) as u8);
+        read_into(&pad, "axes", &mut input.axes, |v| v.clamp(i16::MIN as i64, i16::MAX as i64) as i16);
+    }
+
+    fn send_command(&mut self, command: &str) {
+        if self.socket.is_none() {
+            self.socket = UdpSocket::bind("0.0.0.0:0").ok();
+        }
+        let result = match &self.socket {
+            Some(socket) => socket.send_to(command.as_bytes(), self.retroarch).map(drop),
+            None => Err(std::io::Error::other("no se pudo abrir un socket UDP")),
+        };
+        if let Err(e) = result {
+            log_detail(Verbosity::Low, "Error enviando comando a RetroArch", &format!("{}: {}", command, e));
+        }
+    }
+}
+
+impl InputFilter for Script {
+    fn apply(&mut self, input: &mut PadInput, now: Instant) {
+        self.reload_if_changed(now);
+        self.run_hooks(input);
+        let actions = std::mem::take(&mut *self.actions.lock().unwrap());
+        input.keys.extend(actions.keys);
+        for command in actions.commands {
+            self.send_command(&command);
+        }
+    }
+}
+
+/// Copia `pad[field]` sobre `target`; los elementos que falten o no sean
+/// enteros se dejan como estaban.
+fn read_into<T>(pad: &Map, field: &str, target: &mut [T], convert: impl Fn(i64) -> T) {
+    let Some(values) = pad.get(field).and_then(|value| value.read_lock::<Array>()) else {
+        return;
+    };
+    for (slot, value) in target.iter_mut().zip(values.iter()) {
+        if let Ok(value) = value.as_int() {
+            *slot = convert(value);
+        }
+    }
+}
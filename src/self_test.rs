@@ -0,0 +1,134 @@
+use crate::config::AxisRanges;
+use crate::devices::xbox360::create_virtual_gamepad;
+use crate::devices::xbox360_layout::Xbox360Layout;
+use crate::devices::{create_virtual_keyboard, create_virtual_mouse};
+use crate::keyboard_layout::KeyboardLayout;
+use crate::doctor;
+use evdev::uinput::VirtualDevice;
+use evdev::{EventType, InputEvent, Key, RelativeAxisType};
+use std::io::Write;
+use std::time::Duration;
+use tokio::time::sleep;
+
+// Long enough for evtest / jstest-gtk to show every step
+const STEP: Duration = Duration::from_millis(250);
+const SWEEP_STEPS: i32 = 16;
+// Time for udev and the desktop to pick up the new devices
+const SETTLE: Duration = Duration::from_secs(2);
+
+const BUTTON_NAMES: [&str; Xbox360Layout::BUTTON_COUNT] =
+    ["A", "B", "X", "Y", "LB", "RB", "Back", "Start", "Guide", "L3", "R3"];
+const AXIS_NAMES: [&str; Xbox360Layout::AXIS_COUNT] = [
+    "Stick izq. X",
+    "Stick izq. Y",
+    "Stick der. X",
+    "Stick der. Y",
+    "Gatillo izq.",
+    "Gatillo der.",
+    "D-pad X",
+    "D-pad Y",
+];
+
+/// Subcomando `test`: crea los dispositivos virtuales y reproduce un patrón
+/// fijo, sin red ni cliente, para comprobar el lado uinput con `evtest`.
+pub async fn run_self_test(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut only = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--only" => only = iter.next().cloned(),
+            other => return Err(format!("argumento desconocido para test: {}", other).into()),
+        }
+    }
+    let wants = |part: &str| only.as_deref().is_none_or(|o| o == part);
+    if let Some(part) = &only
+        && !["gamepad", "mouse", "keyboard"].contains(&part.as_str())
+    {
+        return Err(format!("--only acepta gamepad, mouse o keyboard, no '{}'", part).into());
+    }
+
+    let explain = |e: Box<dyn std::error::Error>| {
+        format!("no se pudo crear el dispositivo: {} ({})", e, doctor::remediation(e.as_ref()))
+    };
+    let mut gamepad = if wants("gamepad") { Some(create_virtual_gamepad(0, false, &[], &AxisRanges::default()).map_err(explain)?) } else { None };
+    let mut mouse = if wants("mouse") { Some(create_virtual_mouse().map_err(explain)?) } else { None };
+    let mut keyboard = if wants("keyboard") { Some(create_virtual_keyboard().map_err(explain)?) } else { None };
+    println!("✓ Dispositivos virtuales creados; esperando {}s a que el sistema los detecte...", SETTLE.as_secs());
+    sleep(SETTLE).await;
+
+    if let Some(device) = &mut gamepad {
+        test_gamepad(device).await?;
+    }
+    if let Some(device) = &mut mouse {
+        test_mouse(device).await?;
+    }
+    if let Some(device) = &mut keyboard {
+        test_keyboard(device).await?;
+    }
+    println!("\n✓ Patrón completo");
+    Ok(())
+}
+
+async fn test_gamepad(device: &mut VirtualDevice) -> std::io::Result<()> {
+    println!("\n🎮 Gamepad: botones");
+    for (code, name) in Xbox360Layout::BUTTON_CODES.iter().zip(BUTTON_NAMES) {
+        println!("   - {}", name);
+        tap(device, *code).await?;
+    }
+
+    println!("🎮 Gamepad: ejes");
+    for (i, (code, name)) in Xbox360Layout::AXIS_CODES.iter().zip(AXIS_NAMES).enumerate() {
+        let (min, max) = match i {
+            0..=3 => (Xbox360Layout::STICK_MIN, Xbox360Layout::STICK_MAX),
+            4 | 5 => (Xbox360Layout::TRIGGER_MIN, Xbox360Layout::TRIGGER_MAX),
+            _ => (Xbox360Layout::HAT_MIN, Xbox360Layout::HAT_MAX),
+        };
+        println!("   - {} ({}..{})", name, min, max);
+        let steps = if max - min < SWEEP_STEPS { max - min } else { SWEEP_STEPS };
+        for step in 0..=steps {
+            let value = min + (max - min) / steps * step;
+            device.emit(&[InputEvent::new(EventType::ABSOLUTE, *code as u16, value)])?;
+            sleep(STEP / 4).await;
+        }
+        device.emit(&[InputEvent::new(EventType::ABSOLUTE, *code as u16, 0)])?;
+        sleep(STEP).await;
+    }
+    Ok(())
+}
+
+async fn test_mouse(device: &mut VirtualDevice) -> std::io::Result<()> {
+    println!("\n🖱️  Mouse: cuadrado de 200 px");
+    for (name, dx, dy) in [("derecha", 10, 0), ("abajo", 0, 10), ("izquierda", -10, 0), ("arriba", 0, -10)] {
+        println!("   - {}", name);
+        for _ in 0..20 {
+            device.emit(&[
+                InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_X.0, dx),
+                InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_Y.0, dy),
+            ])?;
+            sleep(Duration::from_millis(15)).await;
+        }
+    }
+    Ok(())
+}
+
+async fn test_keyboard(device: &mut VirtualDevice) -> std::io::Result<()> {
+    println!("\n⌨️  Teclado: a-z (con el foco en un editor de texto)");
+    for c in 'a'..='z' {
+        // Positions, not characters: on a non-US host some letters come out swapped
+        if let Some(stroke) = KeyboardLayout::Us.stroke(c) {
+            print!("{}", c);
+            let _ = std::io::stdout().flush();
+            tap(device, stroke.key.code()).await?;
+        }
+    }
+    println!();
+    tap(device, Key::KEY_ENTER.code()).await
+}
+
+async fn tap(device: &mut VirtualDevice, code: u16) -> std::io::Result<()> {
+    device.emit(&[InputEvent::new(EventType::KEY, code, 1)])?;
+    sleep(STEP / 2).await;
+    device.emit(&[InputEvent::new(EventType::KEY, code, 0)])?;
+    sleep(STEP / 2).await;
+    Ok(())
+}
@@ -0,0 +1,481 @@
+use crate::admin::{bind_admin_socket, run_admin_socket};
+use crate::api::run_api;
+use crate::dsu::run_dsu;
+use crate::mqtt::run_mqtt;
+use crate::remote_retropad::{bind_remote_retropad, run_remote_retropad};
+use crate::synergy::run_synergy;
+use crate::observer::run_observer;
+use crate::battery::Batteries;
+use crate::bus::Bus;
+use crate::config::ConfigHandle;
+use crate::identity::Identities;
+use crate::devices::lazy::{LazyDevice, SharedDevice};
+use crate::devices::{
+    create_virtual_consumer, create_virtual_encoder, create_virtual_keyboard, create_virtual_lightgun,
+    create_virtual_mouse,
+};
+use crate::discovery::{run_discovery_broadcast, DiscoveryPorts};
+use crate::devices::xbox360::create_virtual_gamepad;
+use crate::servers::gamepad_server::{coalesce_snapshots, run_udp_gamepad_server};
+use crate::input_mode::InputMode;
+use crate::servers::keyboard_server::run_tcp_keyboard_server;
+use crate::logger::{log, log_detail, LogFormat, Verbosity, VerbositySpec};
+use crate::power;
+use crate::profiles::ProfileManager;
+use crate::protocol::{QUIC_CHANNEL_GAMEPAD, QUIC_CHANNEL_MOUSE};
+use crate::quic::{run_quic, QuicRoutes};
+use crate::rendezvous;
+use crate::servers::mouse_server::{coalesce_mouse, run_udp_mouse_server};
+use crate::stats::STATS;
+use crate::{capture, doctor, monitor, network, privileges, process_watch, quic, scheduling, session, systemd, trace};
+use evdev::uinput::VirtualDevice;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+
+/// Subcomando `record <captura> [opciones de serve]`: graba lo que envían
+/// los clientes sin tocar /dev/uinput. Es `serve --capture <captura>
+/// --dry-run`, para grabar en cualquier máquina y reproducir con `replay`.
+pub async fn run_record(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let Some((path, rest)) = args.split_first().filter(|(path, _)| !path.starts_with("--")) else {
+        return Err("uso: record <captura> [opciones de serve]".into());
+    };
+    let mut serve_args = vec!["--capture".to_string(), path.clone(), "--dry-run".to_string()];
+    serve_args.extend_from_slice(rest);
+    run_serve(&serve_args, false).await
+}
+
+/// Subcomando `serve` (el de siempre): levanta los dispositivos virtuales y
+/// los servidores de red hasta recibir SIGTERM/Ctrl+C. Con `monitor` además
+/// imprime los eventos que el kernel entrega en cada dispositivo.
+pub async fn run_serve(args: &[String], monitor: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut verbosity = VerbositySpec::default();
+    let mut config_path = None;
+    let mut dry_run = false;
+    let mut capture_path = None;
+    let mut log_format = None;
+    let mut traced_client = None;
+    let mut args = args.iter().cloned();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--verbosity" => verbosity = VerbositySpec::parse(&args.next().unwrap_or_default())?,
+            "--config" => config_path = args.next().map(PathBuf::from),
+            "--dry-run" => dry_run = true,
+            "--capture" => capture_path = args.next().map(PathBuf::from),
+            "--log-format" => log_format = Some(LogFormat::parse(&args.next().unwrap_or_default())?),
+            "--trace-client" => {
+                let ip = args.next().unwrap_or_default();
+                traced_client = Some(ip.parse::<IpAddr>().map_err(|_| format!("--trace-client necesita una IP, no '{}'", ip))?);
+            }
+            other => return Err(format!("argumento desconocido para serve: {}", other).into()),
+        }
+    }
+    // The point of monitor is seeing packets next to kernel events
+    if monitor && verbosity.level.is_none() {
+        verbosity.level = Some(Verbosity::High as u8);
+    }
+    let config = ConfigHandle::load(config_path, verbosity, log_format)?;
+
+    log(Verbosity::Low, "🚀 Iniciando Retro Control Server...");
+    if let Some(ip) = traced_client {
+        trace::set_traced_client(ip);
+        log_detail(Verbosity::Low, "Trazando los paquetes de", &ip.to_string());
+    }
+    if let Some(path) = &capture_path {
+        capture::start(path).map_err(|e| format!("no se pudo crear la captura {}: {}", path.display(), e))?;
+    }
+
+    if dry_run {
+        log(Verbosity::Low, "⚠ --dry-run: no se crean dispositivos uinput, los eventos sólo se registran");
+    } else {
+        let mut checks = doctor::diagnose();
+        if checks.iter().any(|c| !c.ok) && config.get().devices.modprobe && doctor::load_module() {
+            checks = doctor::diagnose();
+        }
+        if checks.iter().any(|c| !c.ok && c.required) {
+            doctor::print_checks(&checks);
+            return Err("uinput no está disponible; ejecuta `retro-control-server doctor`".into());
+        }
+        for check in checks.iter().filter(|c| !c.ok) {
+            log_detail(Verbosity::Low, &format!("⚠ {}", check.name), &check.detail);
+        }
+    }
+
+    power::apply(&config.get().power);
+    let mouse = virtual_device("Mouse", dry_run, create_virtual_mouse);
+    let keyboard = virtual_device("Teclado", dry_run, create_virtual_keyboard);
+    let encoder = virtual_device("Encoder", dry_run, create_virtual_encoder);
+    let lightgun = virtual_device("Lightgun", dry_run, create_virtual_lightgun);
+    let consumer =
+        config.get().devices.consumer.then(|| virtual_device("Control multimedia", dry_run, create_virtual_consumer));
+    let pressure = config.get().gamepad.pressure;
+    let extra_axes = config.get().gamepad.extra_axes.clone();
+    let profiles = ProfileManager::default();
+    let gamepads: Vec<_> = (0..config.get().gamepad.slots.max(1))
+        .map(|slot| {
+            let (config, profiles, extra_axes) = (config.clone(), profiles.clone(), extra_axes.clone());
+            // Axis ranges of the profile active when the pad (re)appears
+            virtual_device(format!("Gamepad P{}", slot + 1), dry_run, move || {
+                create_virtual_gamepad(slot, pressure, &extra_axes, &profiles.gamepad_config(&config.get()).axis_ranges)
+            })
+        })
+        .collect();
+    let coalesce_ms = config.get().devices.coalesce_ms;
+    let bus = Bus::new(
+        mouse.clone(),
+        keyboard.clone(),
+        encoder.clone(),
+        lightgun.clone(),
+        consumer.clone(),
+        gamepads.clone(),
+        coalesce_ms > 0,
+    );
+    if coalesce_ms > 0 {
+        tokio::spawn(bus.clone().run_coalescer(std::time::Duration::from_millis(coalesce_ms)));
+    }
+    let (input_mode, _) = watch::channel(InputMode::MouseKeyboard);
+    if monitor {
+        for device in [&mouse, &keyboard, &encoder, &lightgun].into_iter().chain(&consumer).chain(&gamepads) {
+            monitor::spawn_kernel_monitor(device.clone());
+        }
+    }
+
+    if config.get().devices.lazy {
+        log(Verbosity::Low, "✓ Dispositivos virtuales bajo demanda");
+    } else {
+        // Only P1: the other players' pads still appear when someone takes the slot
+        for device in [&mouse, &keyboard, &gamepads[0]] {
+            device.create()?;
+        }
+        log(Verbosity::Low, "✓ Dispositivos virtuales creados");
+    }
+
+    let net = config.get().network.clone();
+    let bind_for = |spec: &Option<String>| network::resolve_bind_address(spec.as_ref().unwrap_or(&net.bind));
+    let reuse_port = net.udp_receivers > 1;
+    let mouse_socket =
+        network::bind_udp("Mouse UDP", bind_for(&net.mouse_bind)?, net.mouse_port, net.port_fallback, reuse_port)
+            .await?;
+    let keyboard_listener = match systemd::activated_tcp_listener()? {
+        Some(listener) => TcpListener::from_std(listener)?,
+        None => {
+            network::bind_tcp("Teclado TCP", bind_for(&net.keyboard_bind)?, net.keyboard_port, net.port_fallback)
+                .await?
+        }
+    };
+    let mut keyboard_listener = network::StreamListener::new(keyboard_listener);
+    let gamepad_socket =
+        network::bind_udp("Gamepad UDP", bind_for(&net.gamepad_bind)?, net.gamepad_port, net.port_fallback, reuse_port)
+            .await?;
+    let mouse_addr = mouse_socket.local_addr()?;
+    let keyboard_addr = keyboard_listener.local_addr()?;
+    let gamepad_addr = gamepad_socket.local_addr()?;
+    let mut mouse_endpoint =
+        network::udp_endpoint("Mouse UDP", mouse_socket, &config, coalesce_mouse, &STATS.mouse, QUIC_CHANNEL_MOUSE)?;
+    let mut gamepad_endpoint = network::udp_endpoint(
+        "Gamepad UDP",
+        gamepad_socket,
+        &config,
+        coalesce_snapshots,
+        &STATS.gamepad,
+        QUIC_CHANNEL_GAMEPAD,
+    )?;
+    STATS.mouse.set_udp_port(mouse_addr.port());
+    STATS.gamepad.set_udp_port(gamepad_addr.port());
+    let admin_listener = match &config.get().admin.socket {
+        Some(path) => Some(bind_admin_socket(path)?),
+        None => None,
+    };
+    let observer_listener = match config.get().observer.listen {
+        Some(addr) => Some(TcpListener::bind(addr).await?),
+        None => None,
+    };
+    let observer_addr = observer_listener.as_ref().map(TcpListener::local_addr).transpose()?;
+    let api_listener = match config.get().api.listen {
+        Some(addr) => Some(TcpListener::bind(addr).await?),
+        None => None,
+    };
+    let api_addr = api_listener.as_ref().map(TcpListener::local_addr).transpose()?;
+    let retropad = config.get().remote_retropad.clone();
+    let retropad_sockets = if retropad.enabled {
+        Some(bind_remote_retropad(bind_for(&None)?, retropad.port, bus.slots()).await?)
+    } else {
+        None
+    };
+    let retropad_addr = retropad_sockets.as_ref().and_then(|sockets| sockets.first()).map(UdpSocket::local_addr).transpose()?;
+    let dsu_socket = match config.get().dsu.listen {
+        Some(addr) => Some(UdpSocket::bind(addr).await?),
+        None => None,
+    };
+    let dsu_addr = dsu_socket.as_ref().map(UdpSocket::local_addr).transpose()?;
+    let quic_endpoint = match config.get().quic.listen {
+        Some(addr) => Some(quic::bind(addr, &config.get().quic)?),
+        None => None,
+    };
+    let quic_addr = quic_endpoint.as_ref().map(quinn::Endpoint::local_addr).transpose()?;
+
+    scheduling::apply(&config.get().scheduling);
+    // Sockets and startup devices are open; lazy devices, P2 onwards and the
+    // auxiliary devices are created later as the unprivileged user
+    privileges::drop_privileges(&config.get().privileges)?;
+    if !dry_run
+        && config.get().privileges.user.is_some()
+        && let Err(e) = doctor::open_for_writing()
+    {
+        let devices = &config.get().devices;
+        if devices.lazy || devices.teardown_after_secs > 0 {
+            return Err(format!(
+                "[devices] lazy y teardown_after_secs crean dispositivos tras abandonar root, pero el usuario \
+                 no puede escribir /dev/uinput ({}): ejecuta `doctor --install --user` o usa lazy = false y \
+                 teardown_after_secs = 0",
+                e
+            )
+            .into());
+        }
+        log_detail(
+            Verbosity::Low,
+            "Sin acceso a /dev/uinput tras abandonar root",
+            &format!("{}: los mandos P2 en adelante y los dispositivos auxiliares no se podrán crear", e),
+        );
+    }
+
+    // Si cualquiera de los servidores muere, el proceso entero termina con error
+    // (systemd lo reinicia) en vez de seguir corriendo a medias.
+    let mut servers = JoinSet::new();
+
+    let connected_clients = Arc::new(AtomicUsize::new(0));
+    let identities = Identities::default();
+    let batteries = Batteries::default();
+    if config.get().rendezvous.server.is_some() {
+        let mouse_socket = mouse_endpoint.socket.udp().clone();
+        let gamepad_socket = gamepad_endpoint.socket.udp().clone();
+        tokio::spawn(rendezvous::run_rendezvous(mouse_socket, gamepad_socket, config.clone()));
+    }
+
+    if let Some(endpoint) = quic_endpoint {
+        let routes = QuicRoutes {
+            streams: keyboard_listener.attach_quic(),
+            mouse: mouse_endpoint.attach_quic(),
+            gamepad: gamepad_endpoint.attach_quic(),
+        };
+        let quic_config = config.clone();
+        tokio::spawn(async move {
+            if let Err(e) = run_quic(endpoint, routes, quic_config).await {
+                log(Verbosity::Low, &format!("Error en listener QUIC: {}", e));
+            }
+        });
+    }
+
+    let mouse_bus = bus.clone();
+    let mouse_config = config.clone();
+    let mouse_identities = identities.clone();
+    let mouse_mode = input_mode.subscribe();
+    servers.spawn(async move {
+        run_udp_mouse_server(mouse_endpoint, mouse_bus, mouse_mode, mouse_identities, mouse_config)
+            .await
+            .map_err(|e| format!("Error en servidor UDP Mouse: {}", e))
+    });
+
+    let keyboard_bus = bus.clone();
+    let mode_clone = input_mode.clone();
+    let tcp_clients_clone = connected_clients.clone();
+    let keyboard_config = config.clone();
+    let keyboard_identities = identities.clone();
+    let keyboard_profiles = profiles.clone();
+    servers.spawn(async move {
+        run_tcp_keyboard_server(
+            keyboard_listener,
+            keyboard_bus,
+            mode_clone,
+            tcp_clients_clone,
+            keyboard_identities,
+            keyboard_config,
+            keyboard_profiles,
+        )
+        .await
+        .map_err(|e| format!("Error en servidor TCP Teclado: {}", e))
+    });
+
+    let gamepad_bus = bus.clone();
+    let gamepad_mode = input_mode.subscribe();
+    let gamepad_config = config.clone();
+    let gamepad_identities = identities.clone();
+    let gamepad_profiles = profiles.clone();
+    let gamepad_batteries = batteries.clone();
+    servers.spawn(async move {
+        run_udp_gamepad_server(
+            gamepad_endpoint,
+            gamepad_bus,
+            gamepad_mode,
+            gamepad_identities,
+            gamepad_config,
+            gamepad_profiles,
+            gamepad_batteries,
+        )
+            .await
+            .map_err(|e| format!("Error en servidor UDP Gamepad: {}", e))
+    });
+
+    let discovery_clients = connected_clients.clone();
+    let discovery_config = config.clone();
+    let ports = DiscoveryPorts {
+        tcp: keyboard_addr.port(),
+        mouse: mouse_addr.port(),
+        gamepad: gamepad_addr.port(),
+    };
+    tokio::spawn(async move {
+        if let Err(e) = run_discovery_broadcast(ports, discovery_clients, discovery_config).await {
+            log(Verbosity::Low, &format!("Error en broadcast de descubrimiento: {}", e));
+        }
+    });
+
+    tokio::spawn(process_watch::run_process_watcher(config.clone(), profiles.clone()));
+    tokio::spawn(power::run_inhibitor(connected_clients.clone(), config.clone()));
+
+    let teardown_config = config.clone();
+    let mut teardown_devices = vec![mouse.clone(), keyboard.clone(), encoder.clone(), lightgun.clone()];
+    teardown_devices.extend(consumer.iter().chain(&gamepads).cloned());
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(session::SWEEP_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let idle_secs = teardown_config.get().devices.teardown_after_secs;
+            if idle_secs > 0 {
+                for device in &teardown_devices {
+                    device.destroy_if_idle(std::time::Duration::from_secs(idle_secs));
+                }
+            }
+        }
+    });
+
+    if let Some(listener) = admin_listener {
+        let admin_config = config.clone();
+        let admin_profiles = profiles.clone();
+        let admin_batteries = batteries.clone();
+        tokio::spawn(async move {
+            if let Err(e) = run_admin_socket(listener, admin_config, admin_profiles, admin_batteries).await {
+                log(Verbosity::Low, &format!("Error en socket de administración: {}", e));
+            }
+        });
+    }
+
+    if let Some(listener) = observer_listener {
+        let observer_bus = bus.clone();
+        let observer_config = config.clone();
+        tokio::spawn(async move {
+            if let Err(e) = run_observer(listener, observer_bus, observer_config).await {
+                log(Verbosity::Low, &format!("Error en canal de observación: {}", e));
+            }
+        });
+    }
+
+    if let Some(listener) = api_listener {
+        let api_bus = bus.clone();
+        let api_config = config.clone();
+        tokio::spawn(async move {
+            if let Err(e) = run_api(listener, api_bus, api_config).await {
+                log(Verbosity::Low, &format!("Error en la API REST: {}", e));
+            }
+        });
+    }
+
+    if let Some(sockets) = retropad_sockets {
+        let retropad_config = config.clone();
+        tokio::spawn(async move {
+            if let Err(e) = run_remote_retropad(sockets, retropad_config).await {
+                log(Verbosity::Low, &format!("Error en Remote RetroPad: {}", e));
+            }
+        });
+    }
+
+    if let Some(socket) = dsu_socket {
+        let dsu_config = config.clone();
+        tokio::spawn(async move {
+            if let Err(e) = run_dsu(socket, dsu_config).await {
+                log(Verbosity::Low, &format!("Error en servidor DSU: {}", e));
+            }
+        });
+    }
+
+    if config.get().synergy.server.is_some() {
+        tokio::spawn(run_synergy(bus.clone(), config.clone()));
+    }
+
+    if config.get().mqtt.broker.is_some() {
+        tokio::spawn(run_mqtt(
+            bus.clone(),
+            config.clone(),
+            profiles.clone(),
+            batteries.clone(),
+            input_mode.subscribe(),
+            connected_clients.clone(),
+        ));
+    }
+
+    // SIGHUP recarga la configuración sin tocar los dispositivos uinput
+    let mut hangup = signal(SignalKind::hangup())?;
+    let reload_config = config.clone();
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            if let Err(e) = reload_config.reload() {
+                log(Verbosity::Low, &format!("Error recargando configuración: {}", e));
+            }
+        }
+    });
+
+    log(Verbosity::Low, "✓ Servidores de red iniciados");
+    log(Verbosity::Low, &format!("   - Mouse UDP: {}", mouse_addr));
+    log(Verbosity::Low, &format!("   - Teclado TCP: {}", keyboard_addr));
+    log(Verbosity::Low, &format!("   - Gamepad UDP: {}", gamepad_addr));
+    if let Some(addr) = observer_addr {
+        log(Verbosity::Low, &format!("   - Observador TCP: {}", addr));
+    }
+    if let Some(addr) = api_addr {
+        log(Verbosity::Low, &format!("   - API REST: {}", addr));
+    }
+    if let Some(addr) = retropad_addr {
+        log(Verbosity::Low, &format!("   - Remote RetroPad UDP: {}", addr));
+    }
+    if let Some(addr) = dsu_addr {
+        log(Verbosity::Low, &format!("   - DSU UDP: {}", addr));
+    }
+    if let Some(addr) = quic_addr {
+        log(Verbosity::Low, &format!("   - QUIC: {}", addr));
+    }
+    log(Verbosity::Low, "Esperando conexiones...");
+
+    systemd::notify_ready();
+    systemd::spawn_watchdog();
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = terminate.recv() => {}
+        Some(result) = servers.join_next() => {
+            let message = match result {
+                Ok(Ok(())) => "un servidor terminó inesperadamente".to_string(),
+                Ok(Err(e)) => e,
+                Err(e) => e.to_string(),
+            };
+            log(Verbosity::Low, &format!("✗ {}", message));
+            systemd::notify_stopping();
+            return Err(message.into());
+        }
+    }
+    systemd::notify_stopping();
+    log(Verbosity::Low, "\nApagando Retro Control Server...");
+
+    Ok(())
+}
+fn virtual_device(
+    name: impl Into<String>,
+    dry_run: bool,
+    factory: impl Fn() -> Result<VirtualDevice, Box<dyn std::error::Error>> + Send + Sync + 'static,
+) -> SharedDevice {
+    if dry_run { LazyDevice::simulated(name) } else { LazyDevice::new(name, factory) }
+}
@@ -1,174 +1,1041 @@
-use crate::logger::{log, log_data, Verbosity};
-use crate::protocol::HEADER_GAMEPAD_SNAPSHOT;
+use crate::api::{Injection, INJECTOR};
+use crate::battery::Batteries;
+use crate::client_profile::{AxisCalibration, ClientProfiles, CALIBRATED_AXES, MAX_RUMBLE_INTENSITY};
+use crate::identity::{hello_ack, parse_udp_hello, ClientKey, Identities};
+use crate::filters::{PadInput, Pipeline};
+use crate::haptics::Haptic;
+use crate::input_mode::InputMode;
+use crate::capture::{self, Channel};
+use crate::copilot::{Merger, COPILOT};
+use crate::dsu::{parse_motion, MOTION};
+use crate::config::{AxisRanges, ConfigHandle, EncoderConfig, ExtraAxis, GamepadConfig, GuideMode, TriggerMode};
+use crate::logger::{log_data, log_detail, log_lazy, Verbosity};
+use crate::profiles::ProfileManager;
+use crate::protocol::{
+    CALIBRATE_CLEAR, CALIBRATE_FINISH, CALIBRATE_START, HEADER_BATTERY, HEADER_CALIBRATE, HEADER_CALIBRATE_ACK,
+    HEADER_GAMEPAD_SNAPSHOT, HEADER_GAMEPAD_SNAPSHOT_EXTENDED, HEADER_MOTION, HEADER_RUMBLE, HEADER_RUMBLE_SETTINGS, HEADER_SLOT_ASSIGN, HEADER_SLOT_RELEASE, HEADER_SLOT_REQUEST, MAX_DATAGRAM, MAX_EXTRA_AXES, MODE_ARCADE,
+    MODE_XBOX,
+};
+use crate::network::{Carrier, ReplySocket, UdpEndpoint};
+use crate::packet_auth::{PacketAuth, Verdict};
+use crate::quarantine::QUARANTINE;
+use crate::relay::{RelayChannel, RELAY};
+use crate::rumble::spawn_rumble_monitor;
+use crate::rate_limit::RateLimiter;
+use crate::session::{Admission, SessionTable, Transport, SWEEP_INTERVAL};
+use crate::slots::PlayerSlots;
+use crate::stats::STATS;
+use crate::trace;
 use crate::devices::xbox360_layout::Xbox360Layout;
-use evdev::{EventType, InputEvent, Key, uinput::VirtualDevice};
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicU8, Ordering};
-use tokio::net::UdpSocket;
+use crate::bus::{InputAction, Origin, SharedBus, Target};
+use evdev::Key;
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{interval, Duration, Instant};
 
-// Mode detection constants
-const MODE_ARCADE: u8 = 1;   // Arcade layout (snap to 8 directions + -32768)
-const MODE_XBOX: u8 = 2;     // Xbox layout with real intermediate values
+// Snapshots from app versions that sent one byte per button and no mode byte
+const LEGACY_SNAPSHOT_LEN: usize = 29;
+// Current snapshots without pressure bytes; longer ones may carry pressure or padding
+const SNAPSHOT_LEN: usize = 20;
 
-// Global variable to remember the detected mode
-static CURRENT_MODE: AtomicU8 = AtomicU8::new(0); // 0 = not detected yet
+type Pressure = [u8; Xbox360Layout::PRESSURE_COUNT];
+type ExtraAxes = [i16; MAX_EXTRA_AXES];
+// Mode, buttons, axes, extra axes and pressure of a parsed snapshot
+type Snapshot = (u8, [u8; 12], [i16; 8], ExtraAxes, Option<Pressure>);
+
+/// Ejes con los que se crearon los gamepads virtuales además de los del
+/// layout Xbox; se leen al arrancar.
+struct PadAxes {
+    pressure: bool,
+    extra: Vec<ExtraAxis>,
+}
+
+// Motor changes waiting to be sent to the players' clients
+const RUMBLE_BACKLOG: usize = 64;
+
+// Position of Guide in the snapshot's button array
+const GUIDE_BUTTON: usize = 8;
+// A and B, which become Enter and Esc in navigation mode
+const NAV_ACCEPT: usize = 0;
+const NAV_BACK: usize = 1;
+
+/// Teclas del teclado virtual que mantiene pulsadas el mando: el botón
+/// Guide, sus combinaciones y la navegación.
+#[derive(Default)]
+struct PadKeys {
+    down: HashSet<u16>,
+}
+
+impl PadKeys {
+    /// Quita del snapshot lo que consume el servidor y devuelve los cambios
+    /// de teclas a emitir en el teclado.
+    fn process(&mut self, buttons: &mut [u8; 12], axes: &mut [i16; 8], config: &GamepadConfig) -> Vec<InputAction> {
+        let guide = buttons[GUIDE_BUTTON] != 0;
+        let mut wanted = HashSet::new();
+        match config.guide {
+            GuideMode::Button => {}
+            GuideMode::Key => {
+                buttons[GUIDE_BUTTON] = 0;
+                if guide {
+                    wanted.insert(config.guide_key);
+                }
+            }
+            GuideMode::Hotkey => {
+                buttons[GUIDE_BUTTON] = 0;
+                if guide {
+                    // Buttons used in a combo don't reach the game while Guide is held
+                    for &(index, key) in &config.hotkeys {
+                        if let Some(button) = buttons.get_mut(index)
+                            && *button != 0
+                        {
+                            *button = 0;
+                            wanted.insert(key);
+                        }
+                    }
+                }
+            }
+        }
+        if config.navigation {
+            navigate(buttons, axes, config, &mut wanted);
+        }
+        self.update(wanted)
+    }
+
+    fn release_all(&mut self) -> Vec<InputAction> {
+        self.update(HashSet::new())
+    }
+
+    fn update(&mut self, wanted: HashSet<u16>) -> Vec<InputAction> {
+        let mut actions: Vec<InputAction> =
+            self.down.difference(&wanted).map(|&code| InputAction::key(code, false)).collect();
+        actions.extend(wanted.difference(&self.down).map(|&code| InputAction::key(code, true)));
+        self.down = wanted;
+        actions
+    }
+}
+
+/// Modo navegación: d-pad (o stick izquierdo) como flechas, A como Enter y
+/// B como Esc, para interfaces que ignoran los mandos (Kodi, escritorio).
+/// Lo que se traduce no llega al gamepad virtual, salvo con `mirror`.
+fn navigate(buttons: &mut [u8; 12], axes: &mut [i16; 8], config: &GamepadConfig, wanted: &mut HashSet<u16>) {
+    let threshold = config.hat_threshold;
+    let x = if axes[6] < 0 || axes[0] <= -threshold { -1 } else if axes[6] > 0 || axes[0] >= threshold { 1 } else { 0 };
+    let y = if axes[7] < 0 || axes[1] <= -threshold { -1 } else if axes[7] > 0 || axes[1] >= threshold { 1 } else { 0 };
+    for (active, key) in [
+        (x < 0, Key::KEY_LEFT),
+        (x > 0, Key::KEY_RIGHT),
+        (y < 0, Key::KEY_UP),
+        (y > 0, Key::KEY_DOWN),
+        (buttons[NAV_ACCEPT] != 0, Key::KEY_ENTER),
+        (buttons[NAV_BACK] != 0, Key::KEY_ESC),
+    ] {
+        if active {
+            wanted.insert(key.code());
+        }
+    }
+    if config.mirror {
+        return;
+    }
+    buttons[NAV_ACCEPT] = 0;
+    buttons[NAV_BACK] = 0;
+    for axis in [0, 1, 6, 7] {
+        axes[axis] = 0;
+    }
+}
+
+// Wheel repeat of `flick_scroll`: the first notch comes at once, the second
+// after FLICK_DELAY, and from then on one per FLICK_SLOWEST at the threshold
+// down to one per FLICK_FASTEST at full tilt
+const FLICK_DELAY: Duration = Duration::from_millis(300);
+const FLICK_SLOWEST: Duration = Duration::from_millis(250);
+const FLICK_FASTEST: Duration = Duration::from_millis(30);
+// Right stick Y in the snapshot
+const FLICK_AXIS: usize = 3;
+
+/// Desplazamiento con el stick derecho (`flick_scroll`): pasar el umbral
+/// da una muesca de rueda y mantenerlo repite, más deprisa cuanto más se
+/// inclina. Como el turbo, sólo avanza con cada snapshot recibido.
+#[derive(Default)]
+struct FlickScroll {
+    next: Option<Instant>,
+}
+
+impl FlickScroll {
+    /// Muescas a emitir ahora (positivo = hacia arriba). El eje no llega al
+    /// gamepad, salvo con `mirror`.
+    fn process(&mut self, axes: &mut [i16; 8], config: &GamepadConfig, now: Instant) -> i32 {
+        if !config.flick_scroll {
+            self.next = None;
+            return 0;
+        }
+        let value = axes[FLICK_AXIS];
+        if !config.mirror {
+            axes[FLICK_AXIS] = 0;
+        }
+        let threshold = config.hat_threshold.clamp(1, i16::MAX - 1) as u32;
+        let tilt = value.unsigned_abs() as u32;
+        if tilt < threshold {
+            self.next = None;
+            return 0;
+        }
+        let delay = match self.next {
+            Some(next) if now < next => return 0,
+            Some(_) => {
+                // How far past the threshold, 0 to 1
+                let reach = (tilt - threshold) as f64 / (32768 - threshold) as f64;
+                FLICK_SLOWEST.mul_f64(1.0 - reach) + FLICK_FASTEST.mul_f64(reach)
+            }
+            None => FLICK_DELAY,
+        };
+        self.next = Some(now + delay);
+        // Stick up is negative, wheel up positive
+        -(value.signum() as i32)
+    }
+}
+
+// Pseudo button indices of the four directions in `[encoder] players`
+const ENCODER_UP: usize = 12;
+
+/// Modo encoder: cada jugador emite teclas en un teclado propio, como un
+/// I-PAC. Lo que tiene tecla asignada no llega a su gamepad virtual, salvo
+/// con `mirror`.
+struct Encoder {
+    bus: SharedBus,
+    /// Teclas pulsadas por cada slot
+    keys: Vec<PadKeys>,
+}
+
+impl Encoder {
+    fn new(bus: SharedBus) -> Self {
+        let keys = (0..bus.slots()).map(|_| PadKeys::default()).collect();
+        Self { bus, keys }
+    }
+
+    fn process(&mut self, slot: usize, buttons: &mut [u8; 12], axes: &mut [i16; 8], gamepad: &GamepadConfig, encoder: &EncoderConfig) {
+        let map = encoder.players.get(slot).filter(|_| gamepad.encoder);
+        let mut wanted = HashSet::new();
+        if let Some(map) = map {
+            let threshold = gamepad.hat_threshold;
+            let directions = [
+                axes[7] < 0 || axes[1] <= -threshold,
+                axes[7] > 0 || axes[1] >= threshold,
+                axes[6] < 0 || axes[0] <= -threshold,
+                axes[6] > 0 || axes[0] >= threshold,
+            ];
+            let mut directions_mapped = false;
+            for &(index, key) in map {
+                let pressed = match index.checked_sub(ENCODER_UP) {
+                    Some(direction) => {
+                        directions_mapped = true;
+                        directions.get(direction).copied().unwrap_or(false)
+                    }
+                    None if gamepad.mirror => buttons.get(index).is_some_and(|&button| button != 0),
+                    None => buttons.get_mut(index).is_some_and(|button| std::mem::take(button) != 0),
+                };
+                if pressed {
+                    wanted.insert(key);
+                }
+            }
+            if directions_mapped && !gamepad.mirror {
+                for axis in [0, 1, 6, 7] {
+                    axes[axis] = 0;
+                }
+            }
+        }
+        let actions = self.keys[slot].update(wanted);
+        let _ = self.bus.emit(Target::Encoder, &actions);
+    }
+
+    fn release(&mut self, slot: usize) {
+        self.bus.release(Target::Encoder, &self.keys[slot].release_all());
+    }
+}
+
+/// Estado de `[gamepad] chords` de un slot.
+#[derive(Default)]
+struct ChordState {
+    /// Por combinación: cuándo bajó el primero de sus botones
+    started: Vec<Option<Instant>>,
+    /// Por combinación: reconocida y con alguno de sus botones aún pulsado
+    active: Vec<bool>,
+    keys: PadKeys,
+}
+
+/// Combinaciones de botones que emiten una tecla propia en el teclado
+/// virtual. Un botón que puede formar parte de una combinación se retiene
+/// hasta `chord_window_ms` esperando a los demás; si no llegan a tiempo,
+/// pasa al gamepad como siempre.
+struct Chords {
+    bus: SharedBus,
+    slots: Vec<ChordState>,
+}
+
+impl Chords {
+    fn new(bus: SharedBus) -> Self {
+        let slots = (0..bus.slots()).map(|_| ChordState::default()).collect();
+        Self { bus, slots }
+    }
+
+    fn process(&mut self, slot: usize, buttons: &mut [u8; 12], config: &GamepadConfig) {
+        let state = &mut self.slots[slot];
+        let window = Duration::from_millis(config.chord_window_ms);
+        state.started.resize(config.chords.len(), None);
+        state.active.resize(config.chords.len(), false);
+        let raw = *buttons;
+        let pressed = |index: &usize| raw.get(*index).is_some_and(|&b| b != 0);
+        let mut wanted = HashSet::new();
+        for (i, (members, key)) in config.chords.iter().enumerate() {
+            let down = members.iter().filter(|m| pressed(m)).count();
+            if down == 0 {
+                state.started[i] = None;
+                state.active[i] = false;
+                continue;
+            }
+            let started = *state.started[i].get_or_insert_with(Instant::now);
+            if down == members.len() && started.elapsed() <= window {
+                state.active[i] = true;
+            }
+            if state.active[i] && down == members.len() {
+                wanted.insert(*key);
+            }
+            // Held back while the chord may still form, swallowed while it is active
+            if state.active[i] || started.elapsed() < window {
+                for &member in members {
+                    if let Some(button) = buttons.get_mut(member) {
+                        *button = 0;
+                    }
+                }
+            }
+        }
+        let actions = state.keys.update(wanted);
+        let _ = self.bus.emit(Target::Keyboard, &actions);
+    }
+
+    fn release(&mut self, slot: usize) {
+        let state = &mut self.slots[slot];
+        state.started.clear();
+        state.active.clear();
+        self.bus.release(Target::Keyboard, &state.keys.release_all());
+    }
+}
+
+/// Suelta todo lo que un slot tenga pulsado: su gamepad, el encoder y las
+/// combinaciones.
+fn neutralize(
+    bus: &SharedBus,
+    encoder: &mut Encoder,
+    chords: &mut Chords,
+    merger: &mut Merger,
+    slot: usize,
+    pad_axes: &PadAxes,
+    ranges: &AxisRanges,
+) {
+    bus.release(Target::Gamepad(slot), &neutral_actions(pad_axes, ranges));
+    merger.forget(slot);
+    encoder.release(slot);
+    chords.release(slot);
+}
+
+// An axis that moved less than this during calibration keeps its nominal range
+const MIN_CALIBRATION_TRAVEL: i32 = 4096;
+
+/// Recorrido observado mientras el cliente calibra.
+struct CalibrationRun {
+    min: [i16; CALIBRATED_AXES],
+    max: [i16; CALIBRATED_AXES],
+    last: [i16; CALIBRATED_AXES],
+    samples: u32,
+}
+
+impl CalibrationRun {
+    fn new() -> Self {
+        Self {
+            min: [i16::MAX; CALIBRATED_AXES],
+            max: [i16::MIN; CALIBRATED_AXES],
+            last: [0; CALIBRATED_AXES],
+            samples: 0,
+        }
+    }
+
+    fn record(&mut self, axes: &[i16; 8]) {
+        for (i, &value) in axes.iter().take(CALIBRATED_AXES).enumerate() {
+            self.min[i] = self.min[i].min(value);
+            self.max[i] = self.max[i].max(value);
+            self.last[i] = value;
+        }
+        self.samples += 1;
+    }
+
+    // The last snapshot, with everything released, is taken as the center
+    fn finish(&self) -> Option<Vec<AxisCalibration>> {
+        if self.samples == 0 {
+            return None;
+        }
+        let axes = (0..CALIBRATED_AXES)
+            .map(|i| {
+                if (self.max[i] as i32 - self.min[i] as i32) < MIN_CALIBRATION_TRAVEL {
+                    AxisCalibration::FULL_RANGE
+                } else {
+                    AxisCalibration { min: self.min[i], center: self.last[i], max: self.max[i] }
+                }
+            })
+            .collect();
+        Some(axes)
+    }
+}
+
+/// Lo que despierta al bucle del servidor con un snapshot que procesar.
+enum Received {
+    Datagram(usize, SocketAddr, Carrier),
+    /// De la API REST o de Remote RetroPad; ver `api::Injection`.
+    Injected(Injection),
+}
 
 pub async fn run_udp_gamepad_server(
-    port: u16,
-    device: Arc<Mutex<VirtualDevice>>,
+    endpoint: UdpEndpoint,
+    bus: SharedBus,
+    mut input_mode: watch::Receiver<InputMode>,
+    identities: Identities,
+    config: ConfigHandle,
+    profiles: ProfileManager,
+    batteries: Batteries,
 ) -> std::io::Result<()> {
-    let socket = UdpSocket::bind(format!("0.0.0.0:{}", port)).await?;
-    let mut buf = [0u8; 64];
+    // One extra byte to tell an exact fit from a truncated datagram
+    let mut buf = [0u8; MAX_DATAGRAM + 1];
+    let UdpEndpoint { socket, mut source } = endpoint;
+    let mut encoder = Encoder::new(bus.clone());
+    let mut chords = Chords::new(bus.clone());
+    let mut merger = Merger::default();
+    let mut pipelines: Vec<Pipeline> = (0..bus.slots()).map(|_| Pipeline::default()).collect();
+    let mut limiter = RateLimiter::new();
+    let mut auth = PacketAuth::default();
+    let mut sessions = SessionTable::new("UDP Gamepad", Transport::Datagram);
+    let mut client_profiles = ClientProfiles::load(config.get().gamepad.profiles_file.clone());
+    let mut calibrations: HashMap<ClientKey, CalibrationRun> = HashMap::new();
+    let mut pad_keys = PadKeys::default();
+    let mut scrolls: Vec<FlickScroll> = (0..bus.slots()).map(|_| FlickScroll::default()).collect();
+    let mut slots = PlayerSlots::new(bus.slots());
+    // Where to send player number updates
+    let mut client_addrs: HashMap<ClientKey, SocketAddr> = HashMap::new();
+    // Per slot, the last snapshot that had buttons down; cleared by neutral snapshots
+    let mut held_since: Vec<Option<Instant>> = vec![None; bus.slots()];
+    // Processing mode of each client, detected from its first snapshot
+    let mut modes: HashMap<ClientKey, u8> = HashMap::new();
+    let pad_axes = PadAxes { pressure: config.get().gamepad.pressure, extra: config.get().gamepad.extra_axes.clone() };
+    // Axis ranges each pad was created with; a profile with others recreates it
+    let mut ranges = vec![config.get().gamepad.axis_ranges; bus.slots()];
+
+    let mut sweep = interval(SWEEP_INTERVAL);
+    let mut injections = INJECTOR.take_receiver();
+    let (rumble_tx, mut rumble) = mpsc::channel(RUMBLE_BACKLOG);
+    for slot in 0..bus.slots() {
+        spawn_rumble_monitor(slot, bus.device(Target::Gamepad(slot)).clone(), rumble_tx.clone());
+    }
 
     loop {
-        let (len, _) = socket.recv_from(&mut buf).await?;
-        let data = buf[..len].to_vec();
-        let device_clone = Arc::clone(&device);
-
-        // Spawn processing to keep recv loop fast
-        tokio::spawn(async move {
-            if let Some((mode, buttons, axes)) = parse_gamepad_snapshot(&data) {
-                log(Verbosity::Low, &format!("Gamepad Snapshot: mode={}, buttons={:?}, axes={:?}", mode, buttons, axes));
-                let semantic = describe_snapshot(&buttons, &axes);
-                log_data(Verbosity::Low, &format!("Evento: {}", semantic), &[]);
-
-                let mut events = Vec::new();
-                process_buttons(buttons, &mut events);
-                process_axes(mode, axes, &mut events);
-                emit_events(&device_clone, &events);
+        let received = tokio::select! {
+            result = source.recv_from(socket.udp(), &mut buf) => {
+                let (len, src_addr, carrier) = result?;
+                Received::Datagram(len, src_addr, carrier)
+            }
+            Some(injection) = injections.recv() => Received::Injected(injection),
+            Some((slot, motors)) = rumble.recv() => {
+                // Only the player's own client feels it, at its own intensity
+                if let Some(key) = slots.owner(slot)
+                    && let Some(&addr) = client_addrs.get(&key)
+                {
+                    let profile = client_profiles.get(&key).cloned().unwrap_or_default();
+                    if profile.rumble {
+                        let [strong, weak] = motors.scaled(profile.rumble_intensity);
+                        let _ = socket.send_to(&[HEADER_RUMBLE, strong, weak], addr).await;
+                    }
+                }
+                continue;
+            }
+            Ok(()) = input_mode.changed() => {
+                // The client stops sending snapshots in mouse+keyboard mode, so nothing would release them
+                if *input_mode.borrow_and_update() == InputMode::MouseKeyboard {
+                    for (slot, held) in held_since.iter_mut().enumerate() {
+                        *held = None;
+                        neutralize(&bus, &mut encoder, &mut chords, &mut merger, slot, &pad_axes, &ranges[slot]);
+                    }
+                    bus.release(Target::Keyboard, &pad_keys.release_all());
+                }
+                continue;
+            }
+            _ = sweep.tick() => {
+                let session_config = &config.get().sessions;
+                if let Some(limit) = session_config.held_limit() {
+                    for (slot, held) in held_since.iter_mut().enumerate() {
+                        if held.is_some_and(|at| at.elapsed() > limit) {
+                            *held = None;
+                            log_detail(
+                                Verbosity::Low,
+                                "Mando soltado por el watchdog",
+                                &format!("jugador {} con botones pulsados más de {}s sin refresco", slot + 1, limit.as_secs()),
+                            );
+                            neutralize(&bus, &mut encoder, &mut chords, &mut merger, slot, &pad_axes, &ranges[slot]);
+                        }
+                    }
+                }
+                let expired = sessions.expire_idle(session_config);
+                for key in &expired {
+                    if let Some(slot) = slots.release(key) {
+                        neutralize(&bus, &mut encoder, &mut chords, &mut merger, slot, &pad_axes, &ranges[slot]);
+                    }
+                }
+                if !expired.is_empty() {
+                    bus.release(Target::Keyboard, &pad_keys.release_all());
+                }
+                announce_slots(&socket, &mut slots, &client_addrs).await;
+                for key in &expired {
+                    client_addrs.remove(key);
+                    modes.remove(key);
+                    batteries.forget(key);
+                }
+                continue;
+            }
+        };
+        let mut origin = Origin::now(&STATS.gamepad);
+        let (key, src_addr, mode, slot, mut buttons, mut axes, extra, pressure) = match received {
+            // Injectors check the player against `bus.slots()`; this keeps a stray one from indexing past it
+            Received::Injected(Injection { slot, .. }) if slot >= bus.slots() => continue,
+            Received::Injected(Injection { slot, buttons, axes }) => {
+                (None, None, MODE_XBOX, slot, buttons, axes, [0; MAX_EXTRA_AXES], None)
+            }
+            Received::Datagram(len, src_addr, carrier) => {
+                origin.span = trace::span(src_addr);
+                trace::stage(origin.span, "receive", || format!("{:02X?}", &buf[..len.min(MAX_DATAGRAM)]));
+                STATS.gamepad.record_packet();
+                capture::record(Channel::Gamepad, src_addr, &buf[..len]);
+                if len > MAX_DATAGRAM {
+                    STATS.gamepad.record_oversized();
+                    log_detail(Verbosity::Medium, "Datagrama demasiado grande", &format!("{} desde {}", len, src_addr));
+                    continue;
+                }
+                let current_config = config.get();
+                if !current_config.is_allowed(src_addr.ip()) {
+                    continue;
+                }
+                if QUARANTINE.is_banned(src_addr.ip()) {
+                    STATS.gamepad.record_quarantined();
+                    trace::stage(origin.span, "drop", || "en cuarentena".to_string());
+                    continue;
+                }
+                let security = &current_config.security;
+                if !limiter.allow(src_addr.ip(), security.udp_rate_limit, security.udp_rate_burst) {
+                    STATS.gamepad.record_rate_limited();
+                    trace::stage(origin.span, "drop", || "límite de tasa".to_string());
+                    continue;
+                }
+                let len = match auth.check(security.packet_key.as_deref(), &buf[..len], src_addr, carrier) {
+                    Verdict::Accepted(len) => len,
+                    Verdict::Challenge(reply) => {
+                        let _ = socket.send_to(&reply, src_addr).await;
+                        continue;
+                    }
+                    Verdict::Rejected(reason) => {
+                        STATS.gamepad.record_unauthenticated();
+                        log_detail(Verbosity::Medium, "Datagrama no autenticado", &format!("{}: {}", src_addr, reason));
+                        // Not a quarantine violation: the source address may be a spoofed client's
+                        trace::stage(origin.span, "drop", || reason.to_string());
+                        continue;
+                    }
+                };
+                if !RELAY.forward_datagram(RelayChannel::Gamepad, src_addr, &buf[..len], &socket, &current_config.relay) {
+                    continue;
+                }
+                if let Some(token) = parse_udp_hello(&buf[..len]) {
+                    match identities.hello("UDP Gamepad", src_addr, token) {
+                        Ok(id) => {
+                            let _ = socket.send_to(&hello_ack(id), src_addr).await;
+                        }
+                        Err(e) => log_detail(Verbosity::Low, "Error en HELLO UDP", &format!("{}: {}", src_addr, e)),
+                    }
+                    continue;
+                }
+                let key = identities.key_for(src_addr);
+                if let Admission::Rejected = sessions.admit(key, &current_config.sessions) {
+                    continue;
+                }
+                client_addrs.insert(key, src_addr);
+
+                if len >= 2 && buf[0] == HEADER_CALIBRATE {
+                    let ok = handle_calibration(buf[1], key, &mut calibrations, &mut client_profiles);
+                    let _ = socket.send_to(&[HEADER_CALIBRATE_ACK, buf[1], ok as u8], src_addr).await;
+                    continue;
+                }
+
+                if len >= 3 && buf[0] == HEADER_BATTERY {
+                    if batteries.report(key, slots.slot_of(&key), buf[1], buf[2] != 0) {
+                        let _ = socket.send_to(&Haptic::Long.packet(), src_addr).await;
+                    }
+                    continue;
+                }
+
+                if len >= 3 && buf[0] == HEADER_RUMBLE_SETTINGS {
+                    let (enabled, intensity) = (buf[1] != 0, buf[2].min(MAX_RUMBLE_INTENSITY));
+                    client_profiles.update(key, |profile| {
+                        profile.rumble = enabled;
+                        profile.rumble_intensity = intensity;
+                    });
+                    if !enabled {
+                        // Whatever was playing stops now, not with the game's next change
+                        let _ = socket.send_to(&[HEADER_RUMBLE, 0, 0], src_addr).await;
+                    }
+                    continue;
+                }
+
+                if buf[0] == HEADER_MOTION {
+                    if let Some(sample) = parse_motion(&buf[..len])
+                        && let Some(slot) = slots.slot_of(&key)
+                    {
+                        MOTION.record(slot, sample);
+                    }
+                    continue;
+                }
+
+                if len >= 2 && buf[0] == HEADER_SLOT_REQUEST {
+                    let requested = buf[1].checked_sub(1).map(usize::from);
+                    let previous = slots.slot_of(&key);
+                    let slot = slots.claim(key, requested, |k| sessions.contains(k));
+                    if let Some(previous) = previous
+                        && slot != Some(previous)
+                    {
+                        neutralize(&bus, &mut encoder, &mut chords, &mut merger, previous, &pad_axes, &ranges[previous]);
+                    }
+                    // Always answer the requester, even if nothing changed
+                    if slot == previous {
+                        let _ = socket.send_to(&slot_assign(slot), src_addr).await;
+                    }
+                    announce_slots(&socket, &mut slots, &client_addrs).await;
+                    continue;
+                }
+                if len >= 1 && buf[0] == HEADER_SLOT_RELEASE {
+                    match slots.release(&key) {
+                        Some(slot) => neutralize(&bus, &mut encoder, &mut chords, &mut merger, slot, &pad_axes, &ranges[slot]),
+                        None => {
+                            let _ = socket.send_to(&slot_assign(None), src_addr).await;
+                        }
+                    }
+                    announce_slots(&socket, &mut slots, &client_addrs).await;
+                    continue;
+                }
+
+                let Some((mode, buttons, mut axes, extra, pressure)) = parse_gamepad_snapshot(&buf[..len]) else {
+                    log_detail(Verbosity::Medium, "Snapshot descartado", &format!("{}: {} bytes", src_addr, len));
+                    trace::stage(origin.span, "drop", || "paquete no reconocido".to_string());
+                    // A snapshot of an odd length is a client quirk, not an attack
+                    let snapshot = [HEADER_GAMEPAD_SNAPSHOT, HEADER_GAMEPAD_SNAPSHOT_EXTENDED];
+                    if !buf[..len].first().is_some_and(|header| snapshot.contains(header)) {
+                        QUARANTINE.violation(src_addr.ip(), "gamepad", &current_config.security);
+                    }
+                    continue;
+                };
+                // Clients that never asked for a slot get the first free one
+                let Some(slot) = slots.slot_of(&key).or_else(|| slots.claim(key, None, |k| sessions.contains(k))) else {
+                    trace::stage(origin.span, "drop", || format!("cliente={} sin jugador libre", key));
+                    continue;
+                };
+                announce_slots(&socket, &mut slots, &client_addrs).await;
+                // Raw values while calibrating, so the sweep reflects the real travel
+                match calibrations.get_mut(&key) {
+                    Some(run) => run.record(&axes),
+                    None => {
+                        if let Some(profile) = client_profiles.get(&key) {
+                            profile.calibrate(&mut axes);
+                        }
+                    }
+                }
+                trace::stage(origin.span, "parse", || {
+                    format!("cliente={} jugador={} mode={} buttons={:?} axes={:?}", key, slot + 1, mode, buttons, axes)
+                });
+                (Some(key), Some(src_addr), mode, slot, buttons, axes, extra, pressure)
             }
+        };
+        let current_config = config.get();
+        let gamepad_config = profiles.gamepad_config(&current_config);
+        for released in merger.update(COPILOT.get()) {
+            neutralize(&bus, &mut encoder, &mut chords, &mut merger, released, &pad_axes, &ranges[released]);
+        }
+        // In co-pilot mode both players' snapshots drive the pilot's gamepad from here on
+        let slot = merger.apply(slot, &mut buttons, &mut axes);
+        if gamepad_config.axis_ranges != ranges[slot] {
+            ranges[slot] = gamepad_config.axis_ranges;
+            bus.device(Target::Gamepad(slot)).recreate("por cambio de rangos de los ejes");
+        }
+        let mut input = PadInput { buttons, axes, extra, keys: Vec::new() };
+        if let Some(pipeline) = pipelines.get_mut(slot) {
+            pipeline.run(&gamepad_config.filters, &mut input);
+        }
+        let PadInput { mut buttons, mut axes, extra, keys } = input;
+        trace::stage(origin.span, "filter", || {
+            format!("jugador={} buttons={:?} axes={:?} teclas={:?}", slot + 1, buttons, axes, keys)
         });
+        let script_keys: Vec<InputAction> = keys.into_iter().map(|(code, value)| InputAction::Key { code, value }).collect();
+        let _ = bus.emit(Target::Keyboard, &script_keys);
+        chords.process(slot, &mut buttons, &gamepad_config);
+        let guide_held = buttons[GUIDE_BUTTON] != 0;
+        let key_actions = pad_keys.process(&mut buttons, &mut axes, &gamepad_config);
+        let pressed = key_actions.iter().any(|a| matches!(a, InputAction::Key { value: 1, .. }));
+        if gamepad_config.guide == GuideMode::Hotkey
+            && guide_held
+            && pressed
+            && let Some(src_addr) = src_addr
+        {
+            let _ = socket.send_to(&Haptic::DoubleBuzz.packet(), src_addr).await;
+        }
+        let _ = bus.emit(Target::Keyboard, &key_actions);
+        let notches = scrolls[slot].process(&mut axes, &gamepad_config, Instant::now());
+        if notches != 0 {
+            let _ = bus.emit(Target::Mouse, &[InputAction::Wheel(notches)]);
+        }
+        encoder.process(slot, &mut buttons, &mut axes, &gamepad_config, &current_config.encoder);
+        held_since[slot] = buttons.iter().any(|&b| b != 0).then(Instant::now);
+        // The client's mode byte wins; the heuristic is only for clients that leave it at 0.
+        // Injected snapshots are always in Xbox mode and have no client to remember.
+        let detected_mode = match (key, mode) {
+            (None, _) => mode,
+            (Some(key), MODE_ARCADE | MODE_XBOX) => {
+                if modes.insert(key, mode) != Some(mode) {
+                    log_detail(Verbosity::Low, &format!("Modo de gamepad: {}", mode_name(mode)), &format!("cliente={}", key));
+                }
+                mode
+            }
+            (Some(key), _) => *modes.entry(key).or_insert_with(|| detect_mode(key, &axes)),
+        };
+
+        // Inline rather than a task per snapshot: a flood would pile up tasks and could reorder frames
+        log_lazy(Verbosity::High, || {
+            format!("Gamepad Snapshot: mode={}, buttons={:?}, axes={:?}", mode, buttons, axes)
+        });
+        log_lazy(Verbosity::High, || format!("Evento: {}", describe_snapshot(&buttons, &axes)));
+
+        let mut actions = Vec::new();
+        process_buttons(buttons, &mut actions);
+        process_axes(detected_mode, axes, &gamepad_config, &mut actions);
+        process_extra_axes(&pad_axes.extra, extra, &gamepad_config.axis_ranges, &mut actions);
+        if pad_axes.pressure {
+            process_pressure(pressure, &buttons, &mut actions);
+        }
+        trace::stage(origin.span, "map", || format!("{:?} teclado={:?}", actions, key_actions));
+        // Injected snapshots did not come in as gamepad packets
+        let _ = match src_addr {
+            Some(_) => bus.emit_from(origin, Target::Gamepad(slot), &actions),
+            None => bus.emit(Target::Gamepad(slot), &actions),
+        };
     }
 }
 
-fn parse_gamepad_snapshot(buf: &[u8]) -> Option<(u8, [u8; 12], [i16; 8])> {
-    // Formato: [header:1][mode:1][button_bits:2][axes:16]
-    if buf.len() >= 20 && buf[0] == HEADER_GAMEPAD_SNAPSHOT {
-        log_data(Verbosity::Low, "UDP Gamepad Snapshot", buf);
-
-        let mode = buf[1];
+fn slot_assign(slot: Option<usize>) -> [u8; 2] {
+    [HEADER_SLOT_ASSIGN, slot.map_or(0, |s| s as u8 + 1)]
+}
 
-        // Botones: bitwise en 2 bytes (u16 LE)
-        let button_bits = u16::from_le_bytes([buf[2], buf[3]]);
-        let mut buttons = [0u8; 12];
-        for i in 0..12 {
-            buttons[i] = ((button_bits >> i) & 1) as u8;
+/// Comunica a cada cliente afectado su número de jugador, como los LEDs de
+/// un mando físico.
+async fn announce_slots(socket: &ReplySocket, slots: &mut PlayerSlots, addrs: &HashMap<ClientKey, SocketAddr>) {
+    for (key, slot) in slots.take_changes() {
+        if let Some(addr) = addrs.get(&key) {
+            let _ = socket.send_to(&slot_assign(slot), *addr).await;
         }
+    }
+}
 
-        // Ejes: 8 x i16 LE
-        let mut axes = [0i16; 8];
-        for i in 0..8 {
-            let start = 4 + i * 2;
-            axes[i] = i16::from_le_bytes([buf[start], buf[start + 1]]);
+fn handle_calibration(
+    action: u8,
+    key: ClientKey,
+    calibrations: &mut HashMap<ClientKey, CalibrationRun>,
+    profiles: &mut ClientProfiles,
+) -> bool {
+    match action {
+        CALIBRATE_START => {
+            calibrations.insert(key, CalibrationRun::new());
+            log_detail(Verbosity::Low, "Calibración iniciada", &format!("cliente={}", key));
+            true
         }
+        CALIBRATE_FINISH => {
+            // A retransmitted finish finds no run but the stored profile is what the client wants
+            let Some(run) = calibrations.remove(&key) else {
+                return profiles.get(&key).is_some_and(|p| p.calibration.is_some());
+            };
+            let Some(axes) = run.finish() else {
+                log_detail(Verbosity::Low, "Calibración fallida", &format!("cliente={} sin snapshots", key));
+                return false;
+            };
+            log_detail(Verbosity::Low, "Calibración guardada", &format!("cliente={} ejes={:?}", key, axes));
+            profiles.update(key, |profile| profile.calibration = Some(axes));
+            true
+        }
+        CALIBRATE_CLEAR => {
+            calibrations.remove(&key);
+            profiles.update(key, |profile| profile.calibration = None);
+            log_detail(Verbosity::Low, "Calibración borrada", &format!("cliente={}", key));
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Con atraso, un snapshot sobra si el siguiente del cliente tiene el mismo
+/// modo y los mismos botones: los ejes son absolutos y el posterior manda.
+/// Los cambios de botón nunca se saltan.
+pub fn coalesce_snapshots(earlier: &[u8], later: &mut [u8]) -> bool {
+    let buttons_end = if earlier.len() == LEGACY_SNAPSHOT_LEN { 13 } else { 4 };
+    matches!(earlier.first(), Some(&(HEADER_GAMEPAD_SNAPSHOT | HEADER_GAMEPAD_SNAPSHOT_EXTENDED)))
+        && earlier.len() >= 20
+        && earlier.len() == later.len()
+        && earlier[..buttons_end] == later[..buttons_end]
+}
+
+fn parse_gamepad_snapshot(buf: &[u8]) -> Option<Snapshot> {
+    if buf.first() == Some(&HEADER_GAMEPAD_SNAPSHOT_EXTENDED) {
+        return parse_extended_snapshot(buf);
+    }
+    if buf.first() != Some(&HEADER_GAMEPAD_SNAPSHOT) {
+        return None;
+    }
+    // Formato antiguo (29 bytes): [header:1][buttons: 12 x u8][axes:16], sin byte de modo.
+    // Se reconoce por la longitud y porque cada botón es 0 o 1
+    if buf.len() == LEGACY_SNAPSHOT_LEN && buf[1..13].iter().all(|&b| b <= 1) {
+        log_data(Verbosity::High, "UDP Gamepad Snapshot (legacy)", buf);
+        let mut buttons = [0u8; 12];
+        buttons.copy_from_slice(&buf[1..13]);
+        return Some((0, buttons, parse_axes(&buf[13..29]), [0; MAX_EXTRA_AXES], None));
+    }
+    // Formato: [header:1][mode:1][button_bits:2][axes:16], opcionalmente [pressure:10]; se
+    // ignora lo que venga detrás
+    if buf.len() >= SNAPSHOT_LEN {
+        log_data(Verbosity::High, "UDP Gamepad Snapshot", buf);
 
-        Some((mode, buttons, axes))
+        let mode = buf[1];
+        let buttons = parse_button_bits(buf[2], buf[3]);
+        let pressure = parse_pressure(&buf[20..]);
+        Some((mode, buttons, parse_axes(&buf[4..20]), [0; MAX_EXTRA_AXES], pressure))
     } else {
         None
     }
 }
 
-fn process_buttons(buttons: [u8; 12], events: &mut Vec<InputEvent>) {
+// Formato: [header:1][mode:1][button_bits:2][axes:16][count:1][extra: count x 2], opcionalmente [pressure:10]
+fn parse_extended_snapshot(buf: &[u8]) -> Option<Snapshot> {
+    let count = *buf.get(20)? as usize;
+    let extra_end = 21 + count * 2;
+    if count > MAX_EXTRA_AXES || buf.len() < extra_end {
+        return None;
+    }
+    log_data(Verbosity::High, "UDP Gamepad Snapshot (extendido)", buf);
+    let mut extra = [0i16; MAX_EXTRA_AXES];
+    for (axis, pair) in extra.iter_mut().zip(buf[21..extra_end].chunks_exact(2)) {
+        *axis = i16::from_le_bytes([pair[0], pair[1]]);
+    }
+    let buttons = parse_button_bits(buf[2], buf[3]);
+    Some((buf[1], buttons, parse_axes(&buf[4..20]), extra, parse_pressure(&buf[extra_end..])))
+}
+
+// Botones: bitwise en 2 bytes (u16 LE)
+fn parse_button_bits(low: u8, high: u8) -> [u8; 12] {
+    let button_bits = u16::from_le_bytes([low, high]);
+    let mut buttons = [0u8; 12];
+    for (i, button) in buttons.iter_mut().enumerate() {
+        *button = ((button_bits >> i) & 1) as u8;
+    }
+    buttons
+}
+
+// The optional pressure bytes that may follow the axes
+fn parse_pressure(bytes: &[u8]) -> Option<Pressure> {
+    bytes.get(..Xbox360Layout::PRESSURE_COUNT).map(|bytes| {
+        let mut pressure = [0u8; Xbox360Layout::PRESSURE_COUNT];
+        pressure.copy_from_slice(bytes);
+        pressure
+    })
+}
+
+// Ejes: 8 x i16 LE
+fn parse_axes(bytes: &[u8]) -> [i16; 8] {
+    let mut axes = [0i16; 8];
+    for (axis, pair) in axes.iter_mut().zip(bytes.chunks_exact(2)) {
+        *axis = i16::from_le_bytes([pair[0], pair[1]]);
+    }
+    axes
+}
+
+fn process_buttons(buttons: [u8; 12], actions: &mut Vec<InputAction>) {
     for (i, &state) in buttons.iter().enumerate() {
         if let Some(code) = Xbox360Layout::button_code(i) {
-            // Use Key::new to create a Key from the numeric evdev code
-            let key = Key::new(code);
-            events.push(InputEvent::new(EventType::KEY, key.0, state as i32));
+            actions.push(InputAction::Key { code, value: state as i32 });
         }
     }
 }
 
-fn process_axes(_mode: u8, axes: [i16; 8], events: &mut Vec<InputEvent>) {
-    // ------------------------------------------------------------------
-    // 1. Automatic mode detection (only the first time)
-    // ------------------------------------------------------------------
-    let current = CURRENT_MODE.load(Ordering::Relaxed);
-    if current == 0 {
-        // If we ever see -32768 → almost certainly arcade mode
-        // If we see values like -16384, 12000, etc. → xbox analog mode
-        let is_arcade = axes[0] == -32768 || axes[0] == 32767 || 
-                        axes[1] == -32768 || axes[1] == 32767 ||
-                        axes[0] == -32767 || axes[1] == 32767; // old compatibility
+/// Heurística para clientes cuyo modo no se conoce: un eje en un extremo
+/// exacto es casi seguro un stick digital (arcade).
+fn detect_mode(key: ClientKey, axes: &[i16; 8]) -> u8 {
+    // If we ever see -32768 → almost certainly arcade mode
+    // If we see values like -16384, 12000, etc. → xbox analog mode
+    let is_arcade = axes[0] == -32768 || axes[0] == 32767 ||
+                    axes[1] == -32768 || axes[1] == 32767 ||
+                    axes[0] == -32767; // old compatibility
 
-        let detected_mode = if is_arcade { MODE_ARCADE } else { MODE_XBOX };
-        CURRENT_MODE.store(detected_mode, Ordering::Relaxed);
-        log(Verbosity::Low, &format!("Modo de gamepad detectado: {}", 
-            if detected_mode == MODE_ARCADE { "ARCADE (8 direcciones)" } else { "XBOX (analógico)" }));
-    }
+    let detected_mode = if is_arcade { MODE_ARCADE } else { MODE_XBOX };
+    log_detail(Verbosity::Low, &format!("Modo de gamepad detectado: {}", mode_name(detected_mode)), &format!("cliente={}", key));
+    detected_mode
+}
 
-    let detected_mode = CURRENT_MODE.load(Ordering::Relaxed);
+fn mode_name(mode: u8) -> &'static str {
+    if mode == MODE_ARCADE { "ARCADE (8 direcciones)" } else { "XBOX (analógico)" }
+}
 
-    // ------------------------------------------------------------------
-    // 2. Processing based on detected mode
-    // ------------------------------------------------------------------
+fn process_axes(detected_mode: u8, axes: [i16; 8], config: &GamepadConfig, actions: &mut Vec<InputAction>) {
     if detected_mode == MODE_ARCADE {
         // ===== ARCADE MODE (perfect logs for combos) =====
         // Left stick → ABS_X / ABS_Y (analog, needed for some cores)
-        emit_axis(events, 0x00, axes[0] as i32); // ABS_X
-        emit_axis(events, 0x01, axes[1] as i32); // ABS_Y
+        let ranges = &config.axis_ranges;
+        emit_axis(actions, 0x00, ranges.stick(axes[0])); // ABS_X
+        emit_axis(actions, 0x01, ranges.stick(axes[1])); // ABS_Y
 
         // Left stick → DIGITAL D-PAD (ABS_HAT0X/HAT0Y) → this is what 95% of retro games read
-        let hat_x = if axes[0] <= -20000 { -1 } else if axes[0] >= 20000 { 1 } else { 0 };
-        let hat_y = if axes[1] <= -20000 { -1 } else if axes[1] >= 20000 { 1 } else { 0 };
-        emit_axis(events, 0x10, hat_x); // ABS_HAT0X
-        emit_axis(events, 0x11, hat_y); // ABS_HAT0Y
+        let threshold = config.hat_threshold;
+        let hat_x = if axes[0] <= -threshold { -1 } else if axes[0] >= threshold { 1 } else { 0 };
+        let hat_y = if axes[1] <= -threshold { -1 } else if axes[1] >= threshold { 1 } else { 0 };
+        let (hat_x, hat_y) = restrict_directions(hat_x, hat_y, axes[0], axes[1], config);
+        emit_axis(actions, 0x10, hat_x); // ABS_HAT0X
+        emit_axis(actions, 0x11, hat_y); // ABS_HAT0Y
 
         // Right stick (if used)
-        emit_axis(events, 0x03, axes[2] as i32); // ABS_RX
-        emit_axis(events, 0x04, axes[3] as i32); // ABS_RY
+        emit_axis(actions, 0x03, ranges.stick(axes[2])); // ABS_RX
+        emit_axis(actions, 0x04, ranges.stick(axes[3])); // ABS_RY
 
-        // Triggers
-        emit_axis(events, 0x02, axes[4] as i32); // ABS_Z (L trigger)
-        emit_axis(events, 0x05, axes[5] as i32); // ABS_RZ (R trigger)
+        process_triggers(axes[4], axes[5], config, actions);
 
         // D-pad axes (indices 6, 7) - scale to -1/0/1
         let dpad_x = if axes[6] < 0 { -1 } else if axes[6] > 0 { 1 } else { 0 };
         let dpad_y = if axes[7] < 0 { -1 } else if axes[7] > 0 { 1 } else { 0 };
-        emit_axis(events, 0x10, dpad_x); // ABS_HAT0X (may override, but that's ok)
-        emit_axis(events, 0x11, dpad_y); // ABS_HAT0Y (may override, but that's ok)
+        let (dpad_x, dpad_y) = restrict_directions(dpad_x, dpad_y, axes[6], axes[7], config);
+        emit_axis(actions, 0x10, dpad_x); // ABS_HAT0X (may override, but that's ok)
+        emit_axis(actions, 0x11, dpad_y); // ABS_HAT0Y (may override, but that's ok)
     } 
     else {
         // ===== CLASSIC XBOX 360 MODE (intermediate values) =====
         // Only emit normal analog axes (original code)
-        const TRIGGER_DIGITAL_THRESHOLD: i32 = 10;
-        
         for (i, &value) in axes.iter().enumerate() {
             if let Some(code) = Xbox360Layout::axis_code(i) {
                 match i {
-                    4 | 5 => {
-                        // Triggers: emit ABS (analog) and also emit a digital KEY when above threshold
-                        let abs_code = code as u16;
-                        let abs_value = value as i32;
-                        events.push(InputEvent::new(EventType::ABSOLUTE, abs_code, abs_value));
-
-                        let key_val = if abs_value > TRIGGER_DIGITAL_THRESHOLD { 1 } else { 0 };
-                        let key_code = if i == 4 { Key::BTN_THUMBL.0 } else { Key::BTN_THUMBR.0 };
-                        events.push(InputEvent::new(EventType::KEY, key_code, key_val));
+                    4 | 5 | 7 => {} // Triggers and d-pad, below
+                    6 => { // Hat axes: scale to -1/0/1
+                        let scale = |v: i16| if v < 0 { -1 } else if v > 0 { 1 } else { 0 };
+                        let (hat_x, hat_y) = restrict_directions(scale(value), scale(axes[7]), value, axes[7], config);
+                        emit_axis(actions, code as u16, hat_x);
+                        emit_axis(actions, 0x11, hat_y); // ABS_HAT0Y
                     }
-                    6 | 7 => { // Hat axes: scale to -1/0/1
-                        let scaled = if value < 0 { -1 } else if value > 0 { 1 } else { 0 };
-                        events.push(InputEvent::new(EventType::ABSOLUTE, code as u16, scaled));
-                    }
-                    _ => events.push(InputEvent::new(EventType::ABSOLUTE, code as u16, value as i32)),
+                    _ => emit_axis(actions, code as u16, config.axis_ranges.stick(value)),
                 }
             }
         }
+        process_triggers(axes[4], axes[5], config, actions);
     }
 }
 
-// Helper function to reduce code duplication
-fn emit_axis(events: &mut Vec<InputEvent>, code: u16, value: i32) {
-    events.push(InputEvent::new(EventType::ABSOLUTE, code, value));
+// With `four_way`, a diagonal keeps only the direction pushed further
+fn restrict_directions(x: i32, y: i32, raw_x: i16, raw_y: i16, config: &GamepadConfig) -> (i32, i32) {
+    if !config.four_way || x == 0 || y == 0 {
+        return (x, y);
+    }
+    if raw_x.unsigned_abs() >= raw_y.unsigned_abs() {
+        (x, 0)
+    } else {
+        (0, y)
+    }
+}
+
+// Triggers go to ABS_Z/ABS_RZ in the device's range and/or to BTN_TL2/BTN_TR2
+// past the threshold, which stays on a 0..255 scale whatever that range is
+fn process_triggers(left: i16, right: i16, config: &GamepadConfig, actions: &mut Vec<InputAction>) {
+    let input_max = config.trigger_input_max.max(1) as i32;
+    for (value, abs_code, key_code) in [
+        (left, 0x02, Xbox360Layout::TRIGGER_BUTTON_CODES[0]),  // ABS_Z, BTN_TL2
+        (right, 0x05, Xbox360Layout::TRIGGER_BUTTON_CODES[1]), // ABS_RZ, BTN_TR2
+    ] {
+        if config.trigger_mode != TriggerMode::Digital {
+            emit_axis(actions, abs_code, config.axis_ranges.trigger(value as i32, input_max));
+        }
+        if config.trigger_mode != TriggerMode::Analog {
+            let level = (value as i32).clamp(0, input_max) * Xbox360Layout::TRIGGER_MAX / input_max;
+            actions.push(InputAction::key(key_code, level > config.trigger_threshold));
+        }
+    }
+}
+
+// The declared extra axes in their order: hats as -1/0/1, the rest in the
+// sticks' range
+fn process_extra_axes(declared: &[ExtraAxis], extra: ExtraAxes, ranges: &AxisRanges, actions: &mut Vec<InputAction>) {
+    for (axis, value) in declared.iter().zip(extra) {
+        let value = if axis.is_hat() { value.signum() as i32 } else { ranges.stick(value) };
+        emit_axis(actions, axis.code(), value);
+    }
+}
+
+// Pressure axes of a DS2-style pad follow their buttons as finally emitted,
+// after filters and mappings: the client's value while held (full pressure if it
+// sent none, or 0 for a button that now comes from another one), 0 when released
+fn process_pressure(pressure: Option<Pressure>, buttons: &[u8; 12], actions: &mut Vec<InputAction>) {
+    let hat = |hat_code: u16| {
+        actions.iter().rev().find_map(|action| match *action {
+            InputAction::Axis { code, value } if code == hat_code => Some(value),
+            _ => None,
+        })
+    };
+    let (hat_x, hat_y) = (hat(0x10).unwrap_or(0), hat(0x11).unwrap_or(0)); // ABS_HAT0X, ABS_HAT0Y
+    let held = [
+        buttons[0] != 0,
+        buttons[1] != 0,
+        buttons[2] != 0,
+        buttons[3] != 0,
+        buttons[4] != 0,
+        buttons[5] != 0,
+        hat_y < 0,
+        hat_y > 0,
+        hat_x < 0,
+        hat_x > 0,
+    ];
+    for (i, (code, held)) in Xbox360Layout::PRESSURE_CODES.into_iter().zip(held).enumerate() {
+        let value = match pressure {
+            _ if !held => 0,
+            Some(pressure) if pressure[i] != 0 => pressure[i] as i32,
+            _ => Xbox360Layout::PRESSURE_MAX,
+        };
+        emit_axis(actions, code, value);
+    }
 }
 
-fn emit_events(device: &Arc<Mutex<VirtualDevice>>, events: &[InputEvent]) {
-    if !events.is_empty() {
-        if let Ok(mut dev) = device.lock() {
-            let _ = dev.emit(events);
-            // ¡¡ESTO ES CRÍTICO EN BATOCERA!!
-            let _ = dev.emit(&[InputEvent::new(EventType::SYNCHRONIZATION, 1, 0)]); // SYN_REPORT
+// Everything released and centered, emitted when a client's session expires
+fn neutral_actions(pad_axes: &PadAxes, ranges: &AxisRanges) -> Vec<InputAction> {
+    let mut actions = Vec::new();
+    process_buttons([0; 12], &mut actions);
+    for code in Xbox360Layout::TRIGGER_BUTTON_CODES {
+        actions.push(InputAction::key(code, false));
+    }
+    for (i, code) in Xbox360Layout::AXIS_CODES.into_iter().enumerate() {
+        let value = match i {
+            0..=3 => ranges.stick(0),
+            4 | 5 => ranges.trigger_min,
+            _ => 0,
+        };
+        emit_axis(&mut actions, code as u16, value);
+    }
+    process_extra_axes(&pad_axes.extra, [0; MAX_EXTRA_AXES], ranges, &mut actions);
+    if pad_axes.pressure {
+        for code in Xbox360Layout::PRESSURE_CODES {
+            emit_axis(&mut actions, code, 0);
         }
     }
+    actions
+}
+
+// Helper function to reduce code duplication
+fn emit_axis(actions: &mut Vec<InputAction>, code: u16, value: i32) {
+    actions.push(InputAction::Axis { code, value });
 }
 
 
@@ -224,4 +1091,107 @@ fn describe_snapshot(buttons: &[u8; 12], axes: &[i16; 8]) -> String {
     } else {
         desc.join(", ")
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // [0x42][mode][buttons: A and Start][axes: left stick right, everything else 0]
+    fn snapshot() -> Vec<u8> {
+        let mut buf = vec![HEADER_GAMEPAD_SNAPSHOT, MODE_XBOX, 0x81, 0x00];
+        buf.extend_from_slice(&32767i16.to_le_bytes());
+        buf.extend_from_slice(&[0; 14]);
+        buf
+    }
+
+    #[test]
+    fn snapshot_is_parsed() {
+        let (mode, buttons, axes, extra, pressure) = parse_gamepad_snapshot(&snapshot()).unwrap();
+        assert_eq!(mode, MODE_XBOX);
+        assert_eq!(buttons, [1, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0]);
+        assert_eq!(axes, [32767, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(extra, [0; MAX_EXTRA_AXES]);
+        assert_eq!(pressure, None);
+    }
+
+    #[test]
+    fn legacy_snapshot_has_one_byte_per_button() {
+        let mut buf = vec![HEADER_GAMEPAD_SNAPSHOT, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0];
+        buf.extend_from_slice(&(-32768i16).to_le_bytes());
+        buf.extend_from_slice(&[0; 14]);
+        let (mode, buttons, axes, _, pressure) = parse_gamepad_snapshot(&buf).unwrap();
+        assert_eq!(mode, 0);
+        assert_eq!(buttons, [1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(axes[0], -32768);
+        assert_eq!(pressure, None);
+    }
+
+    #[test]
+    fn padded_snapshot_of_legacy_length_is_told_apart_by_content() {
+        // Bitmask snapshot with X held and 9 bytes of padding: mode 2 and bits 0x04 are not 0 or 1
+        let mut buf = snapshot();
+        buf[2] = 0x04;
+        buf.extend_from_slice(&[0; 9]);
+        let (mode, buttons, axes, _, _) = parse_gamepad_snapshot(&buf).unwrap();
+        assert_eq!(mode, MODE_XBOX);
+        assert_eq!(buttons[2], 1);
+        assert_eq!(axes[0], 32767);
+    }
+
+    #[test]
+    fn trailing_bytes_are_ignored() {
+        let mut buf = snapshot();
+        buf.extend_from_slice(&[0xAA; 3]);
+        assert_eq!(parse_gamepad_snapshot(&buf), parse_gamepad_snapshot(&snapshot()));
+    }
+
+    #[test]
+    fn short_snapshot_is_dropped() {
+        assert_eq!(parse_gamepad_snapshot(&snapshot()[..19]), None);
+        assert_eq!(parse_gamepad_snapshot(&[]), None);
+    }
+
+    #[test]
+    fn pressure_follows_the_axes() {
+        let mut buf = snapshot();
+        buf.extend(1..=10);
+        let (.., pressure) = parse_gamepad_snapshot(&buf).unwrap();
+        assert_eq!(pressure, Some([1, 2, 3, 4, 5, 6, 7, 8, 9, 10]));
+    }
+
+    #[test]
+    fn partial_pressure_is_ignored() {
+        assert_eq!(parse_pressure(&[1, 2, 3]), None);
+        assert_eq!(parse_pressure(&[7; 12]), Some([7; 10]));
+    }
+
+    #[test]
+    fn extended_snapshot_carries_extra_axes_and_pressure() {
+        let mut buf = snapshot();
+        buf[0] = HEADER_GAMEPAD_SNAPSHOT_EXTENDED;
+        buf.push(2);
+        buf.extend_from_slice(&(-1i16).to_le_bytes());
+        buf.extend_from_slice(&300i16.to_le_bytes());
+        let (mode, buttons, axes, extra, pressure) = parse_gamepad_snapshot(&buf).unwrap();
+        assert_eq!((mode, buttons[0], axes[0]), (MODE_XBOX, 1, 32767));
+        assert_eq!(extra, [-1, 300, 0, 0, 0]);
+        assert_eq!(pressure, None);
+
+        buf.extend([255; 10]);
+        let (.., pressure) = parse_gamepad_snapshot(&buf).unwrap();
+        assert_eq!(pressure, Some([255; 10]));
+    }
+
+    #[test]
+    fn extended_snapshot_with_missing_or_too_many_axes_is_dropped() {
+        let mut buf = snapshot();
+        buf[0] = HEADER_GAMEPAD_SNAPSHOT_EXTENDED;
+        assert_eq!(parse_gamepad_snapshot(&buf), None);
+        buf.push(2);
+        buf.extend_from_slice(&[0; 3]);
+        assert_eq!(parse_gamepad_snapshot(&buf), None);
+        buf[20] = MAX_EXTRA_AXES as u8 + 1;
+        buf.extend_from_slice(&[0; 20]);
+        assert_eq!(parse_gamepad_snapshot(&buf), None);
+    }
+}
@@ -1,106 +1,288 @@
+use crate::api::{Injection, INJECTOR};
+use crate::arbitration::{TakeoverRequest, TokenState, ARBITER};
+use crate::capture::{self, CapturedStream, Channel};
+use crate::config::{ConfigHandle, KeyboardArbitration, KeyboardConfig, RolloverPolicy};
+use crate::haptics::Haptic;
+use crate::identity::{hello_ack, ClientKey, Identities};
 use crate::input_mode::InputMode;
+use crate::keyboard_layout::KeyboardLayout;
 use crate::logger::{log_block, log_detail, Verbosity};
+use crate::network::{ClientStream, StreamListener};
+use crate::power;
+use crate::profiles::ProfileManager;
+use crate::stats::STATS;
+use crate::trace;
 use crate::protocol::{
-    HEADER_KEYBOARD, HEADER_MODE_ACK, HEADER_MODE_SWITCH,
+    HEADER_ANALOG_KEY, HEADER_HELLO, HEADER_KEYBOARD, HEADER_KEYBOARD_WIDE, HEADER_LED_STATE, HEADER_MODE_ACK,
+    HEADER_MODE_SWITCH, HEADER_PROFILE_ACK, HEADER_PROFILE_SELECT, HEADER_STICKY_MODIFIER, HEADER_TAKEOVER_ANSWER,
+    HEADER_TAKEOVER_REQUEST, HEADER_TEXT, HEADER_TOKEN_RELEASE, HEADER_TOKEN_REQUEST, HEADER_TOKEN_STATE, HEADER_WAKE,
+    HEADER_WAKE_ACK,
 };
-use evdev::{InputEvent, Key, uinput::VirtualDevice};
+use crate::quarantine::QUARANTINE;
+use crate::relay::{proxy_stream, spawn_stream_tee, RELAY};
+use crate::session::{Admission, SessionRef, SessionTable, Transport, SWEEP_INTERVAL};
+use crate::bus::{InputAction, Origin, SharedBus, Target};
+use crate::devices::lazy::SharedDevice;
+use evdev::{EventType, Key, LedType};
+use std::collections::{HashMap, HashSet};
 use std::io::ErrorKind;
-use std::net::IpAddr;
-use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::net::SocketAddr;
+use std::os::fd::OwnedFd;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{Notify, RwLock};
+use tokio::io::unix::AsyncFd;
+use tokio::sync::{oneshot, watch, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, sleep, timeout, Duration, Instant};
+
+// How long a new connection gets to start HELLO before it is treated as legacy, and then to finish it
+const HELLO_WAIT: Duration = Duration::from_millis(300);
+// Open connections, sessions or not; further ones wait in the listen backlog
+const MAX_CONNECTIONS: usize = 64;
+// MODE_ACK payloads that are not a mode
+const MODE_ACK_INVALID: u8 = 0xFF;
+const MODE_ACK_TOO_SOON: u8 = 0xFE;
+
+// Per-connection keyboard state: keys currently pressed (released when it
+// ends), their autorepeat tasks and the sticky modifiers
+#[derive(Default)]
+struct KeyState {
+    held: HashSet<u16>,
+    repeaters: HashMap<u16, JoinHandle<()>>,
+    // Modifiers tapped via STICKY_MODIFIER, waiting for the next key
+    latched: Vec<u16>,
+    // Latched modifiers pressed along with a key, released with it
+    applied: HashMap<u16, Vec<u16>>,
+    // Last press or client repeat of each held key, for the held-key watchdog
+    pressed_at: HashMap<u16, Instant>,
+    // Keys in [keyboard] analog_keys that are partly or fully pressed, with their value
+    analog: HashMap<u16, u8>,
+}
+
+type SharedKeyState = Arc<Mutex<KeyState>>;
+
+// What belongs to one TCP connection
+struct Client {
+    session: SessionRef,
+    key: ClientKey,
+    connection: u64,
+    keys: SharedKeyState,
+    // Only clients that sent HELLO know about unsolicited messages (LED_STATE, HAPTIC)
+    identified: bool,
+    leds: Option<watch::Receiver<u8>>,
+    // Keyboard input token holder, from ARBITER
+    token: watch::Receiver<Option<u64>>,
+    // Takeover requests to forward when this connection holds the token; identified clients only
+    takeovers: Option<watch::Receiver<Option<TakeoverRequest>>>,
+    // A TAKEOVER_REQUEST was sent and not yet withdrawn
+    asked: bool,
+    // Our own TOKEN_REQUEST waiting for the holder's answer, until the deadline
+    requested: Option<(oneshot::Receiver<bool>, Instant)>,
+    // When this connection last changed the mode
+    mode_switched_at: Option<Instant>,
+    // Stream offset up to which bytes had arrived when the last MODE_ACK went
+    // out: a MODE_SWITCH before it was sent without waiting for that ack
+    mode_ack_horizon: u64,
+}
+
+impl Client {
+    // Whether this connection may inject keys now; with the token model the
+    // first client to type while the token is free takes it
+    fn may_type(&self, config: &KeyboardConfig) -> bool {
+        match config.arbitration {
+            KeyboardArbitration::Exclusive => true,
+            KeyboardArbitration::Token => {
+                ARBITER.claim(self.connection, self.key, Duration::from_secs(config.token_idle_secs))
+            }
+        }
+    }
+}
 
 pub async fn run_tcp_keyboard_server(
-    port: u16,
-    device: Arc<Mutex<VirtualDevice>>,
-    input_mode: Arc<RwLock<InputMode>>,
+    mut listener: StreamListener,
+    bus: SharedBus,
+    input_mode: watch::Sender<InputMode>,
     active_clients: Arc<AtomicUsize>,
+    identities: Identities,
+    config: ConfigHandle,
+    profiles: ProfileManager,
 ) -> std::io::Result<()> {
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
-    let active_session: Arc<Mutex<Option<(IpAddr, u64, Arc<Notify>)>>> = Arc::new(Mutex::new(None));
-    let connection_id_counter = Arc::new(AtomicU64::new(0));
+    let sessions = SessionTable::shared("TCP", Transport::Stream);
+    let leds = spawn_led_monitor(bus.device(Target::Keyboard).clone());
+
+    let sweep_sessions = sessions.clone();
+    let sweep_config = config.clone();
+    tokio::spawn(async move {
+        let mut ticker = interval(SWEEP_INTERVAL);
+        loop {
+            ticker.tick().await;
+            sweep_sessions.lock().unwrap().expire_idle(&sweep_config.get().sessions);
+        }
+    });
 
+    let open = Arc::new(Semaphore::new(MAX_CONNECTIONS));
     loop {
+        let permit = open.clone().acquire_owned().await.map_err(std::io::Error::other)?;
         let (socket, addr) = listener.accept().await?;
         let peer_ip = addr.ip();
-        log_detail(Verbosity::Medium, "Conexión TCP aceptada", &format!("ip={}", peer_ip));
-        let connection_id = connection_id_counter.fetch_add(1, Ordering::SeqCst);
-
-        let old_notifier = {
-            let session = active_session.lock().unwrap();
-            if let Some((existing_ip, _, old_notify)) = session.as_ref() {
-                if *existing_ip == peer_ip {
-                    log_detail(Verbosity::Low, "Conexión TCP existente", &format!("cerrando ip={}", peer_ip));
-                    Some(old_notify.clone())
-                } else {
-                    log_detail(Verbosity::Low, "Conexión TCP rechazada", &format!("ip={} ya ligada a {}", peer_ip, existing_ip));
-                    continue;
-                }
-            } else {
-                None
-            }
-        };
-
-        if let Some(notifier) = old_notifier {
-            notifier.notify_one();
+        if !config.get().is_allowed(peer_ip) {
+            log_detail(Verbosity::Low, "Conexión TCP rechazada", &format!("ip={} fuera de la allowlist", peer_ip));
+            continue;
         }
-
-        let new_notify = Arc::new(Notify::new());
+        if QUARANTINE.is_banned(peer_ip) {
+            STATS.keyboard.record_quarantined();
+            log_detail(Verbosity::Medium, "Conexión TCP rechazada", &format!("ip={} en cuarentena", peer_ip));
+            continue;
+        }
+        log_detail(Verbosity::Medium, "Conexión TCP aceptada", &format!("ip={}", peer_ip));
+        let relay_target = RELAY.stream_target();
+        if let Some(remote) = relay_target
+            && !config.get().relay.local
         {
-            let mut session = active_session.lock().unwrap();
-            *session = Some((peer_ip, connection_id, new_notify.clone()));
+            tokio::spawn(async move {
+                let _permit = permit;
+                if let Err(e) = proxy_stream(socket, remote).await {
+                    log_detail(Verbosity::Medium, "Error en el relé", &format!("{}: {}", remote, e));
+                }
+            });
+            continue;
         }
 
-        log_detail(Verbosity::Low, "Conexión TCP registrada", &format!("ip={}", peer_ip));
-
-        let dev_clone = device.clone();
+        let bus_clone = bus.clone();
         let mode_clone = input_mode.clone();
-        let session_clone = active_session.clone();
-        let cancel_signal = new_notify.clone();
-        let connection_id_clone = connection_id;
+        let sessions_clone = sessions.clone();
+        let identities_clone = identities.clone();
         let client_counter = active_clients.clone();
+        let config_clone = config.clone();
+        let leds_clone = leds.clone();
+        let profiles_clone = profiles.clone();
 
         tokio::spawn(async move {
+            let _permit = permit;
+            let mut socket = socket;
+            let key = match identify_client(&mut socket, addr, &identities_clone).await {
+                Ok(key) => key,
+                Err(e) => {
+                    log_detail(Verbosity::Low, "Error en HELLO TCP", &format!("{}: {}", addr, e));
+                    return;
+                }
+            };
+
+            let current_config = config_clone.get();
+            let token_model = current_config.keyboard.arbitration == KeyboardArbitration::Token;
+            let mut session_config = current_config.sessions.clone();
+            if token_model {
+                // Everyone may connect; the token decides who types
+                session_config.max_clients = MAX_CONNECTIONS;
+            }
+            let admission = sessions_clone.lock().unwrap().admit(key, &session_config);
+            let (connection_id, cancel_signal) = match admission {
+                Admission::Admitted { id, cancel } => (id, cancel),
+                Admission::Existing | Admission::Rejected => return,
+            };
+            let leds = match key {
+                ClientKey::Id(_) => {
+                    let mut leds = leds_clone;
+                    leds.mark_changed();
+                    Some(leds)
+                }
+                ClientKey::Addr(_) => None,
+            };
+
+            let mut token = ARBITER.subscribe();
+            let mut client_takeovers = None;
+            if token_model && leds.is_some() {
+                token.mark_changed();
+                client_takeovers = Some(ARBITER.subscribe_takeovers());
+            }
+            let mut client = Client {
+                session: SessionRef::new(sessions_clone, key, connection_id),
+                key,
+                connection: connection_id,
+                keys: Arc::default(),
+                identified: matches!(key, ClientKey::Id(_)),
+                leds,
+                token,
+                takeovers: client_takeovers,
+                asked: false,
+                requested: None,
+                mode_switched_at: None,
+                mode_ack_horizon: 0,
+            };
+
             let _guard = ConnectionGuard::new(client_counter);
 
+            let mut socket = CapturedStream::new(socket, addr);
+            if let Some(remote) = relay_target {
+                socket.tee(spawn_stream_tee(remote));
+            }
+            let watchdog = held_key_watchdog(client.keys.clone(), bus_clone.clone(), config_clone.clone());
+            let mode_release = release_on_gamepad_mode(client.keys.clone(), bus_clone.clone(), mode_clone.subscribe());
             tokio::select! {
-                result = handle_tcp_client(socket, dev_clone, mode_clone) => {
+                result = handle_tcp_client(socket, bus_clone.clone(), mode_clone, config_clone.clone(), profiles_clone, &mut client) => {
                     if let Err(e) = result {
                         log_detail(Verbosity::Low, "Error en conexión TCP", &format!("{}: {}", addr, e));
                     }
                 }
                 _ = cancel_signal.notified() => {
-                    log_detail(Verbosity::Low, "Conexión TCP terminada", &format!("ip={} por nueva conexión", peer_ip));
+                    log_detail(Verbosity::Low, "Conexión TCP terminada", &format!("cliente={} sesión cerrada por el servidor", key));
                 }
+                _ = watchdog => {}
+                _ = mode_release => {}
             }
 
-            let should_clear_session = {
-                let mut session = session_clone.lock().unwrap();
-                if let Some((active_ip, active_id, _)) = session.as_ref() {
-                    if *active_ip == peer_ip && *active_id == connection_id_clone {
-                        *session = None;
-                        true
-                    } else {
-                        false
-                    }
-                } else {
-                    false
-                }
-            };
-
-            if should_clear_session {
-                log_detail(Verbosity::Low, "Conexión TCP removida", &format!("ip={} contador reseteado", peer_ip));
-            }
+            capture::record(Channel::KeyboardClosed, addr, &[]);
+            // Never leave keys stuck down after the client goes away
+            release_held_keys(&client.keys, &bus_clone);
+            ARBITER.release(connection_id, "desconexión");
+            release_analog_keys(&client.keys, &config_clone.get().keyboard, &bus_clone).await;
+            client.session.remove();
         });
     }
 }
 
+/// Espera brevemente un HELLO. Los clientes que lo envían quedan identificados
+/// por su token; los antiguos (que empiezan directamente con input) por su
+/// dirección.
+async fn identify_client(
+    socket: &mut ClientStream,
+    addr: SocketAddr,
+    identities: &Identities,
+) -> std::io::Result<ClientKey> {
+    let mut first = [0u8; 1];
+    match timeout(HELLO_WAIT, socket.peek(&mut first)).await {
+        Ok(Ok(1..)) if first[0] == HEADER_HELLO => {}
+        Ok(Err(e)) => return Err(e),
+        _ => return Ok(ClientKey::Addr(addr)),
+    }
+
+    // Not in the session table yet, so idle expiry would never drop a client that stalls here
+    let read_hello = async {
+        let mut header = [0u8; 2];
+        socket.read_exact(&mut header).await?;
+        let mut token = vec![0u8; header[1] as usize];
+        socket.read_exact(&mut token).await?;
+        Ok::<_, std::io::Error>((header, token))
+    };
+    let (header, token) = timeout(HELLO_WAIT, read_hello)
+        .await
+        .map_err(|_| std::io::Error::new(ErrorKind::TimedOut, "HELLO sin terminar"))??;
+    capture::record(Channel::Keyboard, addr, &header);
+    capture::record(Channel::Keyboard, addr, &token);
+
+    let id = identities.hello("TCP", addr, &token)?;
+    socket.write_all(&hello_ack(id)).await?;
+    Ok(ClientKey::Id(id))
+}
+
 async fn handle_tcp_client(
-    mut socket: TcpStream,
-    device: Arc<Mutex<VirtualDevice>>,
-    input_mode: Arc<RwLock<InputMode>>,
+    mut socket: CapturedStream,
+    bus: SharedBus,
+    input_mode: watch::Sender<InputMode>,
+    config: ConfigHandle,
+    profiles: ProfileManager,
+    client: &mut Client,
 ) -> std::io::Result<()> {
     fn is_connection_closed(err: &std::io::Error) -> bool {
         matches!(
@@ -109,21 +291,76 @@ async fn handle_tcp_client(
                 | ErrorKind::ConnectionReset
                 | ErrorKind::ConnectionAborted
                 | ErrorKind::BrokenPipe
+                // How a QUIC stream reports that its connection is gone
+                | ErrorKind::NotConnected
         )
     }
 
     let mut header = [0u8; 1];
 
     loop {
-        if let Err(e) = socket.read_exact(&mut header).await {
-            if is_connection_closed(&e) {
-                break;
+        tokio::select! {
+            result = socket.read_exact(&mut header) => {
+                if let Err(e) = result {
+                    if is_connection_closed(&e) {
+                        break;
+                    }
+                    return Err(e);
+                }
+            }
+            state = led_change(&mut client.leds) => {
+                socket.write_all(&[HEADER_LED_STATE, state]).await?;
+                continue;
+            }
+            holder = token_change(&mut client.token) => {
+                if holder != Some(client.connection) {
+                    // Lost or never had it: nothing of ours may stay pressed
+                    release_held_keys(&client.keys, &bus);
+                    release_analog_keys(&client.keys, &config.get().keyboard, &bus).await;
+                }
+                // With a request pending, its answer reports the change
+                if client.identified && client.requested.is_none() {
+                    socket.write_all(&[HEADER_TOKEN_STATE, ARBITER.state_for(client.connection) as u8]).await?;
+                }
+                continue;
+            }
+            answered = takeover_answer(&mut client.requested) => {
+                if !answered {
+                    ARBITER.withdraw(client.connection);
+                }
+                client.token.mark_unchanged();
+                socket.write_all(&[HEADER_TOKEN_STATE, ARBITER.state_for(client.connection) as u8]).await?;
+                continue;
+            }
+            request = takeover_request(&mut client.takeovers) => {
+                match request {
+                    Some(request) if request.holder == client.connection => {
+                        let ip = request.ip.to_string();
+                        let mut message = vec![HEADER_TAKEOVER_REQUEST, ip.len() as u8];
+                        message.extend_from_slice(ip.as_bytes());
+                        socket.write_all(&message).await?;
+                        client.asked = true;
+                    }
+                    // Answered or given up elsewhere: the client can close its prompt
+                    _ if client.asked => {
+                        socket.write_all(&[HEADER_TAKEOVER_REQUEST, 0]).await?;
+                        client.asked = false;
+                    }
+                    _ => {}
+                }
+                continue;
             }
-            return Err(e);
         }
 
+        let origin = Origin::from_client(&STATS.keyboard, socket.peer_addr());
+        trace::stage(origin.span, "receive", || format!("header={:02X}", header[0]));
+        STATS.keyboard.record_packet();
+        client.session.touch();
+
         match header[0] {
             HEADER_MODE_SWITCH => {
+                // The header byte was the last one read
+                let offset = socket.bytes_read() - 1;
                 let mut mode_byte = [0u8; 1];
                 if let Err(e) = socket.read_exact(&mut mode_byte).await {
                     if is_connection_closed(&e) {
@@ -131,31 +368,51 @@ async fn handle_tcp_client(
                     }
                     return Err(e);
                 }
-                log_block("TCP Packet", vec![
+                log_block(Verbosity::High, "TCP Packet", || vec![
                     format!("type=Mode Switch"),
                     format!("header={:02X}", header[0]),
                     format!("mode={}", mode_byte[0]),
                     format!("raw={:02X}", mode_byte[0])
                 ]);
 
-                if let Some(new_mode) = InputMode::from_byte(mode_byte[0]) {
-                    {
-                        let mut guard = input_mode.write().await;
-                        if *guard != new_mode {
-                            match new_mode {
-                                InputMode::Gamepad => {
-                                    log_detail(Verbosity::Low, "Modo cambiado", "a gamepad");
-                                }
-                                InputMode::MouseKeyboard => {
-                                    log_detail(Verbosity::Low, "Modo cambiado", "a mouse+teclado");
+                let ack = match InputMode::from_byte(mode_byte[0]) {
+                    None => MODE_ACK_INVALID,
+                    Some(new_mode) => {
+                        let interval = Duration::from_millis(config.get().keyboard.mode_switch_interval_ms);
+                        let refusal = if offset < client.mode_ack_horizon {
+                            Some("sin esperar el ack anterior")
+                        } else if *input_mode.borrow() != new_mode
+                            && client.mode_switched_at.is_some_and(|at| at.elapsed() < interval)
+                        {
+                            Some("antes del intervalo mínimo")
+                        } else {
+                            None
+                        };
+                        match refusal {
+                            Some(reason) => {
+                                let detail = format!("{}: {}", socket.peer_addr(), reason);
+                                log_detail(Verbosity::Medium, "Cambio de modo demasiado seguido", &detail);
+                                MODE_ACK_TOO_SOON
+                            }
+                            None => {
+                                if input_mode.send_if_modified(|mode| std::mem::replace(mode, new_mode) != new_mode) {
+                                    client.mode_switched_at = Some(Instant::now());
+                                    let detail = match new_mode {
+                                        InputMode::Gamepad => "a gamepad",
+                                        InputMode::MouseKeyboard => "a mouse+teclado",
+                                    };
+                                    log_detail(Verbosity::Low, "Modo cambiado", detail);
                                 }
+                                mode_byte[0]
                             }
                         }
-                        *guard = new_mode;
                     }
-                    socket.write_all(&[HEADER_MODE_ACK, mode_byte[0]]).await?;
-                } else {
-                    socket.write_all(&[HEADER_MODE_ACK, 0xFF]).await?;
+                };
+                // Whatever has already arrived was sent before the client could read this ack
+                client.mode_ack_horizon = socket.bytes_read() + socket.queued().unwrap_or(0);
+                socket.write_all(&[HEADER_MODE_ACK, ack]).await?;
+                if client.identified && InputMode::from_byte(ack).is_some() {
+                    socket.write_all(&Haptic::Tick.packet()).await?;
                 }
             }
             HEADER_KEYBOARD => {
@@ -166,7 +423,7 @@ async fn handle_tcp_client(
                     }
                     return Err(e);
                 }
-                log_block("TCP Packet", vec![
+                log_block(Verbosity::High, "TCP Packet", || vec![
                     format!("type=Keyboard"),
                     format!("header={:02X}", header[0]),
                     format!("scancode={}", payload[0]),
@@ -174,15 +431,228 @@ async fn handle_tcp_client(
                     format!("raw={:02X} {:02X}", payload[0], payload[1])
                 ]);
 
-                if *input_mode.read().await == InputMode::MouseKeyboard {
-                    process_keyboard_event(payload[0], payload[1], &device);
+                let current_config = config.get();
+                if *input_mode.borrow() == InputMode::MouseKeyboard && client.may_type(&current_config.keyboard) {
+                    let key_code = current_config.map_keyboard_key(payload[0].into());
+                    process_keyboard_event(key_code, payload[1], origin, &bus, &client.keys, &current_config.keyboard);
+                }
+            }
+            HEADER_KEYBOARD_WIDE => {
+                let mut payload = [0u8; 3];
+                if let Err(e) = socket.read_exact(&mut payload).await {
+                    if is_connection_closed(&e) {
+                        break;
+                    }
+                    return Err(e);
+                }
+                let scancode = u16::from_le_bytes([payload[0], payload[1]]);
+                log_block(Verbosity::High, "TCP Packet", || vec![
+                    format!("type=Keyboard Wide"),
+                    format!("header={:02X}", header[0]),
+                    format!("scancode={}", scancode),
+                    format!("state={}", payload[2]),
+                ]);
+
+                let current_config = config.get();
+                if *input_mode.borrow() == InputMode::MouseKeyboard && client.may_type(&current_config.keyboard) {
+                    let key_code = current_config.map_keyboard_key(scancode);
+                    process_keyboard_event(key_code, payload[2], origin, &bus, &client.keys, &current_config.keyboard);
+                }
+            }
+            HEADER_ANALOG_KEY => {
+                let mut payload = [0u8; 2];
+                if let Err(e) = socket.read_exact(&mut payload).await {
+                    if is_connection_closed(&e) {
+                        break;
+                    }
+                    return Err(e);
+                }
+                log_block(Verbosity::High, "TCP Packet", || vec![
+                    format!("type=Analog Key"),
+                    format!("header={:02X}", header[0]),
+                    format!("scancode={}", payload[0]),
+                    format!("value={}", payload[1]),
+                ]);
+
+                let current_config = config.get();
+                let key_code = current_config.map_keyboard_key(payload[0].into());
+                let is_axis = current_config.keyboard.analog_keys.iter().any(|&(code, _, _)| code == key_code);
+                let active = is_axis || *input_mode.borrow() == InputMode::MouseKeyboard;
+                if !active || !client.may_type(&current_config.keyboard) {
+                    continue;
+                }
+                if is_axis {
+                    // Axes drive the gamepad, so they work in either input mode
+                    let injection = {
+                        let mut keys = client.keys.lock().unwrap();
+                        if payload[1] == 0 {
+                            keys.analog.remove(&key_code);
+                        } else {
+                            keys.analog.insert(key_code, payload[1]);
+                        }
+                        analog_injection(&keys.analog, &current_config.keyboard, &bus)
+                    };
+                    let _ = INJECTOR.send(injection).await;
+                } else {
+                    process_keyboard_event(key_code, (payload[1] != 0) as u8, origin, &bus, &client.keys, &current_config.keyboard);
+                }
+            }
+            HEADER_STICKY_MODIFIER => {
+                let mut scancode = [0u8; 1];
+                if let Err(e) = socket.read_exact(&mut scancode).await {
+                    if is_connection_closed(&e) {
+                        break;
+                    }
+                    return Err(e);
+                }
+                log_block(Verbosity::High, "TCP Packet", || vec![
+                    format!("type=Sticky Modifier"),
+                    format!("header={:02X}", header[0]),
+                    format!("scancode={}", scancode[0]),
+                ]);
+
+                let current_config = config.get();
+                if *input_mode.borrow() == InputMode::MouseKeyboard && client.may_type(&current_config.keyboard) {
+                    let key_code = current_config.map_keyboard_key(scancode[0].into());
+                    process_sticky_modifier(key_code, &client.keys);
+                }
+            }
+            HEADER_PROFILE_SELECT => {
+                let mut len = [0u8; 1];
+                let mut name = Vec::new();
+                let result = async {
+                    socket.read_exact(&mut len).await?;
+                    name.resize(len[0] as usize, 0);
+                    socket.read_exact(&mut name).await
+                }
+                .await;
+                if let Err(e) = result {
+                    if is_connection_closed(&e) {
+                        break;
+                    }
+                    return Err(e);
+                }
+                let name = String::from_utf8_lossy(&name).into_owned();
+                log_block(Verbosity::High, "TCP Packet", || vec![
+                    format!("type=Profile Select"),
+                    format!("header={:02X}", header[0]),
+                    format!("name={}", name),
+                ]);
+
+                let status = match profiles.select(&config.get(), Some(&name), "cliente") {
+                    Ok(()) => 1,
+                    Err(e) => {
+                        log_detail(Verbosity::Low, "Perfil rechazado", &e);
+                        0
+                    }
+                };
+                let mut ack = vec![HEADER_PROFILE_ACK, status, name.len() as u8];
+                ack.extend_from_slice(name.as_bytes());
+                socket.write_all(&ack).await?;
+            }
+            HEADER_WAKE => {
+                let mut mac = [0u8; 6];
+                if let Err(e) = socket.read_exact(&mut mac).await {
+                    if is_connection_closed(&e) {
+                        break;
+                    }
+                    return Err(e);
+                }
+                log_block(Verbosity::High, "TCP Packet", || vec![
+                    format!("type=Wake"),
+                    format!("header={:02X}", header[0]),
+                    format!("mac={}", mac.map(|b| format!("{:02x}", b)).join(":")),
+                ]);
+
+                let sent = power::wake(mac, &config.get().power).await;
+                socket.write_all(&[HEADER_WAKE_ACK, sent as u8]).await?;
+            }
+            HEADER_TOKEN_REQUEST | HEADER_TOKEN_RELEASE => {
+                log_block(Verbosity::High, "TCP Packet", || vec![
+                    format!("type=Token {}", if header[0] == HEADER_TOKEN_REQUEST { "Request" } else { "Release" }),
+                    format!("header={:02X}", header[0]),
+                ]);
+
+                let keyboard = &config.get().keyboard;
+                if keyboard.arbitration == KeyboardArbitration::Token {
+                    if header[0] == HEADER_TOKEN_REQUEST {
+                        let wait = Duration::from_secs(keyboard.takeover_timeout_secs);
+                        let ip = socket.peer_addr().ip();
+                        // Held by someone else: ask them, and answer once they decide or time runs out
+                        if !client.may_type(keyboard)
+                            && !wait.is_zero()
+                            && let Some(answer) = ARBITER.request_takeover(client.connection, client.key, ip, wait)
+                        {
+                            client.requested = Some((answer, Instant::now() + wait));
+                            continue;
+                        }
+                    } else {
+                        ARBITER.release(client.connection, "cedido por el cliente");
+                    }
+                    // The answer below already reports this change
+                    client.token.mark_unchanged();
+                }
+                // Exclusive sessions type whenever connected, which reads as holding the token
+                let state = match keyboard.arbitration {
+                    KeyboardArbitration::Token => ARBITER.state_for(client.connection),
+                    KeyboardArbitration::Exclusive => TokenState::Held,
+                };
+                socket.write_all(&[HEADER_TOKEN_STATE, state as u8]).await?;
+            }
+            HEADER_TAKEOVER_ANSWER => {
+                let mut approve = [0u8; 1];
+                if let Err(e) = socket.read_exact(&mut approve).await {
+                    if is_connection_closed(&e) {
+                        break;
+                    }
+                    return Err(e);
+                }
+                log_block(Verbosity::High, "TCP Packet", || vec![
+                    format!("type=Takeover Answer"),
+                    format!("header={:02X}", header[0]),
+                    format!("approve={}", approve[0]),
+                ]);
+
+                ARBITER.answer(client.connection, approve[0] == 1);
+            }
+            HEADER_TEXT => {
+                let mut len = [0u8; 1];
+                let mut text = Vec::new();
+                let result = async {
+                    socket.read_exact(&mut len).await?;
+                    text.resize(len[0] as usize, 0);
+                    socket.read_exact(&mut text).await
+                }
+                .await;
+                if let Err(e) = result {
+                    if is_connection_closed(&e) {
+                        break;
+                    }
+                    return Err(e);
+                }
+                let text = String::from_utf8_lossy(&text);
+                log_block(Verbosity::High, "TCP Packet", || vec![
+                    format!("type=Text"),
+                    format!("header={:02X}", header[0]),
+                    format!("text={:?}", text),
+                ]);
+
+                let current_config = config.get();
+                if *input_mode.borrow() == InputMode::MouseKeyboard && client.may_type(&current_config.keyboard) {
+                    type_text(&text, current_config.keyboard.layout, &bus);
                 }
             }
             other => {
-                log_block("TCP Packet", vec![
+                log_block(Verbosity::Medium, "TCP Packet", || vec![
                     format!("type=Unknown"),
                     format!("header={:02X}", other)
                 ]);
+                // The stream is out of sync anyway; drop it once the source is quarantined
+                let ip = socket.peer_addr().ip();
+                QUARANTINE.violation(ip, "keyboard", &config.get().security);
+                if QUARANTINE.is_banned(ip) {
+                    break;
+                }
             }
         }
     }
@@ -190,29 +660,375 @@ async fn handle_tcp_client(
     Ok(())
 }
 
-fn process_keyboard_event(scancode: u8, state: u8, device: &Arc<Mutex<VirtualDevice>>) {
-    let key_code = map_keyboard_key(scancode);
+fn process_keyboard_event(
+    key_code: u16,
+    state: u8,
+    origin: Origin,
+    bus: &SharedBus,
+    keys: &SharedKeyState,
+    config: &KeyboardConfig,
+) {
     let key = Key::new(key_code);
-    let val = if state > 0 { 1 } else { 0 };
-    let event = InputEvent::new(evdev::EventType::KEY, key.0, val);
+    // 0 = release, 1 = press, 2 = repeat sent by the client itself
+    let val = match state {
+        0 => 0,
+        2 => 2,
+        _ => 1,
+    };
+    trace::stage(origin.span, "parse", || format!("{:?} value={}", key, val));
+    let mut actions = Vec::with_capacity(3);
+
+    {
+        let mut keys = keys.lock().unwrap();
+        match val {
+            1 => {
+                if config.max_held_keys > 0
+                    && !keys.pressed_at.contains_key(&key_code)
+                    && keys.pressed_at.len() >= config.max_held_keys
+                {
+                    match config.rollover {
+                        RolloverPolicy::IgnoreNewest => {
+                            log_detail(
+                                Verbosity::Medium,
+                                "Tecla ignorada",
+                                &format!("código {} con {} teclas ya pulsadas", key_code, keys.pressed_at.len()),
+                            );
+                            return;
+                        }
+                        RolloverPolicy::ReleaseOldest => {
+                            let oldest = keys.pressed_at.iter().min_by_key(|(_, at)| **at).map(|(&code, _)| code);
+                            if let Some(oldest) = oldest {
+                                log_detail(
+                                    Verbosity::Medium,
+                                    "Tecla soltada por rollover",
+                                    &format!("código {} para pulsar {}", oldest, key_code),
+                                );
+                                release_key(&mut keys, oldest, &mut actions);
+                            }
+                        }
+                    }
+                }
+                // Latched modifiers go down right before the key they apply to
+                if !is_modifier(key) && !keys.latched.is_empty() {
+                    let latched = std::mem::take(&mut keys.latched);
+                    for &modifier in &latched {
+                        keys.held.insert(modifier);
+                        actions.push(InputAction::key(modifier, true));
+                    }
+                    keys.applied.insert(key_code, latched);
+                }
+                keys.held.insert(key_code);
+                keys.pressed_at.insert(key_code, Instant::now());
+                if config.repeat_rate_hz > 0 && !is_modifier(key) && !keys.repeaters.contains_key(&key_code) {
+                    let task = spawn_repeater(key_code, bus.clone(), config);
+                    keys.repeaters.insert(key_code, task);
+                }
+                actions.push(InputAction::key(key.0, true));
+            }
+            // The client drives the repeat: stop ours so the key doesn't repeat twice
+            2 => {
+                stop_repeater(&mut keys, key_code);
+                if let Some(pressed_at) = keys.pressed_at.get_mut(&key_code) {
+                    *pressed_at = Instant::now();
+                }
+                actions.push(InputAction::Key { code: key.0, value: 2 });
+            }
+            _ => release_key(&mut keys, key_code, &mut actions),
+        }
+    }
 
-    if let Ok(mut dev) = device.lock() {
-        let _ = dev.emit(&[event]);
+    trace::stage(origin.span, "map", || format!("{:?}", actions));
+    // Latched modifiers and the key go out together, in one frame
+    let _ = bus.emit_from(origin, Target::Keyboard, &actions);
+}
+
+/// Snapshot del jugador `analog_player` con los ejes que marcan las teclas
+/// analógicas pulsadas: cada una aporta su valor, escalado al recorrido del
+/// eje, en su dirección. Los botones van sueltos.
+fn analog_injection(analog: &HashMap<u16, u8>, config: &KeyboardConfig, bus: &SharedBus) -> Injection {
+    let mut axes = [0i32; 8];
+    for (&key_code, &value) in analog {
+        for &(_, axis, direction) in config.analog_keys.iter().filter(|(code, _, _)| *code == key_code) {
+            if let Some(axis) = axes.get_mut(axis) {
+                *axis += direction.signum() as i32 * value as i32 * i16::MAX as i32 / u8::MAX as i32;
+            }
+        }
+    }
+    Injection {
+        slot: config.analog_player.clamp(1, bus.slots()) - 1,
+        buttons: [0; 12],
+        axes: axes.map(|value| value.clamp(-(i16::MAX as i32), i16::MAX as i32) as i16),
+    }
+}
+
+fn release_key(keys: &mut KeyState, key_code: u16, actions: &mut Vec<InputAction>) {
+    keys.held.remove(&key_code);
+    keys.pressed_at.remove(&key_code);
+    stop_repeater(keys, key_code);
+    actions.push(InputAction::key(key_code, false));
+    for modifier in keys.applied.remove(&key_code).unwrap_or_default() {
+        keys.held.remove(&modifier);
+        actions.push(InputAction::key(modifier, false));
+    }
+}
+
+/// Suelta las teclas que llevan más de `[sessions] held_limit_secs` pulsadas
+/// sin que el cliente envíe su repetición: casi siempre es un release que se
+/// perdió. No termina nunca; corre mientras dure la conexión.
+async fn held_key_watchdog(keys: SharedKeyState, bus: SharedBus, config: ConfigHandle) {
+    let mut ticker = interval(SWEEP_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let Some(limit) = config.get().sessions.held_limit() else {
+            continue;
+        };
+        let mut actions = Vec::new();
+        {
+            let mut keys = keys.lock().unwrap();
+            let stale: Vec<u16> =
+                keys.pressed_at.iter().filter(|(_, at)| at.elapsed() > limit).map(|(&code, _)| code).collect();
+            for key_code in stale {
+                log_detail(
+                    Verbosity::Low,
+                    "Tecla soltada por el watchdog",
+                    &format!("código {} pulsada más de {}s sin refresco", key_code, limit.as_secs()),
+                );
+                release_key(&mut keys, key_code, &mut actions);
+            }
+        }
+        bus.release(Target::Keyboard, &actions);
     }
 }
 
-fn map_keyboard_key(scancode: u8) -> u16 {
-    match scancode {
-        // Fix for Android clients sending Android Keycodes for some keys
-        // Android KEYCODE_MINUS (69) -> Linux KEY_MINUS (12)
-        69 => 12,
-        // Android KEYCODE_EQUALS (70) -> Linux KEY_EQUAL (13)
-        70 => 13,
-        // Android KEYCODE_PLUS (81) -> Linux KEY_KPPLUS (78)
-        81 => 78,
-        // Pass through others (assuming they are already Linux evdev codes)
-        c => c as u16,
+/// Teclea cada carácter con la tecla y modificadores que le corresponden en
+/// la distribución del host.
+pub fn type_text(text: &str, layout: KeyboardLayout, bus: &SharedBus) {
+    let key_event = |key: Key, pressed| InputAction::key(key.0, pressed);
+    for c in text.chars() {
+        let Some(stroke) = layout.stroke(c) else {
+            log_detail(Verbosity::Medium, "Carácter sin tecla", &format!("{:?} en distribución {:?}", c, layout));
+            continue;
+        };
+        let mut modifiers = Vec::with_capacity(2);
+        if stroke.shift {
+            modifiers.push(Key::KEY_LEFTSHIFT);
+        }
+        if stroke.altgr {
+            modifiers.push(Key::KEY_RIGHTALT);
+        }
+
+        let mut press: Vec<InputAction> = modifiers.iter().map(|&m| key_event(m, true)).collect();
+        press.push(key_event(stroke.key, true));
+        let mut release = vec![key_event(stroke.key, false)];
+        release.extend(modifiers.iter().rev().map(|&m| key_event(m, false)));
+
+        // Separate reports so the press is never merged away with its release
+        let _ = bus.emit_frames(Target::Keyboard, &[&press, &release]);
+    }
+}
+
+/// Modificador "pegajoso": un toque lo deja enganchado para la siguiente
+/// tecla; un segundo toque antes de usarlo lo suelta.
+fn process_sticky_modifier(key_code: u16, keys: &SharedKeyState) {
+    if !is_modifier(Key::new(key_code)) {
+        log_detail(Verbosity::Medium, "Modificador pegajoso ignorado", &format!("código {} no es un modificador", key_code));
+        return;
+    }
+    let mut keys = keys.lock().unwrap();
+    if let Some(pos) = keys.latched.iter().position(|&m| m == key_code) {
+        keys.latched.remove(pos);
+    } else {
+        keys.latched.push(key_code);
+    }
+    log_detail(Verbosity::Medium, "Modificadores enganchados", &format!("{:?}", keys.latched));
+}
+
+/// Emite `value = 2` (autorepetición de evdev) mientras la tecla siga pulsada.
+fn spawn_repeater(key_code: u16, bus: SharedBus, config: &KeyboardConfig) -> JoinHandle<()> {
+    let delay = Duration::from_millis(config.repeat_delay_ms);
+    let period = Duration::from_secs_f64(1.0 / config.repeat_rate_hz as f64);
+    tokio::spawn(async move {
+        sleep(delay).await;
+        let mut ticker = interval(period);
+        loop {
+            ticker.tick().await;
+            let _ = bus.emit(Target::Keyboard, &[InputAction::Key { code: key_code, value: 2 }]);
+        }
+    })
+}
+
+fn stop_repeater(keys: &mut KeyState, key_code: u16) {
+    if let Some(task) = keys.repeaters.remove(&key_code) {
+        task.abort();
+    }
+}
+
+fn is_modifier(key: Key) -> bool {
+    matches!(
+        key,
+        Key::KEY_LEFTSHIFT
+            | Key::KEY_RIGHTSHIFT
+            | Key::KEY_LEFTCTRL
+            | Key::KEY_RIGHTCTRL
+            | Key::KEY_LEFTALT
+            | Key::KEY_RIGHTALT
+            | Key::KEY_LEFTMETA
+            | Key::KEY_RIGHTMETA
+            | Key::KEY_CAPSLOCK
+            | Key::KEY_NUMLOCK
+    )
+}
+
+/// Suelta las teclas de la conexión al pasar a modo gamepad: a partir de ahí
+/// se ignoran los paquetes de teclado, incluidos sus releases.
+async fn release_on_gamepad_mode(keys: SharedKeyState, bus: SharedBus, mut input_mode: watch::Receiver<InputMode>) {
+    while input_mode.changed().await.is_ok() {
+        if *input_mode.borrow_and_update() == InputMode::Gamepad {
+            release_held_keys(&keys, &bus);
+        }
+    }
+    std::future::pending().await
+}
+
+// Centers the gamepad axes this connection's analog keys were moving
+async fn release_analog_keys(keys: &SharedKeyState, config: &KeyboardConfig, bus: &SharedBus) {
+    let analog = std::mem::take(&mut keys.lock().unwrap().analog);
+    if !analog.is_empty() {
+        let _ = INJECTOR.send(analog_injection(&HashMap::new(), config, bus)).await;
+    }
+}
+
+fn release_held_keys(keys: &SharedKeyState, bus: &SharedBus) {
+    let released: Vec<InputAction> = {
+        let mut keys = keys.lock().unwrap();
+        keys.latched.clear();
+        keys.applied.clear();
+        keys.pressed_at.clear();
+        for (_, task) in keys.repeaters.drain() {
+            task.abort();
+        }
+        keys.held
+            .drain()
+            .map(|code| InputAction::key(code, false))
+            .collect()
+    };
+    if released.is_empty() {
+        return;
+    }
+    log_detail(Verbosity::Medium, "Teclas liberadas", &format!("{} teclas seguían pulsadas", released.len()));
+    bus.release(Target::Keyboard, &released);
+}
+
+/// Lee los EV_LED que el kernel envía al teclado virtual (Bloq Mayús,
+/// Bloq Num, Bloq Despl) y publica el estado como máscara de bits.
+fn spawn_led_monitor(device: SharedDevice) -> watch::Receiver<u8> {
+    let (tx, rx) = watch::channel(0u8);
+    tokio::spawn(async move {
+        if let Err(e) = monitor_leds(device, tx).await {
+            log_detail(Verbosity::Low, "Error leyendo LEDs del teclado", &e.to_string());
+        }
+    });
+    rx
+}
+
+async fn monitor_leds(device: SharedDevice, tx: watch::Sender<u8>) -> std::io::Result<()> {
+    loop {
+        // The keyboard is only created with the first key, and may be torn
+        // down and created again later
+        device.created().await;
+        let fd = match device.watch_fd() {
+            Some(fd) => fd?,
+            None => continue,
+        };
+        let async_fd = AsyncFd::new(fd)?;
+        tokio::select! {
+            result = forward_leds(&device, &async_fd, &tx) => result?,
+            _ = device.destroyed() => {}
+        }
+    }
+}
+
+async fn forward_leds(device: &SharedDevice, async_fd: &AsyncFd<OwnedFd>, tx: &watch::Sender<u8>) -> std::io::Result<()> {
+    loop {
+        let mut guard = async_fd.readable().await?;
+        let result = device.if_created(|dev| -> std::io::Result<Vec<(u16, i32)>> {
+            Ok(dev
+                .fetch_events()?
+                .filter(|e| e.event_type() == EventType::LED)
+                .map(|e| (e.code(), e.value()))
+                .collect())
+        });
+        let leds = match result {
+            None => return Ok(()),
+            Some(Ok(leds)) => leds,
+            Some(Err(e)) if e.kind() == ErrorKind::WouldBlock => {
+                guard.clear_ready();
+                continue;
+            }
+            Some(Err(e)) => return Err(e),
+        };
+
+        let mut state = *tx.borrow();
+        for (code, value) in leds {
+            let bit = match LedType(code) {
+                LedType::LED_NUML => 0x01,
+                LedType::LED_CAPSL => 0x02,
+                LedType::LED_SCROLLL => 0x04,
+                _ => continue,
+            };
+            if value != 0 {
+                state |= bit;
+            } else {
+                state &= !bit;
+            }
+        }
+        if state != *tx.borrow() {
+            log_detail(Verbosity::Medium, "LEDs de teclado", &format!("{:03b}", state));
+            tx.send_replace(state);
+        }
+    }
+}
+
+// Next LED state to forward; never resolves for legacy clients or if the
+// monitor is not running
+async fn led_change(leds: &mut Option<watch::Receiver<u8>>) -> u8 {
+    if let Some(rx) = leds
+        && rx.changed().await.is_ok()
+    {
+        return *rx.borrow_and_update();
+    }
+    std::future::pending().await
+}
+
+// Next pending takeover, or None once it is resolved; never resolves for
+// legacy clients or with the exclusive model
+async fn takeover_request(takeovers: &mut Option<watch::Receiver<Option<TakeoverRequest>>>) -> Option<TakeoverRequest> {
+    if let Some(rx) = takeovers
+        && rx.changed().await.is_ok()
+    {
+        return *rx.borrow_and_update();
+    }
+    std::future::pending().await
+}
+
+// Resolves once our takeover request is decided (true) or its time runs out
+// (false); never resolves with no request pending
+async fn takeover_answer(requested: &mut Option<(oneshot::Receiver<bool>, Instant)>) -> bool {
+    let Some((answer, deadline)) = requested else {
+        return std::future::pending().await;
+    };
+    // By reference, so a cancelled select! leaves the request pending
+    let answered = tokio::time::timeout_at(*deadline, answer).await.is_ok();
+    *requested = None;
+    answered
+}
+
+// Next token holder; never resolves if the arbiter is gone
+async fn token_change(token: &mut watch::Receiver<Option<u64>>) -> Option<u64> {
+    if token.changed().await.is_ok() {
+        return *token.borrow_and_update();
     }
+    std::future::pending().await
 }
 
 struct ConnectionGuard {
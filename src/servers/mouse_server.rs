@@ -1,55 +1,277 @@
-use crate::logger::{log, log_data, Verbosity};
-use crate::protocol::HEADER_MOUSE;
-use evdev::{EventType, InputEvent, Key, RelativeAxisType, uinput::VirtualDevice};
-use std::net::IpAddr;
-use std::sync::{Arc, Mutex};
-use tokio::net::UdpSocket;
-use tokio::sync::Notify;
+use crate::identity::{hello_ack, parse_udp_hello, Identities};
+use crate::capture::{self, Channel};
+use crate::config::ConfigHandle;
+use crate::input_mode::InputMode;
+use crate::logger::{log_data, log_detail, log_lazy, Verbosity};
+use crate::protocol::{
+    HEADER_LIGHTGUN, HEADER_MOUSE, HEADER_MOUSE_CLICK, HEADER_MOUSE_DRAG, HEADER_POINTER_POSITION, HEADER_POINTER_QUERY,
+    HEADER_POINTER_WARP, HEADER_SCREENS_QUERY, HEADER_SCREEN_SELECT, MAX_DATAGRAM,
+};
+use crate::network::UdpEndpoint;
+use crate::packet_auth::{PacketAuth, Verdict};
+use crate::quarantine::QUARANTINE;
+use crate::relay::{RelayChannel, RELAY};
+use crate::rate_limit::RateLimiter;
+use crate::screens::{screens, screens_message, Region};
+use crate::session::{Admission, SessionTable, Transport, SWEEP_INTERVAL};
+use crate::stats::STATS;
+use crate::trace;
+use crate::bus::{InputAction, Origin, SharedBus, Target};
+use crate::devices::LIGHTGUN_MAX;
+use evdev::{AbsoluteAxisType, Key};
+use tokio::sync::{mpsc, watch};
+use tokio::time::{interval, sleep, Duration, Instant};
 
 const BTN_MASK_LEFT: u8 = 0x01;
 const BTN_MASK_RIGHT: u8 = 0x02;
 const BTN_MASK_MIDDLE: u8 = 0x04;
+const BTN_MASK_ALL: u8 = BTN_MASK_LEFT | BTN_MASK_RIGHT | BTN_MASK_MIDDLE;
+// Lightgun buttons share the mouse bits (trigger = left); this one is the off-screen shot
+const GUN_MASK_OFFSCREEN: u8 = 0x08;
+// Held by the client for fine pointing: movement scaled by [mouse] precision_scale
+const MASK_PRECISION: u8 = 0x10;
+
+// Timing used to expand CLICK messages; long enough for frontends that poll
+const CLICK_HOLD: Duration = Duration::from_millis(40);
+const CLICK_GAP: Duration = Duration::from_millis(80);
+// CLICK messages waiting their turn; when full the server waits, it never drops a click
+const CLICK_BACKLOG: usize = 16;
+// The aim stays off-screen this long after an off-screen shot lets go of the trigger
+const OFFSCREEN_SETTLE: Duration = Duration::from_millis(30);
 
 pub async fn run_udp_mouse_server(
-    port: u16,
-    device: Arc<Mutex<VirtualDevice>>,
+    endpoint: UdpEndpoint,
+    bus: SharedBus,
+    mut input_mode: watch::Receiver<InputMode>,
+    identities: Identities,
+    config: ConfigHandle,
 ) -> std::io::Result<()> {
-    // Store active session: (IpAddr, Notify for connection reset)
-    let active_session: Arc<Mutex<Option<(IpAddr, Arc<Notify>)>>> = Arc::new(Mutex::new(None));
-
-    let socket = UdpSocket::bind(format!("0.0.0.0:{}", port)).await?;
-    let mut buf = [0u8; 32];
+    let mut sessions = SessionTable::new("UDP Mouse", Transport::Datagram);
+    // One extra byte to tell an exact fit from a truncated datagram
+    let mut buf = [0u8; MAX_DATAGRAM + 1];
+    let UdpEndpoint { socket, mut source } = endpoint;
     let mut last_buttons = 0u8;
+    // Buttons held down by a DRAG begin, regardless of the button bits in movement packets
+    let mut drag_lock = 0u8;
+    let mut last_gun_buttons = 0u8;
+    let mut offscreen_held = false;
+    // Lightgun packets are ignored while an off-screen shot is in progress
+    let mut offscreen_until = Instant::now();
+    // Screen the lightgun is confined to (SCREEN_SELECT), by index
+    let mut gun_screen: Option<(usize, Region)> = None;
+    // Last position emitted on the lightgun, in device units
+    let mut gun_position = (0, 0);
+    // Last accepted packet, for the held-button watchdog
+    let mut last_refresh = Instant::now();
+    let mut limiter = RateLimiter::new();
+    let mut auth = PacketAuth::default();
+    let mut precision = Precision::default();
+    let (clicks, queued_clicks) = mpsc::channel(CLICK_BACKLOG);
+    tokio::spawn(run_clicks(queued_clicks, bus.clone()));
 
-    loop {
-        let (len, src_addr) = socket.recv_from(&mut buf).await?;
-        let src_ip = src_addr.ip();
+    let mut sweep = interval(SWEEP_INTERVAL);
 
-        // Check if this IP is already connected
-        let _is_new_client = {
-            let mut session = active_session.lock().unwrap();
-            if let Some((existing_ip, _)) = session.as_ref() {
-                if *existing_ip == src_ip {
-                    // Same client continuing: keep existing session
-                    false
-                } else {
-                    // Different client: replace session
-                    println!(
-                        "UDP connection from {} replacing previous connection from {}",
-                        src_ip, existing_ip
+    loop {
+        let (len, src_addr, carrier) = tokio::select! {
+            result = source.recv_from(socket.udp(), &mut buf) => result?,
+            Ok(()) = input_mode.changed() => {
+                // The client stops sending mouse packets in gamepad mode, so nothing would release them
+                if *input_mode.borrow_and_update() == InputMode::Gamepad {
+                    drag_lock = 0;
+                    if last_buttons != 0 {
+                        release_buttons(Target::Mouse, last_buttons, &bus);
+                        last_buttons = 0;
+                    }
+                }
+                continue;
+            }
+            _ = sweep.tick() => {
+                let session_config = &config.get().sessions;
+                let stale = session_config.held_limit().is_some_and(|limit| last_refresh.elapsed() > limit);
+                if stale && (last_buttons | drag_lock | last_gun_buttons) != 0 {
+                    log_detail(
+                        Verbosity::Low,
+                        "Botones del ratón soltados por el watchdog",
+                        &format!(
+                            "{:02X} pulsados más de {}s sin refresco",
+                            last_buttons | drag_lock | last_gun_buttons,
+                            session_config.held_limit_secs
+                        ),
                     );
-                    let new_notify = Arc::new(Notify::new());
-                    *session = Some((src_ip, new_notify));
-                    true
                 }
-            } else {
-                // First client
-                println!("UDP connection from {} registered", src_ip);
-                let new_notify = Arc::new(Notify::new());
-                *session = Some((src_ip, new_notify));
-                true
+                if !sessions.expire_idle(session_config).is_empty() || stale {
+                    drag_lock = 0;
+                    if last_buttons != 0 {
+                        release_buttons(Target::Mouse, last_buttons, &bus);
+                        last_buttons = 0;
+                    }
+                    if last_gun_buttons != 0 {
+                        release_buttons(Target::Lightgun, last_gun_buttons, &bus);
+                        last_gun_buttons = 0;
+                    }
+                }
+                continue;
             }
         };
+        let origin = Origin::from_client(&STATS.mouse, src_addr);
+        trace::stage(origin.span, "receive", || format!("{:02X?}", &buf[..len.min(MAX_DATAGRAM)]));
+        let src_ip = src_addr.ip();
+        STATS.mouse.record_packet();
+        capture::record(Channel::Mouse, src_addr, &buf[..len]);
+        if len > MAX_DATAGRAM {
+            STATS.mouse.record_oversized();
+            log_detail(Verbosity::Medium, "Datagrama demasiado grande", &format!("{} desde {}", len, src_addr));
+            continue;
+        }
+        let current_config = config.get();
+        if !current_config.is_allowed(src_ip) {
+            continue;
+        }
+        if QUARANTINE.is_banned(src_ip) {
+            STATS.mouse.record_quarantined();
+            trace::stage(origin.span, "drop", || "en cuarentena".to_string());
+            continue;
+        }
+        let security = &current_config.security;
+        if !limiter.allow(src_ip, security.udp_rate_limit, security.udp_rate_burst) {
+            STATS.mouse.record_rate_limited();
+            trace::stage(origin.span, "drop", || "límite de tasa".to_string());
+            continue;
+        }
+        let len = match auth.check(security.packet_key.as_deref(), &buf[..len], src_addr, carrier) {
+            Verdict::Accepted(len) => len,
+            Verdict::Challenge(reply) => {
+                let _ = socket.send_to(&reply, src_addr).await;
+                continue;
+            }
+            Verdict::Rejected(reason) => {
+                STATS.mouse.record_unauthenticated();
+                log_detail(Verbosity::Medium, "Datagrama no autenticado", &format!("{}: {}", src_addr, reason));
+                // Not a quarantine violation: the source address may be a spoofed client's
+                trace::stage(origin.span, "drop", || reason.to_string());
+                continue;
+            }
+        };
+        if !RELAY.forward_datagram(RelayChannel::Mouse, src_addr, &buf[..len], &socket, &current_config.relay) {
+            continue;
+        }
+
+        if let Some(token) = parse_udp_hello(&buf[..len]) {
+            match identities.hello("UDP Mouse", src_addr, token) {
+                Ok(id) => {
+                    let _ = socket.send_to(&hello_ack(id), src_addr).await;
+                    let screens = screens().await;
+                    let _ = socket.send_to(&screens_message(&screens, gun_screen.map(|(i, _)| i)), src_addr).await;
+                }
+                Err(e) => log_detail(Verbosity::Low, "Error en HELLO UDP", &format!("{}: {}", src_addr, e)),
+            }
+            continue;
+        }
+        if let Admission::Rejected = sessions.admit(identities.key_for(src_addr), &current_config.sessions) {
+            continue;
+        }
+        last_refresh = Instant::now();
+
+        if buf[0] == HEADER_SCREENS_QUERY || (len >= 2 && buf[0] == HEADER_SCREEN_SELECT) {
+            let screens = screens().await;
+            if buf[0] == HEADER_SCREEN_SELECT {
+                let index = (buf[1] as usize).checked_sub(1);
+                gun_screen = index.and_then(|i| Region::of(&screens, i).map(|region| (i, region)));
+                let name = gun_screen.map_or("escritorio completo", |(i, _)| screens[i].name.as_str());
+                log_detail(Verbosity::Low, "Pantalla de la pistola", &format!("{} ({})", name, src_addr));
+            }
+            let _ = socket.send_to(&screens_message(&screens, gun_screen.map(|(i, _)| i)), src_addr).await;
+            continue;
+        }
+
+        if buf[0] == HEADER_POINTER_WARP {
+            let raw = |at: usize| buf.get(at..at + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+            let (x, y) = match (raw(1), raw(3)) {
+                (Some(x), Some(y)) if len >= 5 => (x, y),
+                _ => (u16::MAX / 2, u16::MAX / 2),
+            };
+            let to_device = |value: u16| (value as i64 * LIGHTGUN_MAX as i64 / u16::MAX as i64) as i32;
+            gun_position = (to_device(x), to_device(y));
+            log_lazy(Verbosity::High, || format!("Lightgun warp: x={}, y={}", x, y));
+            let actions = [
+                InputAction::Axis { code: AbsoluteAxisType::ABS_X.0, value: gun_position.0 },
+                InputAction::Axis { code: AbsoluteAxisType::ABS_Y.0, value: gun_position.1 },
+            ];
+            let _ = bus.emit(Target::Lightgun, &actions);
+            continue;
+        }
+
+        if buf[0] == HEADER_POINTER_QUERY {
+            let to_raw = |value: i32| {
+                (value.clamp(0, LIGHTGUN_MAX) as i64 * u16::MAX as i64 / LIGHTGUN_MAX as i64) as u16
+            };
+            let mut reply = vec![HEADER_POINTER_POSITION];
+            reply.extend_from_slice(&to_raw(gun_position.0).to_le_bytes());
+            reply.extend_from_slice(&to_raw(gun_position.1).to_le_bytes());
+            let _ = socket.send_to(&reply, src_addr).await;
+            continue;
+        }
+
+        if len >= 3 && buf[0] == HEADER_MOUSE_DRAG {
+            // Retransmitted begin/end messages are harmless: only changes are emitted
+            let mask = buf[2] & BTN_MASK_ALL;
+            drag_lock = if buf[1] != 0 { drag_lock | mask } else { drag_lock & !mask };
+            log_lazy(Verbosity::High, || format!("Mouse drag: action={}, buttons={:02X}", buf[1], mask));
+            let buttons = (last_buttons & !mask) | drag_lock;
+            emit_buttons(buttons, last_buttons, &bus);
+            last_buttons = buttons;
+            continue;
+        }
+
+        if len >= 3 && buf[0] == HEADER_MOUSE_CLICK {
+            // Don't click a button that is currently held
+            let mask = buf[1] & !last_buttons & BTN_MASK_ALL;
+            let count = buf[2].clamp(1, 3);
+            log_lazy(Verbosity::High, || format!("Mouse click: buttons={:02X}, count={}", mask, count));
+            if mask != 0 {
+                clicks.send((mask, count)).await.map_err(std::io::Error::other)?;
+            }
+            continue;
+        }
+
+        if len >= 6 && buf[0] == HEADER_LIGHTGUN {
+            let x = u16::from_le_bytes([buf[1], buf[2]]);
+            let y = u16::from_le_bytes([buf[3], buf[4]]);
+            let buttons = buf[5];
+            log_lazy(Verbosity::High, || format!("Lightgun: x={}, y={}, buttons={:02X}", x, y, buttons));
+            trace::stage(origin.span, "parse", || format!("lightgun x={} y={} buttons={:02X}", x, y, buttons));
+            // Only the press of the off-screen bit shoots, not its retransmissions
+            let shoot_offscreen = buttons & GUN_MASK_OFFSCREEN != 0 && !offscreen_held;
+            offscreen_held = buttons & GUN_MASK_OFFSCREEN != 0;
+            if Instant::now() < offscreen_until {
+                continue;
+            }
+            let gun = &current_config.lightgun;
+            if shoot_offscreen {
+                let hold = Duration::from_millis(gun.offscreen_hold_ms);
+                offscreen_until = Instant::now() + hold + OFFSCREEN_SETTLE;
+                gun_position = (0, 0);
+                tokio::spawn(shoot_off_screen(last_gun_buttons & BTN_MASK_LEFT != 0, hold, bus.clone()));
+                last_gun_buttons &= !BTN_MASK_LEFT;
+                continue;
+            }
+            let mut aim = gun.aim(x, y);
+            if let Some((_, region)) = gun_screen {
+                aim = region.confine(aim, LIGHTGUN_MAX);
+            }
+            gun_position = aim;
+            let (aim_x, aim_y) = aim;
+            let mut actions = vec![
+                InputAction::Axis { code: AbsoluteAxisType::ABS_X.0, value: aim_x },
+                InputAction::Axis { code: AbsoluteAxisType::ABS_Y.0, value: aim_y },
+            ];
+            let buttons = buttons & BTN_MASK_ALL;
+            push_button_changes(buttons, last_gun_buttons, &mut actions);
+            last_gun_buttons = buttons;
+            trace::stage(origin.span, "map", || format!("{:?}", actions));
+            let _ = bus.emit_from(origin, Target::Lightgun, &actions);
+            continue;
+        }
 
         if len >= 5 && buf[0] == HEADER_MOUSE {
             log_data(Verbosity::High, "UDP Mouse Packet", &buf[..len]);
@@ -58,54 +280,129 @@ pub async fn run_udp_mouse_server(
             let buttons = buf[3];
             let wheel = buf[4] as i8;
 
-            log(Verbosity::High, &format!("Mouse: dx={}, dy={}, buttons={:02X}, wheel={}", dx, dy, buttons, wheel));
+            log_lazy(Verbosity::High, || format!("Mouse: dx={}, dy={}, buttons={:02X}, wheel={}", dx, dy, buttons, wheel));
+            trace::stage(origin.span, "parse", || format!("dx={} dy={} buttons={:02X} wheel={}", dx, dy, buttons, wheel));
 
-            let mut events = Vec::with_capacity(6);
+            let mut actions = Vec::with_capacity(5);
 
-            if dx != 0 {
-                events.push(InputEvent::new(
-                    EventType::RELATIVE,
-                    RelativeAxisType::REL_X.0,
-                    dx as i32,
-                ));
-            }
-            if dy != 0 {
-                events.push(InputEvent::new(
-                    EventType::RELATIVE,
-                    RelativeAxisType::REL_Y.0,
-                    dy as i32,
-                ));
+            let (dx, dy) = if buttons & MASK_PRECISION != 0 {
+                precision.scale(dx, dy, current_config.mouse.precision_scale)
+            } else {
+                precision = Precision::default();
+                (dx as i32, dy as i32)
+            };
+            if dx != 0 || dy != 0 {
+                actions.push(InputAction::Move { dx, dy });
             }
             if wheel != 0 {
-                events.push(InputEvent::new(
-                    EventType::RELATIVE,
-                    RelativeAxisType::REL_WHEEL.0,
-                    wheel as i32,
-                ));
+                actions.push(InputAction::Wheel(wheel as i32));
             }
 
-            let changed = buttons ^ last_buttons;
+            let buttons = buttons | drag_lock;
+            push_button_changes(buttons, last_buttons, &mut actions);
+            last_buttons = buttons;
+            trace::stage(origin.span, "map", || format!("{:?}", actions));
 
-            if changed & BTN_MASK_LEFT != 0 {
-                let val = if buttons & BTN_MASK_LEFT != 0 { 1 } else { 0 };
-                events.push(InputEvent::new(EventType::KEY, Key::BTN_LEFT.0, val));
-            }
-            if changed & BTN_MASK_RIGHT != 0 {
-                let val = if buttons & BTN_MASK_RIGHT != 0 { 1 } else { 0 };
-                events.push(InputEvent::new(EventType::KEY, Key::BTN_RIGHT.0, val));
-            }
-            if changed & BTN_MASK_MIDDLE != 0 {
-                let val = if buttons & BTN_MASK_MIDDLE != 0 { 1 } else { 0 };
-                events.push(InputEvent::new(EventType::KEY, Key::BTN_MIDDLE.0, val));
-            }
+            // dx, dy, wheel and buttons of one packet form a single frame
+            let _ = bus.emit_from(origin, Target::Mouse, &actions);
+            continue;
+        }
 
-            last_buttons = buttons;
+        trace::stage(origin.span, "drop", || "paquete no reconocido".to_string());
+        QUARANTINE.violation(src_ip, "mouse", &current_config.security);
+    }
+}
 
-            if !events.is_empty() {
-                if let Ok(mut dev) = device.lock() {
-                    let _ = dev.emit(&events);
-                }
-            }
+/// Modo precisión: el movimiento se reduce y lo que no llega a un píxel se
+/// guarda para el siguiente paquete, para que los movimientos lentos no se
+/// pierdan.
+#[derive(Default)]
+struct Precision {
+    remainder: (f64, f64),
+}
+
+impl Precision {
+    fn scale(&mut self, dx: i8, dy: i8, factor: f64) -> (i32, i32) {
+        let x = dx as f64 * factor + self.remainder.0;
+        let y = dy as f64 * factor + self.remainder.1;
+        self.remainder = (x.fract(), y.fract());
+        (x.trunc() as i32, y.trunc() as i32)
+    }
+}
+
+/// Con atraso, un movimiento se suma al siguiente paquete del cliente si los
+/// botones no cambian y la suma cabe en un byte: el puntero acaba en el mismo
+/// sitio con un frame menos.
+pub fn coalesce_mouse(earlier: &[u8], later: &mut [u8]) -> bool {
+    if earlier.len() != 5 || later.len() != 5 || earlier[0] != HEADER_MOUSE || later[0] != HEADER_MOUSE || earlier[3] != later[3] {
+        return false;
+    }
+    let sum = |i: usize| (earlier[i] as i8).checked_add(later[i] as i8);
+    let (Some(dx), Some(dy), Some(wheel)) = (sum(1), sum(2), sum(4)) else {
+        return false;
+    };
+    later[1] = dx as u8;
+    later[2] = dy as u8;
+    later[4] = wheel as u8;
+    true
+}
+
+fn push_button_changes(buttons: u8, previous: u8, actions: &mut Vec<InputAction>) {
+    let changed = buttons ^ previous;
+    for (mask, key) in [
+        (BTN_MASK_LEFT, Key::BTN_LEFT),
+        (BTN_MASK_RIGHT, Key::BTN_RIGHT),
+        (BTN_MASK_MIDDLE, Key::BTN_MIDDLE),
+    ] {
+        if changed & mask != 0 {
+            actions.push(InputAction::key(key.0, buttons & mask != 0));
+        }
+    }
+}
+
+fn emit_buttons(buttons: u8, previous: u8, bus: &SharedBus) {
+    let mut actions = Vec::with_capacity(3);
+    push_button_changes(buttons, previous, &mut actions);
+    let _ = bus.emit(Target::Mouse, &actions);
+}
+
+// Press/release `count` times with real gaps, so double clicks register
+// Plays CLICK messages one after another, so a flood of them is one task
+async fn run_clicks(mut clicks: mpsc::Receiver<(u8, u8)>, bus: SharedBus) {
+    while let Some((buttons, count)) = clicks.recv().await {
+        click(buttons, count, &bus).await;
+    }
+}
+
+async fn click(buttons: u8, count: u8, bus: &SharedBus) {
+    for i in 0..count {
+        if i > 0 {
+            sleep(CLICK_GAP).await;
         }
+        emit_buttons(buttons, 0, bus);
+        sleep(CLICK_HOLD).await;
+        emit_buttons(0, buttons, bus);
     }
 }
+
+// Aim at the corner of the gun's range, pull the trigger there and let go.
+// Cores read that as a shot off-screen (reload); the next packet aims back.
+async fn shoot_off_screen(trigger_held: bool, hold: Duration, bus: SharedBus) {
+    let mut aim = vec![
+        InputAction::Axis { code: AbsoluteAxisType::ABS_X.0, value: 0 },
+        InputAction::Axis { code: AbsoluteAxisType::ABS_Y.0, value: 0 },
+    ];
+    if trigger_held {
+        aim.push(InputAction::key(Key::BTN_LEFT.0, false));
+    }
+    let _ = bus.emit_frames(Target::Lightgun, &[&aim, &[InputAction::key(Key::BTN_LEFT.0, true)]]);
+    sleep(hold).await;
+    let _ = bus.emit(Target::Lightgun, &[InputAction::key(Key::BTN_LEFT.0, false)]);
+}
+
+// Neutralize the buttons left down by a client whose session expired
+fn release_buttons(target: Target, buttons: u8, bus: &SharedBus) {
+    let mut actions = Vec::with_capacity(3);
+    push_button_changes(0, buttons, &mut actions);
+    bus.release(target, &actions);
+}
@@ -0,0 +1,284 @@
+use crate::config::{SessionConfig, SessionPolicy};
+use crate::identity::ClientKey;
+use crate::logger::{log_detail, Verbosity};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Cada cuánto revisan los servidores si hay sesiones inactivas.
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Session {
+    pub key: ClientKey,
+    pub id: u64,
+    pub last_seen: Instant,
+    /// Se notifica cuando la sesión es desalojada (p. ej. para cerrar el socket TCP).
+    pub cancel: Arc<Notify>,
+}
+
+pub enum Admission {
+    /// El cliente ya tenía sesión (UDP); sólo se refresca.
+    Existing,
+    /// Sesión nueva; las que tuvieron que terminar para hacerle sitio ya
+    /// fueron notificadas.
+    Admitted { id: u64, cancel: Arc<Notify> },
+    /// No hay hueco y la política es rechazar al recién llegado.
+    Rejected,
+}
+
+/// Cómo trata la tabla a un cliente que ya tiene sesión.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// Cada conexión es una sesión; reconectar con la misma clave cierra la anterior.
+    Stream,
+    /// Los datagramas con la misma clave continúan la sesión existente.
+    Datagram,
+}
+
+/// Tabla de sesiones de un canal (teclado, mouse, gamepad). Todos los
+/// servidores aplican la misma política de `[sessions]`.
+pub struct SessionTable {
+    channel: &'static str,
+    transport: Transport,
+    sessions: Vec<Session>,
+    next_id: u64,
+    // Avoids logging every datagram of a client that keeps being rejected
+    last_rejected: Option<ClientKey>,
+}
+
+pub type SharedSessions = Arc<Mutex<SessionTable>>;
+
+impl SessionTable {
+    pub fn new(channel: &'static str, transport: Transport) -> Self {
+        Self {
+            channel,
+            transport,
+            sessions: Vec::new(),
+            next_id: 0,
+            last_rejected: None,
+        }
+    }
+
+    pub fn shared(channel: &'static str, transport: Transport) -> SharedSessions {
+        Arc::new(Mutex::new(Self::new(channel, transport)))
+    }
+
+    pub fn admit(&mut self, key: ClientKey, config: &SessionConfig) -> Admission {
+        let now = Instant::now();
+
+        if let Some(pos) = self.sessions.iter().position(|s| s.key == key) {
+            match self.transport {
+                Transport::Datagram => {
+                    self.sessions[pos].last_seen = now;
+                    return Admission::Existing;
+                }
+                Transport::Stream => {
+                    let old = self.sessions.remove(pos);
+                    self.end(old, "reconexión del mismo cliente");
+                }
+            }
+        }
+
+        // Free slots held by clients that already left before judging capacity
+        self.expire_idle(config);
+
+        let max = config.max_clients.max(1);
+        while self.sessions.len() >= max {
+            match config.policy {
+                SessionPolicy::RejectNewest => {
+                    if self.last_rejected == Some(key) {
+                        return Admission::Rejected;
+                    }
+                    self.last_rejected = Some(key);
+                    let holders: Vec<String> = self.sessions.iter().map(|s| s.key.to_string()).collect();
+                    log_detail(
+                        Verbosity::Low,
+                        &format!("Cliente {} rechazado", self.channel),
+                        &format!("cliente={} límite={} ocupado por {}", key, max, holders.join(", ")),
+                    );
+                    return Admission::Rejected;
+                }
+                SessionPolicy::EvictOldest => {
+                    let oldest = self.sessions.remove(0);
+                    self.end(oldest, &format!("desalojado por {}", key));
+                }
+            }
+        }
+
+        if self.last_rejected == Some(key) {
+            self.last_rejected = None;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        let cancel = Arc::new(Notify::new());
+        self.sessions.push(Session {
+            key,
+            id,
+            last_seen: now,
+            cancel: cancel.clone(),
+        });
+        log_detail(
+            Verbosity::Low,
+            &format!("Cliente {} registrado", self.channel),
+            &format!("cliente={} sesiones={}/{}", key, self.sessions.len(), max),
+        );
+        Admission::Admitted { id, cancel }
+    }
+
+    /// Termina las sesiones sin input durante más del timeout configurado.
+    /// Devuelve los clientes que expiraron, para que el servidor neutralice su estado.
+    pub fn expire_idle(&mut self, config: &SessionConfig) -> Vec<ClientKey> {
+        let timeout_secs = match self.transport {
+            Transport::Stream => config.idle_timeout_secs,
+            Transport::Datagram => config.udp_idle_timeout_secs,
+        };
+        if timeout_secs == 0 {
+            return Vec::new();
+        }
+        let timeout = Duration::from_secs(timeout_secs);
+        let now = Instant::now();
+        let (idle, live): (Vec<_>, Vec<_>) = self
+            .sessions
+            .drain(..)
+            .partition(|s| now.duration_since(s.last_seen) >= timeout);
+        self.sessions = live;
+        let expired = idle.iter().map(|s| s.key).collect();
+        for session in idle {
+            self.end(session, &format!("inactiva {}s", timeout_secs));
+        }
+        expired
+    }
+
+    pub fn contains(&self, key: &ClientKey) -> bool {
+        self.sessions.iter().any(|s| s.key == *key)
+    }
+
+    pub fn touch(&mut self, key: ClientKey, id: u64) {
+        if let Some(session) = self.sessions.iter_mut().find(|s| s.key == key && s.id == id) {
+            session.last_seen = Instant::now();
+        }
+    }
+
+    /// Quita la sesión `id` si sigue siendo la activa para `key`.
+    pub fn remove(&mut self, key: ClientKey, id: u64) -> bool {
+        let Some(pos) = self.sessions.iter().position(|s| s.key == key && s.id == id) else {
+            return false;
+        };
+        self.sessions.remove(pos);
+        log_detail(
+            Verbosity::Low,
+            &format!("Cliente {} desconectado", self.channel),
+            &format!("cliente={} sesiones={}", key, self.sessions.len()),
+        );
+        true
+    }
+
+    fn end(&self, session: Session, reason: &str) {
+        log_detail(
+            Verbosity::Low,
+            &format!("Sesión {} terminada", self.channel),
+            &format!("cliente={} ({})", session.key, reason),
+        );
+        session.cancel.notify_one();
+    }
+}
+
+/// Referencia a una sesión concreta, para que la tarea del cliente la refresque
+/// y la quite al terminar.
+#[derive(Clone)]
+pub struct SessionRef {
+    table: SharedSessions,
+    key: ClientKey,
+    id: u64,
+}
+
+impl SessionRef {
+    pub fn new(table: SharedSessions, key: ClientKey, id: u64) -> Self {
+        Self { table, key, id }
+    }
+
+    pub fn touch(&self) {
+        self.table.lock().unwrap().touch(self.key, self.id);
+    }
+
+    pub fn remove(&self) {
+        self.table.lock().unwrap().remove(self.key, self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    fn client(port: u16) -> ClientKey {
+        ClientKey::Addr(SocketAddr::from(([192, 168, 1, 20], port)))
+    }
+
+    fn config(max_clients: usize, policy: SessionPolicy) -> SessionConfig {
+        SessionConfig { max_clients, policy, ..SessionConfig::default() }
+    }
+
+    fn admitted(admission: Admission) -> (u64, Arc<Notify>) {
+        match admission {
+            Admission::Admitted { id, cancel } => (id, cancel),
+            Admission::Existing => panic!("se esperaba una sesión nueva, no una existente"),
+            Admission::Rejected => panic!("se esperaba una sesión nueva, no un rechazo"),
+        }
+    }
+
+    #[test]
+    fn datagrams_continue_their_session() {
+        let mut table = SessionTable::new("Test", Transport::Datagram);
+        let config = config(1, SessionPolicy::RejectNewest);
+        admitted(table.admit(client(1), &config));
+        assert!(matches!(table.admit(client(1), &config), Admission::Existing));
+        assert!(table.contains(&client(1)));
+    }
+
+    #[test]
+    fn full_table_rejects_the_newest() {
+        let mut table = SessionTable::new("Test", Transport::Datagram);
+        let config = config(1, SessionPolicy::RejectNewest);
+        admitted(table.admit(client(1), &config));
+        assert!(matches!(table.admit(client(2), &config), Admission::Rejected));
+        assert!(matches!(table.admit(client(2), &config), Admission::Rejected));
+        assert!(!table.contains(&client(2)));
+    }
+
+    #[tokio::test]
+    async fn full_table_evicts_the_oldest() {
+        let mut table = SessionTable::new("Test", Transport::Datagram);
+        let config = config(2, SessionPolicy::EvictOldest);
+        let (_, oldest) = admitted(table.admit(client(1), &config));
+        admitted(table.admit(client(2), &config));
+        admitted(table.admit(client(3), &config));
+        oldest.notified().await;
+        assert!(!table.contains(&client(1)));
+        assert!(table.contains(&client(2)) && table.contains(&client(3)));
+    }
+
+    #[tokio::test]
+    async fn reconnecting_stream_replaces_the_old_one() {
+        let mut table = SessionTable::new("Test", Transport::Stream);
+        let config = config(1, SessionPolicy::RejectNewest);
+        let (old, cancel) = admitted(table.admit(client(1), &config));
+        let (new, _) = admitted(table.admit(client(1), &config));
+        cancel.notified().await;
+        // The old connection's cleanup must not remove the new one
+        assert!(!table.remove(client(1), old));
+        assert!(table.remove(client(1), new));
+        assert!(!table.contains(&client(1)));
+    }
+
+    #[test]
+    fn idle_sessions_expire() {
+        let mut table = SessionTable::new("Test", Transport::Datagram);
+        let config = SessionConfig { udp_idle_timeout_secs: 1, ..config(1, SessionPolicy::RejectNewest) };
+        admitted(table.admit(client(1), &config));
+        assert!(table.expire_idle(&config).is_empty());
+        table.sessions[0].last_seen -= Duration::from_secs(2);
+        assert_eq!(table.expire_idle(&config), vec![client(1)]);
+        admitted(table.admit(client(2), &config));
+    }
+}
@@ -0,0 +1,497 @@
+use crate::devices::xbox360_layout::Xbox360Layout;
+use crate::protocol::{
+    DEFAULT_GAMEPAD_PORT, DEFAULT_KEYBOARD_PORT, DEFAULT_MOUSE_PORT, HEADER_GAMEPAD_SNAPSHOT, HEADER_KEYBOARD,
+    HEADER_KEYBOARD_WIDE, HEADER_MOUSE, HEADER_SLOT_RELEASE, MODE_XBOX,
+};
+use evdev::{AbsoluteAxisType, Device, EventType, InputEvent, Key, RelativeAxisType};
+use std::io::ErrorKind;
+use std::os::fd::{AsRawFd, BorrowedFd};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::watch;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+// An idle pad resends its last snapshot this often, so the remote keeps its
+// slot and the held-button watchdog sees the buttons refreshed
+const KEEPALIVE: Duration = Duration::from_secs(1);
+
+// Trigger range the remote expects by default ([gamepad] trigger_input_max)
+const TRIGGER_MAX: i32 = i16::MAX as i32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Gamepad,
+    Mouse,
+    Keyboard,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Gamepad => "mando",
+            Kind::Mouse => "ratón",
+            Kind::Keyboard => "teclado",
+        }
+    }
+}
+
+struct Options {
+    host: String,
+    mouse_port: u16,
+    keyboard_port: u16,
+    gamepad_port: u16,
+    devices: Vec<PathBuf>,
+    grab: bool,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Self {
+            host: String::new(),
+            mouse_port: DEFAULT_MOUSE_PORT,
+            keyboard_port: DEFAULT_KEYBOARD_PORT,
+            gamepad_port: DEFAULT_GAMEPAD_PORT,
+            devices: Vec::new(),
+            grab: false,
+        };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let mut value = || iter.next().ok_or_else(|| format!("falta el valor de {}", arg));
+            let port = |v: &String| v.parse::<u16>().map_err(|_| format!("puerto inválido para {}: {}", arg, v));
+            match arg.as_str() {
+                "--to" => options.host = value()?.clone(),
+                "--device" => options.devices.push(PathBuf::from(value()?)),
+                "--grab" => options.grab = true,
+                "--mouse-port" => options.mouse_port = port(value()?)?,
+                "--keyboard-port" => options.keyboard_port = port(value()?)?,
+                "--gamepad-port" => options.gamepad_port = port(value()?)?,
+                other => return Err(format!("argumento desconocido para share: {}", other)),
+            }
+        }
+        if options.host.is_empty() {
+            return Err("share necesita --to <host>".to_string());
+        }
+        Ok(options)
+    }
+
+    fn port(&self, kind: Kind) -> u16 {
+        match kind {
+            Kind::Gamepad => self.gamepad_port,
+            Kind::Mouse => self.mouse_port,
+            Kind::Keyboard => self.keyboard_port,
+        }
+    }
+}
+
+/// Subcomando `share` (modo inverso): abre mandos, ratones y teclados
+/// físicos de esta máquina y envía su entrada con el protocolo normal a
+/// otra instancia, como si fuera la app. Cada mando usa su propio socket,
+/// así que el remoto le da un jugador a cada uno.
+pub async fn run_share(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let options = Arc::new(Options::parse(args)?);
+    let devices = open_devices(&options.devices)?;
+    if devices.is_empty() {
+        return Err("no hay mandos, ratones ni teclados físicos que compartir".into());
+    }
+
+    let (stop, _) = watch::channel(false);
+    let mut tasks = Vec::new();
+    for (node, mut device, kind) in devices {
+        let name = device.name().unwrap_or("?").to_string();
+        if options.grab {
+            device.grab()?;
+        }
+        println!("▶ {} ({}) desde {} hacia {}:{}", name, kind.name(), node.display(), options.host, options.port(kind));
+        let options = options.clone();
+        let stop = stop.subscribe();
+        tasks.push(tokio::spawn(async move {
+            if let Err(e) = share_device(device, kind, &options, stop).await {
+                eprintln!("✗ {}: {}", name, e);
+            }
+        }));
+    }
+
+    let all = join_all(tasks);
+    tokio::select! {
+        _ = all => {}
+        result = tokio::signal::ctrl_c() => {
+            result?;
+            let _ = stop.send(true);
+        }
+    }
+    println!("✓ Compartición terminada");
+    Ok(())
+}
+
+async fn join_all(tasks: Vec<tokio::task::JoinHandle<()>>) {
+    for task in tasks {
+        let _ = task.await;
+    }
+}
+
+/// Los nodos pedidos con `--device`, o todos los dispositivos físicos que
+/// parezcan un mando, un ratón o un teclado.
+fn open_devices(requested: &[PathBuf]) -> std::io::Result<Vec<(PathBuf, Device, Kind)>> {
+    let mut devices = Vec::new();
+    if requested.is_empty() {
+        // Virtual devices, including this server's own, are never picked up
+        for (node, device) in evdev::enumerate().filter(|(node, _)| !is_virtual(node)) {
+            if let Some(kind) = classify(&device) {
+                devices.push((node, device, kind));
+            }
+        }
+        devices.sort_by(|a, b| a.0.cmp(&b.0));
+    } else {
+        for node in requested {
+            let device = Device::open(node).map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", node.display(), e)))?;
+            let kind = classify(&device).ok_or_else(|| {
+                std::io::Error::other(format!("{} no es un mando, un ratón ni un teclado", node.display()))
+            })?;
+            devices.push((node.clone(), device, kind));
+        }
+    }
+    Ok(devices)
+}
+
+fn is_virtual(node: &Path) -> bool {
+    let Some(name) = node.file_name() else {
+        return false;
+    };
+    std::fs::canonicalize(Path::new("/sys/class/input").join(name)).is_ok_and(|path| path.starts_with("/sys/devices/virtual"))
+}
+
+fn classify(device: &Device) -> Option<Kind> {
+    let has_key = |key| device.supported_keys().is_some_and(|keys| keys.contains(key));
+    if has_key(Key::BTN_SOUTH) && device.supported_absolute_axes().is_some() {
+        Some(Kind::Gamepad)
+    } else if has_key(Key::BTN_LEFT)
+        && device.supported_relative_axes().is_some_and(|axes| axes.contains(RelativeAxisType::REL_X))
+    {
+        Some(Kind::Mouse)
+    } else if has_key(Key::KEY_A) && has_key(Key::KEY_ENTER) {
+        Some(Kind::Keyboard)
+    } else {
+        None
+    }
+}
+
+async fn share_device(device: Device, kind: Kind, options: &Options, mut stop: watch::Receiver<bool>) -> std::io::Result<()> {
+    set_nonblocking(&device)?;
+    let mut state = match kind {
+        Kind::Gamepad => State::Gamepad(Pad::new(&device)?),
+        Kind::Mouse => State::Mouse(Pointer::default()),
+        Kind::Keyboard => State::Keyboard,
+    };
+    let mut link = Link::connect(kind, options).await?;
+    let mut device = AsyncFd::new(device)?;
+    let mut keepalive = interval(KEEPALIVE);
+    keepalive.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let result = loop {
+        tokio::select! {
+            guard = device.readable_mut() => {
+                let mut guard = guard?;
+                let mut packets = Vec::new();
+                let result = guard.get_inner_mut().fetch_events().map(|events| {
+                    for event in events {
+                        state.handle(event, &mut packets);
+                    }
+                });
+                match result {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => guard.clear_ready(),
+                    // ENODEV once the device is unplugged
+                    Err(e) => break Err(e),
+                }
+                for packet in packets {
+                    link.send(&packet, kind, options).await?;
+                }
+            }
+            _ = keepalive.tick() => {
+                if let State::Gamepad(pad) = &state {
+                    link.send(&pad.snapshot(), kind, options).await?;
+                }
+            }
+            closed = link.closed() => {
+                // The remote drops idle TCP clients; reconnect so the next key gets through
+                closed?;
+                link = Link::connect(kind, options).await?;
+            }
+            _ = stop.changed() => break Ok(()),
+        }
+    };
+    if let Some(packet) = state.release() {
+        link.send(&packet, kind, options).await?;
+    }
+    result
+}
+
+fn set_nonblocking(device: &Device) -> std::io::Result<()> {
+    let fd = unsafe { BorrowedFd::borrow_raw(device.as_raw_fd()) };
+    let flags = nix::fcntl::OFlag::from_bits_truncate(nix::fcntl::fcntl(fd, nix::fcntl::F_GETFL)?);
+    nix::fcntl::fcntl(fd, nix::fcntl::F_SETFL(flags | nix::fcntl::OFlag::O_NONBLOCK))?;
+    Ok(())
+}
+
+enum Link {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+impl Link {
+    async fn connect(kind: Kind, options: &Options) -> std::io::Result<Self> {
+        let remote = (options.host.as_str(), options.port(kind));
+        Ok(match kind {
+            Kind::Keyboard => Link::Tcp(TcpStream::connect(remote).await?),
+            Kind::Gamepad | Kind::Mouse => {
+                let socket = UdpSocket::bind("0.0.0.0:0").await?;
+                socket.connect(remote).await?;
+                Link::Udp(socket)
+            }
+        })
+    }
+
+    async fn send(&mut self, packet: &[u8], kind: Kind, options: &Options) -> std::io::Result<()> {
+        match self {
+            Link::Udp(socket) => {
+                // A refused datagram (remote restarting) must not end the share
+                let _ = socket.send(packet).await;
+                Ok(())
+            }
+            Link::Tcp(stream) => {
+                if stream.write_all(packet).await.is_err() {
+                    // Closed under us between two reads; one retry on a fresh connection
+                    *self = Link::connect(kind, options).await?;
+                    if let Link::Tcp(stream) = self {
+                        stream.write_all(packet).await?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Se resuelve cuando el remoto cierra la conexión TCP. Lo que envíe el
+    /// servidor (sólo LED_STATE y HAPTIC tras HELLO) se descarta.
+    async fn closed(&mut self) -> std::io::Result<()> {
+        let Link::Tcp(stream) = self else {
+            return std::future::pending().await;
+        };
+        let mut discard = [0u8; 64];
+        loop {
+            if stream.read(&mut discard).await? == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+enum State {
+    Gamepad(Pad),
+    Mouse(Pointer),
+    Keyboard,
+}
+
+impl State {
+    fn handle(&mut self, event: InputEvent, packets: &mut Vec<Vec<u8>>) {
+        match self {
+            State::Gamepad(pad) => {
+                if pad.handle(event) {
+                    packets.push(pad.snapshot());
+                }
+            }
+            State::Mouse(pointer) => pointer.handle(event, packets),
+            // Kernel repeats are left out: the remote autorepeats on its own
+            State::Keyboard => {
+                if event.event_type() == EventType::KEY && event.value() != 2 {
+                    let state = event.value() as u8;
+                    match u8::try_from(event.code()) {
+                        Ok(code) => packets.push(vec![HEADER_KEYBOARD, code, state]),
+                        Err(_) => {
+                            let [low, high] = event.code().to_le_bytes();
+                            packets.push(vec![HEADER_KEYBOARD_WIDE, low, high, state]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Lo que hay que enviar al dejar de compartir para no dejar nada
+    /// pulsado en el remoto. El teclado lo suelta el remoto al cerrarse la
+    /// conexión.
+    fn release(&self) -> Option<Vec<u8>> {
+        match self {
+            State::Gamepad(_) => Some(vec![HEADER_SLOT_RELEASE]),
+            State::Mouse(_) => Some(vec![HEADER_MOUSE, 0, 0, 0, 0]),
+            State::Keyboard => None,
+        }
+    }
+}
+
+/// Estado de un mando físico con la forma del snapshot: botones y ejes en
+/// el orden de `Xbox360Layout`.
+struct Pad {
+    buttons: u16,
+    axes: [i16; 8],
+    /// Rango del dispositivo para cada eje del snapshot (`None` si no lo tiene)
+    ranges: [Option<(i32, i32)>; 8],
+    changed: bool,
+}
+
+impl Pad {
+    fn new(device: &Device) -> std::io::Result<Self> {
+        let info = device.get_abs_state()?;
+        let supported = device.supported_absolute_axes();
+        let ranges = Xbox360Layout::AXIS_CODES.map(|code| {
+            let info = &info[code as usize];
+            supported
+                .is_some_and(|axes| axes.contains(AbsoluteAxisType(code as u16)))
+                .then_some((info.minimum, info.maximum))
+                .filter(|(min, max)| max > min)
+        });
+        let mut pad = Self { buttons: 0, axes: [0; 8], ranges, changed: false };
+        // Start from where the sticks and triggers are now, not from zero
+        for (index, &code) in Xbox360Layout::AXIS_CODES.iter().enumerate() {
+            if pad.ranges[index].is_some() {
+                pad.axes[index] = pad.normalize(index, info[code as usize].value);
+            }
+        }
+        Ok(pad)
+    }
+
+    /// Aplica un evento; devuelve si hay que enviar un snapshot (al final de
+    /// cada frame con cambios).
+    fn handle(&mut self, event: InputEvent) -> bool {
+        match event.event_type() {
+            EventType::KEY => self.key(event.code(), event.value() != 0),
+            EventType::ABSOLUTE => {
+                if let Some(index) = Xbox360Layout::AXIS_CODES.iter().position(|&code| code == event.code() as i32) {
+                    self.set_axis(index, self.normalize(index, event.value()));
+                }
+            }
+            EventType::SYNCHRONIZATION => return std::mem::take(&mut self.changed),
+            _ => {}
+        }
+        false
+    }
+
+    fn key(&mut self, code: u16, pressed: bool) {
+        if let Some(index) = Xbox360Layout::BUTTON_CODES.iter().position(|&c| c == code) {
+            let buttons = if pressed { self.buttons | 1 << index } else { self.buttons & !(1 << index) };
+            self.changed |= buttons != self.buttons;
+            self.buttons = buttons;
+            return;
+        }
+        // Digital triggers only stand in when the pad has no analog ones
+        if let Some(side) = Xbox360Layout::TRIGGER_BUTTON_CODES.iter().position(|&c| c == code)
+            && self.ranges[4 + side].is_none()
+        {
+            self.set_axis(4 + side, if pressed { TRIGGER_MAX as i16 } else { 0 });
+            return;
+        }
+        // Pads that report the d-pad as buttons instead of a hat
+        let (index, direction) = match Key(code) {
+            Key::BTN_DPAD_LEFT => (6, -1),
+            Key::BTN_DPAD_RIGHT => (6, 1),
+            Key::BTN_DPAD_UP => (7, -1),
+            Key::BTN_DPAD_DOWN => (7, 1),
+            _ => return,
+        };
+        if pressed {
+            self.set_axis(index, direction * i16::MAX);
+        } else if self.axes[index].signum() == direction {
+            self.set_axis(index, 0);
+        }
+    }
+
+    fn set_axis(&mut self, index: usize, value: i16) {
+        self.changed |= self.axes[index] != value;
+        self.axes[index] = value;
+    }
+
+    /// Lleva un valor del rango del dispositivo al del snapshot: sticks en
+    /// todo i16, gatillos desde 0 y el hat a -MAX/0/MAX.
+    fn normalize(&self, index: usize, value: i32) -> i16 {
+        let Some((min, max)) = self.ranges[index] else {
+            return value.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+        };
+        let span = (max - min) as i64;
+        let offset = (value.clamp(min, max) - min) as i64;
+        match index {
+            4 | 5 => (offset * TRIGGER_MAX as i64 / span) as i16,
+            6 | 7 => value.signum() as i16 * i16::MAX,
+            _ => (offset * u16::MAX as i64 / span + i16::MIN as i64) as i16,
+        }
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(20);
+        packet.extend_from_slice(&[HEADER_GAMEPAD_SNAPSHOT, MODE_XBOX]);
+        packet.extend_from_slice(&self.buttons.to_le_bytes());
+        for axis in self.axes {
+            packet.extend_from_slice(&axis.to_le_bytes());
+        }
+        packet
+    }
+}
+
+/// Movimiento acumulado de un ratón físico hasta el final del frame.
+#[derive(Default)]
+struct Pointer {
+    dx: i32,
+    dy: i32,
+    wheel: i32,
+    buttons: u8,
+    changed: bool,
+}
+
+impl Pointer {
+    fn handle(&mut self, event: InputEvent, packets: &mut Vec<Vec<u8>>) {
+        match event.event_type() {
+            EventType::RELATIVE => match RelativeAxisType(event.code()) {
+                RelativeAxisType::REL_X => self.dx += event.value(),
+                RelativeAxisType::REL_Y => self.dy += event.value(),
+                RelativeAxisType::REL_WHEEL => self.wheel += event.value(),
+                _ => return,
+            },
+            EventType::KEY => {
+                let mask = match Key(event.code()) {
+                    Key::BTN_LEFT => 0x01,
+                    Key::BTN_RIGHT => 0x02,
+                    Key::BTN_MIDDLE => 0x04,
+                    _ => return,
+                };
+                self.buttons = if event.value() != 0 { self.buttons | mask } else { self.buttons & !mask };
+            }
+            EventType::SYNCHRONIZATION => {
+                if self.changed {
+                    self.flush(packets);
+                }
+                return;
+            }
+            _ => return,
+        }
+        self.changed = true;
+    }
+
+    // A packet moves at most 127 per axis, so big motions take several
+    fn flush(&mut self, packets: &mut Vec<Vec<u8>>) {
+        loop {
+            let step = |value: &mut i32| {
+                let step = (*value).clamp(i8::MIN as i32, i8::MAX as i32);
+                *value -= step;
+                step as i8 as u8
+            };
+            let (dx, dy, wheel) = (step(&mut self.dx), step(&mut self.dy), step(&mut self.wheel));
+            packets.push(vec![HEADER_MOUSE, dx, dy, self.buttons, wheel]);
+            if self.dx == 0 && self.dy == 0 && self.wheel == 0 {
+                break;
+            }
+        }
+        self.changed = false;
+    }
+}
@@ -0,0 +1,83 @@
+use crate::identity::ClientKey;
+use crate::logger::{log_detail, Verbosity};
+use std::collections::HashMap;
+
+/// Asignación de jugadores (P1, P2…) a clientes. Cada hueco tiene su propio
+/// gamepad virtual. Los números son 1..=N en el protocolo y 0..N aquí.
+pub struct PlayerSlots {
+    owners: Vec<Option<ClientKey>>,
+    // Last slot of each client, to give it back when it returns
+    remembered: HashMap<ClientKey, usize>,
+    // Assignments not yet reported to their clients
+    changes: Vec<(ClientKey, Option<usize>)>,
+}
+
+impl PlayerSlots {
+    pub fn new(count: usize) -> Self {
+        Self {
+            owners: vec![None; count.max(1)],
+            remembered: HashMap::new(),
+            changes: Vec::new(),
+        }
+    }
+
+    pub fn slot_of(&self, key: &ClientKey) -> Option<usize> {
+        self.owners.iter().position(|owner| owner.as_ref() == Some(key))
+    }
+
+    pub fn owner(&self, slot: usize) -> Option<ClientKey> {
+        self.owners.get(slot).copied().flatten()
+    }
+
+    /// Asigna un hueco a `key`: el pedido si está libre, si no el que tuvo la
+    /// última vez, si no el primero libre. Un hueco cuyo dueño ya no tiene
+    /// sesión (`is_live`) cuenta como libre. `None` si están todos ocupados.
+    pub fn claim(&mut self, key: ClientKey, requested: Option<usize>, is_live: impl Fn(&ClientKey) -> bool) -> Option<usize> {
+        for owner in self.owners.iter_mut() {
+            if let Some(o) = *owner
+                && o != key
+                && !is_live(&o)
+            {
+                *owner = None;
+                self.changes.push((o, None));
+            }
+        }
+        let current = self.slot_of(&key);
+        let is_free = |owners: &[Option<ClientKey>], slot: usize| {
+            owners.get(slot).is_some_and(|o| o.is_none() || *o == Some(key))
+        };
+
+        let slot = requested
+            .filter(|&slot| is_free(&self.owners, slot))
+            .or(current)
+            .or_else(|| self.remembered.get(&key).copied().filter(|&slot| is_free(&self.owners, slot)))
+            .or_else(|| self.owners.iter().position(Option::is_none))?;
+
+        if let Some(previous) = current
+            && previous != slot
+        {
+            self.owners[previous] = None;
+        }
+        self.owners[slot] = Some(key);
+        self.remembered.insert(key, slot);
+        if current != Some(slot) {
+            log_detail(Verbosity::Low, "Jugador asignado", &format!("P{} = {}", slot + 1, key));
+            self.changes.push((key, Some(slot)));
+        }
+        Some(slot)
+    }
+
+    /// Libera el hueco de `key` (lo sigue recordando para cuando vuelva).
+    pub fn release(&mut self, key: &ClientKey) -> Option<usize> {
+        let slot = self.slot_of(key)?;
+        self.owners[slot] = None;
+        log_detail(Verbosity::Low, "Jugador liberado", &format!("P{} ({})", slot + 1, key));
+        self.changes.push((*key, None));
+        Some(slot)
+    }
+
+    /// Cambios de asignación pendientes de comunicar a cada cliente.
+    pub fn take_changes(&mut self) -> Vec<(ClientKey, Option<usize>)> {
+        std::mem::take(&mut self.changes)
+    }
+}
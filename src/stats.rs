@@ -0,0 +1,208 @@
+use std::fmt::Write;
+use crate::network::udp_kernel_drops;
+use std::sync::atomic::{AtomicU16, AtomicU64, Ordering};
+use std::time::Duration;
+
+// Four buckets per doubling from 1 µs; the last one takes everything from ~65 ms up
+const LATENCY_BUCKETS: usize = 64;
+const BUCKETS_PER_DOUBLING: f64 = 4.0;
+
+/// Histograma de latencias en escala logarítmica (un 19% de anchura por
+/// cubeta), sin bloqueos: se registra desde los servidores en caliente.
+pub struct LatencyHistogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS],
+}
+
+impl LatencyHistogram {
+    const fn new() -> Self {
+        Self { buckets: [const { AtomicU64::new(0) }; LATENCY_BUCKETS] }
+    }
+
+    pub fn record(&self, latency: Duration) {
+        let us = latency.as_micros().max(1) as f64;
+        let bucket = ((us.log2() * BUCKETS_PER_DOUBLING) as usize).min(LATENCY_BUCKETS - 1);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Límite superior, en µs, de la cubeta donde cae el percentil `p` (0..1).
+    pub fn percentile_us(&self, p: f64) -> Option<u64> {
+        let counts: Vec<u64> = self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).collect();
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let rank = ((total as f64 * p).ceil() as u64).max(1);
+        let mut seen = 0;
+        let bucket = counts.iter().position(|&count| {
+            seen += count;
+            seen >= rank
+        })?;
+        Some(2f64.powf((bucket + 1) as f64 / BUCKETS_PER_DOUBLING).ceil() as u64)
+    }
+}
+
+/// Contadores por canal. Se consultan con `stats` en el socket de administración.
+pub struct ChannelStats {
+    pub name: &'static str,
+    /// Paquetes recibidos (antes de cualquier filtro)
+    pub packets: AtomicU64,
+    /// Paquetes descartados por superar el límite de tasa de su IP
+    pub rate_limited: AtomicU64,
+    /// Paquetes ignorados porque su IP está en cuarentena
+    pub quarantined: AtomicU64,
+    /// Datagramas mayores que `MAX_DATAGRAM`, descartados
+    pub oversized: AtomicU64,
+    /// Datagramas descartados por `[security] packet_key`: sin firma, con
+    /// firma falsa o repetidos
+    pub unauthenticated: AtomicU64,
+    /// Puerto UDP, para leer del kernel los datagramas que no cupieron en
+    /// el buffer de recepción (0 = canal TCP)
+    pub udp_port: AtomicU16,
+    /// Esperas activas (`busy_poll_us`) que encontraron datagramas y las que
+    /// se agotaron, y el tiempo total gastado en ellas
+    pub busy_poll_hits: AtomicU64,
+    pub busy_poll_misses: AtomicU64,
+    pub busy_poll_us: AtomicU64,
+    /// Datagramas atrasados absorbidos por el siguiente del mismo cliente
+    /// antes de procesarse (no cuentan en `packets`)
+    pub coalesced: AtomicU64,
+    /// De la llegada del paquete a la emisión en el dispositivo virtual
+    pub latency: LatencyHistogram,
+}
+
+impl ChannelStats {
+    const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            packets: AtomicU64::new(0),
+            rate_limited: AtomicU64::new(0),
+            quarantined: AtomicU64::new(0),
+            oversized: AtomicU64::new(0),
+            unauthenticated: AtomicU64::new(0),
+            udp_port: AtomicU16::new(0),
+            busy_poll_hits: AtomicU64::new(0),
+            busy_poll_misses: AtomicU64::new(0),
+            busy_poll_us: AtomicU64::new(0),
+            coalesced: AtomicU64::new(0),
+            latency: LatencyHistogram::new(),
+        }
+    }
+
+    pub fn record_packet(&self) {
+        self.packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_rate_limited(&self) {
+        self.rate_limited.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_quarantined(&self) {
+        self.quarantined.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_oversized(&self) {
+        self.oversized.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_unauthenticated(&self) {
+        self.unauthenticated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_busy_poll(&self, hit: bool, spent: Duration) {
+        let counter = if hit { &self.busy_poll_hits } else { &self.busy_poll_misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.busy_poll_us.fetch_add(spent.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_coalesced(&self) {
+        self.coalesced.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_udp_port(&self, port: u16) {
+        self.udp_port.store(port, Ordering::Relaxed);
+    }
+}
+
+/// Descartes de una cola interna acotada. Sólo descartan las de movimiento
+/// y copias (se pierde lo más antiguo); las de pulsaciones esperan o
+/// rechazan al cliente, nunca pierden una transición en silencio.
+pub struct QueueStats {
+    pub name: &'static str,
+    pub dropped: AtomicU64,
+}
+
+impl QueueStats {
+    const fn new(name: &'static str) -> Self {
+        Self { name, dropped: AtomicU64::new(0) }
+    }
+
+    pub fn record_dropped(&self, count: u64) {
+        self.dropped.fetch_add(count, Ordering::Relaxed);
+    }
+}
+
+pub struct Stats {
+    pub mouse: ChannelStats,
+    pub keyboard: ChannelStats,
+    pub gamepad: ChannelStats,
+    /// Frames que un observador lento se saltó
+    pub observer: QueueStats,
+    /// Lecturas de movimiento que el servidor DSU se saltó
+    pub dsu: QueueStats,
+    /// Copias del relé (`local = true`) cortadas por un remoto que no da abasto
+    pub relay: QueueStats,
+    /// Entradas por MQTT rechazadas con demasiadas en curso
+    pub mqtt: QueueStats,
+}
+
+pub static STATS: Stats = Stats {
+    mouse: ChannelStats::new("mouse"),
+    keyboard: ChannelStats::new("keyboard"),
+    gamepad: ChannelStats::new("gamepad"),
+    observer: QueueStats::new("observer"),
+    dsu: QueueStats::new("dsu"),
+    relay: QueueStats::new("relay"),
+    mqtt: QueueStats::new("mqtt"),
+};
+
+impl Stats {
+    fn channels(&self) -> [&ChannelStats; 3] {
+        [&self.mouse, &self.keyboard, &self.gamepad]
+    }
+
+    /// Una línea `canal.contador valor` por contador.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for channel in self.channels() {
+            let _ = writeln!(out, "{}.packets {}", channel.name, channel.packets.load(Ordering::Relaxed));
+            let _ = writeln!(out, "{}.rate_limited {}", channel.name, channel.rate_limited.load(Ordering::Relaxed));
+            let _ = writeln!(out, "{}.quarantined {}", channel.name, channel.quarantined.load(Ordering::Relaxed));
+            for (label, p) in [("p50", 0.5), ("p95", 0.95), ("p99", 0.99)] {
+                if let Some(us) = channel.latency.percentile_us(p) {
+                    let _ = writeln!(out, "{}.latency_{}_us {}", channel.name, label, us);
+                }
+            }
+            let port = channel.udp_port.load(Ordering::Relaxed);
+            if port != 0 {
+                let _ = writeln!(out, "{}.oversized {}", channel.name, channel.oversized.load(Ordering::Relaxed));
+                let _ = writeln!(out, "{}.coalesced {}", channel.name, channel.coalesced.load(Ordering::Relaxed));
+                let unauthenticated = channel.unauthenticated.load(Ordering::Relaxed);
+                let _ = writeln!(out, "{}.unauthenticated {}", channel.name, unauthenticated);
+                if let Some(drops) = udp_kernel_drops(port) {
+                    let _ = writeln!(out, "{}.kernel_drops {}", channel.name, drops);
+                }
+                let hits = channel.busy_poll_hits.load(Ordering::Relaxed);
+                let misses = channel.busy_poll_misses.load(Ordering::Relaxed);
+                if hits + misses > 0 {
+                    let _ = writeln!(out, "{}.busy_poll_hits {}", channel.name, hits);
+                    let _ = writeln!(out, "{}.busy_poll_misses {}", channel.name, misses);
+                    let _ = writeln!(out, "{}.busy_poll_us {}", channel.name, channel.busy_poll_us.load(Ordering::Relaxed));
+                }
+            }
+        }
+        for queue in [&self.observer, &self.dsu, &self.relay, &self.mqtt] {
+            let _ = writeln!(out, "queue.{}.dropped {}", queue.name, queue.dropped.load(Ordering::Relaxed));
+        }
+        out.trim_end().to_string()
+    }
+}
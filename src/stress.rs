@@ -0,0 +1,269 @@
+use crate::protocol::{
+    DEFAULT_GAMEPAD_PORT, DEFAULT_MOUSE_PORT, HEADER_GAMEPAD_SNAPSHOT, HEADER_MOUSE, HEADER_SLOT_ASSIGN,
+    HEADER_SLOT_REQUEST,
+};
+use crate::replay::XorShift;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UdpSocket, UnixStream};
+use tokio::time::{interval, sleep, Instant, MissedTickBehavior};
+
+// One latency probe in flight at a time, every this often
+const PROBE_INTERVAL: Duration = Duration::from_millis(100);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Mouse,
+    Gamepad,
+}
+
+struct Options {
+    host: String,
+    mouse_port: u16,
+    gamepad_port: u16,
+    targets: Vec<Target>,
+    clients: usize,
+    /// Paquetes por segundo y cliente
+    rate: f64,
+    duration: Duration,
+    admin: Option<PathBuf>,
+    seed: u64,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Self {
+            host: "127.0.0.1".to_string(),
+            mouse_port: DEFAULT_MOUSE_PORT,
+            gamepad_port: DEFAULT_GAMEPAD_PORT,
+            targets: vec![Target::Gamepad],
+            clients: 1,
+            rate: 250.0,
+            duration: Duration::from_secs(10),
+            admin: None,
+            seed: 1,
+        };
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let mut value = || iter.next().ok_or_else(|| format!("falta el valor de {}", arg));
+            let number = |v: &String| v.parse::<f64>().map_err(|_| format!("valor inválido para {}: {}", arg, v));
+            match arg.as_str() {
+                "--to" => options.host = value()?.clone(),
+                "--mouse-port" => options.mouse_port = number(value()?)? as u16,
+                "--gamepad-port" => options.gamepad_port = number(value()?)? as u16,
+                "--channel" => {
+                    options.targets = match value()?.as_str() {
+                        "mouse" => vec![Target::Mouse],
+                        "gamepad" => vec![Target::Gamepad],
+                        "all" => vec![Target::Mouse, Target::Gamepad],
+                        other => return Err(format!("--channel acepta mouse, gamepad o all, no '{}'", other)),
+                    }
+                }
+                "--clients" => options.clients = (number(value()?)? as usize).max(1),
+                "--rate" => options.rate = number(value()?)?.max(1.0),
+                "--duration" => options.duration = Duration::from_secs_f64(number(value()?)?.max(1.0)),
+                "--admin" => options.admin = Some(PathBuf::from(value()?)),
+                "--seed" => options.seed = number(value()?)? as u64,
+                other => return Err(format!("argumento desconocido para stress: {}", other)),
+            }
+        }
+        Ok(options)
+    }
+
+    fn port(&self, target: Target) -> u16 {
+        match target {
+            Target::Mouse => self.mouse_port,
+            Target::Gamepad => self.gamepad_port,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Probes {
+    in_flight: Option<Instant>,
+    latencies: Vec<Duration>,
+    lost: u64,
+}
+
+/// Subcomando `stress`: inunda un servidor por loopback (o la red) con
+/// paquetes válidos aleatorios y mide latencia y pérdidas, para probar el
+/// pipeline en hardware tipo Raspberry Pi antes de cada versión.
+///
+/// Mueve el ratón y pulsa botones de verdad: úsalo contra `serve --dry-run`
+/// o en una máquina de pruebas.
+pub async fn run_stress(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let options = Arc::new(Options::parse(args)?);
+    let before = match &options.admin {
+        Some(path) => Some(received_packets(path).await?),
+        None => None,
+    };
+
+    println!(
+        "▶ {} cliente(s) x {} canal(es) a {} paquetes/s durante {}s hacia {}",
+        options.clients,
+        options.targets.len(),
+        options.rate,
+        options.duration.as_secs(),
+        options.host
+    );
+
+    let sent = Arc::new(AtomicU64::new(0));
+    let probes = Arc::new(Mutex::new(Probes::default()));
+    let mut tasks = Vec::new();
+    for client in 0..options.clients {
+        for (t, &target) in options.targets.iter().enumerate() {
+            let socket = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+            socket.connect((options.host.as_str(), options.port(target))).await?;
+            let seed = options.seed.wrapping_add((client * options.targets.len() + t) as u64);
+            // The first gamepad client also measures latency
+            let probing = target == Target::Gamepad && client == 0;
+            if probing {
+                tasks.push(tokio::spawn(receive_probes(socket.clone(), probes.clone())));
+            }
+            tasks.push(tokio::spawn(flood(
+                socket,
+                target,
+                options.clone(),
+                XorShift::new(seed),
+                sent.clone(),
+                probing.then(|| probes.clone()),
+            )));
+        }
+    }
+    sleep(options.duration).await;
+    for task in &tasks {
+        task.abort();
+    }
+    // Let the server drain its receive buffers before reading its counters
+    sleep(Duration::from_millis(500)).await;
+
+    let sent = sent.load(Ordering::Relaxed);
+    println!("\nEnviados: {} ({:.0}/s)", sent, sent as f64 / options.duration.as_secs_f64());
+    if let (Some(path), Some(before)) = (&options.admin, before) {
+        let (packets, rate_limited) = received_packets(path).await?;
+        let received = packets - before.0;
+        let limited = rate_limited - before.1;
+        let dropped = sent.saturating_sub(received);
+        println!(
+            "Recibidos por el servidor: {} (perdidos antes de llegar: {}, {:.2}%; descartados por límite de tasa: {})",
+            received,
+            dropped,
+            dropped as f64 * 100.0 / sent.max(1) as f64,
+            limited
+        );
+    }
+
+    let mut probes = probes.lock().unwrap();
+    let lost = probes.lost + probes.in_flight.is_some() as u64;
+    probes.latencies.sort();
+    let latencies = &probes.latencies;
+    if latencies.is_empty() {
+        println!("Latencia: sin respuestas a las sondas SLOT_REQUEST ({} sin respuesta)", lost);
+    } else {
+        let percentile = |p: f64| latencies[((latencies.len() - 1) as f64 * p) as usize];
+        println!(
+            "Latencia ida y vuelta ({} sondas, {} sin respuesta): p50 {:?}  p99 {:?}  máx {:?}",
+            latencies.len(),
+            lost,
+            percentile(0.5),
+            percentile(0.99),
+            latencies[latencies.len() - 1]
+        );
+    }
+    Ok(())
+}
+
+async fn flood(
+    socket: Arc<UdpSocket>,
+    target: Target,
+    options: Arc<Options>,
+    mut rng: XorShift,
+    sent: Arc<AtomicU64>,
+    probes: Option<Arc<Mutex<Probes>>>,
+) {
+    let mut ticker = interval(Duration::from_secs_f64(1.0 / options.rate));
+    // Falling behind is part of what is being measured; don't burst to catch up
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut next_probe = Instant::now();
+    loop {
+        ticker.tick().await;
+        if let Some(probes) = &probes
+            && Instant::now() >= next_probe
+        {
+            next_probe = Instant::now() + PROBE_INTERVAL;
+            let mut probes = probes.lock().unwrap();
+            if probes.in_flight.is_some_and(|t| t.elapsed() > PROBE_TIMEOUT) {
+                probes.lost += 1;
+                probes.in_flight = None;
+            }
+            if probes.in_flight.is_none() && socket.try_send(&[HEADER_SLOT_REQUEST, 0]).is_ok() {
+                probes.in_flight = Some(Instant::now());
+                sent.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let packet = match target {
+            Target::Mouse => random_mouse(&mut rng),
+            Target::Gamepad => random_snapshot(&mut rng),
+        };
+        if socket.send(&packet).await.is_ok() {
+            sent.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+async fn receive_probes(socket: Arc<UdpSocket>, probes: Arc<Mutex<Probes>>) {
+    let mut buf = [0u8; 64];
+    while let Ok(len) = socket.recv(&mut buf).await {
+        if len >= 1 && buf[0] == HEADER_SLOT_ASSIGN {
+            let mut probes = probes.lock().unwrap();
+            if let Some(sent_at) = probes.in_flight.take() {
+                probes.latencies.push(sent_at.elapsed());
+            }
+        }
+    }
+}
+
+fn random_mouse(rng: &mut XorShift) -> Vec<u8> {
+    let r = rng.next_u64().to_le_bytes();
+    // Small moves, no wheel, occasional button changes
+    let buttons = if r[2] < 8 { r[3] & 0x07 } else { 0 };
+    vec![HEADER_MOUSE, (r[0] % 21).wrapping_sub(10), (r[1] % 21).wrapping_sub(10), buttons, 0]
+}
+
+fn random_snapshot(rng: &mut XorShift) -> Vec<u8> {
+    let mut packet = vec![HEADER_GAMEPAD_SNAPSHOT, 2];
+    // Guide (bit 8) stays released so hotkeys don't fire on the host
+    let buttons = (rng.next_u64() as u16) & 0x06FF;
+    packet.extend_from_slice(&buttons.to_le_bytes());
+    for _ in 0..2 {
+        packet.extend_from_slice(&rng.next_u64().to_le_bytes());
+    }
+    packet
+}
+
+/// `(paquetes recibidos, descartados por límite de tasa)` sumando todos los
+/// canales, según `stats` en el socket de administración.
+async fn received_packets(path: &PathBuf) -> std::io::Result<(u64, u64)> {
+    let mut stream = UnixStream::connect(path).await?;
+    stream.write_all(b"stats\n").await?;
+    stream.shutdown().await?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply).await?;
+    let mut totals = (0, 0);
+    for line in reply.lines() {
+        let Some((name, value)) = line.split_once(' ') else {
+            continue;
+        };
+        let value: u64 = value.trim().parse().unwrap_or(0);
+        if name.ends_with(".packets") {
+            totals.0 += value;
+        } else if name.ends_with(".rate_limited") {
+            totals.1 += value;
+        }
+    }
+    Ok(totals)
+}
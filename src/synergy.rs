@@ -0,0 +1,287 @@
+use crate::bus::{InputAction, SharedBus, Target};
+use crate::config::{ConfigHandle, SynergyConfig};
+use crate::keyboard_layout::KeyboardLayout;
+use crate::logger::{log_detail, Verbosity};
+use evdev::Key;
+use std::collections::{HashMap, HashSet};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::time::{sleep, Duration};
+
+const DEFAULT_PORT: u16 = 24800;
+const RETRY: Duration = Duration::from_secs(5);
+// Protocol 1.6 (Barrier, Synergy 1.x); newer servers fall back to it
+const PROTOCOL_MAJOR: u16 = 1;
+const PROTOCOL_MINOR: u16 = 6;
+// Larger messages (clipboard contents) are skipped unread
+const MAX_MESSAGE: usize = 64 * 1024;
+// One wheel notch
+const WHEEL_STEP: i32 = 120;
+
+/// Pantalla cliente de Synergy/Barrier/Input Leap (`[synergy] server`): el
+/// PC principal comparte su ratón y teclado y, al llevar el puntero al borde
+/// que da a esta pantalla, pasa a mover el ratón y el teclado virtuales.
+/// En ese protocolo quien comparte es el servidor, así que aquí se conecta
+/// como cliente y se reconecta si se pierde la conexión. Sin TLS.
+pub async fn run_synergy(bus: SharedBus, config: ConfigHandle) {
+    let settings = config.get().synergy.clone();
+    let Some(server) = settings.server.clone() else {
+        return;
+    };
+    let addr = if server.contains(':') { server } else { format!("{}:{}", server, DEFAULT_PORT) };
+    loop {
+        match TcpStream::connect(&addr).await {
+            Ok(stream) => {
+                let mut screen = Screen::new(bus.clone(), &settings);
+                let result = screen.run(stream, &addr, &config).await;
+                screen.leave();
+                let reason = result.err().map_or_else(|| "cerrada por el servidor".to_string(), |e| e.to_string());
+                log_detail(Verbosity::Low, "Synergy desconectado", &reason);
+            }
+            Err(e) => log_detail(Verbosity::Medium, "Error conectando a Synergy", &format!("{}: {}", addr, e)),
+        }
+        sleep(RETRY).await;
+    }
+}
+
+struct Screen {
+    bus: SharedBus,
+    name: String,
+    width: i16,
+    height: i16,
+    // Last absolute position, to turn DMMV into relative motion
+    x: i32,
+    y: i32,
+    wheel: i32,
+    // Server-side key button -> evdev code, so a release matches its press
+    keys: HashMap<u16, u16>,
+    buttons: HashSet<u16>,
+}
+
+impl Screen {
+    fn new(bus: SharedBus, settings: &SynergyConfig) -> Self {
+        Self {
+            bus,
+            name: settings.name.clone(),
+            width: settings.width,
+            height: settings.height,
+            x: settings.width as i32 / 2,
+            y: settings.height as i32 / 2,
+            wheel: 0,
+            keys: HashMap::new(),
+            buttons: HashSet::new(),
+        }
+    }
+
+    async fn run(&mut self, stream: TcpStream, addr: &str, config: &ConfigHandle) -> std::io::Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let mut message = Vec::new();
+
+        // Hello: "Synergy" or "Barrier", then the version; the reply echoes the name
+        read_message(&mut reader, &mut message).await?;
+        let protocol = message.get(..7).filter(|name| *name == b"Synergy" || *name == b"Barrier");
+        let Some(protocol) = protocol.map(<[u8]>::to_vec) else {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "no es un servidor Synergy/Barrier"));
+        };
+        let mut hello = protocol;
+        hello.extend_from_slice(&PROTOCOL_MAJOR.to_be_bytes());
+        hello.extend_from_slice(&PROTOCOL_MINOR.to_be_bytes());
+        hello.extend_from_slice(&(self.name.len() as u32).to_be_bytes());
+        hello.extend_from_slice(self.name.as_bytes());
+        write_message(&mut writer, &hello).await?;
+        log_detail(Verbosity::Low, "Synergy conectado", &format!("{} como '{}'", addr, self.name));
+
+        loop {
+            if !read_message(&mut reader, &mut message).await? || message.len() < 4 {
+                continue;
+            }
+            let layout = config.get().keyboard.layout;
+            let (code, args) = message.split_at(4);
+            let field = |at: usize| args.get(at..at + 2).map_or(0, |b| u16::from_be_bytes([b[0], b[1]]));
+            match code {
+                b"QINF" => {
+                    let mut info = b"DINF".to_vec();
+                    let (x, y) = (self.x as i16, self.y as i16);
+                    for value in [0, 0, self.width, self.height, 0, x, y] {
+                        info.extend_from_slice(&value.to_be_bytes());
+                    }
+                    write_message(&mut writer, &info).await?;
+                }
+                b"CALV" => write_message(&mut writer, b"CALV").await?,
+                b"CINN" => {
+                    self.x = field(0) as i16 as i32;
+                    self.y = field(2) as i16 as i32;
+                }
+                b"COUT" => self.leave(),
+                b"DMMV" => {
+                    let (x, y) = (field(0) as i16 as i32, field(2) as i16 as i32);
+                    self.motion(x - self.x, y - self.y);
+                    (self.x, self.y) = (x, y);
+                }
+                b"DMRM" => self.motion(field(0) as i16 as i32, field(2) as i16 as i32),
+                b"DMDN" | b"DMUP" => {
+                    let pressed = code == b"DMDN";
+                    if let Some(button) = args.first().and_then(|&id| mouse_button(id)) {
+                        self.mouse_button(button, pressed);
+                    }
+                }
+                b"DMWM" => {
+                    // 1.3+ sends x and y deltas; older servers only y
+                    let delta = if args.len() >= 4 { field(2) } else { field(0) } as i16 as i32;
+                    self.wheel += delta;
+                    let notches = self.wheel / WHEEL_STEP;
+                    if notches != 0 {
+                        self.wheel -= notches * WHEEL_STEP;
+                        let _ = self.bus.emit(Target::Mouse, &[InputAction::Wheel(notches)]);
+                    }
+                }
+                b"DKDN" => {
+                    if let Some(key) = synergy_key(field(0), layout) {
+                        self.keys.insert(field(4), key);
+                        let _ = self.bus.emit(Target::Keyboard, &[InputAction::key(key, true)]);
+                    }
+                }
+                b"DKRP" => {
+                    if let Some(&key) = self.keys.get(&field(6)) {
+                        let _ = self.bus.emit(Target::Keyboard, &[InputAction::Key { code: key, value: 2 }]);
+                    }
+                }
+                b"DKUP" => {
+                    if let Some(key) = self.keys.remove(&field(4)) {
+                        let _ = self.bus.emit(Target::Keyboard, &[InputAction::key(key, false)]);
+                    }
+                }
+                b"CBYE" => return Ok(()),
+                b"EICV" => return Err(std::io::Error::other("versión de protocolo incompatible")),
+                b"EBSY" => return Err(std::io::Error::other(format!("ya hay una pantalla '{}' conectada", self.name))),
+                b"EUNK" => return Err(std::io::Error::other(format!("el servidor no tiene una pantalla '{}'", self.name))),
+                b"EBAD" => return Err(std::io::Error::other("el servidor rechazó un mensaje")),
+                // CIAK, CROP, DSOP, CNOP, clipboard and screensaver
+                _ => {}
+            }
+        }
+    }
+
+    fn motion(&self, dx: i32, dy: i32) {
+        if dx != 0 || dy != 0 {
+            let _ = self.bus.emit(Target::Mouse, &[InputAction::Move { dx, dy }]);
+        }
+    }
+
+    fn mouse_button(&mut self, button: u16, pressed: bool) {
+        let changed = if pressed { self.buttons.insert(button) } else { self.buttons.remove(&button) };
+        if changed {
+            let _ = self.bus.emit(Target::Mouse, &[InputAction::key(button, pressed)]);
+        }
+    }
+
+    /// El puntero salió de esta pantalla: nada queda pulsado aquí.
+    fn leave(&mut self) {
+        let keys: Vec<InputAction> = self.keys.drain().map(|(_, key)| InputAction::key(key, false)).collect();
+        self.bus.release(Target::Keyboard, &keys);
+        let buttons: Vec<InputAction> = self.buttons.drain().map(|button| InputAction::key(button, false)).collect();
+        self.bus.release(Target::Mouse, &buttons);
+        self.wheel = 0;
+    }
+}
+
+/// Lee un mensaje `[longitud: u32 BE][datos]`. Devuelve false si era más
+/// largo que `MAX_MESSAGE` y se descartó.
+async fn read_message(reader: &mut BufReader<tokio::net::tcp::OwnedReadHalf>, message: &mut Vec<u8>) -> std::io::Result<bool> {
+    let len = reader.read_u32().await? as usize;
+    if len > MAX_MESSAGE {
+        tokio::io::copy(&mut reader.take(len as u64), &mut tokio::io::sink()).await?;
+        return Ok(false);
+    }
+    message.resize(len, 0);
+    reader.read_exact(message).await?;
+    Ok(true)
+}
+
+async fn write_message(writer: &mut OwnedWriteHalf, message: &[u8]) -> std::io::Result<()> {
+    let mut framed = (message.len() as u32).to_be_bytes().to_vec();
+    framed.extend_from_slice(message);
+    writer.write_all(&framed).await
+}
+
+fn mouse_button(id: u8) -> Option<u16> {
+    match id {
+        1 => Some(Key::BTN_LEFT.0),
+        2 => Some(Key::BTN_MIDDLE.0),
+        3 => Some(Key::BTN_RIGHT.0),
+        _ => None,
+    }
+}
+
+/// Tecla evdev de un KeyID de Synergy: los caracteres son su código
+/// Unicode y se buscan en la distribución del host (sin modificadores, que
+/// llegan como teclas propias); el resto son keysyms de X11 con 0xEF en vez
+/// de 0xFF.
+fn synergy_key(id: u16, layout: KeyboardLayout) -> Option<u16> {
+    if id & 0xFF00 != 0xEF00 {
+        let c = char::from_u32(id as u32)?;
+        return layout.stroke(c).map(|stroke| stroke.key.0);
+    }
+    let key = match id & 0xFF {
+        0x08 => Key::KEY_BACKSPACE,
+        0x09 => Key::KEY_TAB,
+        0x0D => Key::KEY_ENTER,
+        0x13 => Key::KEY_PAUSE,
+        0x14 => Key::KEY_SCROLLLOCK,
+        0x15 | 0x61 => Key::KEY_SYSRQ,
+        0x1B => Key::KEY_ESC,
+        0x50 => Key::KEY_HOME,
+        0x51 => Key::KEY_LEFT,
+        0x52 => Key::KEY_UP,
+        0x53 => Key::KEY_RIGHT,
+        0x54 => Key::KEY_DOWN,
+        0x55 => Key::KEY_PAGEUP,
+        0x56 => Key::KEY_PAGEDOWN,
+        0x57 => Key::KEY_END,
+        0x63 => Key::KEY_INSERT,
+        0x67 => Key::KEY_COMPOSE,
+        0x7E | 0xEA => Key::KEY_RIGHTALT,
+        0x7F => Key::KEY_NUMLOCK,
+        0x8D => Key::KEY_KPENTER,
+        0xAA => Key::KEY_KPASTERISK,
+        0xAB => Key::KEY_KPPLUS,
+        0xAD => Key::KEY_KPMINUS,
+        0xAE => Key::KEY_KPDOT,
+        0xAF => Key::KEY_KPSLASH,
+        0xB0 => Key::KEY_KP0,
+        0xB1 => Key::KEY_KP1,
+        0xB2 => Key::KEY_KP2,
+        0xB3 => Key::KEY_KP3,
+        0xB4 => Key::KEY_KP4,
+        0xB5 => Key::KEY_KP5,
+        0xB6 => Key::KEY_KP6,
+        0xB7 => Key::KEY_KP7,
+        0xB8 => Key::KEY_KP8,
+        0xB9 => Key::KEY_KP9,
+        0xBE => Key::KEY_F1,
+        0xBF => Key::KEY_F2,
+        0xC0 => Key::KEY_F3,
+        0xC1 => Key::KEY_F4,
+        0xC2 => Key::KEY_F5,
+        0xC3 => Key::KEY_F6,
+        0xC4 => Key::KEY_F7,
+        0xC5 => Key::KEY_F8,
+        0xC6 => Key::KEY_F9,
+        0xC7 => Key::KEY_F10,
+        0xC8 => Key::KEY_F11,
+        0xC9 => Key::KEY_F12,
+        0xE1 => Key::KEY_LEFTSHIFT,
+        0xE2 => Key::KEY_RIGHTSHIFT,
+        0xE3 => Key::KEY_LEFTCTRL,
+        0xE4 => Key::KEY_RIGHTCTRL,
+        0xE5 => Key::KEY_CAPSLOCK,
+        0xE7 | 0xEB => Key::KEY_LEFTMETA,
+        0xE8 | 0xEC => Key::KEY_RIGHTMETA,
+        0xE9 => Key::KEY_LEFTALT,
+        0xFF => Key::KEY_DELETE,
+        _ => return None,
+    };
+    Some(key.0)
+}
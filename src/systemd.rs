@@ -0,0 +1,48 @@
+use crate::logger::{log_detail, Verbosity};
+use sd_notify::NotifyState;
+use std::os::fd::FromRawFd;
+use tokio::time::{Duration, interval};
+
+/// Integración con systemd (`Type=notify`). Fuera de systemd todas estas
+/// funciones son no-ops, así que se pueden llamar siempre.
+pub fn notify_ready() {
+    let _ = sd_notify::notify(false, &[NotifyState::Ready]);
+}
+
+pub fn notify_reloading() {
+    let _ = sd_notify::notify(false, &[NotifyState::Reloading]);
+}
+
+pub fn notify_stopping() {
+    let _ = sd_notify::notify(false, &[NotifyState::Stopping]);
+}
+
+/// Envía pings `WATCHDOG=1` a la mitad del intervalo pedido por `WatchdogSec=`.
+pub fn spawn_watchdog() {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(true, &mut usec) || usec == 0 {
+        return;
+    }
+    let period = Duration::from_micros(usec / 2);
+    log_detail(Verbosity::Medium, "Watchdog de systemd activo", &format!("ping cada {:?}", period));
+
+    tokio::spawn(async move {
+        let mut ticker = interval(period);
+        loop {
+            ticker.tick().await;
+            let _ = sd_notify::notify(false, &[NotifyState::Watchdog]);
+        }
+    });
+}
+
+/// Listener TCP recibido por socket activation (`retro-control-server.socket`).
+pub fn activated_tcp_listener() -> std::io::Result<Option<std::net::TcpListener>> {
+    let Some(fd) = sd_notify::listen_fds()?.next() else {
+        return Ok(None);
+    };
+    // SAFETY: systemd hands us ownership of the fds starting at SD_LISTEN_FDS_START
+    let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    listener.set_nonblocking(true)?;
+    log_detail(Verbosity::Low, "Socket TCP heredado de systemd", &format!("fd={}", fd));
+    Ok(Some(listener))
+}
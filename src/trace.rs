@@ -0,0 +1,55 @@
+use crate::locale::tr;
+use crate::logger::log_trace;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+static TRACED: OnceLock<IpAddr> = OnceLock::new();
+// Numbers the traced client's packets in arrival order, across channels
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+/// `--trace-client <ip>`: cada paquete de esa IP deja una línea por etapa
+/// (parse, filter, map, emit), sea cual sea la verbosidad. Sólo se fija al
+/// arrancar.
+pub fn set_traced_client(ip: IpAddr) {
+    let _ = TRACED.set(ip);
+}
+
+/// Un paquete del cliente trazado en su paso por el pipeline.
+#[derive(Debug, Clone, Copy)]
+pub struct Span {
+    pub seq: u64,
+    pub client: SocketAddr,
+    started: Instant,
+}
+
+/// El span de un paquete recién recibido, o nada si `client` no se traza.
+pub fn span(client: SocketAddr) -> Option<Span> {
+    if TRACED.get() != Some(&client.ip()) {
+        return None;
+    }
+    let seq = SEQUENCE.fetch_add(1, Ordering::Relaxed) + 1;
+    Some(Span { seq, client, started: Instant::now() })
+}
+
+impl Span {
+    /// Registra que el paquete pasó por `stage`; el detalle lleva el tiempo
+    /// desde que se recibió.
+    #[track_caller]
+    pub fn stage(&self, stage: &str, detail: impl FnOnce() -> String) {
+        let elapsed = self.started.elapsed().as_micros();
+        log_trace(
+            &format!("[trace] #{} {}", self.seq, stage),
+            &format!("{} +{}µs {}", self.client, elapsed, tr(&detail())),
+        );
+    }
+}
+
+/// `Span::stage` para el span opcional que llevan los paquetes.
+#[track_caller]
+pub fn stage(span: Option<Span>, stage: &str, detail: impl FnOnce() -> String) {
+    if let Some(span) = span {
+        span.stage(stage, detail);
+    }
+}
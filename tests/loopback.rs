@@ -0,0 +1,206 @@
+//! Pruebas de extremo a extremo: arrancan el servidor en puertos efímeros de
+//! localhost, le mandan paquetes reales y leen lo que entrega el kernel en
+//! los nodos /dev/input de sus dispositivos virtuales.
+//!
+//! Necesitan /dev/uinput con permiso de escritura, por eso van detrás de una
+//! feature: `cargo test --features uinput-tests -- --test-threads 1`. Sin
+//! él, cada prueba avisa y se salta en vez de fallar.
+#![cfg(feature = "uinput-tests")]
+
+use evdev::{Device, InputEventKind, Key, RelativeAxisType};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+const STARTUP: Duration = Duration::from_secs(10);
+const EVENT_WAIT: Duration = Duration::from_secs(3);
+
+const CONFIG: &str = r#"
+[network]
+bind = "127.0.0.1"
+mouse_port = 0
+keyboard_port = 0
+gamepad_port = 0
+port_fallback = 0
+
+[gamepad]
+slots = 1
+
+[devices]
+lazy = false
+"#;
+
+/// Servidor en marcha (`monitor`, que además de servir informa de qué nodo
+/// /dev/input corresponde a cada dispositivo). Se mata al soltarlo.
+struct Server {
+    child: Child,
+    config: PathBuf,
+    ports: HashMap<String, SocketAddr>,
+    nodes: HashMap<String, PathBuf>,
+}
+
+impl Server {
+    /// `None` si no hay /dev/uinput en el que escribir.
+    fn start(name: &str) -> Option<Self> {
+        if let Err(err) = std::fs::OpenOptions::new().write(true).open("/dev/uinput") {
+            eprintln!("prueba '{}' saltada: /dev/uinput no disponible ({})", name, err);
+            return None;
+        }
+        let config = std::env::temp_dir().join(format!("retro-loopback-{}-{}.toml", std::process::id(), name));
+        std::fs::write(&config, CONFIG).unwrap();
+        let mut child = Command::new(env!("CARGO_BIN_EXE_retro-control-server"))
+            .arg("monitor")
+            .arg("--config")
+            .arg(&config)
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("no se pudo lanzar el servidor");
+
+        let (tx, rx) = mpsc::channel();
+        let stdout = child.stdout.take().unwrap();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut server = Self { child, config, ports: HashMap::new(), nodes: HashMap::new() };
+        let deadline = Instant::now() + STARTUP;
+        // Mouse, keyboard and P1 are created up front (lazy = false)
+        while server.ports.len() < 3 || server.nodes.len() < 3 {
+            let line = rx
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                .expect("el servidor no terminó de arrancar");
+            server.parse_line(&line);
+        }
+        Some(server)
+    }
+
+    fn parse_line(&mut self, line: &str) {
+        // "   - Mouse UDP: 127.0.0.1:40123"
+        if let Some((name, addr)) = line.split_once(" - ").and_then(|(_, rest)| rest.split_once(": "))
+            && let Ok(addr) = addr.trim().parse()
+        {
+            self.ports.insert(name.to_string(), addr);
+        }
+        // "  └─ Gamepad P1 en /dev/input/event7"
+        if let Some((name, node)) = line.split_once("└─ ").and_then(|(_, rest)| rest.split_once(" en "))
+            && node.starts_with("/dev/input/event")
+        {
+            self.nodes.insert(name.to_string(), PathBuf::from(node.trim()));
+        }
+    }
+
+    fn port(&self, name: &str) -> SocketAddr {
+        self.ports[name]
+    }
+
+    /// Eventos que el kernel entrega en el nodo del dispositivo `name`.
+    fn events(&self, name: &str) -> Receiver<(InputEventKind, i32)> {
+        let mut device = Device::open(&self.nodes[name]).unwrap();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            while let Ok(events) = device.fetch_events() {
+                for event in events {
+                    if tx.send((event.kind(), event.value())).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        rx
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_file(&self.config);
+    }
+}
+
+fn expect_event(events: &Receiver<(InputEventKind, i32)>, kind: InputEventKind, value: i32) {
+    let deadline = Instant::now() + EVENT_WAIT;
+    loop {
+        match events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(event) if event == (kind, value) => return,
+            Ok(_) => continue,
+            Err(_) => panic!("el kernel no entregó {:?}={}", kind, value),
+        }
+    }
+}
+
+fn udp_send(to: SocketAddr, packet: &[u8]) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.send_to(packet, to).unwrap();
+}
+
+#[test]
+fn mouse_motion_reaches_the_kernel() {
+    let Some(server) = Server::start("mouse") else {
+        return;
+    };
+    let events = server.events("Mouse");
+
+    // [0x20][dx:i8][dy:i8][buttons][wheel:i8]
+    udp_send(server.port("Mouse UDP"), &[0x20, 5, -3i8 as u8, 0, 0]);
+
+    expect_event(&events, InputEventKind::RelAxis(RelativeAxisType::REL_X), 5);
+    expect_event(&events, InputEventKind::RelAxis(RelativeAxisType::REL_Y), -3);
+}
+
+#[test]
+fn keyboard_press_and_release_reach_the_kernel() {
+    let Some(server) = Server::start("keyboard") else {
+        return;
+    };
+    let events = server.events("Teclado");
+
+    let mut stream = TcpStream::connect(server.port("Teclado TCP")).unwrap();
+    stream.write_all(&[0x10, Key::KEY_A.code() as u8, 1]).unwrap();
+    stream.write_all(&[0x10, Key::KEY_A.code() as u8, 0]).unwrap();
+
+    expect_event(&events, InputEventKind::Key(Key::KEY_A), 1);
+    expect_event(&events, InputEventKind::Key(Key::KEY_A), 0);
+}
+
+#[test]
+fn gamepad_snapshot_reaches_the_kernel() {
+    let Some(server) = Server::start("gamepad") else {
+        return;
+    };
+    let events = server.events("Gamepad P1");
+
+    // [0x42][mode][buttons:u16][axes: 8 x i16], A pressed and left stick right
+    let mut snapshot = vec![0x42, 2];
+    snapshot.extend_from_slice(&1u16.to_le_bytes());
+    snapshot.extend_from_slice(&32767i16.to_le_bytes());
+    snapshot.extend_from_slice(&[0; 14]);
+    udp_send(server.port("Gamepad UDP"), &snapshot);
+
+    expect_event(&events, InputEventKind::Key(Key::BTN_SOUTH), 1);
+    expect_event(&events, InputEventKind::AbsAxis(evdev::AbsoluteAxisType::ABS_X), 32767);
+}
+
+#[test]
+fn switching_to_gamepad_mode_releases_held_keys() {
+    let Some(server) = Server::start("mode-switch") else {
+        return;
+    };
+    let events = server.events("Teclado");
+
+    let mut stream = TcpStream::connect(server.port("Teclado TCP")).unwrap();
+    stream.write_all(&[0x10, Key::KEY_A.code() as u8, 1]).unwrap();
+    expect_event(&events, InputEventKind::Key(Key::KEY_A), 1);
+
+    // [0x30][mode] 0x02 = gamepad; keyboard packets are ignored from here on
+    stream.write_all(&[0x30, 0x02]).unwrap();
+    expect_event(&events, InputEventKind::Key(Key::KEY_A), 0);
+}